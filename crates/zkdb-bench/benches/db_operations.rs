@@ -1,9 +1,33 @@
 use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
 use tempfile::TempDir;
 use tokio::runtime::Runtime;
-use zkdb_lib::{Database, DatabaseType};
+use zkdb_core::merkle::{MerkleState, ROOT_TREE};
+use zkdb_lib::{Command, Database, DatabaseType, OutputFormat, ProofMode};
 use zkdb_store::file::FileStore;
+use zkdb_store::rocks::RocksStore;
+use zkdb_store::Store;
+
+// Counts the bytes allocated, so benchmarks can print what they copy
+struct CountingAllocator;
+
+static ALLOCATED: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATED.fetch_add(layout.size(), Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
 
 // Helper function to set up a clean database for each benchmark
 async fn setup_db() -> (Database, Arc<FileStore>, TempDir) {
@@ -152,11 +176,299 @@ fn bench_batch_operations(c: &mut Criterion) {
     group.finish();
 }
 
+// Benchmark JSON against binary engine output on a pre-filled tree
+fn bench_output_format(c: &mut Criterion) {
+    let rt = create_benchmark_runtime();
+
+    let mut group = c.benchmark_group("output_format");
+    group
+        .sample_size(10)
+        .measurement_time(std::time::Duration::from_secs(20))
+        .warm_up_time(std::time::Duration::from_secs(5));
+
+    for size in [10, 100].iter() {
+        let (mut db, _store, _temp_dir) = rt.block_on(async {
+            let (mut db, store, temp_dir) = setup_db().await;
+            for i in 0..*size {
+                let key = format!("key_{}", i);
+                let value = vec![i as u8; 100];
+                db.put(&key, &value, false).await.unwrap();
+            }
+            (db, store, temp_dir)
+        });
+        let command = Command::Query {
+            key: format!("key_{}", size - 1),
+        };
+
+        for format in [OutputFormat::Json, OutputFormat::Binary] {
+            db.set_output_format(format);
+            // Criterion only reports wall time, so print the cycle count once.
+            let cycles = db.cycle_count(&command).unwrap();
+            println!("output_format/{:?}/{}: {} cycles", format, size, cycles);
+
+            group.bench_with_input(
+                BenchmarkId::new(format!("{:?}", format), size),
+                &command,
+                |b, command| b.iter(|| db.cycle_count(command).unwrap()),
+            );
+        }
+    }
+    group.finish();
+}
+
+// Benchmark repeated gets with and without prefetching the keys
+fn bench_prefetch(c: &mut Criterion) {
+    let rt = create_benchmark_runtime();
+
+    let mut group = c.benchmark_group("prefetch");
+    group
+        .sample_size(10)
+        .measurement_time(std::time::Duration::from_secs(20))
+        .warm_up_time(std::time::Duration::from_secs(5));
+
+    let keys: Vec<String> = (0..5).map(|i| format!("key_{}", i)).collect();
+    let keys: Vec<&str> = keys.iter().map(String::as_str).collect();
+    for prefetched in [false, true] {
+        let (db, _store, _temp_dir) = rt.block_on(async {
+            let (db, store, temp_dir) = setup_db().await;
+            let mut db = db.with_prefetch_capacity(keys.len());
+            for key in &keys {
+                db.put(key, &[7u8; 100], false).await.unwrap();
+            }
+            if prefetched {
+                db.prefetch(&keys).await.unwrap();
+            }
+            (db, store, temp_dir)
+        });
+
+        let name = if prefetched { "prefetched" } else { "cold" };
+        group.bench_function(BenchmarkId::new(name, 100), |b| {
+            b.to_async(&rt).iter(|| async {
+                for i in 0..100 {
+                    db.get(keys[i % keys.len()], false).await.unwrap();
+                }
+            })
+        });
+    }
+    group.finish();
+}
+
+// Benchmark reading the root of a serialized state with and without the cache
+fn bench_root_cache(c: &mut Criterion) {
+    let mut group = c.benchmark_group("root_cache");
+
+    for leaves in [100u32, 1000, 10000] {
+        let mut state = MerkleState::new();
+        let tree = state.tree_mut(ROOT_TREE);
+        for i in 0..leaves {
+            let mut leaf = [0u8; 32];
+            leaf[..4].copy_from_slice(&i.to_be_bytes());
+            tree.leaves.push(leaf);
+            tree.key_indices
+                .insert(format!("key_{}", i).into_bytes(), i as usize);
+        }
+        tree.invalidate_root();
+        let stale = state.to_bytes();
+        state.refresh_root();
+        let cached = state.to_bytes();
+
+        for (name, bytes) in [("rebuild", &stale), ("cached", &cached)] {
+            group.bench_with_input(BenchmarkId::new(name, leaves), bytes, |b, bytes| {
+                b.iter(|| MerkleState::from_bytes(bytes).unwrap().root())
+            });
+        }
+    }
+    group.finish();
+}
+
+// Benchmark verifying core and compressed proofs, printing their sizes
+fn bench_proof_modes(c: &mut Criterion) {
+    let rt = create_benchmark_runtime();
+
+    let mut group = c.benchmark_group("proof_modes");
+    group
+        .sample_size(10)
+        .measurement_time(std::time::Duration::from_secs(20))
+        .warm_up_time(std::time::Duration::from_secs(5));
+
+    for (name, mode) in [
+        ("core", ProofMode::Core),
+        ("compressed", ProofMode::Compressed),
+    ] {
+        let (db, proof, temp_dir) = rt.block_on(async {
+            let temp_dir = tempfile::tempdir().unwrap();
+            let store = Arc::new(FileStore::new(temp_dir.path().join("db")).await.unwrap());
+            let mut db = Database::builder(DatabaseType::Merkle, store)
+                .proof_mode(mode)
+                .build()
+                .await
+                .unwrap();
+            db.put("test_key", &[0u8; 100], false).await.unwrap();
+            let result = db
+                .execute_query(
+                    Command::Query {
+                        key: "test_key".to_string(),
+                    },
+                    true,
+                )
+                .await
+                .unwrap();
+            (db, result.sp1_proof.unwrap(), temp_dir)
+        });
+
+        let path = temp_dir.path().join(format!("{}.proof", name));
+        proof.proof_data.save(&path).unwrap();
+        println!(
+            "{} proof: {} bytes",
+            name,
+            std::fs::metadata(&path).unwrap().len()
+        );
+
+        group.bench_function(BenchmarkId::new("verify", name), |b| {
+            b.iter(|| assert!(db.verify_proof(&proof).unwrap()))
+        });
+    }
+    group.finish();
+}
+
+// Benchmark compacting a store after 1000 keys were inserted and deleted,
+// printing its size before and after the first compaction
+fn bench_compaction(c: &mut Criterion) {
+    let rt = create_benchmark_runtime();
+
+    let mut group = c.benchmark_group("compaction");
+    group.sample_size(10);
+
+    for name in ["file", "rocks"] {
+        let (db, _temp_dir) = rt.block_on(async {
+            let temp_dir = tempfile::tempdir().unwrap();
+            let path = temp_dir.path().join("db");
+            let store: Arc<dyn Store> = match name {
+                "file" => Arc::new(FileStore::new(&path).await.unwrap()),
+                _ => Arc::new(RocksStore::new(&path).unwrap()),
+            };
+            let mut db = Database::new(DatabaseType::Merkle, store, None)
+                .await
+                .unwrap();
+            let keys: Vec<String> = (0..1000)
+                .map(|i| format!("shard_{}/key_{}", i % 10, i))
+                .collect();
+            db.put_many(
+                keys.iter().map(|key| (key.clone(), vec![0u8; 100])),
+                100,
+                false,
+            )
+            .await
+            .unwrap();
+            for key in &keys {
+                db.delete(key, false).await.unwrap();
+            }
+            (db, temp_dir)
+        });
+
+        let stats = rt.block_on(db.compact()).unwrap();
+        println!(
+            "compaction/{}: {} bytes before, {} bytes after, {} ms",
+            name, stats.bytes_before, stats.bytes_after, stats.duration_ms
+        );
+
+        group.bench_function(BenchmarkId::new("compact", name), |b| {
+            b.to_async(&rt)
+                .iter(|| async { db.compact().await.unwrap() })
+        });
+    }
+    group.finish();
+}
+
+// Benchmark a put against a synthetic 50MB state, printing the bytes one
+// allocates: the new state is moved from the executor into the database
+// on commit rather than copied
+fn bench_state_handoff(c: &mut Criterion) {
+    let rt = create_benchmark_runtime();
+
+    let mut group = c.benchmark_group("state_handoff");
+    group.sample_size(10);
+
+    let mut state = MerkleState::new();
+    let tree = state.tree_mut(ROOT_TREE);
+    tree.leaves = vec![[1u8; 32]; 50 * 1024 * 1024 / 32];
+    tree.invalidate_root();
+    state.refresh_root();
+    let state = state.to_bytes();
+
+    let (mut db, _store, _temp_dir) = rt.block_on(setup_db());
+    db.set_state(state);
+    let before = ALLOCATED.load(Ordering::Relaxed);
+    rt.block_on(db.put("key_0", b"value", false)).unwrap();
+    println!(
+        "state_handoff/put: {} bytes allocated for a {} byte state",
+        ALLOCATED.load(Ordering::Relaxed) - before,
+        db.get_state().len()
+    );
+
+    let mut next = 1;
+    group.bench_function(BenchmarkId::new("put", "50MB"), |b| {
+        b.iter(|| {
+            let key = format!("key_{}", next);
+            next += 1;
+            rt.block_on(db.put(&key, b"value", false)).unwrap();
+        })
+    });
+    group.finish();
+}
+
+// Benchmark the cycles taken to read a 10k-leaf state into the engine,
+// printing the count once; run at an earlier protocol version to compare
+fn bench_state_input(c: &mut Criterion) {
+    let rt = create_benchmark_runtime();
+
+    let mut group = c.benchmark_group("state_input");
+    group
+        .sample_size(10)
+        .measurement_time(std::time::Duration::from_secs(20))
+        .warm_up_time(std::time::Duration::from_secs(5));
+
+    let mut state = MerkleState::new();
+    let tree = state.tree_mut(ROOT_TREE);
+    for i in 0..10_000u32 {
+        let mut leaf = [0u8; 32];
+        leaf[..4].copy_from_slice(&i.to_le_bytes());
+        tree.key_indices
+            .insert(format!("key_{}", i).into_bytes(), i as usize);
+        tree.leaves.push(leaf);
+    }
+    tree.invalidate_root();
+    state.refresh_root();
+    let state = state.to_bytes();
+
+    let (mut db, _store, _temp_dir) = rt.block_on(setup_db());
+    db.set_state(state.clone());
+    let command = Command::GetRoot;
+    let cycles = db.cycle_count(&command).unwrap();
+    println!(
+        "state_input/10k: {} cycles for a {} byte state",
+        cycles,
+        state.len()
+    );
+
+    group.bench_function(BenchmarkId::new("get_root", "10k"), |b| {
+        b.iter(|| db.cycle_count(&command).unwrap())
+    });
+    group.finish();
+}
+
 criterion_group!(
     benches,
     bench_put,
     bench_get,
     bench_proof_generation,
-    bench_batch_operations
+    bench_batch_operations,
+    bench_output_format,
+    bench_prefetch,
+    bench_root_cache,
+    bench_proof_modes,
+    bench_compaction,
+    bench_state_handoff,
+    bench_state_input
 );
 criterion_main!(benches);
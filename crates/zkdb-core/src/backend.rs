@@ -0,0 +1,102 @@
+//! Accumulators the Merkle engine can commit its leaves with.
+//!
+//! The engine builds trees, roots and proofs through `TreeBackend` rather
+//! than through a particular library, so that another accumulator, e.g. a
+//! sparse Merkle tree, can be tried by implementing it. States are written
+//! with `RsMerkle`, and verifiers expect its proofs.
+
+use alloc::vec::Vec;
+use rs_merkle::{algorithms::Sha256, proof_serializers, Hasher, MerkleProof, MerkleTree};
+
+/// An accumulator over 32-byte leaves, identified by their index.
+pub trait TreeBackend: Sized {
+    /// Size in bytes of a node, and so of each sibling in a proof.
+    const HASH_SIZE: usize;
+
+    /// Builds the accumulator over `leaves`, in order.
+    fn from_leaves(leaves: &[[u8; 32]]) -> Self;
+
+    /// Appends a leaf.
+    fn insert(&mut self, leaf: [u8; 32]);
+
+    /// Root over the leaves, or `None` when there are none.
+    fn root(&self) -> Option<[u8; 32]>;
+
+    /// Serialized proof of inclusion of the leaves at `indices`, which must
+    /// be sorted.
+    fn prove(&self, indices: &[usize]) -> Vec<u8>;
+
+    /// Checks a proof from `prove` that `leaves` are at `indices` of a tree
+    /// of `total_leaves` leaves with `root`. A proof that does not decode
+    /// fails the check.
+    fn verify(
+        root: [u8; 32],
+        indices: &[usize],
+        leaves: &[[u8; 32]],
+        total_leaves: usize,
+        proof: &[u8],
+    ) -> bool;
+
+    /// Path from the leaf at `index` to the root: for each level from the
+    /// leaf up, the node's sibling, `None` where the node is promoted
+    /// without one, and the parent they hash into.
+    fn path(&self, index: usize) -> Vec<(Option<[u8; 32]>, [u8; 32])>;
+}
+
+/// Binary SHA-256 Merkle tree built with rs_merkle, an odd node out being
+/// promoted unchanged. Proofs are serialized in `ReverseHashesOrder`.
+pub struct RsMerkle {
+    tree: MerkleTree<Sha256>,
+}
+
+impl TreeBackend for RsMerkle {
+    const HASH_SIZE: usize = core::mem::size_of::<<Sha256 as Hasher>::Hash>();
+
+    fn from_leaves(leaves: &[[u8; 32]]) -> Self {
+        RsMerkle {
+            tree: MerkleTree::from_leaves(leaves),
+        }
+    }
+
+    fn insert(&mut self, leaf: [u8; 32]) {
+        self.tree.insert(leaf).commit();
+    }
+
+    fn root(&self) -> Option<[u8; 32]> {
+        self.tree.root()
+    }
+
+    fn prove(&self, indices: &[usize]) -> Vec<u8> {
+        self.tree
+            .proof(indices)
+            .serialize::<proof_serializers::ReverseHashesOrder>()
+    }
+
+    fn verify(
+        root: [u8; 32],
+        indices: &[usize],
+        leaves: &[[u8; 32]],
+        total_leaves: usize,
+        proof: &[u8],
+    ) -> bool {
+        MerkleProof::<Sha256>::deserialize::<proof_serializers::ReverseHashesOrder>(proof)
+            .map(|proof| proof.verify(root, indices, leaves, total_leaves))
+            .unwrap_or(false)
+    }
+
+    fn path(&self, index: usize) -> Vec<(Option<[u8; 32]>, [u8; 32])> {
+        let mut path = Vec::new();
+        let mut layer = self.tree.leaves().unwrap_or_default();
+        let mut position = index;
+        while layer.len() > 1 {
+            let sibling = layer.get(position ^ 1).copied();
+            layer = layer
+                .chunks(2)
+                .map(|pair| Sha256::concat_and_hash(&pair[0], pair.get(1)))
+                .collect();
+            position /= 2;
+            path.push((sibling, layer[position]));
+        }
+        path
+    }
+}
@@ -0,0 +1,540 @@
+//! State layout of the IAVL engine, shared by the zkVM program and the host.
+//!
+//! Keys live in an AVL tree: a binary search tree whose subtrees differ in
+//! height by at most one, rebalanced by rotations after every write. Every
+//! node holds a key and the hash of its value, and the hash of a node
+//! commits to both, to its height and size, and to the hashes of its
+//! children, so the root commits to the keys in order. Rebalancing only
+//! depends on the keys written, so the same writes give the same root.
+//!
+//! A proof is the path up to the root from the node of a key or, for a key
+//! that is absent, from the empty slot a search for it ends in.
+
+use alloc::boxed::Box;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::cmp::Ordering;
+use rs_merkle::{algorithms::Sha256, Hasher};
+use serde::{Deserialize, Serialize};
+
+#[cfg(feature = "std")]
+use crate::DatabaseError;
+use crate::DEFAULT_RESERVED_PREFIX;
+
+/// Bytes a serialized `IavlState` starts with.
+pub const IAVL_STATE_MAGIC: [u8; 4] = *b"ZKAV";
+
+/// Version of the layout `IavlState::to_bytes` writes.
+pub const IAVL_STATE_VERSION: u16 = 1;
+
+/// Hash of an empty subtree.
+pub const EMPTY: [u8; 32] = [0u8; 32];
+
+/// Hash of a node: the SHA-256 of its height, its size as a little-endian
+/// `u64`, its key's length as a little-endian `u64`, the key, the value hash
+/// and the hashes of its children, `EMPTY` for a missing one.
+pub fn iavl_node_hash(
+    key: &[u8],
+    value_hash: &[u8; 32],
+    height: u8,
+    size: u64,
+    left: &[u8; 32],
+    right: &[u8; 32],
+) -> [u8; 32] {
+    let mut preimage = Vec::with_capacity(17 + key.len() + 96);
+    preimage.push(height);
+    preimage.extend_from_slice(&size.to_le_bytes());
+    preimage.extend_from_slice(&(key.len() as u64).to_le_bytes());
+    preimage.extend_from_slice(key);
+    preimage.extend_from_slice(value_hash);
+    preimage.extend_from_slice(left);
+    preimage.extend_from_slice(right);
+    <Sha256 as Hasher>::hash(&preimage)
+}
+
+/// A subtree, `None` when empty.
+pub type Link = Option<Box<IavlNode>>;
+
+/// A node of the tree.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct IavlNode {
+    pub key: Vec<u8>,
+    pub value_hash: [u8; 32],
+    /// Number of levels of the subtree, 1 for a node without children.
+    pub height: u8,
+    /// Number of keys in the subtree.
+    pub size: u64,
+    pub left: Link,
+    pub right: Link,
+    /// `iavl_node_hash` of the node, kept up to date by every write.
+    pub hash: [u8; 32],
+}
+
+fn height(link: &Link) -> u8 {
+    link.as_ref().map_or(0, |node| node.height)
+}
+
+fn size(link: &Link) -> u64 {
+    link.as_ref().map_or(0, |node| node.size)
+}
+
+fn hash(link: &Link) -> [u8; 32] {
+    link.as_ref().map_or(EMPTY, |node| node.hash)
+}
+
+impl IavlNode {
+    fn new(key: Vec<u8>, value_hash: [u8; 32]) -> Box<Self> {
+        let mut node = Box::new(IavlNode {
+            key,
+            value_hash,
+            height: 1,
+            size: 1,
+            left: None,
+            right: None,
+            hash: EMPTY,
+        });
+        node.update();
+        node
+    }
+
+    /// Recomputes the height, size and hash from the children.
+    fn update(&mut self) {
+        self.height = 1 + height(&self.left).max(height(&self.right));
+        self.size = 1 + size(&self.left) + size(&self.right);
+        self.hash = iavl_node_hash(
+            &self.key,
+            &self.value_hash,
+            self.height,
+            self.size,
+            &hash(&self.left),
+            &hash(&self.right),
+        );
+    }
+
+    /// How much taller the left subtree is than the right one.
+    fn balance(&self) -> i16 {
+        height(&self.left) as i16 - height(&self.right) as i16
+    }
+}
+
+fn rotate_right(mut node: Box<IavlNode>) -> Box<IavlNode> {
+    let mut pivot = node
+        .left
+        .take()
+        .expect("a left-heavy node has a left child");
+    node.left = pivot.right.take();
+    node.update();
+    pivot.right = Some(node);
+    pivot.update();
+    pivot
+}
+
+fn rotate_left(mut node: Box<IavlNode>) -> Box<IavlNode> {
+    let mut pivot = node
+        .right
+        .take()
+        .expect("a right-heavy node has a right child");
+    node.right = pivot.left.take();
+    node.update();
+    pivot.left = Some(node);
+    pivot.update();
+    pivot
+}
+
+/// Updates `node` after a write below it and rotates it back into balance.
+fn rebalance(mut node: Box<IavlNode>) -> Box<IavlNode> {
+    node.update();
+    let balance = node.balance();
+    if balance > 1 {
+        if node.left.as_ref().is_some_and(|left| left.balance() < 0) {
+            node.left = node.left.take().map(rotate_left);
+        }
+        return rotate_right(node);
+    }
+    if balance < -1 {
+        if node.right.as_ref().is_some_and(|right| right.balance() > 0) {
+            node.right = node.right.take().map(rotate_right);
+        }
+        return rotate_left(node);
+    }
+    node
+}
+
+fn insert(link: Link, key: &[u8], value_hash: [u8; 32]) -> (Box<IavlNode>, Option<[u8; 32]>) {
+    let Some(mut node) = link else {
+        return (IavlNode::new(key.to_vec(), value_hash), None);
+    };
+    let old = match key.cmp(&node.key) {
+        Ordering::Less => {
+            let (left, old) = insert(node.left.take(), key, value_hash);
+            node.left = Some(left);
+            old
+        }
+        Ordering::Greater => {
+            let (right, old) = insert(node.right.take(), key, value_hash);
+            node.right = Some(right);
+            old
+        }
+        Ordering::Equal => Some(core::mem::replace(&mut node.value_hash, value_hash)),
+    };
+    (rebalance(node), old)
+}
+
+fn remove(link: Link, key: &[u8]) -> (Link, Option<[u8; 32]>) {
+    let Some(mut node) = link else {
+        return (None, None);
+    };
+    let removed = match key.cmp(&node.key) {
+        Ordering::Less => {
+            let (left, removed) = remove(node.left.take(), key);
+            node.left = left;
+            removed
+        }
+        Ordering::Greater => {
+            let (right, removed) = remove(node.right.take(), key);
+            node.right = right;
+            removed
+        }
+        Ordering::Equal => {
+            let removed = node.value_hash;
+            match (node.left.take(), node.right.take()) {
+                (None, None) => return (None, Some(removed)),
+                (Some(child), None) | (None, Some(child)) => return (Some(child), Some(removed)),
+                // Take the place of the smallest key to the right.
+                (left, Some(right)) => {
+                    let (right, min) = remove_min(right);
+                    node.key = min.key;
+                    node.value_hash = min.value_hash;
+                    node.left = left;
+                    node.right = right;
+                }
+            }
+            Some(removed)
+        }
+    };
+    if removed.is_none() {
+        // Nothing below changed.
+        return (Some(node), None);
+    }
+    (Some(rebalance(node)), removed)
+}
+
+/// Detaches the node of the smallest key, returning the rest of the subtree
+/// and that node.
+fn remove_min(mut node: Box<IavlNode>) -> (Link, Box<IavlNode>) {
+    match node.left.take() {
+        None => (node.right.take(), node),
+        Some(left) => {
+            let (rest, min) = remove_min(left);
+            node.left = rest;
+            (Some(rebalance(node)), min)
+        }
+    }
+}
+
+/// Appends the keys of `link` in `[start, end)` to `out`, in order, until
+/// it holds `limit`.
+fn collect_range(
+    link: &Link,
+    start: &[u8],
+    end: &[u8],
+    limit: usize,
+    out: &mut Vec<(Vec<u8>, [u8; 32])>,
+) {
+    let Some(node) = link else {
+        return;
+    };
+    if node.key.as_slice() > start {
+        collect_range(&node.left, start, end, limit, out);
+    }
+    if out.len() < limit && node.key.as_slice() >= start && node.key.as_slice() < end {
+        out.push((node.key.clone(), node.value_hash));
+    }
+    if out.len() < limit && node.key.as_slice() < end {
+        collect_range(&node.right, start, end, limit, out);
+    }
+}
+
+/// Whether the subtree at `link` is ordered within `(low, high)`, balanced,
+/// and records its height and size: the height if so.
+fn checked_height(link: &Link, low: Option<&[u8]>, high: Option<&[u8]>) -> Option<u8> {
+    let Some(node) = link else {
+        return Some(0);
+    };
+    let key = node.key.as_slice();
+    if low.is_some_and(|low| key <= low) || high.is_some_and(|high| key >= high) {
+        return None;
+    }
+    let left = checked_height(&node.left, low, Some(key))?;
+    let right = checked_height(&node.right, Some(key), high)?;
+    let consistent = left.abs_diff(right) <= 1
+        && node.height == 1 + left.max(right)
+        && node.size == 1 + size(&node.left) + size(&node.right);
+    consistent.then_some(node.height)
+}
+
+fn collect_all(link: &Link, out: &mut Vec<(Vec<u8>, [u8; 32])>) {
+    if let Some(node) = link {
+        collect_all(&node.left, out);
+        out.push((node.key.clone(), node.value_hash));
+        collect_all(&node.right, out);
+    }
+}
+
+/// The node a proof starts from, for a key that is present.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct IavlNodeProof {
+    pub height: u8,
+    pub size: u64,
+    pub left: [u8; 32],
+    pub right: [u8; 32],
+}
+
+/// An ancestor on the path of a proof.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct IavlStep {
+    pub key: Vec<u8>,
+    pub value_hash: [u8; 32],
+    pub height: u8,
+    pub size: u64,
+    /// Hash of the child off the path.
+    pub sibling: [u8; 32],
+    /// Whether the path comes up from the left child, the proven key then
+    /// sorting before `key`.
+    pub from_left: bool,
+}
+
+/// Proof of what a key holds: its node, `None` when it is absent, and the
+/// ancestors from there up to the root.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct IavlProof {
+    pub node: Option<IavlNodeProof>,
+    pub path: Vec<IavlStep>,
+}
+
+impl IavlProof {
+    /// Root of the tree in which `key` holds `value_hash`, or nothing for
+    /// `None`. `None` if the path does not order `key` as it claims or the
+    /// node does not match `value_hash`.
+    pub fn root(&self, key: &[u8], value_hash: Option<&[u8; 32]>) -> Option<[u8; 32]> {
+        let mut hash = match (&self.node, value_hash) {
+            (Some(node), Some(value_hash)) => iavl_node_hash(
+                key,
+                value_hash,
+                node.height,
+                node.size,
+                &node.left,
+                &node.right,
+            ),
+            (None, None) => EMPTY,
+            _ => return None,
+        };
+        for step in &self.path {
+            if (key < step.key.as_slice()) != step.from_left || key == step.key.as_slice() {
+                return None;
+            }
+            let (left, right) = if step.from_left {
+                (&hash, &step.sibling)
+            } else {
+                (&step.sibling, &hash)
+            };
+            hash = iavl_node_hash(
+                &step.key,
+                &step.value_hash,
+                step.height,
+                step.size,
+                left,
+                right,
+            );
+        }
+        Some(hash)
+    }
+}
+
+/// Serializable state of the IAVL engine.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct IavlState {
+    pub root: Link,
+    /// Prefix of the keys the host keeps for bookkeeping, which inserts
+    /// reject.
+    pub reserved_prefix: String,
+}
+
+impl Default for IavlState {
+    fn default() -> Self {
+        IavlState {
+            root: None,
+            reserved_prefix: DEFAULT_RESERVED_PREFIX.into(),
+        }
+    }
+}
+
+impl IavlState {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Deserializes a state, treating empty bytes as an empty tree, and
+    /// checks it with `validate`.
+    #[cfg(feature = "std")]
+    pub fn from_bytes(state: &[u8]) -> Result<Self, DatabaseError> {
+        let iavl = Self::decode(state)?;
+        iavl.validate()?;
+        Ok(iavl)
+    }
+
+    #[cfg(feature = "std")]
+    fn decode(state: &[u8]) -> Result<Self, DatabaseError> {
+        if state.is_empty() {
+            return Ok(IavlState::new());
+        }
+        let failed = DatabaseError::StateDecode;
+        let rest = state
+            .strip_prefix(&IAVL_STATE_MAGIC)
+            .ok_or_else(|| failed("Not an IAVL state".into()))?;
+        let version = rest
+            .get(..2)
+            .map(|version| u16::from_le_bytes([version[0], version[1]]));
+        if version != Some(IAVL_STATE_VERSION) {
+            return Err(failed(format!(
+                "Unsupported IAVL state version {:?}, this engine reads {}",
+                version, IAVL_STATE_VERSION
+            )));
+        }
+        bincode::deserialize(&rest[2..])
+            .map_err(|e| failed(format!("Failed to deserialize state: {}", e)))
+    }
+
+    /// Checks that keys are ordered and that every node is balanced and
+    /// records its height and size. Node hashes are taken as written.
+    #[cfg(feature = "std")]
+    pub fn validate(&self) -> Result<(), DatabaseError> {
+        match checked_height(&self.root, None, None) {
+            Some(_) => Ok(()),
+            None => Err(DatabaseError::StateDecode("corrupted state".into())),
+        }
+    }
+
+    /// Serializes the state as `IAVL_STATE_MAGIC`, `IAVL_STATE_VERSION` as a
+    /// little-endian `u16`, then the bincode-encoded state.
+    #[cfg(feature = "std")]
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = IAVL_STATE_MAGIC.to_vec();
+        bytes.extend_from_slice(&IAVL_STATE_VERSION.to_le_bytes());
+        bincode::serialize_into(&mut bytes, self).expect("Failed to serialize state");
+        bytes
+    }
+
+    /// Whether no key is held.
+    pub fn is_empty(&self) -> bool {
+        self.root.is_none()
+    }
+
+    /// Number of keys held.
+    pub fn len(&self) -> usize {
+        size(&self.root) as usize
+    }
+
+    /// Hash of the root node, `None` when no key is held.
+    pub fn root_hash(&self) -> Option<[u8; 32]> {
+        self.root.as_ref().map(|node| node.hash)
+    }
+
+    /// Value hash held for `key`.
+    pub fn get(&self, key: &[u8]) -> Option<[u8; 32]> {
+        let mut link = &self.root;
+        while let Some(node) = link {
+            link = match key.cmp(&node.key) {
+                Ordering::Less => &node.left,
+                Ordering::Greater => &node.right,
+                Ordering::Equal => return Some(node.value_hash),
+            };
+        }
+        None
+    }
+
+    /// Number of keys below `key`: its position in key order.
+    pub fn rank(&self, key: &[u8]) -> usize {
+        let mut rank = 0;
+        let mut link = &self.root;
+        while let Some(node) = link {
+            link = match key.cmp(&node.key) {
+                Ordering::Less => &node.left,
+                Ordering::Greater => {
+                    rank += size(&node.left) + 1;
+                    &node.right
+                }
+                Ordering::Equal => return (rank + size(&node.left)) as usize,
+            };
+        }
+        rank as usize
+    }
+
+    /// Sets the value hash of `key`, returning the one it replaced.
+    pub fn insert(&mut self, key: &[u8], value_hash: [u8; 32]) -> Option<[u8; 32]> {
+        let (root, old) = insert(self.root.take(), key, value_hash);
+        self.root = Some(root);
+        old
+    }
+
+    /// Removes `key`, returning its value hash.
+    pub fn remove(&mut self, key: &[u8]) -> Option<[u8; 32]> {
+        let (root, removed) = remove(self.root.take(), key);
+        self.root = root;
+        removed
+    }
+
+    /// Up to `limit` keys in `[start, end)` with their value hashes, in key
+    /// order.
+    pub fn range(&self, start: &[u8], end: &[u8], limit: usize) -> Vec<(Vec<u8>, [u8; 32])> {
+        let mut out = Vec::new();
+        collect_range(&self.root, start, end, limit, &mut out);
+        out
+    }
+
+    /// Every key with its value hash, in key order.
+    pub fn entries(&self) -> Vec<(Vec<u8>, [u8; 32])> {
+        let mut out = Vec::with_capacity(self.len());
+        collect_all(&self.root, &mut out);
+        out
+    }
+
+    /// Proves what `key` holds: the path from its node, or from the empty
+    /// slot where it would be.
+    pub fn prove(&self, key: &[u8]) -> IavlProof {
+        let mut path = Vec::new();
+        let mut link = &self.root;
+        let mut found = None;
+        while let Some(node) = link {
+            let from_left = match key.cmp(&node.key) {
+                Ordering::Less => true,
+                Ordering::Greater => false,
+                Ordering::Equal => {
+                    found = Some(IavlNodeProof {
+                        height: node.height,
+                        size: node.size,
+                        left: hash(&node.left),
+                        right: hash(&node.right),
+                    });
+                    break;
+                }
+            };
+            let (next, sibling) = if from_left {
+                (&node.left, &node.right)
+            } else {
+                (&node.right, &node.left)
+            };
+            path.push(IavlStep {
+                key: node.key.clone(),
+                value_hash: node.value_hash,
+                height: node.height,
+                size: node.size,
+                sibling: hash(sibling),
+                from_left,
+            });
+            link = next;
+        }
+        path.reverse();
+        IavlProof { node: found, path }
+    }
+}
@@ -2,10 +2,17 @@
 
 extern crate alloc;
 
+use alloc::boxed::Box;
 use alloc::string::String;
 use alloc::vec::Vec;
+use core::fmt;
 use serde::{Deserialize, Serialize};
 
+pub mod backend;
+pub mod iavl;
+pub mod merkle;
+pub mod smt;
+
 pub trait DatabaseEngine {
     fn execute_query(
         &mut self,
@@ -16,18 +23,627 @@ pub trait DatabaseEngine {
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub enum Command {
-    Query { key: String },
-    Prove { key: String },
-    Insert { key: String, value: String },
+    Query {
+        key: String,
+    },
+    Prove {
+        key: String,
+    },
+    Insert {
+        key: String,
+        value: String,
+    },
+    History {
+        key: String,
+    },
+    Inspect {
+        key: String,
+    },
+    GetRoot,
+    MultiProve {
+        keys: Vec<String>,
+    },
+    /// Inserts `(key, value)` pairs in order, as if by consecutive `Insert`s.
+    BatchInsert {
+        entries: Vec<(String, String)>,
+    },
+    /// Removes a key, zeroing its leaf so the indices of other keys are kept.
+    Delete {
+        key: String,
+    },
+    /// Removes several keys, as if by consecutive `Delete`s.
+    BatchDelete {
+        keys: Vec<String>,
+    },
+    /// Proves every key in `[start, end)` with a single multiproof.
+    ProveRange {
+        start: String,
+        end: String,
+    },
+    /// Reports the size of the proof `Prove` would produce, without hashing.
+    ProofSize {
+        key: String,
+    },
+    /// Empties the tree, dropping every leaf, key and history entry.
+    Clear,
+    /// Drops the leaves no key references, those left behind by overwritten
+    /// and deleted keys under `TreeLayout::Append`, numbering the rest anew
+    /// in the order they were written. Proofs generated before no longer
+    /// match the tree, and history entries keep the leaf indices they were
+    /// recorded with.
+    Compact,
+    /// Runs `command` against the tree named `tree` instead of the root
+    /// tree. Scopes do not nest.
+    InTree {
+        tree: String,
+        command: Box<Command>,
+    },
+    /// Returns the root the tree had after its `version`th mutation,
+    /// counting from 1, if it is still among the recorded ones.
+    RootAt {
+        version: u64,
+    },
+    /// `Insert` with a key of arbitrary bytes. A UTF-8 key is the same key
+    /// as the string it spells.
+    InsertBytes {
+        key: Vec<u8>,
+        value: String,
+    },
+    /// `Query` with a key of arbitrary bytes.
+    QueryBytes {
+        key: Vec<u8>,
+    },
+    /// `Delete` with a key of arbitrary bytes.
+    DeleteBytes {
+        key: Vec<u8>,
+    },
+    /// `Prove` with a key of arbitrary bytes.
+    ProveBytes {
+        key: Vec<u8>,
+    },
+    /// Looks up several keys at once, reporting missing ones rather than
+    /// failing on them.
+    MultiQuery {
+        keys: Vec<String>,
+    },
+    /// Lists up to `limit` keys in `[start, end)` in key order, with a
+    /// multiproof over their leaves and the key to continue from.
+    Range {
+        start: String,
+        end: String,
+        limit: usize,
+    },
+    /// Proves that `key` is not in the tree, by the adjacent leaves of the
+    /// keys around it. Only states laid out as `TreeLayout::Sorted` can, or
+    /// by its empty leaf under the sparse Merkle engine.
+    ProveAbsence {
+        key: String,
+    },
+    /// `Insert` that also reports the leaf and value hash the key held
+    /// before, null if it held none, read in the same execution.
+    Replace {
+        key: String,
+        value: String,
+    },
+    /// Runs `commands` in order in one execution, each against the state
+    /// the one before left, and reports their outputs under `results`. The
+    /// state is only committed if every command succeeds; the first failure
+    /// aborts the script with `DatabaseError::ScriptFailed`. Scripts nest at
+    /// most `MAX_SCRIPT_DEPTH` deep and cannot be scoped to a tree, though
+    /// their commands can.
+    Script {
+        commands: Vec<Command>,
+    },
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+impl Command {
+    /// Name of the variant, used to label operations outside the engine.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Command::Query { .. } => "Query",
+            Command::Prove { .. } => "Prove",
+            Command::Insert { .. } => "Insert",
+            Command::History { .. } => "History",
+            Command::Inspect { .. } => "Inspect",
+            Command::GetRoot => "GetRoot",
+            Command::MultiProve { .. } => "MultiProve",
+            Command::BatchInsert { .. } => "BatchInsert",
+            Command::Delete { .. } => "Delete",
+            Command::BatchDelete { .. } => "BatchDelete",
+            Command::ProveRange { .. } => "ProveRange",
+            Command::ProofSize { .. } => "ProofSize",
+            Command::Clear => "Clear",
+            Command::Compact => "Compact",
+            Command::InTree { command, .. } => command.kind(),
+            Command::RootAt { .. } => "RootAt",
+            Command::InsertBytes { .. } => "InsertBytes",
+            Command::QueryBytes { .. } => "QueryBytes",
+            Command::DeleteBytes { .. } => "DeleteBytes",
+            Command::ProveBytes { .. } => "ProveBytes",
+            Command::MultiQuery { .. } => "MultiQuery",
+            Command::Range { .. } => "Range",
+            Command::ProveAbsence { .. } => "ProveAbsence",
+            Command::Replace { .. } => "Replace",
+            Command::Script { .. } => "Script",
+        }
+    }
+
+    /// Scopes the command to the tree named `tree`.
+    pub fn in_tree(self, tree: impl Into<String>) -> Command {
+        Command::InTree {
+            tree: tree.into(),
+            command: Box::new(self),
+        }
+    }
+
+    /// Name of the tree the command is scoped to, `None` for the root tree.
+    pub fn tree(&self) -> Option<&str> {
+        match self {
+            Command::InTree { tree, .. } => Some(tree),
+            _ => None,
+        }
+    }
+
+    /// The key the command operates on, `None` for the commands taking a
+    /// key of arbitrary bytes: see `key_bytes`.
+    pub fn key(&self) -> Option<&str> {
+        match self {
+            Command::Query { key }
+            | Command::Prove { key }
+            | Command::Insert { key, .. }
+            | Command::Replace { key, .. }
+            | Command::History { key }
+            | Command::Inspect { key }
+            | Command::Delete { key }
+            | Command::ProofSize { key }
+            | Command::ProveAbsence { key } => Some(key),
+            Command::GetRoot
+            | Command::MultiProve { .. }
+            | Command::BatchInsert { .. }
+            | Command::BatchDelete { .. }
+            | Command::ProveRange { .. }
+            | Command::Clear
+            | Command::Compact
+            | Command::RootAt { .. }
+            | Command::InsertBytes { .. }
+            | Command::QueryBytes { .. }
+            | Command::DeleteBytes { .. }
+            | Command::ProveBytes { .. }
+            | Command::MultiQuery { .. }
+            | Command::Range { .. }
+            | Command::Script { .. } => None,
+            Command::InTree { command, .. } => command.key(),
+        }
+    }
+
+    /// The key the command operates on, as the bytes the engine indexes.
+    pub fn key_bytes(&self) -> Option<&[u8]> {
+        match self {
+            Command::InsertBytes { key, .. }
+            | Command::QueryBytes { key }
+            | Command::DeleteBytes { key }
+            | Command::ProveBytes { key } => Some(key),
+            Command::InTree { command, .. } => command.key_bytes(),
+            command => command.key().map(str::as_bytes),
+        }
+    }
+
+    /// Whether executing the command changes the engine state.
+    pub fn is_mutating(&self) -> bool {
+        match self {
+            Command::InTree { command, .. } => command.is_mutating(),
+            Command::Script { commands } => commands.iter().any(Command::is_mutating),
+            command => matches!(
+                command,
+                Command::Insert { .. }
+                    | Command::Replace { .. }
+                    | Command::BatchInsert { .. }
+                    | Command::Delete { .. }
+                    | Command::BatchDelete { .. }
+                    | Command::Clear
+                    | Command::Compact
+                    | Command::InsertBytes { .. }
+                    | Command::DeleteBytes { .. }
+            ),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
 pub struct QueryResult {
     pub data: serde_json::Value,
     pub new_state: Vec<u8>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+/// When and on whose behalf the host runs a command.
+///
+/// The host writes it to stdin after the output format, as an
+/// `Option<OperationContext>`, and engines commit it with the output of
+/// every mutation under `context`, so a proof attests when the mutation was
+/// made.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct OperationContext {
+    /// Unix time in milliseconds, as read by the host.
+    pub unix_millis: u64,
+    /// Who asked for the command, if the host names them.
+    pub actor: Option<String>,
+    /// Ed25519 signature of `write_message` for the command, by the admin
+    /// key of a state that records one.
+    pub signature: Option<Vec<u8>>,
+}
+
+/// Domain separating the messages signed by `write_message` from anything
+/// else the admin key signs.
+pub const WRITE_MESSAGE_DOMAIN: &[u8] = b"zkdb-write-v1";
+
+/// Message the admin key of a state signs to authorize `command`:
+/// `WRITE_MESSAGE_DOMAIN`, the root of the tree the command writes before
+/// it runs, zeros for an empty tree, and the command as JSON.
+///
+/// Binding the root means a signature authorizes the command against one
+/// version of the tree only.
+pub fn write_message(prev_root: &[u8; 32], command: &Command) -> Vec<u8> {
+    let mut message = WRITE_MESSAGE_DOMAIN.to_vec();
+    message.extend_from_slice(prev_root);
+    message.extend_from_slice(&serde_json::to_vec(command).expect("commands always serialize"));
+    message
+}
+
+impl QueryResult {
+    /// Records `context` in the output of `command` if it is a mutation.
+    pub fn with_context(mut self, command: &Command, context: Option<OperationContext>) -> Self {
+        if let (Some(context), true) = (context, command.is_mutating()) {
+            if let Some(data) = self.data.as_object_mut() {
+                data.insert(
+                    "context".into(),
+                    serde_json::json!({
+                        "unix_millis": context.unix_millis,
+                        "actor": context.actor,
+                    }),
+                );
+            }
+        }
+        self
+    }
+}
+
+/// Prefix marking engine output encoded with `OutputFormat::Binary`.
+pub const BINARY_OUTPUT_MAGIC: [u8; 4] = *b"ZKB1";
+
+/// Encoding of the `QueryResult` committed by an engine.
+///
+/// The host writes the format to stdin after the command, followed by the
+/// `OperationContext`.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OutputFormat {
+    /// `QueryResult` as JSON. Slower to produce, but readable in the raw output.
+    #[default]
+    Json,
+    /// `BINARY_OUTPUT_MAGIC` followed by a bincode-encoded `BinaryQueryResult`.
+    Binary,
+}
+
+/// Wire layout of `OutputFormat::Binary`.
+///
+/// `data` stays JSON since its shape depends on the command, but `new_state`
+/// is copied as raw bytes instead of being written out as a JSON array.
+#[derive(Serialize, Deserialize)]
+struct BinaryQueryResult {
+    data: Vec<u8>,
+    new_state: Vec<u8>,
+}
+
+#[cfg(feature = "std")]
+impl QueryResult {
+    /// Encodes the result in the given output format.
+    pub fn encode(&self, format: OutputFormat) -> Vec<u8> {
+        match format {
+            OutputFormat::Json => serde_json::to_vec(self).expect("Failed to serialize output"),
+            OutputFormat::Binary => {
+                let binary = BinaryQueryResult {
+                    data: serde_json::to_vec(&self.data).expect("Failed to serialize output"),
+                    new_state: self.new_state.clone(),
+                };
+                let mut output = BINARY_OUTPUT_MAGIC.to_vec();
+                bincode::serialize_into(&mut output, &binary).expect("Failed to serialize output");
+                output
+            }
+        }
+    }
+
+    /// Decodes engine output, detecting the format from its prefix.
+    pub fn decode(output: &[u8]) -> Result<Self, DatabaseError> {
+        match output.strip_prefix(&BINARY_OUTPUT_MAGIC) {
+            Some(encoded) => {
+                let binary: BinaryQueryResult = bincode::deserialize(encoded).map_err(|e| {
+                    DatabaseError::QueryExecutionFailed(format!(
+                        "Failed to parse binary output: {}",
+                        e
+                    ))
+                })?;
+                let data = serde_json::from_slice(&binary.data).map_err(|e| {
+                    DatabaseError::QueryExecutionFailed(format!(
+                        "Failed to parse output data as JSON: {}",
+                        e
+                    ))
+                })?;
+                Ok(QueryResult {
+                    data,
+                    new_state: binary.new_state,
+                })
+            }
+            None => serde_json::from_slice(output).map_err(|e| {
+                DatabaseError::QueryExecutionFailed(format!(
+                    "Failed to parse output as JSON: {}",
+                    e
+                ))
+            }),
+        }
+    }
+}
+
+/// Version of the layout in which the host writes the state and the command
+/// to the engine.
+///
+/// Since version 2 the state is written as a raw slice, and the command as
+/// `COMMAND_INPUT_MAGIC`, the version in four little-endian bytes and the
+/// bincode-encoded command, see `encode_command_input`, so that the engine
+/// copies the state in rather than deserializing it. Version 1 wrote both
+/// with `SP1Stdin::write`; engines still read it, see `decode_input`.
+pub const PROTOCOL_VERSION: u32 = 2;
+
+/// Prefix of the command input since protocol version 2. Read as the
+/// variant index a version 1 command starts with, it names no variant.
+pub const COMMAND_INPUT_MAGIC: [u8; 4] = *b"ZKC1";
+
+/// The command input the host writes after the state, in the layout of
+/// `PROTOCOL_VERSION`.
+#[cfg(feature = "std")]
+pub fn encode_command_input(command: &Command) -> Vec<u8> {
+    let mut input = COMMAND_INPUT_MAGIC.to_vec();
+    input.extend_from_slice(&PROTOCOL_VERSION.to_le_bytes());
+    bincode::serialize_into(&mut input, command).expect("commands always serialize");
+    input
+}
+
+/// The state and command of the inputs the engine read as raw slices, in
+/// the layout of `PROTOCOL_VERSION` or of version 1.
+///
+/// Fails with `DatabaseError::InvalidCommand` on inputs of any other
+/// version, written by a host the engine does not speak with.
+#[cfg(feature = "std")]
+pub fn decode_input(state: Vec<u8>, command: &[u8]) -> Result<(Vec<u8>, Command), DatabaseError> {
+    let invalid = |e: bincode::Error| {
+        DatabaseError::InvalidCommand(format!("Failed to decode the command: {}", e))
+    };
+    let Some(input) = command.strip_prefix(&COMMAND_INPUT_MAGIC) else {
+        // Version 1: both written with serde.
+        let state = bincode::deserialize(&state).map_err(|e| {
+            DatabaseError::StateDecode(format!("Failed to decode the state input: {}", e))
+        })?;
+        return Ok((state, bincode::deserialize(command).map_err(invalid)?));
+    };
+    let version = input
+        .get(..4)
+        .map(|version| u32::from_le_bytes(version.try_into().expect("four bytes")))
+        .ok_or_else(|| DatabaseError::InvalidCommand("Truncated command input".to_string()))?;
+    if version != PROTOCOL_VERSION {
+        return Err(DatabaseError::InvalidCommand(format!(
+            "Input of protocol version {}, the engine speaks version {}",
+            version, PROTOCOL_VERSION
+        )));
+    }
+    Ok((state, bincode::deserialize(&input[4..]).map_err(invalid)?))
+}
+
+/// A superseded value of a key, recorded when the key is overwritten.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct HistoryEntry {
+    /// Logical timestamp of the overwrite: the version of the tree the
+    /// overwriting command produced, as numbered by `Command::RootAt`.
+    pub timestamp: u64,
+    /// Hex-encoded hash of the superseded value.
+    pub value_hash: String,
+    /// Index of the superseded leaf in the tree when it was recorded, kept
+    /// for reference only. Under `TreeLayout::Sorted` later inserts and
+    /// deletes shift leaves, and `Command::Compact` drops superseded leaves
+    /// and renumbers the rest without rewriting it, so the index may no
+    /// longer name the same leaf, or any leaf at all.
+    pub leaf_index: usize,
+}
+
+/// Longest key the engine accepts, in bytes.
+///
+/// Built into the guest, so proofs enforce it whatever limits the host is
+/// configured with; hosts may only lower it.
+pub const MAX_KEY_LEN: usize = 256;
+
+/// Prefix of the keys a database keeps for its own bookkeeping unless it
+/// was created with another. Writes of keys under it are rejected with
+/// `DatabaseError::InvalidKey`.
+pub const DEFAULT_RESERVED_PREFIX: &str = "_";
+
+/// Number of past roots each tree keeps for `Command::RootAt`; older ones
+/// are dropped as new ones are recorded.
+pub const MAX_ROOT_VERSIONS: usize = 1024;
+
+/// Deepest `Command::Script`s nest: a script holding a script is two deep.
+pub const MAX_SCRIPT_DEPTH: usize = 4;
+
+/// Largest serialized state the engine produces, in bytes. Enforced in the
+/// guest like `MAX_KEY_LEN`.
+pub const MAX_STATE_BYTES: usize = 64 * 1024 * 1024;
+
+/// Failure of an engine. Guests report it in their output as `to_output`
+/// lays out, and the host converts it into its own `DatabaseError`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum DatabaseError {
     QueryExecutionFailed(String),
+    KeyNotFound(String),
+    EmptyTree,
+    /// `actual` is over the limit named `which`, e.g. `key_len`.
+    LimitExceeded {
+        which: String,
+        limit: u64,
+        actual: u64,
+    },
+    /// No root is recorded for the version.
+    UnknownVersion(u64),
+    /// The key cannot be written, for the reason given.
+    InvalidKey(String),
+    /// The mutation is not signed by the state's admin key, for the reason
+    /// given.
+    Unauthorized(String),
+    /// The command at `index` of a `Command::Script` failed with `error`,
+    /// aborting the script.
+    ScriptFailed {
+        index: u64,
+        error: Box<DatabaseError>,
+    },
+    /// The state does not decode, is of a version the engine cannot read or
+    /// is inconsistent, for the reason given.
+    StateDecode(String),
+    /// The command is malformed or not supported by the engine or the
+    /// state, for the reason given.
+    InvalidCommand(String),
+}
+
+impl fmt::Display for DatabaseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DatabaseError::QueryExecutionFailed(msg) => {
+                write!(f, "Query execution failed: {}", msg)
+            }
+            DatabaseError::KeyNotFound(key) => write!(f, "Key not found: {}", key),
+            DatabaseError::EmptyTree => write!(f, "Tree is empty: no key can be proven"),
+            DatabaseError::LimitExceeded {
+                which,
+                limit,
+                actual,
+            } => write!(f, "{} limit exceeded: {} is over {}", which, actual, limit),
+            DatabaseError::UnknownVersion(version) => {
+                write!(f, "Unknown root version: {}", version)
+            }
+            DatabaseError::InvalidKey(reason) => write!(f, "Invalid key: {}", reason),
+            DatabaseError::Unauthorized(reason) => write!(f, "Unauthorized: {}", reason),
+            DatabaseError::ScriptFailed { index, error } => {
+                write!(f, "Command {} of the script failed: {}", index, error)
+            }
+            DatabaseError::StateDecode(reason) => write!(f, "Invalid state: {}", reason),
+            DatabaseError::InvalidCommand(reason) => write!(f, "Invalid command: {}", reason),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for DatabaseError {}
+
+impl DatabaseError {
+    /// Name of the variant, used to tag errors in engine output.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            DatabaseError::QueryExecutionFailed(_) => "QueryExecutionFailed",
+            DatabaseError::KeyNotFound(_) => "KeyNotFound",
+            DatabaseError::EmptyTree => "EmptyTree",
+            DatabaseError::LimitExceeded { .. } => "LimitExceeded",
+            DatabaseError::UnknownVersion(_) => "UnknownVersion",
+            DatabaseError::InvalidKey(_) => "InvalidKey",
+            DatabaseError::Unauthorized(_) => "Unauthorized",
+            DatabaseError::ScriptFailed { .. } => "ScriptFailed",
+            DatabaseError::StateDecode(_) => "StateDecode",
+            DatabaseError::InvalidCommand(_) => "InvalidCommand",
+        }
+    }
+
+    /// Describes the error for the `error` field of engine output: the
+    /// variant under `type`, the message under `details` and the error
+    /// itself under `encoded`, which deserializes back into it. The fields
+    /// of some variants are repeated on their own, and the error a script
+    /// failed with is described the same way under `cause`.
+    pub fn to_output(&self, state_len: usize) -> serde_json::Value {
+        serde_json::json!({
+            "type": self.kind(),
+            "state_len": state_len,
+            "details": alloc::format!("{}", self),
+            "encoded": serde_json::to_value(self).ok(),
+            "key": match self {
+                DatabaseError::KeyNotFound(key) => Some(key.clone()),
+                _ => None,
+            },
+            "version": match self {
+                DatabaseError::UnknownVersion(version) => Some(*version),
+                _ => None,
+            },
+            "limit": match self {
+                DatabaseError::LimitExceeded { which, limit, actual } => Some(serde_json::json!({
+                    "which": which,
+                    "limit": limit,
+                    "actual": actual,
+                })),
+                _ => None,
+            },
+            "reason": match self {
+                DatabaseError::InvalidKey(reason)
+                | DatabaseError::Unauthorized(reason)
+                | DatabaseError::StateDecode(reason)
+                | DatabaseError::InvalidCommand(reason) => Some(reason.clone()),
+                _ => None,
+            },
+            "index": match self {
+                DatabaseError::ScriptFailed { index, .. } => Some(*index),
+                _ => None,
+            },
+            "cause": match self {
+                DatabaseError::ScriptFailed { error, .. } => Some(error.to_output(state_len)),
+                _ => None,
+            },
+        })
+    }
+
+    /// Fails with `LimitExceeded` if `actual` is over `limit`.
+    pub fn check_limit(which: &str, limit: usize, actual: usize) -> Result<(), DatabaseError> {
+        if actual > limit {
+            return Err(DatabaseError::LimitExceeded {
+                which: which.into(),
+                limit: limit as u64,
+                actual: actual as u64,
+            });
+        }
+        Ok(())
+    }
+}
+
+/// Fails with `InvalidKey` unless `key` may be written by users of a
+/// database whose bookkeeping lives under `reserved_prefix`.
+///
+/// The length of keys is checked separately, with `check_limit`.
+pub fn validate_key(key: &str, reserved_prefix: &str) -> Result<(), DatabaseError> {
+    validate_key_bytes(key.as_bytes(), reserved_prefix)
+}
+
+/// `validate_key` for a key of arbitrary bytes.
+pub fn validate_key_bytes(key: &[u8], reserved_prefix: &str) -> Result<(), DatabaseError> {
+    if key.is_empty() {
+        return Err(DatabaseError::InvalidKey("key is empty".into()));
+    }
+    if key.starts_with(reserved_prefix.as_bytes()) {
+        return Err(DatabaseError::InvalidKey(alloc::format!(
+            "key {:?} starts with the reserved prefix {:?}",
+            display_key(key),
+            reserved_prefix
+        )));
+    }
+    Ok(())
+}
+
+/// A key as engine output and errors report it: itself when it is UTF-8,
+/// hex-encoded otherwise.
+pub fn display_key(key: &[u8]) -> String {
+    match core::str::from_utf8(key) {
+        Ok(key) => key.into(),
+        Err(_) => key
+            .iter()
+            .map(|byte| alloc::format!("{:02x}", byte))
+            .collect(),
+    }
 }
@@ -0,0 +1,461 @@
+//! State layout of the Merkle engine, shared by the zkVM program and the host.
+
+use alloc::borrow::Cow;
+use alloc::collections::{BTreeMap, BTreeSet, VecDeque};
+use alloc::string::String;
+use alloc::vec::Vec;
+use rs_merkle::{algorithms::Sha256, Hasher};
+use serde::{Deserialize, Serialize};
+
+use crate::backend::{RsMerkle, TreeBackend};
+use crate::{DatabaseError, HistoryEntry, DEFAULT_RESERVED_PREFIX, MAX_ROOT_VERSIONS};
+
+/// Name under which the tree that unscoped commands operate on is stored.
+pub const ROOT_TREE: &str = "";
+
+/// Bytes a versioned state starts with, see `MerkleState::to_bytes`. No
+/// unversioned layout can start with them: they would be read as the length
+/// of its first collection, far beyond any state's size.
+pub const STATE_MAGIC: [u8; 4] = *b"ZKDS";
+
+/// Version of the layout `MerkleState::to_bytes` writes.
+pub const STATE_VERSION: u16 = 1;
+
+/// How the leaves of every tree of a state are laid out, chosen when the
+/// state is created.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TreeLayout {
+    /// Leaves are value hashes appended in insertion order; a deleted key
+    /// leaves a zeroed leaf behind. Proofs show a value is in the tree, but
+    /// not under which key, nor that a key is absent.
+    #[default]
+    Append,
+    /// Leaves are `keyed_leaf`s kept sorted by key, a key's leaf being
+    /// replaced when it is written again and removed when it is deleted.
+    /// Adjacent leaves then prove that no key lies between them, so
+    /// absence and the completeness of a range can be proven.
+    Sorted,
+}
+
+/// How the keys of every tree of a state are stored, chosen when the state
+/// is created.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum KeyDerivation {
+    /// Keys are stored as given.
+    #[default]
+    Raw,
+    /// Keys are stored as the hex-encoded SHA-256 of their bytes, see
+    /// `hashed_key`, so that the state neither reveals them nor grows with
+    /// their length. Commands still take the keys themselves, but the
+    /// state no longer knows them: keys are listed as stored, and the key
+    /// order that `Range`, `ProveRange` and `ProveAbsence` rely on is lost.
+    Sha256,
+}
+
+impl KeyDerivation {
+    /// The key `key` is stored under.
+    pub fn stored_key<'a>(&self, key: &'a [u8]) -> Cow<'a, [u8]> {
+        match self {
+            KeyDerivation::Raw => Cow::Borrowed(key),
+            KeyDerivation::Sha256 => Cow::Owned(hashed_key(key).into_bytes()),
+        }
+    }
+}
+
+/// Hex-encoded SHA-256 of `key`, the key it is stored under with
+/// `KeyDerivation::Sha256`.
+pub fn hashed_key(key: &[u8]) -> String {
+    <Sha256 as Hasher>::hash(key)
+        .iter()
+        .map(|byte| alloc::format!("{:02x}", byte))
+        .collect()
+}
+
+/// Options of the Merkle engine, fixed when a state is created.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MerkleConfig {
+    pub layout: TreeLayout,
+    /// How keys are stored, as given unless set.
+    #[serde(default)]
+    pub key_derivation: KeyDerivation,
+}
+
+/// Leaf committing to `key` holding a value hashing to `value_hash`, as
+/// laid out under `TreeLayout::Sorted`: the SHA-256 of the key's length as
+/// a little-endian `u64`, the key and the value hash.
+pub fn keyed_leaf(key: &[u8], value_hash: &[u8; 32]) -> [u8; 32] {
+    let mut preimage = Vec::with_capacity(8 + key.len() + 32);
+    preimage.extend_from_slice(&(key.len() as u64).to_le_bytes());
+    preimage.extend_from_slice(key);
+    preimage.extend_from_slice(value_hash);
+    <Sha256 as Hasher>::hash(&preimage)
+}
+
+/// Serializable state of the Merkle engine: independent trees by name.
+///
+/// Every collection is ordered and every field of fixed size, so that the
+/// bincode encoding depends on the contents alone; keep it so, since proofs
+/// chain states by their bytes. Use `canonical_bytes` to compare states.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct MerkleState {
+    /// The trees, by name, created on their first write. The tree commands
+    /// operate on unless scoped with `Command::InTree` is `ROOT_TREE`.
+    pub trees: BTreeMap<String, TreeData>,
+    /// Prefix of the keys kept for bookkeeping, which inserts reject.
+    pub reserved_prefix: String,
+    /// Layout of the leaves of every tree.
+    pub layout: TreeLayout,
+    /// Ed25519 public key that must sign every mutation, see
+    /// `write_message`. Anyone may write when it is `None`.
+    pub admin_pubkey: Option<[u8; 32]>,
+    /// How keys are stored in every tree.
+    pub key_derivation: KeyDerivation,
+}
+
+impl Default for MerkleState {
+    fn default() -> Self {
+        MerkleState {
+            trees: BTreeMap::new(),
+            reserved_prefix: DEFAULT_RESERVED_PREFIX.into(),
+            layout: TreeLayout::Append,
+            admin_pubkey: None,
+            key_derivation: KeyDerivation::Raw,
+        }
+    }
+}
+
+/// A single Merkle tree.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct TreeData {
+    /// The list of leaves in the Merkle tree.
+    pub leaves: Vec<[u8; 32]>,
+    /// Map from keys to leaf indices. Keys are arbitrary bytes; a string
+    /// key is its UTF-8 encoding.
+    pub key_indices: BTreeMap<Vec<u8>, usize>,
+    /// Superseded values of each key, oldest first.
+    pub history: BTreeMap<Vec<u8>, Vec<HistoryEntry>>,
+    /// Root of the tree as of the last `refresh_root`, valid unless
+    /// `root_dirty` is set.
+    pub cached_root: Option<[u8; 32]>,
+    /// Whether the leaves changed since `cached_root` was computed.
+    pub root_dirty: bool,
+    /// Root after each of the last `MAX_ROOT_VERSIONS` mutations, oldest
+    /// first.
+    pub roots: VecDeque<[u8; 32]>,
+    /// Number of versions dropped from the front of `roots`.
+    pub roots_dropped: u64,
+    /// Hash of the value of each key. Kept under `TreeLayout::Sorted` only,
+    /// where leaves are `keyed_leaf`s; otherwise the leaf is the hash.
+    pub value_hashes: BTreeMap<Vec<u8>, [u8; 32]>,
+}
+
+/// State layout written before states were versioned: the root tree's
+/// leaves and key indices alone.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct LegacyMerkleState {
+    pub leaves: Vec<[u8; 32]>,
+    pub key_indices: BTreeMap<String, usize>,
+}
+
+impl From<LegacyMerkleState> for MerkleState {
+    fn from(legacy: LegacyMerkleState) -> Self {
+        let mut merkle_state = MerkleState::new();
+        // An empty tree is left out, as in a state that was never written.
+        if !legacy.leaves.is_empty() {
+            let tree = TreeData {
+                leaves: legacy.leaves,
+                key_indices: legacy
+                    .key_indices
+                    .into_iter()
+                    .map(|(key, index)| (key.into_bytes(), index))
+                    .collect(),
+                root_dirty: true,
+                ..TreeData::default()
+            };
+            merkle_state.trees.insert(ROOT_TREE.into(), tree);
+        }
+        merkle_state
+    }
+}
+
+/// Splits a versioned state into its version and encoded state, `None` for
+/// an unversioned one.
+pub fn split_version(state: &[u8]) -> Option<(u16, &[u8])> {
+    let rest = state.strip_prefix(&STATE_MAGIC)?;
+    let version = rest.get(..2)?;
+    Some((u16::from_le_bytes([version[0], version[1]]), &rest[2..]))
+}
+
+/// Encodes `state` tagged as layout `version`.
+#[cfg(feature = "std")]
+pub fn encode_versioned<T: Serialize>(version: u16, state: &T) -> Vec<u8> {
+    let mut bytes = STATE_MAGIC.to_vec();
+    bytes.extend_from_slice(&version.to_le_bytes());
+    bincode::serialize_into(&mut bytes, state).expect("Failed to serialize state");
+    bytes
+}
+
+impl MerkleState {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Deserializes a state, treating empty bytes as an empty tree and
+    /// upgrading a `LegacyMerkleState` if needed.
+    ///
+    /// A versioned state is decoded by its version; an unversioned one, as
+    /// written before versions were recorded, as a `LegacyMerkleState`.
+    /// Either way the state must then pass `validate`, so that corrupted
+    /// bytes which happen to decode are refused rather than misread.
+    #[cfg(feature = "std")]
+    pub fn from_bytes(state: &[u8]) -> Result<Self, DatabaseError> {
+        let state = Self::decode(state)?;
+        state.validate()?;
+        Ok(state)
+    }
+
+    #[cfg(feature = "std")]
+    fn decode(state: &[u8]) -> Result<Self, DatabaseError> {
+        if state.is_empty() {
+            return Ok(MerkleState::new());
+        }
+        if let Some((version, encoded)) = split_version(state) {
+            let failed = |e: bincode::Error| {
+                DatabaseError::StateDecode(format!("Failed to deserialize state: {}", e))
+            };
+            return match version {
+                STATE_VERSION => bincode::deserialize(encoded).map_err(failed),
+                version => Err(DatabaseError::StateDecode(format!(
+                    "Unsupported state version {}, this engine reads up to {}",
+                    version, STATE_VERSION
+                ))),
+            };
+        }
+        let legacy: LegacyMerkleState = bincode::deserialize(state).map_err(|e| {
+            DatabaseError::StateDecode(format!("Failed to deserialize state: {}", e))
+        })?;
+        Ok(legacy.into())
+    }
+
+    /// Serializes the state in the layout expected by `from_bytes`:
+    /// `STATE_MAGIC`, `STATE_VERSION` as a little-endian `u16`, then the
+    /// bincode-encoded state.
+    #[cfg(feature = "std")]
+    pub fn to_bytes(&self) -> Vec<u8> {
+        encode_versioned(STATE_VERSION, self)
+    }
+
+    /// Serializes the state as `to_bytes` does, in its canonical form: stale
+    /// cached roots are recomputed and trees holding nothing, as left by
+    /// commands that touched a tree without writing to it, are dropped. Two
+    /// states with the same trees and settings encode to the same bytes.
+    ///
+    /// Leaves keep their order, which is part of the state under
+    /// `TreeLayout::Append`: there the order keys were inserted in decides
+    /// the root, whereas under `TreeLayout::Sorted` it does not.
+    #[cfg(feature = "std")]
+    pub fn canonical_bytes(&self) -> Vec<u8> {
+        if !self.root_dirty() && !self.trees.values().any(TreeData::is_blank) {
+            return self.to_bytes();
+        }
+        let mut canonical = self.clone();
+        canonical.trees.retain(|_, tree| !tree.is_blank());
+        canonical.refresh_root();
+        canonical.to_bytes()
+    }
+
+    /// SHA-256 of `canonical_bytes`, identifying the state.
+    #[cfg(feature = "std")]
+    pub fn digest(&self) -> [u8; 32] {
+        <Sha256 as Hasher>::hash(&self.canonical_bytes())
+    }
+
+    /// Checks that every tree is consistent, failing with `StateDecode` and
+    /// "corrupted state" otherwise: each key points at a leaf of its own
+    /// and, under `TreeLayout::Sorted`, every leaf belongs to a key with a
+    /// value hash. Leaves are fixed-size arrays, so their length needs no
+    /// check.
+    pub fn validate(&self) -> Result<(), DatabaseError> {
+        if self
+            .trees
+            .values()
+            .all(|tree| tree.is_consistent(self.layout))
+        {
+            Ok(())
+        } else {
+            Err(DatabaseError::StateDecode("corrupted state".into()))
+        }
+    }
+
+    /// The tree named `name`, if it was ever written.
+    pub fn tree(&self, name: &str) -> Option<&TreeData> {
+        self.trees.get(name)
+    }
+
+    /// The tree named `name`, created empty if needed.
+    pub fn tree_mut(&mut self, name: &str) -> &mut TreeData {
+        self.trees.entry(name.into()).or_default()
+    }
+
+    /// Root of the root tree, or `None` when it is empty.
+    pub fn root(&self) -> Option<[u8; 32]> {
+        self.tree(ROOT_TREE).and_then(TreeData::root)
+    }
+
+    /// Whether any tree's cached root is stale.
+    pub fn root_dirty(&self) -> bool {
+        self.trees.values().any(|tree| tree.root_dirty)
+    }
+
+    /// Recomputes the cached roots that are stale.
+    pub fn refresh_root(&mut self) {
+        for tree in self.trees.values_mut() {
+            tree.refresh_root();
+        }
+    }
+}
+
+impl TreeData {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Whether the tree holds nothing: no leaves, keys, history or recorded
+    /// versions.
+    fn is_blank(&self) -> bool {
+        self.leaves.is_empty()
+            && self.key_indices.is_empty()
+            && self.history.is_empty()
+            && self.value_hashes.is_empty()
+            && self.roots.is_empty()
+            && self.roots_dropped == 0
+    }
+
+    /// Whether the keys point at distinct leaves in bounds and, under
+    /// `TreeLayout::Sorted`, account for every leaf and value hash.
+    fn is_consistent(&self, layout: TreeLayout) -> bool {
+        let mut indices = BTreeSet::new();
+        let in_bounds = self
+            .key_indices
+            .values()
+            .all(|&index| index < self.leaves.len() && indices.insert(index));
+        match layout {
+            TreeLayout::Append => in_bounds,
+            TreeLayout::Sorted => {
+                in_bounds
+                    && self.leaves.len() == self.key_indices.len()
+                    && self.value_hashes.len() == self.key_indices.len()
+                    && self
+                        .value_hashes
+                        .keys()
+                        .all(|key| self.key_indices.contains_key(key))
+            }
+        }
+    }
+
+    /// Root of the tree, or `None` when empty.
+    ///
+    /// Served from `cached_root` when it is up to date. The cache travels with
+    /// the serialized state, so the zkVM program uses `compute_root` for
+    /// anything it commits to.
+    pub fn root(&self) -> Option<[u8; 32]> {
+        if self.root_dirty {
+            self.compute_root()
+        } else {
+            self.cached_root
+        }
+    }
+
+    /// Root of the tree built from the current leaves, or `None` when empty.
+    pub fn compute_root(&self) -> Option<[u8; 32]> {
+        self.compute_root_with::<RsMerkle>()
+    }
+
+    /// Root of the current leaves accumulated with `B`.
+    pub fn compute_root_with<B: TreeBackend>(&self) -> Option<[u8; 32]> {
+        B::from_leaves(&self.leaves).root()
+    }
+
+    /// Marks the cached root stale after the leaves change.
+    pub fn invalidate_root(&mut self) {
+        self.root_dirty = true;
+    }
+
+    /// Recomputes the cached root if it is stale.
+    pub fn refresh_root(&mut self) {
+        if self.root_dirty {
+            self.cached_root = self.compute_root();
+            self.root_dirty = false;
+        }
+    }
+
+    /// Drops the leaves no key references, keeping the rest in order, and
+    /// returns how many were dropped. `history` is left as recorded, see
+    /// `HistoryEntry::leaf_index`. See `Command::Compact`.
+    pub fn compact(&mut self) -> usize {
+        let mut live: Vec<usize> = self.key_indices.values().copied().collect();
+        live.sort_unstable();
+        let pruned = self.leaves.len() - live.len();
+        if pruned == 0 {
+            return 0;
+        }
+        let renumbered: BTreeMap<usize, usize> = live
+            .iter()
+            .enumerate()
+            .map(|(new, &old)| (old, new))
+            .collect();
+        self.leaves = live.iter().map(|&old| self.leaves[old]).collect();
+        for index in self.key_indices.values_mut() {
+            *index = renumbered[index];
+        }
+        self.invalidate_root();
+        pruned
+    }
+
+    /// Computes the root after a mutation and records it as the next
+    /// version, dropping the oldest beyond `MAX_ROOT_VERSIONS`.
+    ///
+    /// Computed from the leaves rather than the cache, since the recorded
+    /// roots are served by `RootAt`.
+    pub fn record_root(&mut self) {
+        self.record_root_with::<RsMerkle>();
+    }
+
+    /// Records the root of the leaves accumulated with `B`, as
+    /// `record_root`.
+    pub fn record_root_with<B: TreeBackend>(&mut self) {
+        self.cached_root = self.compute_root_with::<B>();
+        self.root_dirty = false;
+        if let Some(root) = self.cached_root {
+            self.roots.push_back(root);
+        }
+        if self.roots.len() > MAX_ROOT_VERSIONS {
+            self.roots.pop_front();
+            self.roots_dropped += 1;
+        }
+    }
+
+    /// Number of the latest recorded version, 0 before the first.
+    pub fn latest_version(&self) -> u64 {
+        self.roots_dropped + self.roots.len() as u64
+    }
+
+    /// Root recorded for `version`, if it is still kept.
+    pub fn root_at(&self, version: u64) -> Option<[u8; 32]> {
+        let position = version.checked_sub(self.roots_dropped + 1)?;
+        self.roots.get(usize::try_from(position).ok()?).copied()
+    }
+
+    /// Hash of the value `key` holds, if it is in the tree.
+    ///
+    /// Under `TreeLayout::Append` the leaf is the value hash itself.
+    pub fn value_hash(&self, key: &[u8]) -> Option<[u8; 32]> {
+        if let Some(hash) = self.value_hashes.get(key) {
+            return Some(*hash);
+        }
+        self.key_indices
+            .get(key)
+            .and_then(|&index| self.leaves.get(index))
+            .copied()
+    }
+}
@@ -0,0 +1,330 @@
+//! State layout of the sparse Merkle engine, shared by the zkVM program and
+//! the host.
+//!
+//! The tree has a leaf for every one of the 2^256 key paths, the SHA-256 of
+//! a key, read from its most significant bit down. A leaf holding nothing
+//! and a node over two empty children are all zeros, so the root commits to
+//! every key at once and a proof has `SMT_DEPTH` siblings whether the key
+//! is present or not. Proofs list only the siblings that are not empty.
+//!
+//! Only the nodes over two leaves or more are stored: an empty subtree is
+//! zeros and one holding a single leaf is recomputed from it.
+
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::ops::Bound;
+use rs_merkle::{algorithms::Sha256, Hasher};
+use serde::{Deserialize, Serialize};
+
+#[cfg(feature = "std")]
+use crate::DatabaseError;
+use crate::DEFAULT_RESERVED_PREFIX;
+
+/// Levels of the tree below the root, one per bit of a key path.
+pub const SMT_DEPTH: usize = 256;
+
+/// Bytes a serialized `SmtState` starts with, which no `MerkleState` does.
+pub const SMT_STATE_MAGIC: [u8; 4] = *b"ZKSM";
+
+/// Version of the layout `SmtState::to_bytes` writes.
+pub const SMT_STATE_VERSION: u16 = 1;
+
+/// Hash of an empty leaf or subtree.
+pub const EMPTY: [u8; 32] = [0u8; 32];
+
+/// Path of the leaf of `key`: its SHA-256.
+pub fn key_path(key: &[u8]) -> [u8; 32] {
+    <Sha256 as Hasher>::hash(key)
+}
+
+/// Leaf at `path` holding a value hashing to `value_hash`: the SHA-256 of a
+/// zero byte, the path and the value hash.
+pub fn smt_leaf(path: &[u8; 32], value_hash: &[u8; 32]) -> [u8; 32] {
+    let mut preimage = Vec::with_capacity(65);
+    preimage.push(0);
+    preimage.extend_from_slice(path);
+    preimage.extend_from_slice(value_hash);
+    <Sha256 as Hasher>::hash(&preimage)
+}
+
+/// Node over `left` and `right`: `EMPTY` if both are, otherwise the SHA-256
+/// of a one byte and both children.
+pub fn smt_node(left: &[u8; 32], right: &[u8; 32]) -> [u8; 32] {
+    if *left == EMPTY && *right == EMPTY {
+        return EMPTY;
+    }
+    let mut preimage = Vec::with_capacity(65);
+    preimage.push(1);
+    preimage.extend_from_slice(left);
+    preimage.extend_from_slice(right);
+    <Sha256 as Hasher>::hash(&preimage)
+}
+
+/// Bit `index` of `path`, counted from the most significant bit.
+pub fn path_bit(path: &[u8; 32], index: usize) -> bool {
+    path[index / 8] >> (7 - index % 8) & 1 == 1
+}
+
+/// Bits of byte `byte` of a path among its `height` last ones.
+fn low_bits(byte: usize, height: usize) -> u8 {
+    let kept = SMT_DEPTH - height;
+    match kept.saturating_sub(byte * 8) {
+        0 => 0xff,
+        1..=7 => 0xff >> (kept - byte * 8),
+        _ => 0,
+    }
+}
+
+/// `path` with its `height` last bits cleared: the first path under the
+/// node at `height` above the leaf.
+fn masked(path: &[u8; 32], height: usize) -> [u8; 32] {
+    let mut prefix = *path;
+    for (byte, bits) in prefix.iter_mut().enumerate() {
+        *bits &= !low_bits(byte, height);
+    }
+    prefix
+}
+
+/// `path` with its `height` last bits set: the last path under the node at
+/// `height` above the leaf.
+fn filled(path: &[u8; 32], height: usize) -> [u8; 32] {
+    let mut last = *path;
+    for (byte, bits) in last.iter_mut().enumerate() {
+        *bits |= low_bits(byte, height);
+    }
+    last
+}
+
+/// Path of the sibling of the node at `height` over `path`.
+fn sibling_path(path: &[u8; 32], height: usize) -> [u8; 32] {
+    let mut sibling = *path;
+    let index = SMT_DEPTH - 1 - height;
+    sibling[index / 8] ^= 1 << (7 - index % 8);
+    masked(&sibling, height)
+}
+
+/// Inclusion or exclusion proof of a key path: the siblings from the leaf
+/// up, leaving out those that are `EMPTY`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct SmtProof {
+    /// Bit `height % 8` of byte `height / 8` is set when the sibling at
+    /// `height` above the leaf is in `siblings`.
+    pub bitmap: [u8; 32],
+    /// The siblings that are not empty, leaf level first.
+    pub siblings: Vec<[u8; 32]>,
+}
+
+impl SmtProof {
+    /// Encodes the proof as the bitmap followed by the siblings.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(32 * (1 + self.siblings.len()));
+        bytes.extend_from_slice(&self.bitmap);
+        for sibling in &self.siblings {
+            bytes.extend_from_slice(sibling);
+        }
+        bytes
+    }
+
+    /// Root of the tree in which `path` holds `leaf`, `EMPTY` for none.
+    pub fn root(&self, path: &[u8; 32], leaf: [u8; 32]) -> Option<[u8; 32]> {
+        let mut siblings = self.siblings.iter();
+        let mut node = leaf;
+        for height in 0..SMT_DEPTH {
+            let sibling = if self.bitmap[height / 8] >> (height % 8) & 1 == 1 {
+                *siblings.next()?
+            } else {
+                EMPTY
+            };
+            node = if path_bit(path, SMT_DEPTH - 1 - height) {
+                smt_node(&sibling, &node)
+            } else {
+                smt_node(&node, &sibling)
+            };
+        }
+        siblings.next().is_none().then_some(node)
+    }
+}
+
+/// Serializable state of the sparse Merkle engine.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SmtState {
+    /// Value hash of every key, by key path.
+    pub values: BTreeMap<[u8; 32], [u8; 32]>,
+    /// Hashes of the nodes over two leaves or more, by height above the
+    /// leaves and first path under them. The root is at `SMT_DEPTH`.
+    nodes: BTreeMap<(u16, [u8; 32]), [u8; 32]>,
+    /// Prefix of the keys the host keeps for bookkeeping, which inserts
+    /// reject.
+    pub reserved_prefix: String,
+}
+
+impl Default for SmtState {
+    fn default() -> Self {
+        SmtState {
+            values: BTreeMap::new(),
+            nodes: BTreeMap::new(),
+            reserved_prefix: DEFAULT_RESERVED_PREFIX.into(),
+        }
+    }
+}
+
+impl SmtState {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Deserializes a state, treating empty bytes as an empty tree.
+    #[cfg(feature = "std")]
+    pub fn from_bytes(state: &[u8]) -> Result<Self, DatabaseError> {
+        if state.is_empty() {
+            return Ok(SmtState::new());
+        }
+        let failed = DatabaseError::StateDecode;
+        let rest = state
+            .strip_prefix(&SMT_STATE_MAGIC)
+            .ok_or_else(|| failed("Not a sparse Merkle state".into()))?;
+        let version = rest
+            .get(..2)
+            .map(|version| u16::from_le_bytes([version[0], version[1]]));
+        if version != Some(SMT_STATE_VERSION) {
+            return Err(failed(format!(
+                "Unsupported sparse Merkle state version {:?}, this engine reads {}",
+                version, SMT_STATE_VERSION
+            )));
+        }
+        bincode::deserialize(&rest[2..])
+            .map_err(|e| failed(format!("Failed to deserialize state: {}", e)))
+    }
+
+    /// Serializes the state as `SMT_STATE_MAGIC`, `SMT_STATE_VERSION` as a
+    /// little-endian `u16`, then the bincode-encoded state.
+    #[cfg(feature = "std")]
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = SMT_STATE_MAGIC.to_vec();
+        bytes.extend_from_slice(&SMT_STATE_VERSION.to_le_bytes());
+        bincode::serialize_into(&mut bytes, self).expect("Failed to serialize state");
+        bytes
+    }
+
+    /// Whether no key is held.
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
+    /// Root of the tree, `EMPTY` when no key is held.
+    pub fn root(&self) -> [u8; 32] {
+        self.node(SMT_DEPTH, &[0u8; 32])
+    }
+
+    /// Value hash held for `key`.
+    pub fn get(&self, key: &[u8]) -> Option<[u8; 32]> {
+        self.values.get(&key_path(key)).copied()
+    }
+
+    /// Sets the value hash of `key`, returning the one it replaced.
+    pub fn insert(&mut self, key: &[u8], value_hash: [u8; 32]) -> Option<[u8; 32]> {
+        let path = key_path(key);
+        let old = self.values.insert(path, value_hash);
+        self.update(&path);
+        old
+    }
+
+    /// Removes `key`, returning its value hash.
+    pub fn remove(&mut self, key: &[u8]) -> Option<[u8; 32]> {
+        let path = key_path(key);
+        let old = self.values.remove(&path)?;
+        self.update(&path);
+        Some(old)
+    }
+
+    /// Proves what `key` holds: its leaf if present, `EMPTY` otherwise.
+    pub fn prove(&self, key: &[u8]) -> SmtProof {
+        let path = key_path(key);
+        let mut proof = SmtProof {
+            bitmap: [0u8; 32],
+            siblings: Vec::new(),
+        };
+        for height in 0..SMT_DEPTH {
+            let sibling = self.node(height, &sibling_path(&path, height));
+            if sibling != EMPTY {
+                proof.bitmap[height / 8] |= 1 << (height % 8);
+                proof.siblings.push(sibling);
+            }
+        }
+        proof
+    }
+
+    /// Leaf at `path`, `EMPTY` if no key is held there.
+    pub fn leaf(&self, path: &[u8; 32]) -> [u8; 32] {
+        self.values
+            .get(path)
+            .map_or(EMPTY, |value_hash| smt_leaf(path, value_hash))
+    }
+
+    /// Number of keys under the node at `height` over `prefix`, counting up
+    /// to two.
+    fn leaves_under(&self, height: usize, prefix: &[u8; 32]) -> usize {
+        self.values
+            .range((
+                Bound::Included(*prefix),
+                Bound::Included(filled(prefix, height)),
+            ))
+            .take(2)
+            .count()
+    }
+
+    /// Hash of the node at `height` over `prefix`.
+    fn node(&self, height: usize, prefix: &[u8; 32]) -> [u8; 32] {
+        if height == 0 {
+            return self.leaf(prefix);
+        }
+        if let Some(node) = self.nodes.get(&(height as u16, *prefix)) {
+            return *node;
+        }
+        let mut under = self.values.range((
+            Bound::Included(*prefix),
+            Bound::Included(filled(prefix, height)),
+        ));
+        match (under.next(), under.next()) {
+            (None, _) => EMPTY,
+            // Hash the lone leaf up to this node, every sibling being empty.
+            (Some((path, value_hash)), None) => {
+                let mut node = smt_leaf(path, value_hash);
+                for below in 0..height {
+                    node = if path_bit(path, SMT_DEPTH - 1 - below) {
+                        smt_node(&EMPTY, &node)
+                    } else {
+                        smt_node(&node, &EMPTY)
+                    };
+                }
+                node
+            }
+            // Not stored, which only a state written by hand allows.
+            _ => {
+                let left = self.node(height - 1, prefix);
+                let right = self.node(height - 1, &sibling_path(prefix, height - 1));
+                smt_node(&left, &right)
+            }
+        }
+    }
+
+    /// Rehashes the nodes over `path` after its leaf changed.
+    fn update(&mut self, path: &[u8; 32]) {
+        let mut node = self.leaf(path);
+        for height in 0..SMT_DEPTH {
+            let sibling = self.node(height, &sibling_path(path, height));
+            node = if path_bit(path, SMT_DEPTH - 1 - height) {
+                smt_node(&sibling, &node)
+            } else {
+                smt_node(&node, &sibling)
+            };
+            let parent = (height as u16 + 1, masked(path, height + 1));
+            if self.leaves_under(height + 1, &parent.1) >= 2 {
+                self.nodes.insert(parent, node);
+            } else {
+                self.nodes.remove(&parent);
+            }
+        }
+    }
+}
@@ -0,0 +1,331 @@
+//! A SP1 program for IAVL tree-based database operations.
+//!
+//! Supports `insert`, `replace`, `batch_insert`, `delete`, `batch_delete`,
+//! `query`, `multi_query`, `range`, `prove`, `prove_absence`, `get_root`
+//! and `clear` commands, and the `_bytes` forms of those taking a single
+//! key, against one AVL tree ordered by key. Other commands fail.
+//! A key's `index` is its position in key order. Proofs are the path from
+//! a key's node, or from the empty slot where it would be, up to the root,
+//! and `range` pages come with one for each key.
+//! Every mutation reports the tree's new root under `root`, and the
+//! `OperationContext` the host sent under `context`.
+//! Keys longer than `MAX_KEY_LEN` and states larger than `MAX_STATE_BYTES`
+//! are rejected, as are inserts of empty keys and of keys under the state's
+//! reserved prefix.
+
+sp1_zkvm::entrypoint!(main);
+
+extern crate alloc;
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use sp1_zkvm::io;
+use zkdb_core::iavl::IavlState;
+use zkdb_core::{
+    decode_input, display_key, validate_key_bytes, Command, DatabaseEngine, DatabaseError,
+    OperationContext, OutputFormat, QueryResult, MAX_KEY_LEN, MAX_STATE_BYTES,
+};
+
+pub struct IavlEngine;
+
+impl DatabaseEngine for IavlEngine {
+    fn execute_query(
+        &mut self,
+        state: &[u8],
+        command: &Command,
+    ) -> Result<QueryResult, DatabaseError> {
+        main_internal(state, command)
+    }
+}
+
+pub fn main() {
+    let state: Vec<u8> = io::read_vec();
+    let input: Vec<u8> = io::read_vec();
+    let format: OutputFormat = io::read::<OutputFormat>();
+    let context: Option<OperationContext> = io::read::<Option<OperationContext>>();
+
+    let (state, command) = match decode_input(state, &input) {
+        Ok(decoded) => decoded,
+        Err(e) => {
+            let result = QueryResult {
+                data: serde_json::json!({ "error": e.to_output(0) }),
+                new_state: Vec::new(),
+            };
+            sp1_zkvm::io::commit_slice(&result.encode(format));
+            return;
+        }
+    };
+
+    let result = main_internal(&state, &command)
+        .map(|result| result.with_context(&command, context))
+        .unwrap_or_else(|e| QueryResult {
+            data: serde_json::json!({ "error": e.to_output(state.len()) }),
+            new_state: state,
+        });
+
+    let output = result.encode(format);
+    sp1_zkvm::io::commit_slice(&output);
+}
+
+/// Runs `command` against `state`.
+fn main_internal(state: &[u8], command: &Command) -> Result<QueryResult, DatabaseError> {
+    let mut iavl = IavlState::from_bytes(state)?;
+    let mut data = match command {
+        Command::Insert { key, value } => insert(&mut iavl, key.as_bytes(), value)?,
+        Command::InsertBytes { key, value } => insert(&mut iavl, key, value)?,
+        Command::Replace { key, value } => replace(&mut iavl, key.as_bytes(), value)?,
+        Command::BatchInsert { entries } => batch_insert(&mut iavl, entries)?,
+        Command::Delete { key } => delete(&mut iavl, key.as_bytes())?,
+        Command::DeleteBytes { key } => delete(&mut iavl, key)?,
+        Command::BatchDelete { keys } => batch_delete(&mut iavl, keys)?,
+        Command::Query { key } => query(&iavl, key.as_bytes())?,
+        Command::QueryBytes { key } => query(&iavl, key)?,
+        Command::MultiQuery { keys } => multi_query(&iavl, keys),
+        Command::Range { start, end, limit } => range(&iavl, start, end, *limit)?,
+        Command::Prove { key } => prove(&iavl, key.as_bytes())?,
+        Command::ProveBytes { key } => prove(&iavl, key)?,
+        Command::ProveAbsence { key } => prove_absence(&iavl, key.as_bytes())?,
+        Command::GetRoot => get_root(&iavl),
+        Command::Clear => clear(&mut iavl),
+        command => {
+            return Err(DatabaseError::InvalidCommand(format!(
+                "{} is not supported by the IAVL engine",
+                command.kind()
+            )))
+        }
+    };
+    // Reads hand back the state they were given, without reserializing it.
+    let new_state = if command.is_mutating() {
+        data["root"] = root_json(&iavl);
+        let new_state = iavl.to_bytes();
+        DatabaseError::check_limit("state_bytes", MAX_STATE_BYTES, new_state.len())?;
+        new_state
+    } else {
+        state.to_vec()
+    };
+    Ok(QueryResult { data, new_state })
+}
+
+/// Hex-encoded root, null for an empty tree.
+fn root_json(iavl: &IavlState) -> serde_json::Value {
+    serde_json::json!(iavl.root_hash().map(hex::encode))
+}
+
+/// Sets the value hash of a key, given hex-encoded.
+fn insert(
+    iavl: &mut IavlState,
+    key: &[u8],
+    value: &str,
+) -> Result<serde_json::Value, DatabaseError> {
+    insert_node(iavl, key, value)?;
+    Ok(serde_json::json!({
+        "key": display_key(key),
+        "value": value,
+        "index": iavl.rank(key),
+        "leaf": value,
+        "inserted": true,
+    }))
+}
+
+/// Sets the value hash of a key, reporting the one it replaced.
+fn replace(
+    iavl: &mut IavlState,
+    key: &[u8],
+    value: &str,
+) -> Result<serde_json::Value, DatabaseError> {
+    let old = insert_node(iavl, key, value)?.map(hex::encode);
+    Ok(serde_json::json!({
+        "key": display_key(key),
+        "index": iavl.rank(key),
+        "old_leaf": old,
+        "old_value_hash": old,
+        "new_leaf": value,
+    }))
+}
+
+/// Inserts several key-value pairs in order, serializing the state once.
+fn batch_insert(
+    iavl: &mut IavlState,
+    entries: &[(String, String)],
+) -> Result<serde_json::Value, DatabaseError> {
+    for (key, value) in entries {
+        insert_node(iavl, key.as_bytes(), value)?;
+    }
+    Ok(serde_json::json!({
+        "inserted": entries.len(),
+        "total_keys": iavl.len(),
+    }))
+}
+
+/// Writes the node of `key` holding the hex-encoded value hash `value` and
+/// returns the value hash it replaced.
+fn insert_node(
+    iavl: &mut IavlState,
+    key: &[u8],
+    value: &str,
+) -> Result<Option<[u8; 32]>, DatabaseError> {
+    validate_key_bytes(key, &iavl.reserved_prefix)?;
+    DatabaseError::check_limit("key_len", MAX_KEY_LEN, key.len())?;
+    let value_hash: [u8; 32] = hex::decode(value)
+        .ok()
+        .and_then(|bytes| bytes.try_into().ok())
+        .ok_or_else(|| {
+            DatabaseError::InvalidCommand(format!(
+                "Value {} is not a hex-encoded 32-byte hash",
+                value
+            ))
+        })?;
+    Ok(iavl.insert(key, value_hash))
+}
+
+/// Removes a key and its node.
+fn delete(iavl: &mut IavlState, key: &[u8]) -> Result<serde_json::Value, DatabaseError> {
+    let index = iavl.rank(key);
+    iavl.remove(key)
+        .ok_or_else(|| DatabaseError::KeyNotFound(display_key(key)))?;
+    Ok(serde_json::json!({
+        "key": display_key(key),
+        "index": index,
+        "deleted": true,
+    }))
+}
+
+/// Removes several keys in order, failing on the first missing one.
+fn batch_delete(iavl: &mut IavlState, keys: &[String]) -> Result<serde_json::Value, DatabaseError> {
+    for key in keys {
+        iavl.remove(key.as_bytes())
+            .ok_or_else(|| DatabaseError::KeyNotFound(key.clone()))?;
+    }
+    Ok(serde_json::json!({
+        "deleted": keys.len(),
+        "keys": keys,
+    }))
+}
+
+/// Queries the value hash of a key.
+fn query(iavl: &IavlState, key: &[u8]) -> Result<serde_json::Value, DatabaseError> {
+    let value_hash = iavl
+        .get(key)
+        .ok_or_else(|| DatabaseError::KeyNotFound(display_key(key)))?;
+    Ok(serde_json::json!({
+        "key": display_key(key),
+        "value": hex::encode(value_hash),
+        "index": iavl.rank(key),
+        "found": true,
+    }))
+}
+
+/// Looks up several keys, reporting for each whether the tree holds it and,
+/// if so, its value hash and position.
+fn multi_query(iavl: &IavlState, keys: &[String]) -> serde_json::Value {
+    let results: Vec<_> = keys
+        .iter()
+        .map(|key| match iavl.get(key.as_bytes()) {
+            Some(value_hash) => serde_json::json!({
+                "key": key,
+                "found": true,
+                "value": hex::encode(value_hash),
+                "index": iavl.rank(key.as_bytes()),
+            }),
+            None => serde_json::json!({
+                "key": key,
+                "found": false,
+                "value": null,
+                "index": null,
+            }),
+        })
+        .collect();
+    serde_json::json!(results)
+}
+
+/// Lists up to `limit` keys in `[start, end)` in key order, each with the
+/// proof of its value hash, and the key to continue from.
+fn range(
+    iavl: &IavlState,
+    start: &str,
+    end: &str,
+    limit: usize,
+) -> Result<serde_json::Value, DatabaseError> {
+    if start >= end {
+        return Err(DatabaseError::InvalidCommand(format!(
+            "Invalid range [{}, {})",
+            start, end
+        )));
+    }
+    if limit == 0 {
+        return Err(DatabaseError::InvalidCommand(
+            "Range limit must be at least 1".to_string(),
+        ));
+    }
+    let mut page = iavl.range(start.as_bytes(), end.as_bytes(), limit + 1);
+    let next = (page.len() > limit).then(|| display_key(&page[limit].0));
+    page.truncate(limit);
+    let first = iavl.rank(start.as_bytes());
+    let keys: Vec<_> = page
+        .iter()
+        .enumerate()
+        .map(|(offset, (key, value_hash))| {
+            serde_json::json!({
+                "key": display_key(key),
+                "binary": core::str::from_utf8(key).is_err(),
+                "index": first + offset,
+                "leaf": hex::encode(value_hash),
+                "value_hash": hex::encode(value_hash),
+                "proof": iavl.prove(key),
+            })
+        })
+        .collect();
+    Ok(serde_json::json!({
+        "keys": keys,
+        "next": next,
+        "root": root_json(iavl),
+    }))
+}
+
+/// Proves the value hash of a key the tree holds.
+fn prove(iavl: &IavlState, key: &[u8]) -> Result<serde_json::Value, DatabaseError> {
+    let root = iavl.root_hash().ok_or(DatabaseError::EmptyTree)?;
+    let value_hash = iavl
+        .get(key)
+        .ok_or_else(|| DatabaseError::KeyNotFound(display_key(key)))?;
+    Ok(serde_json::json!({
+        "key": display_key(key),
+        "root": hex::encode(root),
+        "proof": iavl.prove(key),
+        "index": iavl.rank(key),
+        "value_hash": hex::encode(value_hash),
+    }))
+}
+
+/// Proves that the tree does not hold a key: a search for it ends in an
+/// empty slot.
+fn prove_absence(iavl: &IavlState, key: &[u8]) -> Result<serde_json::Value, DatabaseError> {
+    if iavl.get(key).is_some() {
+        return Err(DatabaseError::QueryExecutionFailed(format!(
+            "Key {} is present",
+            display_key(key)
+        )));
+    }
+    Ok(serde_json::json!({
+        "key": display_key(key),
+        "root": root_json(iavl),
+        "proof": iavl.prove(key),
+    }))
+}
+
+fn get_root(iavl: &IavlState) -> serde_json::Value {
+    serde_json::json!({
+        "root": root_json(iavl),
+        "leaf_count": iavl.len(),
+    })
+}
+
+/// Removes every key.
+fn clear(iavl: &mut IavlState) -> serde_json::Value {
+    let removed = iavl.len();
+    iavl.root = None;
+    serde_json::json!({
+        "cleared": true,
+        "keys_removed": removed,
+    })
+}
@@ -1,26 +1,63 @@
+use sha2::{Digest, Sha256};
 use std::env;
-use std::path::PathBuf;
+use std::fs;
+use std::path::{Path, PathBuf};
 use std::process::Command;
 
+/// Guest programs, as (feature, crate directory, ELF name, prebuilt ELF variable).
+const ENGINES: &[(&str, &str, &str, &str)] = &[
+    ("merkle", "zkdb-merkle", "zkdb_merkle", "ZKDB_PREBUILT_ELF"),
+    ("smt", "zkdb-smt", "zkdb_smt", "ZKDB_SMT_PREBUILT_ELF"),
+    ("iavl", "zkdb-iavl", "zkdb_iavl", "ZKDB_IAVL_PREBUILT_ELF"),
+];
+
 fn main() {
     let target_dir = env::var("CARGO_MANIFEST_DIR").unwrap();
     let binding = PathBuf::from(&target_dir);
     let workspace_root = &binding.parent().unwrap().parent().unwrap();
+
+    for &(feature, crate_dir, elf_name, prebuilt_var) in ENGINES {
+        println!("cargo:rustc-check-cfg=cfg(zkdb_embedded_{})", feature);
+        // Only build the engines that were asked for.
+        let feature_var = format!("CARGO_FEATURE_{}", feature.to_uppercase());
+        if env::var(feature_var).is_err() {
+            continue;
+        }
+
+        println!("cargo:rerun-if-env-changed={}", prebuilt_var);
+        let prebuilt = env::var_os(prebuilt_var)
+            .map(PathBuf::from)
+            .filter(|path| path.exists());
+        let elf_path = match prebuilt {
+            Some(path) => {
+                println!(
+                    "cargo:warning=Using prebuilt {} ELF at {}",
+                    elf_name,
+                    path.display()
+                );
+                path
+            }
+            None => build_engine(workspace_root, crate_dir, elf_name),
+        };
+        embed_engine(feature, &elf_path);
+    }
+}
+
+/// Builds a guest program and returns where its ELF is expected.
+fn build_engine(workspace_root: &Path, crate_dir: &str, elf_name: &str) -> PathBuf {
     let elf_path = workspace_root
-        .join("target/elf-compilation/riscv32im-succinct-zkvm-elf/release/zkdb_merkle");
-    // Set the ELF env variable.
-    let elf_path_str = elf_path.to_str().unwrap();
-    println!("cargo:rustc-env=ZKDB_ELF_PATH={}", elf_path_str);
+        .join("target/elf-compilation/riscv32im-succinct-zkvm-elf/release")
+        .join(elf_name);
 
-    // Skip RISC-V compilation if running under clippy.
-    if env::var("CLIPPY_ARGS").is_ok() {
+    // Skip RISC-V compilation if running under clippy or on docs.rs.
+    if env::var("CLIPPY_ARGS").is_ok() || env::var("DOCS_RS").is_ok() {
         println!("cargo:warning=Skipping RISC-V compilation during clippy check");
-        return;
+        return elf_path;
     }
 
     // Run cargo prove build.
     let status = Command::new("cargo")
-        .current_dir(workspace_root.join("crates/zkdb-merkle"))
+        .current_dir(workspace_root.join("crates").join(crate_dir))
         .args([
             "prove",
             "build",
@@ -30,21 +67,52 @@ fn main() {
                 .display()
                 .to_string(),
             "--elf-name",
-            "zkdb_merkle",
+            elf_name,
         ])
         .status()
         .expect("Failed to execute cargo prove build");
 
     if !status.success() {
-        panic!("Failed to build zkdb_merkle with cargo prove build");
+        panic!("Failed to build {} with cargo prove build", elf_name);
     }
 
     if !elf_path.exists() {
         panic!(
-            "zkdb_merkle.elf not found at {:?} after cargo prove build",
-            elf_path
+            "{}.elf not found at {:?} after cargo prove build",
+            elf_name, elf_path
         );
     }
+    elf_path
+}
+
+/// Embeds the ELF at `elf_path` and records its hash, if the file exists.
+///
+/// Without an ELF the crate can only load one at runtime, checked against a
+/// `ZKDB_<ENGINE>_ELF_SHA256` set in the build environment.
+fn embed_engine(feature: &str, elf_path: &Path) {
+    let hash_var = format!("ZKDB_{}_ELF_SHA256", feature.to_uppercase());
+    println!("cargo:rerun-if-env-changed={}", hash_var);
+    let Ok(elf) = fs::read(elf_path) else {
+        println!(
+            "cargo:warning=No {} ELF at {}, it must be loaded at runtime",
+            feature,
+            elf_path.display()
+        );
+        return;
+    };
+
+    let hash: String = Sha256::digest(&elf)
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect();
+    println!("cargo:rustc-cfg=zkdb_embedded_{}", feature);
+    // Set the ELF env variables.
+    println!(
+        "cargo:rustc-env=ZKDB_{}_ELF_PATH={}",
+        feature.to_uppercase(),
+        elf_path.display()
+    );
+    println!("cargo:rustc-env={}={}", hash_var, hash);
 
     // Tell cargo to rerun this script if the ELF file changes
     println!("cargo:rerun-if-changed={}", elf_path.display());
@@ -1,8 +1,9 @@
 use clap::{Parser, Subcommand};
 use std::path::PathBuf;
 use std::sync::Arc;
+use std::time::Duration;
 use tracing::info;
-use zkdb_lib::{Database, DatabaseType};
+use zkdb_lib::{init_tracing, ConflictPolicy, Database, DatabaseType, LogFormat, Metadata};
 use zkdb_store::file::FileStore;
 
 #[derive(Parser)]
@@ -16,6 +17,23 @@ struct Cli {
     #[arg(short, long, default_value = ".zkdb/state.bin")]
     state_file: PathBuf,
 
+    /// Log output format
+    #[arg(long, value_enum, default_value_t = LogFormat::Pretty)]
+    log_format: LogFormat,
+
+    /// Print the zkVM cycles the command used
+    #[arg(long)]
+    show_cycles: bool,
+
+    /// Open the database read-only, refusing every command that writes
+    #[arg(long)]
+    read_only: bool,
+
+    /// Keep bookkeeping under this prefix instead of `_`, allowing keys that
+    /// start with `_`. Only takes effect when the database is created
+    #[arg(long)]
+    reserved_prefix: Option<String>,
+
     #[command(subcommand)]
     command: Commands,
 }
@@ -31,6 +49,15 @@ enum Commands {
         /// Generate proof
         #[arg(short, long)]
         proof: bool,
+        /// MIME type recorded as metadata of the value
+        #[arg(long)]
+        content_type: Option<String>,
+        /// Seconds until the key expires
+        #[arg(long, conflicts_with = "content_type")]
+        ttl: Option<u64>,
+        /// Read the key as hex, for keys that are not UTF-8
+        #[arg(long, conflicts_with_all = ["content_type", "ttl"])]
+        key_hex: bool,
     },
     /// Query a value by key
     Get {
@@ -39,15 +66,107 @@ enum Commands {
         /// Generate proof
         #[arg(short, long)]
         proof: bool,
+        /// Read as of this hex-encoded historical root
+        #[arg(long)]
+        at_root: Option<String>,
+        /// Show the value's metadata
+        #[arg(short, long)]
+        verbose: bool,
+        /// Read the key as hex, for keys that are not UTF-8
+        #[arg(long, conflicts_with_all = ["at_root", "verbose"])]
+        key_hex: bool,
+    },
+    /// Generate a Merkle inclusion proof for a key
+    Prove {
+        /// Key to prove
+        key: String,
+        /// Generate an SP1 proof as well
+        #[arg(short, long)]
+        proof: bool,
+    },
+    /// List the keys holding a value
+    Find {
+        /// Hex-encoded SHA-256 of the value
+        #[arg(long)]
+        hash: String,
+    },
+    /// Remove expired keys
+    Sweep {
+        /// Generate proof
+        #[arg(short, long)]
+        proof: bool,
     },
     /// Initialize a new database
     Init,
+    /// Print the verifying key hash of the engine, for pinning
+    Vk {
+        /// Also write a Solidity contract verifying the engine's proofs
+        #[arg(long)]
+        output_solidity: Option<PathBuf>,
+    },
+    /// Import key-value pairs from a newline-delimited JSON file
+    Import {
+        /// Path to the NDJSON file
+        file: PathBuf,
+        /// Field holding the key
+        #[arg(long, default_value = "key")]
+        key_field: String,
+        /// Field holding the base64-encoded value
+        #[arg(long, default_value = "value")]
+        value_field: String,
+    },
+    /// Import every key-value pair of a RocksDB database, such as a backup
+    ImportRocksdb {
+        /// Path to the RocksDB directory
+        path: PathBuf,
+        /// Replace the values of keys the database already holds
+        #[arg(long)]
+        overwrite: bool,
+        /// Generate proof
+        #[arg(short, long)]
+        proof: bool,
+    },
+    /// Merge the keys of another database into this one
+    Merge {
+        /// Path to the other database's state file
+        #[arg(long)]
+        state: PathBuf,
+        /// Path to the other database's storage directory
+        #[arg(long)]
+        data: PathBuf,
+        /// What to do with keys both databases hold with different values
+        #[arg(long, value_enum, default_value_t = ConflictPolicy::Error)]
+        conflict: ConflictPolicy,
+        /// Generate proof
+        #[arg(short, long)]
+        proof: bool,
+    },
+    /// Check the store, executor and state, printing their status as JSON
+    Health,
+    /// Inspect the operation log
+    Log {
+        #[command(subcommand)]
+        command: LogCommands,
+    },
+}
+
+#[derive(Subcommand)]
+enum LogCommands {
+    /// Print the most recent entries as JSON
+    Tail {
+        /// Number of entries to print
+        #[arg(short = 'n', long, default_value_t = 10)]
+        count: u64,
+    },
+    /// Check every entry's checksum and report the corrupt ones
+    Verify,
 }
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    tracing_subscriber::fmt::init();
     let cli = Cli::parse();
+    init_tracing(cli.log_format);
+    let show_cycles = cli.show_cycles;
 
     // Create data directory if it doesn't exist
     tokio::fs::create_dir_all(&cli.data_dir).await?;
@@ -63,27 +182,127 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     };
 
     // Initialize database
-    let mut db = Database::new(DatabaseType::Merkle, Arc::new(store), state_bytes).await?;
+    let mut builder = Database::builder(DatabaseType::Merkle, Arc::new(store))
+        .operation_log(true)
+        .value_index(true)
+        .read_only(cli.read_only);
+    if let Some(state) = state_bytes {
+        builder = builder.state(state);
+    }
+    if let Some(prefix) = cli.reserved_prefix {
+        builder = builder.reserved_prefix(prefix);
+    }
+    let mut db = builder.build().await?;
 
     match cli.command {
-        Commands::Put { key, value, proof } => {
+        Commands::Put {
+            key,
+            value,
+            proof,
+            key_hex: true,
+            ..
+        } => {
+            info!("Inserting hex key: {}", key);
+            db.put_bytes(&hex::decode(&key)?, value.as_bytes(), proof)
+                .await?;
+            // Save state after modification
+            db.save_state(&cli.state_file)?;
+            println!("Successfully inserted key: {}", key);
+        }
+        Commands::Put {
+            key,
+            value,
+            proof,
+            content_type,
+            ttl,
+            ..
+        } => {
             info!("Inserting key: {}", key);
-            db.put(&key, value.as_bytes(), proof).await?;
+            match (content_type, ttl) {
+                (_, Some(ttl)) => {
+                    db.put_with_ttl(&key, value.as_bytes(), Duration::from_secs(ttl), proof)
+                        .await?;
+                }
+                (Some(content_type), None) => {
+                    let metadata = Metadata {
+                        content_type: Some(content_type),
+                        ..Metadata::default()
+                    };
+                    db.put_with_meta(&key, value.as_bytes(), metadata, proof)
+                        .await?;
+                }
+                (None, None) => db.put(&key, value.as_bytes(), proof).await?,
+            }
             // Save state after modification
             db.save_state(&cli.state_file)?;
             println!("Successfully inserted key: {}", key);
         }
-        Commands::Get { key, proof } => {
+        Commands::Get {
+            key,
+            proof,
+            key_hex: true,
+            ..
+        } => {
+            info!("Querying hex key: {}", key);
+            match db.get_bytes(&hex::decode(&key)?, proof).await {
+                Ok(value) => println!("Value: {:?}", String::from_utf8_lossy(&value)),
+                Err(e) => println!("Error retrieving key {}: {}", key, e),
+            }
+        }
+        Commands::Get {
+            key,
+            proof,
+            at_root: Some(root),
+            ..
+        } => {
+            info!("Querying key: {} at root {}", key, root);
+            let read = db.get_at(&key, &root, proof).await?;
+            println!("Value: {:?}", String::from_utf8_lossy(&read.value));
+            println!("Root: {}", read.root);
+        }
+        Commands::Get {
+            key,
+            proof,
+            verbose,
+            ..
+        } => {
             info!("Querying key: {}", key);
-            match db.get(&key, proof).await {
-                Ok(value) => {
+            match db.get_with_meta(&key, proof).await {
+                Ok((value, metadata)) => {
                     println!("Value: {:?}", String::from_utf8_lossy(&value));
+                    if verbose {
+                        match metadata {
+                            Some(metadata) => {
+                                println!("Metadata: {}", serde_json::to_string(&metadata)?)
+                            }
+                            None => println!("Metadata: none"),
+                        }
+                    }
                 }
                 Err(e) => {
                     println!("Error retrieving key {}: {}", key, e);
                 }
             }
         }
+        Commands::Find { hash } => {
+            info!("Finding keys with value hash: {}", hash);
+            for key in db.find_keys_by_hash(&hash).await? {
+                println!("{}", key);
+            }
+        }
+        Commands::Sweep { proof } => {
+            info!("Sweeping expired keys");
+            let removed = db.sweep_expired(proof).await?;
+            // Save state after modification
+            db.save_state(&cli.state_file)?;
+            println!("Removed {} expired keys", removed);
+        }
+        Commands::Init if cli.state_file.exists() => {
+            // Keep the existing state rather than overwriting it
+            db.load_state(&cli.state_file)?;
+            println!("Database already initialized at {:?}", cli.data_dir);
+            println!("Using state file {:?}", cli.state_file);
+        }
         Commands::Init => {
             info!("Initializing new database");
             // Save initial empty state
@@ -91,7 +310,106 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
             println!("Database initialized at {:?}", cli.data_dir);
             println!("State file created at {:?}", cli.state_file);
         }
+        Commands::Prove { key, proof } => {
+            info!("Proving key: {}", key);
+            let result = db.prove(&key, proof)?;
+            println!("{}", serde_json::to_string_pretty(&result.data)?);
+        }
+        Commands::Vk { output_solidity } => {
+            println!("{}", db.vk_hash());
+            if let Some(path) = output_solidity {
+                tokio::fs::write(&path, db.generate_solidity_verifier()?).await?;
+                println!("Solidity verifier written to {:?}", path);
+            }
+        }
+        Commands::Import {
+            file,
+            key_field,
+            value_field,
+        } => {
+            info!("Importing from {:?}", file);
+            let reader = tokio::io::BufReader::new(tokio::fs::File::open(&file).await?);
+            let report = db
+                .import_json_lines(reader, &key_field, &value_field, false)
+                .await?;
+            // Save state after modification
+            db.save_state(&cli.state_file)?;
+            println!(
+                "Imported {} keys, skipped {} lines",
+                report.imported, report.skipped
+            );
+            for error in &report.errors {
+                println!("  {}", error);
+            }
+        }
+        Commands::ImportRocksdb {
+            path,
+            overwrite,
+            proof,
+        } => {
+            info!("Importing RocksDB from {:?}", path);
+            let report = db.import_rocksdb_backup(&path, overwrite, proof).await?;
+            // Save state after modification
+            db.save_state(&cli.state_file)?;
+            println!(
+                "Imported {} keys, skipped {} keys",
+                report.imported, report.skipped
+            );
+            for error in &report.errors {
+                println!("  {}", error);
+            }
+        }
+        Commands::Merge {
+            state,
+            data,
+            conflict,
+            proof,
+        } => {
+            info!("Merging {:?} from {:?}", state, data);
+            let other_state = tokio::fs::read(&state).await?;
+            let source = FileStore::new(&data).await?;
+            let report = db
+                .merge_from(&other_state, &source, conflict, proof)
+                .await?;
+            // Save state after modification
+            db.save_state(&cli.state_file)?;
+            println!(
+                "Added {} keys, skipped {} keys",
+                report.added, report.skipped
+            );
+            for key in &report.conflicts {
+                println!("  conflict: {}", key);
+            }
+        }
+        Commands::Health => {
+            let status = db.healthcheck().await?;
+            println!("{}", serde_json::to_string_pretty(&status)?);
+            if !status.is_ok() {
+                return Err("database is not healthy".into());
+            }
+        }
+        Commands::Log { command } => match command {
+            LogCommands::Tail { count } => {
+                let len = db.log_len().await?;
+                for entry in db.read_log(len.saturating_sub(count)..len).await? {
+                    println!("{}", serde_json::to_string(&entry)?);
+                }
+            }
+            LogCommands::Verify => {
+                let corrupt = db.verify_log().await?;
+                for error in &corrupt {
+                    println!("{}", error);
+                }
+                if !corrupt.is_empty() {
+                    return Err(format!("{} corrupt log entries", corrupt.len()).into());
+                }
+                println!("Operation log is intact");
+            }
+        },
     }
 
+    if show_cycles {
+        println!("Cycles: {}", db.total_cycles());
+    }
     Ok(())
 }
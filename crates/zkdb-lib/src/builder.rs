@@ -0,0 +1,409 @@
+//! Step-by-step configuration of a `Database`.
+
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use zkdb_core::merkle::{KeyDerivation, MerkleConfig};
+use zkdb_store::Store;
+
+use crate::throttle::ProofLimiter;
+use crate::{
+    encryption, reserved, Clock, Codec, Database, DatabaseError, DatabaseLimits, DatabaseType,
+    EncryptionConfig, ProofMode, Signer, Writer,
+};
+
+/// Configures a `Database` before it is created.
+///
+/// Created with `Database::builder`.
+pub struct DatabaseBuilder {
+    engine: DatabaseType,
+    store: Arc<dyn Store>,
+    state: Option<Vec<u8>>,
+    elf_path: Option<PathBuf>,
+    prefetch_capacity: usize,
+    proof_cache_capacity: usize,
+    codec: Codec,
+    expected_vk_hash: Option<String>,
+    operation_log: bool,
+    key_history: bool,
+    history_retention: Option<usize>,
+    proof_timeout: Option<Duration>,
+    max_concurrent_proofs: Option<usize>,
+    proof_mode: ProofMode,
+    execute_timeout: Option<Duration>,
+    clock: Option<Arc<dyn Clock>>,
+    actor: Option<String>,
+    root_history: Option<PathBuf>,
+    value_index: bool,
+    read_only: bool,
+    limits: DatabaseLimits,
+    reserved_prefix: Option<String>,
+    merkle_config: Option<MerkleConfig>,
+    encryption: Option<EncryptionConfig>,
+    signer: Option<Arc<dyn Signer>>,
+    writer: Option<Arc<dyn Signer>>,
+    verifying_key: Option<[u8; 32]>,
+    require_signatures: bool,
+}
+
+impl DatabaseBuilder {
+    pub(crate) fn new(engine: DatabaseType, store: Arc<dyn Store>) -> Self {
+        DatabaseBuilder {
+            engine,
+            store,
+            state: None,
+            elf_path: None,
+            prefetch_capacity: 0,
+            proof_cache_capacity: 0,
+            codec: Codec::default(),
+            expected_vk_hash: None,
+            operation_log: false,
+            key_history: false,
+            history_retention: None,
+            proof_timeout: None,
+            max_concurrent_proofs: None,
+            proof_mode: ProofMode::default(),
+            execute_timeout: None,
+            clock: None,
+            actor: None,
+            root_history: None,
+            value_index: false,
+            read_only: false,
+            limits: DatabaseLimits::default(),
+            reserved_prefix: None,
+            merkle_config: None,
+            encryption: None,
+            signer: None,
+            writer: None,
+            verifying_key: None,
+            require_signatures: false,
+        }
+    }
+
+    /// Starts from a serialized state instead of an empty tree.
+    pub fn state(mut self, state: Vec<u8>) -> Self {
+        self.state = Some(state);
+        self
+    }
+
+    /// Runs the guest program at `path` instead of the embedded one.
+    pub fn elf_path(mut self, path: impl Into<PathBuf>) -> Self {
+        self.elf_path = Some(path.into());
+        self
+    }
+
+    /// Caches up to `capacity` values loaded by `Database::prefetch`.
+    pub fn prefetch_capacity(mut self, capacity: usize) -> Self {
+        self.prefetch_capacity = capacity;
+        self
+    }
+
+    /// Keeps up to `capacity` results of `Database::prove`, reused while the
+    /// root is unchanged.
+    pub fn proof_cache_capacity(mut self, capacity: usize) -> Self {
+        self.proof_cache_capacity = capacity;
+        self
+    }
+
+    /// Sets the codec used by `Database::put_typed` and `Database::get_typed`.
+    pub fn codec(mut self, codec: Codec) -> Self {
+        self.codec = codec;
+        self
+    }
+
+    /// Records every mutation in the operation log under `_wal/`.
+    ///
+    /// `build` then calls `Database::recover`, so entries the given state
+    /// is missing are re-applied before the database is returned.
+    pub fn operation_log(mut self, enabled: bool) -> Self {
+        self.operation_log = enabled;
+        self
+    }
+
+    /// Keeps every value written with `put` under `_hist/`, for
+    /// `Database::history` and `Database::get_version`.
+    pub fn key_history(mut self, enabled: bool) -> Self {
+        self.key_history = enabled;
+        self
+    }
+
+    /// Keeps only the latest `limit` versions of each key. Enables the key
+    /// history.
+    pub fn history_retention(mut self, limit: usize) -> Self {
+        self.key_history = true;
+        self.history_retention = Some(limit);
+        self
+    }
+
+    /// Fails proof generation that takes longer than `duration`, as
+    /// `SP1Executor::with_timeout`.
+    pub fn proof_timeout(mut self, duration: Duration) -> Self {
+        self.proof_timeout = Some(duration);
+        self
+    }
+
+    /// Generates at most `limit` proofs at once, as
+    /// `SP1Executor::with_max_concurrent_proofs`.
+    pub fn max_concurrent_proofs(mut self, limit: usize) -> Self {
+        self.max_concurrent_proofs = Some(limit);
+        self
+    }
+
+    /// Generates proofs in `mode`, as `SP1Executor::with_proof_mode`.
+    pub fn proof_mode(mut self, mode: ProofMode) -> Self {
+        self.proof_mode = mode;
+        self
+    }
+
+    /// Fails execution without a proof that takes longer than `duration`, as
+    /// `SP1Executor::with_execute_timeout`.
+    pub fn execute_timeout(mut self, duration: Duration) -> Self {
+        self.execute_timeout = Some(duration);
+        self
+    }
+
+    /// Keeps the reverse index from value hashes to keys under `_idx/hash/`,
+    /// for `Database::find_keys_by_hash`. Call `Database::rebuild_index`
+    /// when enabling it on a database written without it. `build` fails
+    /// if the state stores hashed keys, see `KeyDerivation::Sha256`.
+    pub fn value_index(mut self, enabled: bool) -> Self {
+        self.value_index = enabled;
+        self
+    }
+
+    /// Replaces the default limits on keys, values, batches and state.
+    pub fn limits(mut self, limits: DatabaseLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Keeps the bookkeeping under `prefix` instead of `_`, so that users may
+    /// write keys starting with `_` and not with `prefix`.
+    ///
+    /// The prefix is recorded in the state, and later opens use it without
+    /// this call. It can only be chosen while the state is empty: `build`
+    /// fails with `DatabaseError::InvalidKey` if the state records another.
+    pub fn reserved_prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.reserved_prefix = Some(prefix.into());
+        self
+    }
+
+    /// Lays the tree out and stores keys as `config` says,
+    /// `TreeLayout::Append` and `KeyDerivation::Raw` otherwise.
+    ///
+    /// Both are recorded in the state and kept by later opens. They can
+    /// only be chosen while the state is empty: `build` fails with
+    /// `DatabaseError::QueryExecutionFailed` if the state records others.
+    pub fn merkle_config(mut self, config: MerkleConfig) -> Self {
+        self.merkle_config = Some(config);
+        self
+    }
+
+    /// Seals everything written to the store under `config`, values and
+    /// bookkeeping alike, and opens it again on reads.
+    ///
+    /// A database must always be opened with the key it was written with:
+    /// reads fail with `DatabaseError::Store` under any other, as they do for
+    /// values altered in the store.
+    pub fn encryption(mut self, config: EncryptionConfig) -> Self {
+        self.encryption = Some(config);
+        self
+    }
+
+    /// Signs state files and proof bundles with the ed25519 secret key
+    /// `secret_key`, see `Signer`.
+    pub fn signing_key(self, secret_key: [u8; 32]) -> Self {
+        self.signer(Arc::new(ed25519_dalek::SigningKey::from_bytes(&secret_key)))
+    }
+
+    /// Signs state files and proof bundles with `signer`.
+    pub fn signer(mut self, signer: Arc<dyn Signer>) -> Self {
+        self.signer = Some(signer);
+        self
+    }
+
+    /// Checks the signatures of loaded state files and bundles against
+    /// `public_key` instead of the signer's own key.
+    pub fn verifying_key(mut self, public_key: [u8; 32]) -> Self {
+        self.verifying_key = Some(public_key);
+        self
+    }
+
+    /// Rejects unsigned state files and bundles on load with
+    /// `DatabaseError::InvalidSignature`.
+    pub fn require_signatures(mut self, required: bool) -> Self {
+        self.require_signatures = required;
+        self
+    }
+
+    /// Signs every mutation with the ed25519 secret key `secret_key`, whose
+    /// public key the engine then requires on every mutation of the state.
+    ///
+    /// The public key is recorded in the state, as its admin key, when the
+    /// state is empty. Databases opened on the state without the key can
+    /// still read, but their mutations fail with
+    /// `DatabaseError::Unauthorized`, and so does `build` given another key.
+    /// Only the Merkle engine checks admin keys.
+    pub fn writer_key(self, secret_key: [u8; 32]) -> Self {
+        self.writer(Arc::new(ed25519_dalek::SigningKey::from_bytes(&secret_key)))
+    }
+
+    /// Signs every mutation with `signer`, as `writer_key`.
+    pub fn writer(mut self, signer: Arc<dyn Signer>) -> Self {
+        self.writer = Some(signer);
+        self
+    }
+
+    /// Opens the database read-only: every mutation, including saving the
+    /// state, fails with `DatabaseError::ReadOnly`, while reads and proofs
+    /// work as usual.
+    pub fn read_only(mut self, enabled: bool) -> Self {
+        self.read_only = enabled;
+        self
+    }
+
+    /// Records the root after every mutation in the JSON file at `path`, for
+    /// `Database::get_root_history`. Keep it alongside the state file.
+    pub fn root_history(mut self, path: impl Into<PathBuf>) -> Self {
+        self.root_history = Some(path.into());
+        self
+    }
+
+    /// Reads the time from `clock` instead of the system clock, e.g. to test
+    /// expiry without waiting. The engine is sent the same time with every
+    /// command.
+    pub fn clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = Some(clock);
+        self
+    }
+
+    /// Names `actor` in the `OperationContext` the engine commits with
+    /// every mutation, next to the time read from the clock.
+    pub fn actor(mut self, actor: impl Into<String>) -> Self {
+        self.actor = Some(actor.into());
+        self
+    }
+
+    /// Pins the hash of the verifying key, as printed by `zkdb vk`.
+    ///
+    /// `build` fails with `DatabaseError::VkMismatch` if the program's key
+    /// differs, and proofs are checked against the pin when verified.
+    pub fn expect_vk_hash(mut self, hash: impl Into<String>) -> Self {
+        self.expected_vk_hash = Some(hash.into());
+        self
+    }
+
+    pub async fn build(self) -> Result<Database, DatabaseError> {
+        let db = match self.elf_path {
+            Some(path) => {
+                Database::with_elf_path(self.engine, self.store, self.state, path).await?
+            }
+            None => Database::new(self.engine, self.store, self.state).await?,
+        };
+        let mut db = db
+            .with_prefetch_capacity(self.prefetch_capacity)
+            .with_proof_cache_capacity(self.proof_cache_capacity);
+        db.codec = self.codec;
+        db.operation_log = self.operation_log;
+        db.key_history = self.key_history;
+        db.history_retention = self.history_retention;
+        db.executor.proof_timeout = self.proof_timeout;
+        db.executor.proof_mode = self.proof_mode;
+        db.executor.execute_timeout = self.execute_timeout;
+        if let Some(limit) = self.max_concurrent_proofs {
+            db.executor.proof_limiter = ProofLimiter::new(Some(limit));
+        }
+        db.root_history = self.root_history;
+        db.value_index = self.value_index;
+        db.read_only = self.read_only;
+        db.limits = self.limits;
+        db.signer = self.signer;
+        db.verifying_key = self.verifying_key;
+        db.require_signatures = self.require_signatures;
+        if let Some(clock) = self.clock {
+            db.clock = clock;
+        }
+        db.executor.clock = db.clock.clone();
+        db.executor.actor = self.actor;
+        if let Some(hash) = &self.expected_vk_hash {
+            db.executor.expect_vk_hash(hash)?;
+        }
+        if let Some(config) = self.encryption {
+            db.store = encryption::store_for(&db.store, &config);
+            db.encryption = Some(config);
+            db.adopt_reserved_prefix();
+        }
+        // Init-time options each write the state, so they look at it as given.
+        let empty = db.state.is_empty();
+        if let Some(prefix) = self.reserved_prefix {
+            if prefix != db.reserved_prefix {
+                reserved::check_prefix(&prefix)?;
+                // Bookkeeping already written stays under the old prefix.
+                if !empty {
+                    return Err(DatabaseError::InvalidKey(format!(
+                        "the state reserves {:?}; another prefix can only be chosen for an empty state",
+                        db.reserved_prefix
+                    )));
+                }
+                db.state = (db.spec.with_reserved_prefix)(&db.state, &prefix)?;
+                db.adopt_reserved_prefix();
+            }
+        }
+        if let Some(config) = self.merkle_config {
+            let layout = (db.spec.layout)(&db.state)?;
+            if config.layout != layout {
+                if !empty {
+                    return Err(DatabaseError::QueryExecutionFailed(format!(
+                        "the state is laid out {:?}; another layout can only be chosen for an empty state",
+                        layout
+                    )));
+                }
+                db.state = (db.spec.with_layout)(&db.state, config.layout)?;
+            }
+            let derivation = (db.spec.key_derivation)(&db.state)?;
+            if config.key_derivation != derivation {
+                if !empty {
+                    return Err(DatabaseError::QueryExecutionFailed(format!(
+                        "the state stores keys {:?}; another derivation can only be chosen for an empty state",
+                        derivation
+                    )));
+                }
+                db.state = (db.spec.with_key_derivation)(&db.state, config.key_derivation)?;
+            }
+        }
+        // The index looks keys up in the state as given.
+        if self.value_index && (db.spec.key_derivation)(&db.state)? != KeyDerivation::Raw {
+            return Err(DatabaseError::QueryExecutionFailed(
+                "the value index needs keys stored as given".to_string(),
+            ));
+        }
+        if let Some(signer) = self.writer {
+            let public_key = signer.public_key();
+            match (db.spec.admin_key)(&db.state)? {
+                Some(admin) if admin == public_key => {}
+                Some(admin) => {
+                    return Err(DatabaseError::Unauthorized(format!(
+                        "the state admits writes signed by {}, not {}",
+                        hex::encode(admin),
+                        hex::encode(public_key)
+                    )))
+                }
+                None if empty => {
+                    db.state = (db.spec.with_admin_key)(&db.state, public_key)?;
+                }
+                None => {
+                    return Err(DatabaseError::QueryExecutionFailed(
+                        "the state has no admin key; one can only be recorded for an empty state"
+                            .to_string(),
+                    ))
+                }
+            }
+            db.executor.writer = Some(Writer::new(signer, db.spec.tree_root));
+        }
+        // Recovery may write; a read-only database takes the state as given.
+        if db.operation_log && !db.read_only {
+            db.recover().await?;
+        }
+        Ok(db)
+    }
+}
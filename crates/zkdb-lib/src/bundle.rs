@@ -0,0 +1,75 @@
+//! Self-contained proofs of a value, see `Database::prove_bundle`.
+//!
+//! A `verify::ProofBundle` proves a value against a root with an inclusion
+//! proof anyone can check, and names the public values of an SP1 proof
+//! without carrying it. A `ProofBundle` adds the SP1 proof and the
+//! verifying key it checks against, so that the whole result can be
+//! checked from the bundle alone.
+
+use serde::{Deserialize, Serialize};
+use sp1_sdk::{HashableKey, ProverClient, SP1VerifyingKey};
+
+use crate::{normalize_vk_hash, verify, DatabaseError, ProvenOutput};
+
+/// A value, its leaf, the root, the inclusion proof and, optionally, the
+/// SP1 proof of the `Prove` command that produced them.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ProofBundle {
+    /// The value, leaf, root and inclusion proof.
+    pub inclusion: verify::ProofBundle,
+    /// SP1 proof of the `Prove` command, whose public values are those
+    /// named by `inclusion`.
+    pub sp1_proof: Option<ProvenOutput>,
+    /// Verifying key of the engine, present with `sp1_proof`.
+    pub vk: Option<SP1VerifyingKey>,
+}
+
+impl ProofBundle {
+    /// Encodes the bundle with bincode.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        bincode::serialize(self).expect("bundles always encode")
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, DatabaseError> {
+        bincode::deserialize(bytes)
+            .map_err(|e| DatabaseError::Codec(format!("Invalid proof bundle: {}", e)))
+    }
+
+    /// Checks the bundle without trusting any of its fields: the leaf
+    /// against the value, the inclusion proof against the root and, when
+    /// the bundle carries one, the SP1 proof against its verifying key and
+    /// its public values against the root and leaf.
+    pub fn verify(&self) -> Result<(), DatabaseError> {
+        let failed = |reason: &str| DatabaseError::ProofVerificationFailed(reason.to_string());
+        let report = verify::verify_bundle(&self.inclusion)
+            .map_err(|e| DatabaseError::Codec(e.to_string()))?;
+        if !report.leaf_matches_value {
+            return Err(failed("leaf does not match the value"));
+        }
+        if !report.proof_valid {
+            return Err(failed("inclusion proof does not lead to the root"));
+        }
+        if report.public_values_match == Some(false) {
+            return Err(failed("public values do not match the root and leaf"));
+        }
+        let Some(proof) = &self.sp1_proof else {
+            return Ok(());
+        };
+        let vk = self
+            .vk
+            .as_ref()
+            .ok_or_else(|| failed("bundle has an SP1 proof but no verifying key"))?;
+        let expected = normalize_vk_hash(&vk.bytes32());
+        let actual = normalize_vk_hash(&String::from_utf8_lossy(&proof.vk));
+        if actual != expected {
+            return Err(DatabaseError::VkMismatch { expected, actual });
+        }
+        let public_values = hex::encode(proof.proof_data.public_values.as_slice());
+        if self.inclusion.public_values.as_deref() != Some(public_values.as_str()) {
+            return Err(failed("SP1 proof is of other public values"));
+        }
+        ProverClient::new()
+            .verify(&proof.proof_data, vk)
+            .map_err(|e| failed(&e.to_string()))
+    }
+}
@@ -0,0 +1,20 @@
+//! Source of the current time, replaceable so expiry can be tested without
+//! waiting.
+
+/// Tells the time to a `Database`.
+///
+/// Set with `DatabaseBuilder::clock`; `SystemClock` is the default.
+pub trait Clock: Send + Sync {
+    /// Current Unix time in milliseconds.
+    fn now_millis(&self) -> u64;
+}
+
+/// Reads the system clock.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now_millis(&self) -> u64 {
+        chrono::Utc::now().timestamp_millis() as u64
+    }
+}
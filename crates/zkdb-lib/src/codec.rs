@@ -0,0 +1,40 @@
+//! Encodings used by `Database::put_typed` and `Database::get_typed`.
+
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+use crate::DatabaseError;
+
+/// How typed values are turned into the bytes that are stored and hashed.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Codec {
+    /// Compact binary encoding.
+    #[default]
+    Bincode,
+    /// JSON, readable when inspecting the store directly.
+    Json,
+}
+
+impl Codec {
+    pub fn encode<T: Serialize>(&self, value: &T) -> Result<Vec<u8>, DatabaseError> {
+        match self {
+            Codec::Bincode => bincode::serialize(value).map_err(|e| {
+                DatabaseError::Codec(format!("Failed to encode value as bincode: {}", e))
+            }),
+            Codec::Json => serde_json::to_vec(value).map_err(|e| {
+                DatabaseError::Codec(format!("Failed to encode value as JSON: {}", e))
+            }),
+        }
+    }
+
+    pub fn decode<T: DeserializeOwned>(&self, bytes: &[u8]) -> Result<T, DatabaseError> {
+        match self {
+            Codec::Bincode => bincode::deserialize(bytes).map_err(|e| {
+                DatabaseError::Codec(format!("Failed to decode value as bincode: {}", e))
+            }),
+            Codec::Json => serde_json::from_slice(bytes).map_err(|e| {
+                DatabaseError::Codec(format!("Failed to decode value as JSON: {}", e))
+            }),
+        }
+    }
+}
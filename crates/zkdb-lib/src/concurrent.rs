@@ -0,0 +1,135 @@
+//! Shared access to a `Database` from many tasks.
+
+use std::sync::Arc;
+use tokio::runtime::Handle;
+use tokio::sync::RwLock;
+
+use crate::{Database, DatabaseError, ProvenQueryResult};
+
+/// A cloneable handle to a `Database` behind a read-write lock.
+///
+/// Reads run concurrently; writes hold the lock exclusively for the whole
+/// operation, so readers never observe a value stored without its leaf.
+/// This is the recommended type when the database is shared between tasks.
+///
+/// Operations that run the zkVM do so on a blocking thread, as
+/// `Database::verify_proof_async` does, so that a proof, or a writer
+/// holding the lock through one, never stalls a worker of the runtime.
+#[derive(Clone)]
+pub struct ConcurrentDatabase {
+    inner: Arc<RwLock<Database>>,
+}
+
+impl ConcurrentDatabase {
+    pub fn new(db: Database) -> Self {
+        ConcurrentDatabase {
+            inner: Arc::new(RwLock::new(db)),
+        }
+    }
+
+    pub async fn get(&self, key: &str, generate_proof: bool) -> Result<Vec<u8>, DatabaseError> {
+        let key = key.to_string();
+        self.read_blocking(move |db| Handle::current().block_on(db.get(&key, generate_proof)))
+            .await?
+    }
+
+    pub async fn list_keys(&self) -> Result<Vec<String>, DatabaseError> {
+        self.inner.read().await.list_keys()
+    }
+
+    pub async fn prove(
+        &self,
+        key: &str,
+        generate_proof: bool,
+    ) -> Result<ProvenQueryResult, DatabaseError> {
+        let key = key.to_string();
+        self.read_blocking(move |db| db.prove(&key, generate_proof))
+            .await?
+    }
+
+    pub async fn put(
+        &self,
+        key: &str,
+        value: &[u8],
+        generate_proof: bool,
+    ) -> Result<(), DatabaseError> {
+        let (key, value) = (key.to_string(), value.to_vec());
+        self.write_blocking(move |db| {
+            Handle::current().block_on(db.put(&key, &value, generate_proof))
+        })
+        .await?
+    }
+
+    pub async fn delete(&self, key: &str, generate_proof: bool) -> Result<(), DatabaseError> {
+        let key = key.to_string();
+        self.write_blocking(move |db| Handle::current().block_on(db.delete(&key, generate_proof)))
+            .await?
+    }
+
+    pub async fn update(
+        &self,
+        key: &str,
+        value: &[u8],
+        generate_proof: bool,
+    ) -> Result<(), DatabaseError> {
+        let (key, value) = (key.to_string(), value.to_vec());
+        self.write_blocking(move |db| {
+            Handle::current().block_on(db.update(&key, &value, generate_proof))
+        })
+        .await?
+    }
+
+    /// Runs `f` with shared access to the database on a blocking thread.
+    async fn read_blocking<F, R>(&self, f: F) -> Result<R, DatabaseError>
+    where
+        F: FnOnce(&Database) -> R + Send + 'static,
+        R: Send + 'static,
+    {
+        let db = self.inner.clone().read_owned().await;
+        tokio::task::spawn_blocking(move || f(&db))
+            .await
+            .map_err(|e| {
+                DatabaseError::QueryExecutionFailed(format!("database task failed: {}", e))
+            })
+    }
+
+    /// Runs `f` with exclusive access to the database on a blocking thread.
+    async fn write_blocking<F, R>(&self, f: F) -> Result<R, DatabaseError>
+    where
+        F: FnOnce(&mut Database) -> R + Send + 'static,
+        R: Send + 'static,
+    {
+        let mut db = self.inner.clone().write_owned().await;
+        tokio::task::spawn_blocking(move || f(&mut db))
+            .await
+            .map_err(|e| {
+                DatabaseError::QueryExecutionFailed(format!("database task failed: {}", e))
+            })
+    }
+
+    /// Runs `f` with shared access to the database.
+    ///
+    /// `f` runs on the calling task, so it must not generate proofs: use
+    /// the methods above, which move the zkVM off the runtime.
+    pub async fn with_read<F, R>(&self, f: F) -> R
+    where
+        F: FnOnce(&Database) -> R,
+    {
+        f(&*self.inner.read().await)
+    }
+
+    /// Runs `f` with exclusive access to the database, on the calling task
+    /// like `with_read`.
+    pub async fn with_write<F, R>(&self, f: F) -> R
+    where
+        F: FnOnce(&mut Database) -> R,
+    {
+        f(&mut *self.inner.write().await)
+    }
+}
+
+impl From<Database> for ConcurrentDatabase {
+    fn from(db: Database) -> Self {
+        ConcurrentDatabase::new(db)
+    }
+}
@@ -0,0 +1,85 @@
+//! Time limits for work done in the zkVM.
+
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use crate::DatabaseError;
+
+/// Count of tasks that missed their deadline and are still running.
+#[derive(Debug, Default)]
+pub struct Overdue(AtomicUsize);
+
+impl Overdue {
+    pub fn new() -> Arc<Self> {
+        Arc::new(Overdue::default())
+    }
+
+    /// Number of abandoned tasks that have not finished yet.
+    pub fn count(&self) -> usize {
+        self.0.load(Ordering::SeqCst)
+    }
+}
+
+/// Runs `task` to completion, or on its own thread until `deadline` when
+/// one is set, returning `expired(deadline)` if it takes longer.
+///
+/// The zkVM cannot be interrupted, so a task that misses its deadline keeps
+/// running in the background and its result is discarded. It is counted in
+/// `overdue`, if given, until it finishes.
+pub fn run_with_deadline<T, F>(
+    deadline: Option<Duration>,
+    overdue: Option<&Arc<Overdue>>,
+    expired: impl FnOnce(Duration) -> DatabaseError,
+    task: F,
+) -> Result<T, DatabaseError>
+where
+    T: Send + 'static,
+    F: FnOnce() -> Result<T, DatabaseError> + Send + 'static,
+{
+    let Some(deadline) = deadline else {
+        return task();
+    };
+    let (sender, receiver) = mpsc::channel();
+    // Set once the caller stops waiting; the result is sent under the same
+    // lock, so either the caller gets it or the task counts as overdue.
+    let abandoned = Arc::new(Mutex::new(false));
+    let task_abandoned = abandoned.clone();
+    let task_overdue = overdue.cloned();
+    thread::spawn(move || {
+        let result = panic::catch_unwind(AssertUnwindSafe(task)).unwrap_or_else(|_| {
+            Err(DatabaseError::QueryExecutionFailed(
+                "zkVM task panicked".to_string(),
+            ))
+        });
+        let abandoned = task_abandoned.lock().unwrap();
+        if *abandoned {
+            if let Some(overdue) = task_overdue {
+                overdue.0.fetch_sub(1, Ordering::SeqCst);
+            }
+        } else {
+            let _ = sender.send(result);
+        }
+    });
+    match receiver.recv_timeout(deadline) {
+        Ok(result) => result,
+        Err(RecvTimeoutError::Timeout) => {
+            let mut abandoned = abandoned.lock().unwrap();
+            // The task may have finished while the lock was taken.
+            if let Ok(result) = receiver.try_recv() {
+                return result;
+            }
+            *abandoned = true;
+            if let Some(overdue) = overdue {
+                overdue.0.fetch_add(1, Ordering::SeqCst);
+            }
+            Err(expired(deadline))
+        }
+        Err(RecvTimeoutError::Disconnected) => Err(DatabaseError::QueryExecutionFailed(
+            "zkVM task panicked".to_string(),
+        )),
+    }
+}
@@ -0,0 +1,185 @@
+//! Encryption of everything a database writes to its store.
+//!
+//! Configured with `DatabaseBuilder::encryption`, the database wraps its
+//! store in an `EncryptedStore`, so values and bookkeeping alike reach the
+//! store sealed with ChaCha20-Poly1305; keys stay readable. Leaves commit
+//! to the hash of the value or of its ciphertext, as `EncryptionConfig`
+//! says.
+//!
+//! Encryption is deterministic: the nonce is a MAC of the value alone, so a
+//! value always seals to the same ciphertext, whatever key it is stored
+//! under, and its leaf can be computed before it is stored. Equal values are
+//! therefore visible as equal ciphertexts, as they already are as equal
+//! leaves. The MAC and the cipher use separate subkeys of the configured
+//! key, see `NONCE_KEY_LABEL` and `CIPHER_KEY_LABEL`.
+
+use async_trait::async_trait;
+use chacha20poly1305::aead::{Aead, KeyInit};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::fmt;
+use std::sync::Arc;
+use zkdb_store::{CompactionStats, Store, StoreError, StoreResult};
+
+pub use zkdb_verify::CommitTo;
+
+/// Length of the nonce stored in front of each ciphertext.
+const NONCE_LEN: usize = 12;
+
+/// Label of the subkey the nonce of a value is a MAC under.
+const NONCE_KEY_LABEL: &[u8] = b"zkdb-encryption-v1/nonce";
+
+/// Label of the subkey values are encrypted under.
+const CIPHER_KEY_LABEL: &[u8] = b"zkdb-encryption-v1/cipher";
+
+/// Encryption of a database's store.
+#[derive(Clone)]
+pub struct EncryptionConfig {
+    /// Key the ChaCha20-Poly1305 key and the nonce key are derived from.
+    /// Losing it loses every value.
+    pub key: [u8; 32],
+    /// What leaves commit to. `CommitTo::Ciphertext` hides values even from
+    /// verifiers, who then check proofs against ciphertexts.
+    pub commit_to: CommitTo,
+}
+
+impl fmt::Debug for EncryptionConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("EncryptionConfig")
+            .field("key", &"<redacted>")
+            .field("commit_to", &self.commit_to)
+            .finish()
+    }
+}
+
+impl EncryptionConfig {
+    /// Seals `value` as it is stored: the nonce followed by the ciphertext
+    /// and its tag.
+    pub(crate) fn seal(&self, value: &[u8]) -> Vec<u8> {
+        let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(&self.subkey(NONCE_KEY_LABEL))
+            .expect("HMAC takes keys of any length");
+        mac.update(value);
+        let digest = mac.finalize().into_bytes();
+        let nonce = Nonce::from_slice(&digest[..NONCE_LEN]);
+        let ciphertext = self
+            .cipher()
+            .encrypt(nonce, value)
+            .expect("ChaCha20-Poly1305 encrypts values of any practical length");
+        let mut sealed = nonce.to_vec();
+        sealed.extend_from_slice(&ciphertext);
+        sealed
+    }
+
+    /// Recovers the value sealed by `seal`, failing if `sealed` was written
+    /// under another key or altered.
+    pub(crate) fn open(&self, sealed: &[u8]) -> Option<Vec<u8>> {
+        if sealed.len() < NONCE_LEN {
+            return None;
+        }
+        let (nonce, ciphertext) = sealed.split_at(NONCE_LEN);
+        self.cipher()
+            .decrypt(Nonce::from_slice(nonce), ciphertext)
+            .ok()
+    }
+
+    /// Hex-encoded leaf committed for `value`.
+    pub(crate) fn value_hash(&self, value: &[u8]) -> String {
+        match self.commit_to {
+            CommitTo::Plaintext => zkdb_verify::hash_value_hex(value),
+            CommitTo::Ciphertext => zkdb_verify::hash_value_hex(&self.seal(value)),
+        }
+    }
+
+    fn cipher(&self) -> ChaCha20Poly1305 {
+        ChaCha20Poly1305::new(Key::from_slice(&self.subkey(CIPHER_KEY_LABEL)))
+    }
+
+    /// Subkey of `key` for the use named by `label`: HKDF-SHA256 expanded
+    /// to one block, `key` being uniformly random and so serving as the
+    /// pseudorandom key without the extract step.
+    fn subkey(&self, label: &[u8]) -> [u8; 32] {
+        let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(&self.key)
+            .expect("HMAC takes keys of any length");
+        mac.update(label);
+        mac.update(&[1]);
+        mac.finalize().into_bytes().into()
+    }
+}
+
+/// `store` with everything written through it sealed under `config`.
+pub(crate) fn store_for(store: &Arc<dyn Store>, config: &EncryptionConfig) -> Arc<dyn Store> {
+    Arc::new(EncryptedStore {
+        inner: store.clone(),
+        config: config.clone(),
+    })
+}
+
+/// Wraps a store and seals values on their way in, opening them on their
+/// way out.
+struct EncryptedStore {
+    inner: Arc<dyn Store>,
+    config: EncryptionConfig,
+}
+
+#[async_trait]
+impl Store for EncryptedStore {
+    async fn put(&self, key: &str, value: &[u8]) -> StoreResult<()> {
+        self.inner.put(key, &self.config.seal(value)).await
+    }
+
+    async fn get(&self, key: &str) -> StoreResult<Vec<u8>> {
+        let sealed = self.inner.get(key).await?;
+        self.config.open(&sealed).ok_or_else(|| {
+            StoreError::Storage(format!(
+                "Failed to decrypt the value of {}: wrong key or altered value",
+                key
+            ))
+        })
+    }
+
+    async fn delete(&self, key: &str) -> StoreResult<()> {
+        self.inner.delete(key).await
+    }
+
+    async fn exists(&self, key: &str) -> StoreResult<bool> {
+        self.inner.exists(key).await
+    }
+
+    async fn keys_with_prefix(&self, prefix: &str) -> StoreResult<Vec<String>> {
+        self.inner.keys_with_prefix(prefix).await
+    }
+
+    async fn atomic_swap(&self, key: &str, new_value: &[u8]) -> StoreResult<Option<Vec<u8>>> {
+        let Some(sealed) = self
+            .inner
+            .atomic_swap(key, &self.config.seal(new_value))
+            .await?
+        else {
+            return Ok(None);
+        };
+        self.config.open(&sealed).map(Some).ok_or_else(|| {
+            StoreError::Storage(format!(
+                "Failed to decrypt the value of {}: wrong key or altered value",
+                key
+            ))
+        })
+    }
+
+    async fn copy(&self, src_key: &str, dst_key: &str) -> StoreResult<()> {
+        // Sealing depends only on the value, so the ciphertext is copied as is.
+        self.inner.copy(src_key, dst_key).await
+    }
+
+    async fn compact(&self) -> StoreResult<CompactionStats> {
+        self.inner.compact().await
+    }
+
+    async fn flush(&self) -> StoreResult<()> {
+        self.inner.flush().await
+    }
+
+    async fn total_bytes(&self) -> StoreResult<u64> {
+        self.inner.total_bytes().await
+    }
+}
@@ -0,0 +1,790 @@
+//! Per-engine configuration selected by `DatabaseType`.
+//!
+//! Each engine is a guest program built into its own ELF by the build script
+//! when the engine's feature is enabled. Every engine speaks the zkdb-core
+//! protocol, so output is parsed the same way for all of them; what differs
+//! is the program and the layout of its state.
+//!
+//! The build script embeds the ELF when it could build one, or was given a
+//! prebuilt one through `ZKDB_PREBUILT_ELF` (`ZKDB_SMT_PREBUILT_ELF` for the
+//! sparse Merkle engine, `ZKDB_IAVL_PREBUILT_ELF` for the IAVL one), and
+//! records its SHA-256. An ELF can also be loaded at runtime, in which case
+//! it must match that hash.
+
+use sha2::{Digest, Sha256};
+use std::borrow::Cow;
+use std::ops::Range;
+use std::path::Path;
+use zkdb_core::merkle::{KeyDerivation, TreeLayout};
+
+use crate::{DatabaseError, DatabaseType};
+
+/// Environment variable naming an ELF file to load instead of the embedded one.
+pub const ELF_RUNTIME_PATH_ENV: &str = "ZKDB_ELF_RUNTIME_PATH";
+
+/// Lists the keys of a tree of a serialized state, `None` for the root tree.
+pub type ListKeysFn = fn(&[u8], Option<&str>) -> Result<Vec<String>, DatabaseError>;
+
+/// Lists the keys of a tree of a serialized state as bytes, `None` for the
+/// root tree.
+pub type ListKeyBytesFn = fn(&[u8], Option<&str>) -> Result<Vec<Vec<u8>>, DatabaseError>;
+
+/// Looks up the hex-encoded value hashes of keys in the root tree of a
+/// serialized state, `None` for keys it does not hold.
+pub type LeafHashesFn = fn(&[u8], &[String]) -> Result<Vec<Option<String>>, DatabaseError>;
+
+/// Checks the output of `Command::ProveAbsence` against the serialized
+/// state it was run on.
+pub type CheckAbsenceFn = fn(&[u8], &serde_json::Value) -> Result<(), DatabaseError>;
+
+/// Checks the output of `Command::Range` over a key range against the
+/// serialized state it was run on.
+pub type CheckRangeFn = fn(&[u8], &serde_json::Value, &Range<&str>) -> Result<(), DatabaseError>;
+
+/// Reads the admin key recorded in a serialized state.
+pub type AdminKeyFn = fn(&[u8]) -> Result<Option<[u8; 32]>, DatabaseError>;
+
+/// Records an admin key in a serialized state.
+pub type WithAdminKeyFn = fn(&[u8], [u8; 32]) -> Result<Vec<u8>, DatabaseError>;
+
+/// Everything the host needs to drive one engine.
+#[derive(Clone, Copy, Debug)]
+pub struct EngineSpec {
+    /// Name of the engine.
+    pub name: &'static str,
+    /// The guest program, if it was embedded at build time.
+    pub embedded_elf: Option<&'static [u8]>,
+    /// Hex-encoded SHA-256 of the guest program the crate was built for.
+    pub elf_sha256: Option<&'static str>,
+    /// Hex-encoded root of a serialized state, `None` for an empty or
+    /// undecodable state.
+    pub state_root: fn(&[u8]) -> Option<String>,
+    /// Hex-encoded root of the named tree of a serialized state, `None` for
+    /// an empty tree or undecodable state.
+    pub tree_root: fn(&[u8], &str) -> Option<String>,
+    /// UTF-8 keys held in a tree of a serialized state, in sorted order.
+    /// `None` selects the root tree.
+    pub list_keys: ListKeysFn,
+    /// Every key held in a tree of a serialized state, in sorted order.
+    pub list_key_bytes: ListKeyBytesFn,
+    /// Value hashes committed for keys of the root tree, read without the
+    /// zkVM. They are the leaves themselves under `TreeLayout::Append`.
+    pub leaf_hashes: LeafHashesFn,
+    /// Number of keys held across the trees of a serialized state, `None`
+    /// if it cannot be decoded.
+    pub key_count: fn(&[u8]) -> Option<usize>,
+    /// Fills in any root cache of a serialized state, so that `state_root`
+    /// is cheap until the next mutation. Returns the state unchanged if it
+    /// has no stale cache or cannot be decoded.
+    pub refresh_root: fn(Vec<u8>) -> Vec<u8>,
+    /// Prefix of the keys kept for bookkeeping, as recorded in a serialized
+    /// state.
+    pub reserved_prefix: fn(&[u8]) -> Result<String, DatabaseError>,
+    /// Records another reserved prefix in a serialized state.
+    pub with_reserved_prefix: fn(&[u8], &str) -> Result<Vec<u8>, DatabaseError>,
+    /// Layout of the leaves, as recorded in a serialized state.
+    pub layout: fn(&[u8]) -> Result<TreeLayout, DatabaseError>,
+    /// Records another layout in a serialized state, which must be empty.
+    pub with_layout: fn(&[u8], TreeLayout) -> Result<Vec<u8>, DatabaseError>,
+    /// How keys are stored, as recorded in a serialized state.
+    pub key_derivation: fn(&[u8]) -> Result<KeyDerivation, DatabaseError>,
+    /// Records another key derivation in a serialized state, which must be
+    /// empty.
+    pub with_key_derivation: fn(&[u8], KeyDerivation) -> Result<Vec<u8>, DatabaseError>,
+    /// Ed25519 key mutations must be signed with, as recorded in a
+    /// serialized state.
+    pub admin_key: AdminKeyFn,
+    /// Records an admin key in a serialized state, which must be empty.
+    pub with_admin_key: WithAdminKeyFn,
+    /// Checks a proof of absence, which each engine proves its own way.
+    pub check_absence: CheckAbsenceFn,
+    /// Checks the proofs of a page of a key range.
+    pub check_range: CheckRangeFn,
+}
+
+impl EngineSpec {
+    /// Returns the guest program, loading it from `ZKDB_ELF_RUNTIME_PATH`
+    /// when set and falling back to the embedded copy.
+    pub fn load_elf(&self) -> Result<Cow<'static, [u8]>, DatabaseError> {
+        if let Some(path) = std::env::var_os(ELF_RUNTIME_PATH_ENV) {
+            return self.load_elf_from(path).map(Cow::Owned);
+        }
+        self.embedded_elf.map(Cow::Borrowed).ok_or_else(|| {
+            DatabaseError::ElfUnavailable(format!(
+                "the {} engine was built without an embedded ELF; set {}",
+                self.name, ELF_RUNTIME_PATH_ENV
+            ))
+        })
+    }
+
+    /// Reads the guest program from `path` and checks it against `elf_sha256`.
+    pub fn load_elf_from(&self, path: impl AsRef<Path>) -> Result<Vec<u8>, DatabaseError> {
+        let path = path.as_ref();
+        let elf = std::fs::read(path).map_err(|e| {
+            DatabaseError::ElfUnavailable(format!("failed to read {}: {}", path.display(), e))
+        })?;
+        self.verify_elf(&elf)?;
+        Ok(elf)
+    }
+
+    /// Checks that `elf` is the guest program the crate was built for.
+    pub fn verify_elf(&self, elf: &[u8]) -> Result<(), DatabaseError> {
+        let expected = self.elf_sha256.ok_or_else(|| {
+            DatabaseError::ElfUnavailable(format!(
+                "no expected hash was recorded for the {} engine; set ZKDB_{}_ELF_SHA256 at build time",
+                self.name,
+                self.name.to_uppercase()
+            ))
+        })?;
+        let actual = hex::encode(Sha256::digest(elf));
+        if !actual.eq_ignore_ascii_case(expected) {
+            return Err(DatabaseError::ElfHashMismatch {
+                expected: expected.to_string(),
+                actual,
+            });
+        }
+        Ok(())
+    }
+}
+
+impl DatabaseType {
+    /// The configuration of this engine.
+    pub fn spec(&self) -> EngineSpec {
+        match *self {
+            #[cfg(feature = "merkle")]
+            DatabaseType::Merkle => EngineSpec {
+                name: "merkle",
+                embedded_elf: merkle::EMBEDDED_ELF,
+                elf_sha256: option_env!("ZKDB_MERKLE_ELF_SHA256"),
+                state_root: merkle::state_root,
+                tree_root: merkle::tree_root,
+                list_keys: merkle::list_keys,
+                list_key_bytes: merkle::list_key_bytes,
+                leaf_hashes: merkle::leaf_hashes,
+                key_count: merkle::key_count,
+                refresh_root: merkle::refresh_root,
+                reserved_prefix: merkle::reserved_prefix,
+                with_reserved_prefix: merkle::with_reserved_prefix,
+                layout: merkle::layout,
+                with_layout: merkle::with_layout,
+                key_derivation: merkle::key_derivation,
+                with_key_derivation: merkle::with_key_derivation,
+                admin_key: merkle::admin_key,
+                with_admin_key: merkle::with_admin_key,
+                check_absence: merkle::check_absence,
+                check_range: merkle::check_range,
+            },
+            #[cfg(feature = "smt")]
+            DatabaseType::SparseMerkle => EngineSpec {
+                name: "smt",
+                embedded_elf: smt::EMBEDDED_ELF,
+                elf_sha256: option_env!("ZKDB_SMT_ELF_SHA256"),
+                state_root: smt::state_root,
+                tree_root: smt::tree_root,
+                list_keys: smt::list_keys,
+                list_key_bytes: smt::list_key_bytes,
+                leaf_hashes: smt::leaf_hashes,
+                key_count: smt::key_count,
+                refresh_root: smt::refresh_root,
+                reserved_prefix: smt::reserved_prefix,
+                with_reserved_prefix: smt::with_reserved_prefix,
+                layout: smt::layout,
+                with_layout: smt::with_layout,
+                key_derivation: smt::key_derivation,
+                with_key_derivation: smt::with_key_derivation,
+                admin_key: smt::admin_key,
+                with_admin_key: smt::with_admin_key,
+                check_absence: smt::check_absence,
+                check_range: smt::check_range,
+            },
+            #[cfg(feature = "iavl")]
+            DatabaseType::Iavl => EngineSpec {
+                name: "iavl",
+                embedded_elf: iavl::EMBEDDED_ELF,
+                elf_sha256: option_env!("ZKDB_IAVL_ELF_SHA256"),
+                state_root: iavl::state_root,
+                tree_root: iavl::tree_root,
+                list_keys: iavl::list_keys,
+                list_key_bytes: iavl::list_key_bytes,
+                leaf_hashes: iavl::leaf_hashes,
+                key_count: iavl::key_count,
+                refresh_root: iavl::refresh_root,
+                reserved_prefix: iavl::reserved_prefix,
+                with_reserved_prefix: iavl::with_reserved_prefix,
+                layout: iavl::layout,
+                with_layout: iavl::with_layout,
+                key_derivation: iavl::key_derivation,
+                with_key_derivation: iavl::with_key_derivation,
+                admin_key: iavl::admin_key,
+                with_admin_key: iavl::with_admin_key,
+                check_absence: iavl::check_absence,
+                check_range: iavl::check_range,
+            },
+        }
+    }
+}
+
+/// Returns the embedded guest program of `engine`, if there is one.
+pub fn get_elf_for(engine: &DatabaseType) -> Option<&'static [u8]> {
+    engine.spec().embedded_elf
+}
+
+#[cfg(feature = "merkle")]
+mod merkle {
+    use std::ops::Range;
+    use zkdb_core::merkle::{KeyDerivation, MerkleState, TreeData, TreeLayout, ROOT_TREE};
+
+    use crate::{range, DatabaseError};
+
+    #[cfg(zkdb_embedded_merkle)]
+    pub(super) const EMBEDDED_ELF: Option<&[u8]> =
+        Some(include_bytes!(env!("ZKDB_MERKLE_ELF_PATH")));
+    #[cfg(not(zkdb_embedded_merkle))]
+    pub(super) const EMBEDDED_ELF: Option<&[u8]> = None;
+
+    pub(super) fn state_root(state: &[u8]) -> Option<String> {
+        MerkleState::from_bytes(state).ok()?.root().map(hex::encode)
+    }
+
+    pub(super) fn tree_root(state: &[u8], tree: &str) -> Option<String> {
+        MerkleState::from_bytes(state)
+            .ok()?
+            .tree(tree)?
+            .root()
+            .map(hex::encode)
+    }
+
+    pub(super) fn key_count(state: &[u8]) -> Option<usize> {
+        let merkle_state = MerkleState::from_bytes(state).ok()?;
+        Some(
+            merkle_state
+                .trees
+                .values()
+                .map(|tree| tree.key_indices.len())
+                .sum(),
+        )
+    }
+
+    pub(super) fn refresh_root(state: Vec<u8>) -> Vec<u8> {
+        match MerkleState::from_bytes(&state) {
+            Ok(merkle_state) if merkle_state.root_dirty() => merkle_state.canonical_bytes(),
+            _ => state,
+        }
+    }
+
+    pub(super) fn reserved_prefix(state: &[u8]) -> Result<String, DatabaseError> {
+        Ok(MerkleState::from_bytes(state)?.reserved_prefix)
+    }
+
+    pub(super) fn with_reserved_prefix(
+        state: &[u8],
+        prefix: &str,
+    ) -> Result<Vec<u8>, DatabaseError> {
+        let mut merkle_state = MerkleState::from_bytes(state)?;
+        merkle_state.reserved_prefix = prefix.to_string();
+        Ok(merkle_state.to_bytes())
+    }
+
+    pub(super) fn layout(state: &[u8]) -> Result<TreeLayout, DatabaseError> {
+        Ok(MerkleState::from_bytes(state)?.layout)
+    }
+
+    pub(super) fn with_layout(state: &[u8], layout: TreeLayout) -> Result<Vec<u8>, DatabaseError> {
+        let mut merkle_state = MerkleState::from_bytes(state)?;
+        merkle_state.layout = layout;
+        Ok(merkle_state.to_bytes())
+    }
+
+    pub(super) fn key_derivation(state: &[u8]) -> Result<KeyDerivation, DatabaseError> {
+        Ok(MerkleState::from_bytes(state)?.key_derivation)
+    }
+
+    pub(super) fn with_key_derivation(
+        state: &[u8],
+        derivation: KeyDerivation,
+    ) -> Result<Vec<u8>, DatabaseError> {
+        let mut merkle_state = MerkleState::from_bytes(state)?;
+        merkle_state.key_derivation = derivation;
+        Ok(merkle_state.to_bytes())
+    }
+
+    pub(super) fn admin_key(state: &[u8]) -> Result<Option<[u8; 32]>, DatabaseError> {
+        Ok(MerkleState::from_bytes(state)?.admin_pubkey)
+    }
+
+    pub(super) fn with_admin_key(state: &[u8], key: [u8; 32]) -> Result<Vec<u8>, DatabaseError> {
+        let mut merkle_state = MerkleState::from_bytes(state)?;
+        merkle_state.admin_pubkey = Some(key);
+        Ok(merkle_state.to_bytes())
+    }
+
+    pub(super) fn list_keys(
+        state: &[u8],
+        tree: Option<&str>,
+    ) -> Result<Vec<String>, DatabaseError> {
+        Ok(list_key_bytes(state, tree)?
+            .into_iter()
+            .filter_map(|key| String::from_utf8(key).ok())
+            .collect())
+    }
+
+    pub(super) fn list_key_bytes(
+        state: &[u8],
+        tree: Option<&str>,
+    ) -> Result<Vec<Vec<u8>>, DatabaseError> {
+        let mut merkle_state = MerkleState::from_bytes(state)?;
+        let tree = merkle_state
+            .trees
+            .remove(tree.unwrap_or(ROOT_TREE))
+            .unwrap_or_else(TreeData::new);
+        Ok(tree.key_indices.into_keys().collect())
+    }
+
+    pub(super) fn leaf_hashes(
+        state: &[u8],
+        keys: &[String],
+    ) -> Result<Vec<Option<String>>, DatabaseError> {
+        let merkle_state = MerkleState::from_bytes(state)?;
+        let Some(tree) = merkle_state.tree(ROOT_TREE) else {
+            return Ok(vec![None; keys.len()]);
+        };
+        Ok(keys
+            .iter()
+            .map(|key| tree.value_hash(key.as_bytes()).map(hex::encode))
+            .collect())
+    }
+
+    pub(super) fn check_absence(
+        state: &[u8],
+        data: &serde_json::Value,
+    ) -> Result<(), DatabaseError> {
+        let output: range::AbsenceOutput = serde_json::from_value(data.clone()).map_err(|e| {
+            DatabaseError::QueryExecutionFailed(format!("Invalid absence result format: {}", e))
+        })?;
+        output.check(state_root(state).as_deref())
+    }
+
+    pub(super) fn check_range(
+        state: &[u8],
+        data: &serde_json::Value,
+        key_range: &Range<&str>,
+    ) -> Result<(), DatabaseError> {
+        let output: range::RangeOutput = serde_json::from_value(data.clone()).map_err(|e| {
+            DatabaseError::QueryExecutionFailed(format!("Invalid range result format: {}", e))
+        })?;
+        output.check(state_root(state).as_deref(), key_range)
+    }
+}
+
+#[cfg(feature = "smt")]
+mod smt {
+    use serde::Deserialize;
+    use std::ops::Range;
+    use zkdb_core::merkle::{KeyDerivation, TreeLayout, ROOT_TREE};
+    use zkdb_core::smt::SmtState;
+
+    use crate::{verify, DatabaseError};
+
+    #[cfg(zkdb_embedded_smt)]
+    pub(super) const EMBEDDED_ELF: Option<&[u8]> = Some(include_bytes!(env!("ZKDB_SMT_ELF_PATH")));
+    #[cfg(not(zkdb_embedded_smt))]
+    pub(super) const EMBEDDED_ELF: Option<&[u8]> = None;
+
+    /// Output of `Command::ProveAbsence`.
+    #[derive(Deserialize)]
+    struct AbsenceOutput {
+        key: String,
+        root: Option<String>,
+        proof: String,
+    }
+
+    pub(super) fn state_root(state: &[u8]) -> Option<String> {
+        let smt = SmtState::from_bytes(state).ok()?;
+        (!smt.is_empty()).then(|| hex::encode(smt.root()))
+    }
+
+    /// The engine keeps a single tree.
+    pub(super) fn tree_root(state: &[u8], tree: &str) -> Option<String> {
+        if tree == ROOT_TREE {
+            state_root(state)
+        } else {
+            None
+        }
+    }
+
+    pub(super) fn key_count(state: &[u8]) -> Option<usize> {
+        Some(SmtState::from_bytes(state).ok()?.values.len())
+    }
+
+    /// The root is never cached.
+    pub(super) fn refresh_root(state: Vec<u8>) -> Vec<u8> {
+        state
+    }
+
+    pub(super) fn reserved_prefix(state: &[u8]) -> Result<String, DatabaseError> {
+        Ok(SmtState::from_bytes(state)?.reserved_prefix)
+    }
+
+    pub(super) fn with_reserved_prefix(
+        state: &[u8],
+        prefix: &str,
+    ) -> Result<Vec<u8>, DatabaseError> {
+        let mut smt = SmtState::from_bytes(state)?;
+        smt.reserved_prefix = prefix.to_string();
+        Ok(smt.to_bytes())
+    }
+
+    /// Leaves sit at the hash of their key, which no layout changes.
+    pub(super) fn layout(state: &[u8]) -> Result<TreeLayout, DatabaseError> {
+        SmtState::from_bytes(state)?;
+        Ok(TreeLayout::Append)
+    }
+
+    pub(super) fn with_layout(state: &[u8], layout: TreeLayout) -> Result<Vec<u8>, DatabaseError> {
+        if layout != TreeLayout::Append {
+            return Err(DatabaseError::QueryExecutionFailed(
+                "the sparse Merkle engine has no leaf layouts".to_string(),
+            ));
+        }
+        Ok(SmtState::from_bytes(state)?.to_bytes())
+    }
+
+    pub(super) fn key_derivation(state: &[u8]) -> Result<KeyDerivation, DatabaseError> {
+        SmtState::from_bytes(state)?;
+        Ok(KeyDerivation::Raw)
+    }
+
+    pub(super) fn with_key_derivation(
+        state: &[u8],
+        derivation: KeyDerivation,
+    ) -> Result<Vec<u8>, DatabaseError> {
+        if derivation != KeyDerivation::Raw {
+            return Err(DatabaseError::QueryExecutionFailed(
+                "the sparse Merkle engine stores keys as given".to_string(),
+            ));
+        }
+        Ok(SmtState::from_bytes(state)?.to_bytes())
+    }
+
+    pub(super) fn admin_key(state: &[u8]) -> Result<Option<[u8; 32]>, DatabaseError> {
+        SmtState::from_bytes(state)?;
+        Ok(None)
+    }
+
+    /// Writes are not authorized by the engine.
+    pub(super) fn with_admin_key(_state: &[u8], _key: [u8; 32]) -> Result<Vec<u8>, DatabaseError> {
+        Err(DatabaseError::QueryExecutionFailed(
+            "the sparse Merkle engine does not check writer keys".to_string(),
+        ))
+    }
+
+    /// The state holds hashes of keys, not the keys themselves.
+    pub(super) fn list_keys(
+        _state: &[u8],
+        _tree: Option<&str>,
+    ) -> Result<Vec<String>, DatabaseError> {
+        Err(unlisted())
+    }
+
+    pub(super) fn list_key_bytes(
+        _state: &[u8],
+        _tree: Option<&str>,
+    ) -> Result<Vec<Vec<u8>>, DatabaseError> {
+        Err(unlisted())
+    }
+
+    fn unlisted() -> DatabaseError {
+        DatabaseError::QueryExecutionFailed(
+            "the sparse Merkle engine cannot list keys, it only holds their hashes".to_string(),
+        )
+    }
+
+    pub(super) fn leaf_hashes(
+        state: &[u8],
+        keys: &[String],
+    ) -> Result<Vec<Option<String>>, DatabaseError> {
+        let smt = SmtState::from_bytes(state)?;
+        Ok(keys
+            .iter()
+            .map(|key| smt.get(key.as_bytes()).map(hex::encode))
+            .collect())
+    }
+
+    /// Checks that the empty leaf of the key leads to the root of `state`.
+    pub(super) fn check_absence(
+        state: &[u8],
+        data: &serde_json::Value,
+    ) -> Result<(), DatabaseError> {
+        let failed = |reason: &str| DatabaseError::ProofVerificationFailed(reason.to_string());
+        let codec = |e: verify::VerifyError| DatabaseError::Codec(e.to_string());
+        let output: AbsenceOutput = serde_json::from_value(data.clone()).map_err(|e| {
+            DatabaseError::QueryExecutionFailed(format!("Invalid absence result format: {}", e))
+        })?;
+        let root = state_root(state);
+        if root != output.root {
+            return Err(failed("absence was proven against another root"));
+        }
+        let root = match root {
+            Some(root) => verify::decode_hash("root", &root).map_err(codec)?,
+            // Nothing is in an empty tree.
+            None => return Ok(()),
+        };
+        let proof = base64::decode(&output.proof)
+            .map_err(|e| DatabaseError::Codec(format!("invalid base64 proof: {}", e)))?;
+        if !verify::verify_smt_proof(root, output.key.as_bytes(), None, &proof).map_err(codec)? {
+            return Err(failed("absence proof does not hold"));
+        }
+        Ok(())
+    }
+
+    /// Keys are not ordered, so the guest serves no ranges.
+    pub(super) fn check_range(
+        _state: &[u8],
+        _data: &serde_json::Value,
+        _key_range: &Range<&str>,
+    ) -> Result<(), DatabaseError> {
+        Err(DatabaseError::QueryExecutionFailed(
+            "the sparse Merkle engine cannot read ranges".to_string(),
+        ))
+    }
+}
+
+#[cfg(feature = "iavl")]
+mod iavl {
+    use serde::Deserialize;
+    use std::ops::Range;
+    use zkdb_core::iavl::IavlState;
+    use zkdb_core::merkle::{KeyDerivation, TreeLayout, ROOT_TREE};
+
+    use crate::{verify, DatabaseError};
+
+    #[cfg(zkdb_embedded_iavl)]
+    pub(super) const EMBEDDED_ELF: Option<&[u8]> = Some(include_bytes!(env!("ZKDB_IAVL_ELF_PATH")));
+    #[cfg(not(zkdb_embedded_iavl))]
+    pub(super) const EMBEDDED_ELF: Option<&[u8]> = None;
+
+    /// Output of `Command::ProveAbsence`.
+    #[derive(Deserialize)]
+    struct AbsenceOutput {
+        key: String,
+        root: Option<String>,
+        proof: verify::IavlProof,
+    }
+
+    /// A key of the output of `Command::Range`.
+    #[derive(Deserialize)]
+    struct RangeKey {
+        key: String,
+        binary: bool,
+        value_hash: String,
+        proof: verify::IavlProof,
+    }
+
+    /// Output of `Command::Range`.
+    #[derive(Deserialize)]
+    struct RangeOutput {
+        keys: Vec<RangeKey>,
+        next: Option<String>,
+        root: Option<String>,
+    }
+
+    pub(super) fn state_root(state: &[u8]) -> Option<String> {
+        IavlState::from_bytes(state)
+            .ok()?
+            .root_hash()
+            .map(hex::encode)
+    }
+
+    /// The engine keeps a single tree.
+    pub(super) fn tree_root(state: &[u8], tree: &str) -> Option<String> {
+        if tree == ROOT_TREE {
+            state_root(state)
+        } else {
+            None
+        }
+    }
+
+    pub(super) fn key_count(state: &[u8]) -> Option<usize> {
+        Some(IavlState::from_bytes(state).ok()?.len())
+    }
+
+    /// Every node holds its own hash, so the root is never stale.
+    pub(super) fn refresh_root(state: Vec<u8>) -> Vec<u8> {
+        state
+    }
+
+    pub(super) fn reserved_prefix(state: &[u8]) -> Result<String, DatabaseError> {
+        Ok(IavlState::from_bytes(state)?.reserved_prefix)
+    }
+
+    pub(super) fn with_reserved_prefix(
+        state: &[u8],
+        prefix: &str,
+    ) -> Result<Vec<u8>, DatabaseError> {
+        let mut iavl = IavlState::from_bytes(state)?;
+        iavl.reserved_prefix = prefix.to_string();
+        Ok(iavl.to_bytes())
+    }
+
+    /// Nodes are ordered by key, which no layout changes.
+    pub(super) fn layout(state: &[u8]) -> Result<TreeLayout, DatabaseError> {
+        IavlState::from_bytes(state)?;
+        Ok(TreeLayout::Append)
+    }
+
+    pub(super) fn with_layout(state: &[u8], layout: TreeLayout) -> Result<Vec<u8>, DatabaseError> {
+        if layout != TreeLayout::Append {
+            return Err(DatabaseError::QueryExecutionFailed(
+                "the IAVL engine has no leaf layouts".to_string(),
+            ));
+        }
+        Ok(IavlState::from_bytes(state)?.to_bytes())
+    }
+
+    pub(super) fn key_derivation(state: &[u8]) -> Result<KeyDerivation, DatabaseError> {
+        IavlState::from_bytes(state)?;
+        Ok(KeyDerivation::Raw)
+    }
+
+    pub(super) fn with_key_derivation(
+        state: &[u8],
+        derivation: KeyDerivation,
+    ) -> Result<Vec<u8>, DatabaseError> {
+        if derivation != KeyDerivation::Raw {
+            return Err(DatabaseError::QueryExecutionFailed(
+                "the IAVL engine stores keys as given".to_string(),
+            ));
+        }
+        Ok(IavlState::from_bytes(state)?.to_bytes())
+    }
+
+    pub(super) fn admin_key(state: &[u8]) -> Result<Option<[u8; 32]>, DatabaseError> {
+        IavlState::from_bytes(state)?;
+        Ok(None)
+    }
+
+    /// Writes are not authorized by the engine.
+    pub(super) fn with_admin_key(_state: &[u8], _key: [u8; 32]) -> Result<Vec<u8>, DatabaseError> {
+        Err(DatabaseError::QueryExecutionFailed(
+            "the IAVL engine does not check writer keys".to_string(),
+        ))
+    }
+
+    pub(super) fn list_keys(
+        state: &[u8],
+        tree: Option<&str>,
+    ) -> Result<Vec<String>, DatabaseError> {
+        Ok(list_key_bytes(state, tree)?
+            .into_iter()
+            .filter_map(|key| String::from_utf8(key).ok())
+            .collect())
+    }
+
+    pub(super) fn list_key_bytes(
+        state: &[u8],
+        tree: Option<&str>,
+    ) -> Result<Vec<Vec<u8>>, DatabaseError> {
+        let iavl = IavlState::from_bytes(state)?;
+        if tree.is_some_and(|tree| tree != ROOT_TREE) {
+            return Ok(Vec::new());
+        }
+        Ok(iavl.entries().into_iter().map(|(key, _)| key).collect())
+    }
+
+    pub(super) fn leaf_hashes(
+        state: &[u8],
+        keys: &[String],
+    ) -> Result<Vec<Option<String>>, DatabaseError> {
+        let iavl = IavlState::from_bytes(state)?;
+        Ok(keys
+            .iter()
+            .map(|key| iavl.get(key.as_bytes()).map(hex::encode))
+            .collect())
+    }
+
+    /// Root of `state`, which the output of a command must report.
+    fn checked_root(
+        state: &[u8],
+        reported: Option<&str>,
+    ) -> Result<Option<[u8; 32]>, DatabaseError> {
+        let root = state_root(state);
+        if root.as_deref() != reported {
+            return Err(DatabaseError::ProofVerificationFailed(
+                "proof was made against another root".to_string(),
+            ));
+        }
+        root.map(|root| verify::decode_hash("root", &root))
+            .transpose()
+            .map_err(|e| DatabaseError::Codec(e.to_string()))
+    }
+
+    /// Checks that the search path of the key ends in an empty slot under
+    /// the root of `state`.
+    pub(super) fn check_absence(
+        state: &[u8],
+        data: &serde_json::Value,
+    ) -> Result<(), DatabaseError> {
+        let output: AbsenceOutput = serde_json::from_value(data.clone()).map_err(|e| {
+            DatabaseError::QueryExecutionFailed(format!("Invalid absence result format: {}", e))
+        })?;
+        // Nothing is in an empty tree.
+        let Some(root) = checked_root(state, output.root.as_deref())? else {
+            return Ok(());
+        };
+        let holds = verify::verify_iavl_proof(root, output.key.as_bytes(), None, &output.proof)
+            .map_err(|e| DatabaseError::Codec(e.to_string()))?;
+        if !holds {
+            return Err(DatabaseError::ProofVerificationFailed(
+                "absence proof does not hold".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Checks the proof of every key of the page against the root of
+    /// `state`, and that the keys are ordered and within `key_range`.
+    pub(super) fn check_range(
+        state: &[u8],
+        data: &serde_json::Value,
+        key_range: &Range<&str>,
+    ) -> Result<(), DatabaseError> {
+        let failed = |reason: &str| DatabaseError::ProofVerificationFailed(reason.to_string());
+        let codec = |e: verify::VerifyError| DatabaseError::Codec(e.to_string());
+        let output: RangeOutput = serde_json::from_value(data.clone()).map_err(|e| {
+            DatabaseError::QueryExecutionFailed(format!("Invalid range result format: {}", e))
+        })?;
+        if output.keys.is_empty() {
+            return Ok(());
+        }
+        let root = checked_root(state, output.root.as_deref())?
+            .ok_or_else(|| failed("range has no root"))?;
+        let mut previous: Option<Vec<u8>> = None;
+        for key in &output.keys {
+            let bytes = if key.binary {
+                hex::decode(&key.key)
+                    .map_err(|e| DatabaseError::Codec(format!("invalid binary key: {}", e)))?
+            } else {
+                key.key.as_bytes().to_vec()
+            };
+            if bytes.as_slice() < key_range.start.as_bytes()
+                || bytes.as_slice() >= key_range.end.as_bytes()
+                || previous.as_ref().is_some_and(|previous| *previous >= bytes)
+            {
+                return Err(failed("range keys are out of order"));
+            }
+            let value_hash = verify::decode_hash("value_hash", &key.value_hash).map_err(codec)?;
+            if !verify::verify_iavl_proof(root, &bytes, Some(&value_hash), &key.proof)
+                .map_err(codec)?
+            {
+                return Err(failed("range proof does not hold"));
+            }
+            previous = Some(bytes);
+        }
+        if let (Some(next), Some(last)) = (&output.next, &previous) {
+            if next.as_bytes() <= last.as_slice() {
+                return Err(failed("range keys are out of order"));
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,356 @@
+//! Static cost estimates for commands, computed without running the zkVM.
+
+use serde::{Deserialize, Serialize};
+use std::ops::Bound;
+use zkdb_core::merkle::{MerkleState, TreeLayout, ROOT_TREE};
+use zkdb_core::{display_key, Command};
+
+/// Describes the work a command will trigger inside the Merkle engine.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ExplanationReport {
+    /// Human readable summary of the operations the engine will perform.
+    pub description: String,
+    /// Number of leaves the engine will read or write.
+    pub estimated_leaves_affected: usize,
+    /// Whether the engine rebuilds the whole tree from its leaves.
+    pub requires_tree_rebuild: bool,
+    /// Whether the command changes the state.
+    pub is_mutating: bool,
+}
+
+/// Explains `command` against a serialized state.
+pub(crate) fn explain(state: &[u8], command: &Command) -> ExplanationReport {
+    if let Command::Script { commands } = command {
+        let reports: Vec<_> = commands
+            .iter()
+            .map(|command| explain(state, command))
+            .collect();
+        let steps: Vec<_> = reports
+            .iter()
+            .enumerate()
+            .map(|(index, report)| format!("[{}] {}", index, report.description))
+            .collect();
+        return ExplanationReport {
+            description: format!(
+                "Runs {} commands in order in one execution, each explained against the current state: {}",
+                commands.len(),
+                steps.join(" ")
+            ),
+            estimated_leaves_affected: reports
+                .iter()
+                .map(|report| report.estimated_leaves_affected)
+                .sum(),
+            requires_tree_rebuild: reports.iter().any(|report| report.requires_tree_rebuild),
+            is_mutating: command.is_mutating(),
+        };
+    }
+    let (mut merkle_state, state_note) = match MerkleState::from_bytes(state) {
+        Ok(merkle_state) => (merkle_state, String::new()),
+        Err(e) => (
+            MerkleState::new(),
+            format!(
+                " The current state cannot be decoded ({:?}), so the command will fail.",
+                e
+            ),
+        ),
+    };
+    let (name, scoped) = match command {
+        Command::InTree { tree, command } => (tree.as_str(), command.as_ref()),
+        command => (ROOT_TREE, command),
+    };
+    let sorted = merkle_state.layout == TreeLayout::Sorted;
+    let tree = merkle_state.trees.remove(name).unwrap_or_default();
+    let leaf_count = tree.leaves.len();
+    let deserialize_note = if state.is_empty() {
+        "The state is empty, so no deserialization is needed.".to_string()
+    } else {
+        format!("Deserializes {} bytes of state.", state.len())
+    };
+
+    // Byte-key commands are explained as their string counterparts.
+    let key_bytes = scoped.key_bytes().unwrap_or_default();
+    let key = display_key(key_bytes);
+    let (summary, estimated_leaves_affected, requires_tree_rebuild) = match scoped {
+        Command::Query { .. } | Command::QueryBytes { .. } => match tree.key_indices.get(key_bytes) {
+            Some(index) => (
+                format!("Reads leaf {} for key '{}' without hashing.", index, key),
+                1,
+                false,
+            ),
+            None => (
+                format!("Looks up key '{}', which is not in the tree.", key),
+                0,
+                false,
+            ),
+        },
+        Command::Prove { .. } | Command::ProveBytes { .. } => {
+            let found = tree.key_indices.contains_key(key_bytes);
+            (
+                format!(
+                    "Rebuilds the tree from {} leaves (about {} hashes) and serializes an inclusion proof for key '{}'{}.",
+                    leaf_count,
+                    leaf_count.saturating_sub(1),
+                    key,
+                    if found { "" } else { ", which is not in the tree" }
+                ),
+                leaf_count,
+                true,
+            )
+        }
+        Command::Insert { .. } | Command::InsertBytes { .. } | Command::Replace { .. } => {
+            let action = if tree.key_indices.contains_key(key_bytes) {
+                "Appends a new leaf for existing key"
+            } else {
+                "Appends the first leaf for key"
+            };
+            (
+                format!(
+                    "{} '{}' and reserializes the state with {} leaves.",
+                    action,
+                    key,
+                    leaf_count + 1
+                ),
+                1,
+                false,
+            )
+        }
+        Command::History { key } => {
+            let entries = tree.history.get(key.as_bytes()).map_or(0, Vec::len);
+            (
+                format!(
+                    "Reads {} history entries for key '{}' without hashing.",
+                    entries, key
+                ),
+                0,
+                false,
+            )
+        }
+        Command::Inspect { key } => (
+            format!(
+                "Rebuilds the tree from {} leaves (about {} hashes) to collect the path of key '{}'.",
+                leaf_count,
+                leaf_count.saturating_sub(1),
+                key
+            ),
+            leaf_count,
+            true,
+        ),
+        Command::GetRoot => (
+            format!(
+                "Rebuilds the tree from {} leaves (about {} hashes) to compute the root.",
+                leaf_count,
+                leaf_count.saturating_sub(1)
+            ),
+            leaf_count,
+            true,
+        ),
+        Command::MultiQuery { keys } => {
+            let found = keys
+                .iter()
+                .filter(|key| tree.key_indices.contains_key(key.as_bytes()))
+                .count();
+            (
+                format!(
+                    "Reads the leaves of the {} of {} keys in the tree without hashing.",
+                    found,
+                    keys.len()
+                ),
+                found,
+                false,
+            )
+        }
+        Command::MultiProve { keys } => (
+            format!(
+                "Rebuilds the tree from {} leaves (about {} hashes) once and serializes proofs for {} keys.",
+                leaf_count,
+                leaf_count.saturating_sub(1),
+                keys.len()
+            ),
+            leaf_count,
+            true,
+        ),
+        Command::BatchInsert { entries } => (
+            format!(
+                "Appends {} leaves and reserializes the state once with {} leaves.",
+                entries.len(),
+                leaf_count + entries.len()
+            ),
+            entries.len(),
+            false,
+        ),
+        Command::ProveRange { start, end } => {
+            // An inverted range makes `BTreeMap::range` panic; the engine rejects it.
+            let keys = if start < end {
+                tree
+                    .key_indices
+                    .range::<[u8], _>((Bound::Included(start.as_bytes()), Bound::Excluded(end.as_bytes())))
+                    .count()
+            } else {
+                0
+            };
+            (
+                format!(
+                    "Rebuilds the tree from {} leaves (about {} hashes) once and serializes a multiproof for the {} keys in ['{}', '{}').",
+                    leaf_count,
+                    leaf_count.saturating_sub(1),
+                    keys,
+                    start,
+                    end
+                ),
+                leaf_count,
+                true,
+            )
+        }
+        Command::Range { start, end, limit } => {
+            let keys = if start < end {
+                tree
+                    .key_indices
+                    .range::<[u8], _>((Bound::Included(start.as_bytes()), Bound::Excluded(end.as_bytes())))
+                    .take(*limit)
+                    .count()
+            } else {
+                0
+            };
+            // A sorted page proves the gap it found, even when empty.
+            if keys == 0 && !sorted {
+                (
+                    format!("Finds no keys in ['{}', '{}') and returns an empty page without hashing.", start, end),
+                    0,
+                    false,
+                )
+            } else {
+                (
+                    format!(
+                        "Rebuilds the tree from {} leaves (about {} hashes) once and serializes a multiproof for a page of {} keys in ['{}', '{}').",
+                        leaf_count,
+                        leaf_count.saturating_sub(1),
+                        keys,
+                        start,
+                        end
+                    ),
+                    leaf_count,
+                    true,
+                )
+            }
+        }
+        Command::ProveAbsence { key } if sorted && leaf_count > 0 => (
+            format!(
+                "Rebuilds the tree from {} leaves (about {} hashes) once and serializes a multiproof for the keys around '{}'.",
+                leaf_count,
+                leaf_count.saturating_sub(1),
+                key
+            ),
+            leaf_count,
+            true,
+        ),
+        Command::ProveAbsence { key } if sorted => (
+            format!("Finds the tree empty, so key '{}' is absent without hashing.", key),
+            0,
+            false,
+        ),
+        Command::ProveAbsence { key } => (
+            format!(
+                "Rejects proving key '{}' absent, since the state is not laid out sorted.",
+                key
+            ),
+            0,
+            false,
+        ),
+        Command::ProofSize { key } => (
+            format!(
+                "Counts the siblings of key '{}' from the layer sizes of a tree with {} leaves, without hashing.",
+                key, leaf_count
+            ),
+            0,
+            false,
+        ),
+        Command::Clear => (
+            format!(
+                "Drops all {} leaves and {} keys and serializes an empty state.",
+                leaf_count,
+                tree.key_indices.len()
+            ),
+            leaf_count,
+            false,
+        ),
+        Command::Compact => (
+            format!(
+                "Drops the {} of {} leaves no key references and reserializes the state.",
+                leaf_count - tree.key_indices.len(),
+                leaf_count
+            ),
+            0,
+            false,
+        ),
+        Command::BatchDelete { keys } => (
+            format!(
+                "Zeroes the leaves of {} keys and reserializes the state once.",
+                keys.len()
+            ),
+            keys.len(),
+            false,
+        ),
+        Command::InTree { .. } => (
+            "Nests tree scopes, which the engine rejects.".to_string(),
+            0,
+            false,
+        ),
+        Command::Script { .. } => (
+            "Scopes a script to a tree, which the engine rejects.".to_string(),
+            0,
+            false,
+        ),
+        Command::RootAt { version } => (
+            match tree.root_at(*version) {
+                Some(_) => format!("Reads the recorded root of version {} without hashing.", version),
+                None => format!(
+                    "Looks up version {}, which is not among the {} recorded versions.",
+                    version,
+                    tree.roots.len()
+                ),
+            },
+            0,
+            false,
+        ),
+        Command::Delete { .. } | Command::DeleteBytes { .. } => match tree.key_indices.get(key_bytes) {
+            Some(index) => (
+                format!(
+                    "Zeroes leaf {} for key '{}' and reserializes the state.",
+                    index, key
+                ),
+                1,
+                false,
+            ),
+            None => (
+                format!("Looks up key '{}', which is not in the tree.", key),
+                0,
+                false,
+            ),
+        },
+    };
+
+    // Every mutation but `Clear` ends by recording the new root.
+    let (summary, requires_tree_rebuild) =
+        if scoped.is_mutating() && !matches!(scoped, Command::Clear) {
+            (
+                format!(
+                    "{} Then rebuilds the tree to record its root as version {}.",
+                    summary,
+                    tree.latest_version() + 1
+                ),
+                true,
+            )
+        } else {
+            (summary, requires_tree_rebuild)
+        };
+    let summary = match command.tree() {
+        Some(name) => format!("In tree '{}': {}", name, summary),
+        None => summary,
+    };
+    ExplanationReport {
+        description: format!("{} {}{}", summary, deserialize_note, state_note),
+        estimated_leaves_affected,
+        requires_tree_rebuild,
+        is_mutating: command.is_mutating(),
+    }
+}
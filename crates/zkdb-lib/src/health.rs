@@ -0,0 +1,87 @@
+//! Liveness report of a database, see `Database::healthcheck`.
+
+use serde::{Deserialize, Serialize};
+use std::time::Instant;
+
+use crate::DatabaseError;
+
+/// Key `Database::healthcheck` looks up to reach the store. It is never
+/// written.
+pub const HEALTHCHECK_KEY: &str = "__healthcheck__";
+
+/// How well a component works.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Health {
+    Ok,
+    /// Works, but not fully: an executor that can run commands but not
+    /// prove them.
+    Degraded,
+    Down,
+}
+
+/// Outcome of checking one component.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ComponentStatus {
+    pub status: Health,
+    /// Time the check took.
+    pub latency_ms: u64,
+    /// Why the component is not `Ok`.
+    pub error: Option<String>,
+}
+
+impl ComponentStatus {
+    /// Runs `check`, reporting the component down if it fails.
+    pub(crate) fn measure<F>(check: F) -> Self
+    where
+        F: FnOnce() -> Result<Health, DatabaseError>,
+    {
+        let started = Instant::now();
+        Self::from_outcome(started, check())
+    }
+
+    /// `measure` for a check that awaits.
+    pub(crate) async fn measure_async<F>(check: F) -> Self
+    where
+        F: std::future::Future<Output = Result<Health, DatabaseError>>,
+    {
+        let started = Instant::now();
+        Self::from_outcome(started, check.await)
+    }
+
+    fn from_outcome(started: Instant, outcome: Result<Health, DatabaseError>) -> Self {
+        let latency_ms = started.elapsed().as_millis() as u64;
+        match outcome {
+            Ok(status) => ComponentStatus {
+                status,
+                latency_ms,
+                error: None,
+            },
+            Err(e) => ComponentStatus {
+                status: Health::Down,
+                latency_ms,
+                error: Some(e.to_string()),
+            },
+        }
+    }
+}
+
+/// Outcome of `Database::healthcheck`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HealthStatus {
+    /// Reaching the value store.
+    pub store: ComponentStatus,
+    /// Running a command in the zkVM.
+    pub executor: ComponentStatus,
+    /// Decoding the current state.
+    pub state: ComponentStatus,
+}
+
+impl HealthStatus {
+    /// Whether every component is `Ok`.
+    pub fn is_ok(&self) -> bool {
+        [&self.store, &self.executor, &self.state]
+            .iter()
+            .all(|component| component.status == Health::Ok)
+    }
+}
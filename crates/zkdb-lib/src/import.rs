@@ -0,0 +1,38 @@
+//! Parsing of newline-delimited JSON for bulk imports.
+
+use serde::{Deserialize, Serialize};
+
+/// Outcome of `Database::import_json_lines` and
+/// `Database::import_rocksdb_backup`.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ImportReport {
+    /// Number of entries whose value was stored and inserted into the tree.
+    pub imported: usize,
+    /// Number of entries left out: lines that could not be parsed, or keys
+    /// that were invalid or already held.
+    pub skipped: usize,
+    /// Why each skipped entry was left out, prefixed with its line number
+    /// or key.
+    pub errors: Vec<String>,
+}
+
+/// Extracts the key and base64-decoded value from one NDJSON line.
+pub(crate) fn parse_line(
+    line: &str,
+    key_field: &str,
+    value_field: &str,
+) -> Result<(String, Vec<u8>), String> {
+    let object: serde_json::Value =
+        serde_json::from_str(line).map_err(|e| format!("invalid JSON: {}", e))?;
+    let key = object
+        .get(key_field)
+        .and_then(serde_json::Value::as_str)
+        .ok_or_else(|| format!("missing string field '{}'", key_field))?;
+    let value = object
+        .get(value_field)
+        .and_then(serde_json::Value::as_str)
+        .ok_or_else(|| format!("missing string field '{}'", value_field))?;
+    let value = base64::decode(value)
+        .map_err(|e| format!("field '{}' is not valid base64: {}", value_field, e))?;
+    Ok((key.to_string(), value))
+}
@@ -0,0 +1,194 @@
+//! Reverse index from value hashes to keys, kept in the `Store` under
+//! `_idx/hash/`.
+//!
+//! `_idx/hash/<hash>` holds the sorted keys of the root tree whose leaf is
+//! `hash`, as a JSON array. The index follows the leaves rather than the
+//! stored values, so it is updated from the commands committed and can be
+//! rebuilt from the state alone.
+
+use std::collections::{BTreeMap, HashMap};
+use zkdb_core::Command;
+use zkdb_store::{Store, StoreError};
+
+use crate::{DatabaseError, EngineSpec};
+
+/// Prefix of every key the index writes to the store.
+pub const INDEX_PREFIX: &str = "_idx/hash/";
+
+fn index_key(value_hash: &str) -> String {
+    format!("{}{}", INDEX_PREFIX, value_hash)
+}
+
+/// A key whose leaf changes from `old` to `new`.
+pub(crate) struct IndexChange {
+    key: String,
+    old: Option<String>,
+    new: Option<String>,
+}
+
+/// Reads the keys indexed under the hex-encoded `value_hash`.
+pub(crate) async fn keys(
+    store: &dyn Store,
+    value_hash: &str,
+) -> Result<Vec<String>, DatabaseError> {
+    match store.get(&index_key(value_hash)).await {
+        Ok(bytes) => serde_json::from_slice(&bytes)
+            .map_err(|e| DatabaseError::Codec(format!("failed to decode index entry: {}", e))),
+        Err(StoreError::NotFound(_)) => Ok(Vec::new()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+async fn write(store: &dyn Store, value_hash: &str, keys: &[String]) -> Result<(), DatabaseError> {
+    if keys.is_empty() {
+        return match store.delete(&index_key(value_hash)).await {
+            Ok(()) | Err(StoreError::NotFound(_)) => Ok(()),
+            Err(e) => Err(e.into()),
+        };
+    }
+    let encoded = serde_json::to_vec(keys)
+        .map_err(|e| DatabaseError::Codec(format!("failed to encode index entry: {}", e)))?;
+    store.put(&index_key(value_hash), &encoded).await?;
+    Ok(())
+}
+
+/// Works out how committing `command` against `state`, leaving
+/// `new_state`, changes the leaves of the root tree. Commands scoped to a
+/// named tree change none.
+pub(crate) fn changes(
+    spec: &EngineSpec,
+    state: &[u8],
+    new_state: &[u8],
+    command: &Command,
+) -> Result<Vec<IndexChange>, DatabaseError> {
+    let writes: Vec<(&str, Option<&str>)> = match command {
+        Command::Insert { key, value } | Command::Replace { key, value } => {
+            vec![(key, Some(value))]
+        }
+        Command::BatchInsert { entries } => entries
+            .iter()
+            .map(|(key, value)| (key.as_str(), Some(value.as_str())))
+            .collect(),
+        Command::Delete { key } => vec![(key, None)],
+        Command::BatchDelete { keys } => keys.iter().map(|key| (key.as_str(), None)).collect(),
+        Command::Clear => {
+            let keys = (spec.list_keys)(state, None)?;
+            let old = (spec.leaf_hashes)(state, &keys)?;
+            return Ok(keys
+                .into_iter()
+                .zip(old)
+                .map(|(key, old)| IndexChange {
+                    key,
+                    old,
+                    new: None,
+                })
+                .collect());
+        }
+        // Scripts may mix any commands, so compare the states they leave.
+        Command::Script { .. } => {
+            let mut keys = (spec.list_keys)(state, None)?;
+            keys.extend((spec.list_keys)(new_state, None)?);
+            keys.sort();
+            keys.dedup();
+            let old = (spec.leaf_hashes)(state, &keys)?;
+            let new = (spec.leaf_hashes)(new_state, &keys)?;
+            return Ok(keys
+                .into_iter()
+                .zip(old.into_iter().zip(new))
+                .filter(|(_, (old, new))| old != new)
+                .map(|(key, (old, new))| IndexChange { key, old, new })
+                .collect());
+        }
+        _ => return Ok(Vec::new()),
+    };
+
+    let keys: Vec<String> = writes.iter().map(|(key, _)| key.to_string()).collect();
+    let mut current: HashMap<&str, Option<String>> = HashMap::new();
+    for ((key, _), old) in writes.iter().zip((spec.leaf_hashes)(state, &keys)?) {
+        current.entry(*key).or_insert(old);
+    }
+    // Batches may write a key more than once; each write replaces the last.
+    let mut changes = Vec::new();
+    for (key, new) in writes {
+        let new = new.map(str::to_string);
+        let old = current.insert(key, new.clone()).flatten();
+        if old != new {
+            changes.push(IndexChange {
+                key: key.to_string(),
+                old,
+                new,
+            });
+        }
+    }
+    Ok(changes)
+}
+
+/// Moves each changed key to the entry of its new leaf.
+pub(crate) async fn apply(
+    store: &dyn Store,
+    changes: Vec<IndexChange>,
+) -> Result<(), DatabaseError> {
+    for change in changes {
+        if let Some(old) = &change.old {
+            let mut keys = keys(store, old).await?;
+            keys.retain(|key| *key != change.key);
+            write(store, old, &keys).await?;
+        }
+        if let Some(new) = &change.new {
+            let mut keys = keys(store, new).await?;
+            if let Err(position) = keys.binary_search(&change.key) {
+                keys.insert(position, change.key.clone());
+                write(store, new, &keys).await?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// The index `state` calls for, by value hash.
+pub(crate) fn expected(
+    spec: &EngineSpec,
+    state: &[u8],
+) -> Result<BTreeMap<String, Vec<String>>, DatabaseError> {
+    let keys = (spec.list_keys)(state, None)?;
+    let hashes = (spec.leaf_hashes)(state, &keys)?;
+    let mut index: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    // `list_keys` is sorted, so every entry comes out sorted too.
+    for (key, value_hash) in keys.into_iter().zip(hashes) {
+        if let Some(value_hash) = value_hash {
+            index.entry(value_hash).or_default().push(key);
+        }
+    }
+    Ok(index)
+}
+
+/// Reads every entry of the index, by value hash.
+pub(crate) async fn stored(
+    store: &dyn Store,
+) -> Result<BTreeMap<String, Vec<String>>, DatabaseError> {
+    let mut index = BTreeMap::new();
+    for store_key in store.keys_with_prefix(INDEX_PREFIX).await? {
+        let value_hash = &store_key[INDEX_PREFIX.len()..];
+        index.insert(value_hash.to_string(), keys(store, value_hash).await?);
+    }
+    Ok(index)
+}
+
+/// Replaces the whole index with `index`.
+pub(crate) async fn replace(
+    store: &dyn Store,
+    index: &BTreeMap<String, Vec<String>>,
+) -> Result<(), DatabaseError> {
+    for store_key in store.keys_with_prefix(INDEX_PREFIX).await? {
+        if !index.contains_key(&store_key[INDEX_PREFIX.len()..]) {
+            match store.delete(&store_key).await {
+                Ok(()) | Err(StoreError::NotFound(_)) => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+    for (value_hash, keys) in index {
+        write(store, value_hash, keys).await?;
+    }
+    Ok(())
+}
@@ -0,0 +1,62 @@
+//! Proving and verifying keys of a guest program kept in a directory, so
+//! that restarts skip the setup, see `SP1Executor::with_pk_vk_cache`.
+//!
+//! The directory holds the bincode-encoded keys in `PK_FILE` and `VK_FILE`,
+//! and the hex-encoded SHA-256 of the program they were set up for in
+//! `ELF_HASH_FILE`, so that keys of another build are never loaded.
+
+use serde::de::DeserializeOwned;
+use sp1_sdk::{SP1ProvingKey, SP1VerifyingKey};
+use std::fs;
+use std::io::ErrorKind;
+use std::path::Path;
+use zkdb_store::StoreError;
+
+use crate::DatabaseError;
+
+const PK_FILE: &str = "pk.bin";
+const VK_FILE: &str = "vk.bin";
+const ELF_HASH_FILE: &str = "elf.sha256";
+
+/// The keys cached in `dir` for the program hashing to `elf_hash`, `None`
+/// if there are none, they are for another program or fail to decode.
+pub(crate) fn load(dir: &Path, elf_hash: &str) -> Option<(SP1ProvingKey, SP1VerifyingKey)> {
+    if fs::read_to_string(dir.join(ELF_HASH_FILE)).ok()? != elf_hash {
+        return None;
+    }
+    Some((decode(&dir.join(PK_FILE))?, decode(&dir.join(VK_FILE))?))
+}
+
+fn decode<T: DeserializeOwned>(path: &Path) -> Option<T> {
+    bincode::deserialize(&fs::read(path).ok()?).ok()
+}
+
+/// Caches `pk` and `vk` in `dir`, creating it if needed. The program hash
+/// is written last, so that keys only partly written are never loaded.
+pub(crate) fn save(
+    dir: &Path,
+    elf_hash: &str,
+    pk: &SP1ProvingKey,
+    vk: &SP1VerifyingKey,
+) -> Result<(), DatabaseError> {
+    let io = |e: std::io::Error| DatabaseError::Store(StoreError::from(e));
+    let codec = |e: bincode::Error| DatabaseError::Codec(format!("failed to encode keys: {}", e));
+    invalidate(dir)?;
+    fs::create_dir_all(dir).map_err(io)?;
+    fs::write(dir.join(PK_FILE), bincode::serialize(pk).map_err(codec)?).map_err(io)?;
+    fs::write(dir.join(VK_FILE), bincode::serialize(vk).map_err(codec)?).map_err(io)?;
+    fs::write(dir.join(ELF_HASH_FILE), elf_hash).map_err(io)
+}
+
+/// Removes the keys cached in `dir`, if any.
+pub(crate) fn invalidate(dir: &Path) -> Result<(), DatabaseError> {
+    for file in [ELF_HASH_FILE, PK_FILE, VK_FILE] {
+        match fs::remove_file(dir.join(file)) {
+            Err(e) if e.kind() != ErrorKind::NotFound => {
+                return Err(DatabaseError::Store(StoreError::from(e)))
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
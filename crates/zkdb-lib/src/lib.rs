@@ -1,30 +1,179 @@
-use sha2::{Digest, Sha256};
+use rayon::prelude::*;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
 use sp1_sdk::{
-    HashableKey, ProverClient, SP1ProofWithPublicValues, SP1ProvingKey, SP1PublicValues, SP1Stdin,
-    SP1VerifyingKey,
+    ExecutionReport, HashableKey, ProverClient, SP1Proof, SP1ProofWithPublicValues, SP1ProvingKey,
+    SP1PublicValues, SP1Stdin, SP1VerifyingKey,
 };
-use std::env;
+use std::borrow::Cow;
+use std::collections::HashMap;
 use std::fs;
-use std::path::Path;
-use std::sync::Arc;
+use std::future::Future;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, Instant};
 use thiserror::Error;
-use tracing::{debug, error, instrument};
-use zkdb_store::{Store, StoreError};
+use tokio::io::{AsyncBufRead, AsyncBufReadExt};
+use tokio::sync::{broadcast, watch};
+use tracing::{debug, error, info, instrument, warn};
+use zkdb_store::{CompactionStats, Store, StoreError};
+
+mod builder;
+mod bundle;
+mod clock;
+mod codec;
+mod concurrent;
+pub mod deadline;
+mod encryption;
+mod engine;
+mod explain;
+mod health;
+mod import;
+mod index;
+mod key_cache;
+mod limits;
+mod logging;
+mod merge;
+mod meta;
+pub mod metrics;
+mod migrate;
+mod notify;
+mod prefetch;
+mod proof_cache;
+mod range;
+mod reserved;
+mod results;
+mod roots;
+mod signing;
+mod solidity;
+mod throttle;
+mod tree;
+mod versions;
+mod wal;
+
+pub use builder::DatabaseBuilder;
+pub use bundle::ProofBundle;
+pub use clock::{Clock, SystemClock};
+pub use codec::Codec;
+pub use concurrent::ConcurrentDatabase;
+pub use encryption::{CommitTo, EncryptionConfig};
+pub use engine::{
+    get_elf_for, EngineSpec, LeafHashesFn, ListKeyBytesFn, ListKeysFn, ELF_RUNTIME_PATH_ENV,
+};
+pub use explain::ExplanationReport;
+pub use health::{ComponentStatus, Health, HealthStatus, HEALTHCHECK_KEY};
+pub use import::ImportReport;
+pub use index::INDEX_PREFIX;
+pub use limits::DatabaseLimits;
+pub use logging::{build_subscriber, init_tracing, LogFormat};
+pub use merge::{ConflictPolicy, MergeReport};
+pub use meta::{Metadata, META_PREFIX};
+pub use migrate::{migrate_state, MigrationReport, MigrationV0toV1, StateVersion};
+
+use notify::StateNotifier;
+pub use notify::{StateChange, STATE_CHANGE_CHANNEL_CAPACITY};
+use prefetch::ValueCache;
+use proof_cache::ProofCache;
+pub use range::{RangeEntry, RangePage};
+pub use results::{
+    BatchInsertResult, CompactResult, DeleteResult, InsertResult, MultiQueryEntry, ProveResult,
+    QueryHit, ReplaceResult, RootResult,
+};
+pub use roots::RootEntry;
+pub use signing::{signature_path, Signer, Writer};
+pub use solidity::SolidityProof;
+use throttle::{ProofLimiter, ProofPermit};
+pub use tree::{Tree, TREE_PREFIX};
+pub use versions::{KeyVersion, HISTORY_PREFIX};
+pub use wal::{HistoricalValue, RecoveryReport, ReplayReport, WalEntry, WAL_PREFIX};
 
 // reexport zkdb_core
-pub use zkdb_core::{Command, QueryResult};
+pub use zkdb_core::merkle::{KeyDerivation, MerkleConfig, TreeLayout};
+pub use zkdb_core::{
+    display_key, Command, HistoryEntry, OperationContext, OutputFormat, QueryResult,
+    DEFAULT_RESERVED_PREFIX, MAX_ROOT_VERSIONS,
+};
+// reexport the verification rules shared with non-host clients
+pub use zkdb_verify as verify;
 
 #[derive(Debug, Clone)]
 pub enum DatabaseType {
+    #[cfg(feature = "merkle")]
     Merkle,
+    /// A sparse Merkle tree over the hashes of keys, see `zkdb_core::smt`.
+    /// It proves any key absent, but cannot list keys, scope commands to
+    /// named trees, read ranges or keep history.
+    #[cfg(feature = "smt")]
+    SparseMerkle,
+    /// A balanced binary search tree ordered by key, see `zkdb_core::iavl`.
+    /// Every node commits to its key, so it lists keys, reads ranges and
+    /// proves keys absent, but cannot scope commands to named trees or keep
+    /// history.
+    #[cfg(feature = "iavl")]
+    Iavl,
 }
 
 pub struct Database {
     #[allow(dead_code)]
     engine: DatabaseType,
+    spec: EngineSpec,
     store: Arc<dyn Store>,
     state: Vec<u8>,
     executor: SP1Executor,
+    notifier: StateNotifier,
+    cache: ValueCache,
+    proofs: ProofCache,
+    codec: Codec,
+    operation_log: bool,
+    key_history: bool,
+    history_retention: Option<usize>,
+    clock: Arc<dyn Clock>,
+    root_history: Option<PathBuf>,
+    value_index: bool,
+    read_only: bool,
+    limits: DatabaseLimits,
+    /// Store the bookkeeping is written through, see `reserved`.
+    reserved: Arc<dyn Store>,
+    reserved_prefix: String,
+    encryption: Option<EncryptionConfig>,
+    signer: Option<Arc<dyn Signer>>,
+    verifying_key: Option<[u8; 32]>,
+    require_signatures: bool,
+}
+
+/// A clone shares the SP1 prover and keys, and the store, with the original
+/// but has its own state: mutations of one leave the root of the other
+/// unchanged. It starts with empty caches and no state change subscribers.
+impl Clone for Database {
+    fn clone(&self) -> Self {
+        Database {
+            engine: self.engine.clone(),
+            spec: self.spec,
+            store: self.store.clone(),
+            state: self.state.clone(),
+            executor: self.executor.clone(),
+            notifier: StateNotifier::new(),
+            cache: ValueCache::new(self.cache.capacity()),
+            proofs: ProofCache::new(self.proofs.capacity()),
+            codec: self.codec,
+            operation_log: self.operation_log,
+            key_history: self.key_history,
+            history_retention: self.history_retention,
+            clock: self.clock.clone(),
+            root_history: self.root_history.clone(),
+            value_index: self.value_index,
+            read_only: self.read_only,
+            limits: self.limits,
+            reserved: self.reserved.clone(),
+            reserved_prefix: self.reserved_prefix.clone(),
+            encryption: self.encryption.clone(),
+            signer: self.signer.clone(),
+            verifying_key: self.verifying_key,
+            require_signatures: self.require_signatures,
+        }
+    }
 }
 
 #[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
@@ -32,11 +181,17 @@ pub struct ProvenQueryResult {
     pub data: serde_json::Value,
     pub new_state: Vec<u8>,
     pub sp1_proof: Option<ProvenOutput>,
+    /// Instructions the zkVM executed for the command.
+    #[serde(default)]
+    pub cycles: u64,
 }
 
+/// Returns the embedded Merkle guest program.
+///
+/// Panics if the crate was built without one; see `EngineSpec::load_elf`.
+#[cfg(feature = "merkle")]
 pub fn get_elf() -> &'static [u8] {
-    debug!("Loading ELF binary from {}", env!("ZKDB_ELF_PATH"));
-    include_bytes!(env!("ZKDB_ELF_PATH"))
+    get_elf_for(&DatabaseType::Merkle).expect("zkdb-lib was built without an embedded Merkle ELF")
 }
 
 impl Database {
@@ -48,15 +203,149 @@ impl Database {
         state: Option<Vec<u8>>,
     ) -> Result<Self, DatabaseError> {
         debug!("Creating new Database instance");
-        let elf = get_elf();
-        debug!("Loaded ELF binary, size: {} bytes", elf.len());
+        let spec = engine.spec();
+        let elf = spec.load_elf()?;
+        Ok(Self::with_elf(engine, spec, store, state, elf))
+    }
+
+    /// Creates a database running the guest program at `path` instead of
+    /// the embedded one. The program must match the hash recorded at build
+    /// time.
+    #[instrument(skip(store, path))]
+    pub async fn with_elf_path(
+        engine: DatabaseType,
+        store: Arc<dyn Store>,
+        state: Option<Vec<u8>>,
+        path: impl AsRef<Path>,
+    ) -> Result<Self, DatabaseError> {
+        let spec = engine.spec();
+        let elf = spec.load_elf_from(path)?;
+        Ok(Self::with_elf(engine, spec, store, state, Cow::Owned(elf)))
+    }
 
-        Ok(Database {
+    fn with_elf(
+        engine: DatabaseType,
+        spec: EngineSpec,
+        store: Arc<dyn Store>,
+        state: Option<Vec<u8>>,
+        elf: Cow<'static, [u8]>,
+    ) -> Self {
+        debug!("Loaded {} ELF binary, size: {} bytes", spec.name, elf.len());
+        let mut db = Database {
             engine,
+            spec,
+            reserved: store.clone(),
             store,
             state: state.unwrap_or_default(),
-            executor: SP1Executor::new(elf),
-        })
+            executor: SP1Executor::from_elf(elf),
+            notifier: StateNotifier::new(),
+            cache: ValueCache::new(0),
+            proofs: ProofCache::new(0),
+            codec: Codec::default(),
+            operation_log: false,
+            key_history: false,
+            history_retention: None,
+            clock: Arc::new(SystemClock),
+            root_history: None,
+            value_index: false,
+            read_only: false,
+            limits: DatabaseLimits::default(),
+            reserved_prefix: DEFAULT_RESERVED_PREFIX.to_string(),
+            encryption: None,
+            signer: None,
+            verifying_key: None,
+            require_signatures: false,
+        };
+        db.adopt_reserved_prefix();
+        db
+    }
+
+    /// Writes the bookkeeping under the reserved prefix recorded in the
+    /// state. A state that cannot be decoded keeps the default; it fails as
+    /// soon as it reaches the zkVM.
+    fn adopt_reserved_prefix(&mut self) {
+        self.reserved_prefix = (self.spec.reserved_prefix)(&self.state)
+            .unwrap_or_else(|_| DEFAULT_RESERVED_PREFIX.to_string());
+        self.reserved = reserved::store_for(&self.store, &self.reserved_prefix);
+    }
+
+    /// Layout of the leaves of the tree, `TreeLayout::Append` unless the
+    /// database was created with `DatabaseBuilder::merkle_config`.
+    pub fn layout(&self) -> TreeLayout {
+        (self.spec.layout)(&self.state).unwrap_or_default()
+    }
+
+    /// How keys are stored in the tree, `KeyDerivation::Raw` unless the
+    /// database was created with `DatabaseBuilder::merkle_config`.
+    pub fn key_derivation(&self) -> KeyDerivation {
+        (self.spec.key_derivation)(&self.state).unwrap_or_default()
+    }
+
+    /// Prefix of the keys kept for bookkeeping, which `put` rejects.
+    /// `DEFAULT_RESERVED_PREFIX` unless the database was created
+    /// with `DatabaseBuilder::reserved_prefix`.
+    pub fn reserved_prefix(&self) -> &str {
+        &self.reserved_prefix
+    }
+
+    /// Hex-encoded leaf committed for `value`: its hash, or that of its
+    /// ciphertext per `DatabaseBuilder::encryption`.
+    pub(crate) fn value_hash(&self, value: &[u8]) -> String {
+        match &self.encryption {
+            Some(encryption) => encryption.value_hash(value),
+            None => hash_value(value),
+        }
+    }
+
+    /// What the leaves of this database commit to.
+    pub fn commit_to(&self) -> CommitTo {
+        self.encryption
+            .as_ref()
+            .map_or(CommitTo::Plaintext, |encryption| encryption.commit_to)
+    }
+
+    /// Fails with `DatabaseError::InvalidKey` unless users may write `key`.
+    pub(crate) fn validate_key(&self, key: &str) -> Result<(), DatabaseError> {
+        zkdb_core::validate_key(key, &self.reserved_prefix).map_err(DatabaseError::from)
+    }
+
+    /// Starts configuring a database with a `DatabaseBuilder`.
+    pub fn builder(engine: DatabaseType, store: Arc<dyn Store>) -> DatabaseBuilder {
+        DatabaseBuilder::new(engine, store)
+    }
+
+    /// Enables a cache holding up to `capacity` values loaded by `prefetch`.
+    pub fn with_prefetch_capacity(mut self, capacity: usize) -> Self {
+        self.cache = ValueCache::new(capacity);
+        self
+    }
+
+    /// Loads the values of `keys` from the store into the prefetch cache so
+    /// later `get` calls skip the store.
+    ///
+    /// Does nothing unless a capacity was set with `with_prefetch_capacity`.
+    #[instrument(skip(self))]
+    pub async fn prefetch(&self, keys: &[&str]) -> Result<(), DatabaseError> {
+        if !self.cache.is_enabled() {
+            return Ok(());
+        }
+        let values = self.store.batch_get(keys).await?;
+        for (key, value) in keys.iter().zip(values) {
+            self.cache.insert(key.to_string(), value);
+        }
+        Ok(())
+    }
+
+    /// Keeps up to `capacity` results of `prove`, reused while the root is
+    /// unchanged.
+    pub fn with_proof_cache_capacity(mut self, capacity: usize) -> Self {
+        self.proofs = ProofCache::new(capacity);
+        self
+    }
+
+    /// Drops the cached value of `key`, if any.
+    pub fn invalidate_cache(&mut self, key: &str) {
+        self.cache.invalidate(key);
     }
 
     #[instrument(skip(self, value))]
@@ -66,102 +355,2190 @@ impl Database {
         value: &[u8],
         generate_proof: bool,
     ) -> Result<(), DatabaseError> {
+        self.ensure_writable("put")?;
+        self.validate_key(key)?;
+        self.limits.check_entry(key, value)?;
         // 1. Store the actual value
+        self.invalidate_cache(key);
+        self.store.put(key, value).await?;
+        self.insert_stored(key, value, false, generate_proof)
+            .await
+            .map(drop)
+    }
+
+    /// `put` that leaves the database untouched if `key` already holds
+    /// `value`, and returns whether it wrote.
+    ///
+    /// The value held is judged by the hash committed to the state, read
+    /// without running the engine, so an unchanged value costs no zkVM
+    /// execution and keeps the root as it is.
+    #[instrument(skip(self, value))]
+    pub async fn put_if_changed(
+        &mut self,
+        key: &str,
+        value: &[u8],
+        generate_proof: bool,
+    ) -> Result<bool, DatabaseError> {
+        self.ensure_writable("put")?;
+        self.validate_key(key)?;
+        self.limits.check_entry(key, value)?;
+        if self.committed_hash(key)? == Some(self.value_hash(value)) {
+            debug!("put if changed: {} is unchanged", key);
+            return Ok(false);
+        }
+        self.put(key, value, generate_proof).await?;
+        Ok(true)
+    }
+
+    /// Hex-encoded value hash the state commits to for `key`, read without
+    /// running the engine.
+    fn committed_hash(&self, key: &str) -> Result<Option<String>, DatabaseError> {
+        Ok(self.committed_hashes(&[key])?.pop().flatten())
+    }
+
+    /// `committed_hash` of each of `keys`, decoding the state once.
+    fn committed_hashes(&self, keys: &[&str]) -> Result<Vec<Option<String>>, DatabaseError> {
+        let stored: Vec<String> = keys
+            .iter()
+            .map(|key| match self.key_derivation() {
+                KeyDerivation::Raw => key.to_string(),
+                KeyDerivation::Sha256 => zkdb_core::merkle::hashed_key(key.as_bytes()),
+            })
+            .collect();
+        (self.spec.leaf_hashes)(&self.state, &stored)
+    }
+
+    /// `put` that returns the hex-encoded hash of the value `key` held
+    /// before, `None` if the tree did not hold the key.
+    ///
+    /// The old hash is read by the same engine execution that installs the
+    /// new leaf, with `Command::Replace`, so no write can land in between.
+    #[instrument(skip(self, value))]
+    pub async fn replace(
+        &mut self,
+        key: &str,
+        value: &[u8],
+    ) -> Result<Option<String>, DatabaseError> {
+        self.ensure_writable("replace")?;
+        self.validate_key(key)?;
+        self.limits.check_entry(key, value)?;
+        self.invalidate_cache(key);
         self.store.put(key, value).await?;
+        let result = self.insert_stored(key, value, true, false).await?;
+        Ok(result.as_replace()?.old_value_hash)
+    }
+
+    /// Replaces the value of `key` and returns the value it replaced, `None`
+    /// if the tree did not hold the key.
+    ///
+    /// The store swaps the values atomically, see `Store::atomic_swap`, so
+    /// concurrent swaps through a shared store each get back a different
+    /// value. The new value is committed as by `put`. The replaced value is
+    /// checked against its leaf after the commit: a mismatch fails the call
+    /// with the new value in place.
+    #[instrument(skip(self, new_value))]
+    pub async fn swap(
+        &mut self,
+        key: &str,
+        new_value: &[u8],
+        generate_proof: bool,
+    ) -> Result<Option<Vec<u8>>, DatabaseError> {
+        self.ensure_writable("swap")?;
+        self.validate_key(key)?;
+        self.limits.check_entry(key, new_value)?;
+        self.invalidate_cache(key);
+        let old = self.store.atomic_swap(key, new_value).await?;
+        let committed = self
+            .insert_stored(key, new_value, true, generate_proof)
+            .await?
+            .as_replace()?
+            .old_value_hash;
+
+        // The store may hold a value the tree never committed to.
+        let (Some(hash), Some(old)) = (committed, old) else {
+            return Ok(None);
+        };
+        if self.value_hash(&old) != hash {
+            return Err(DatabaseError::Store(StoreError::Storage(
+                "Value hash mismatch - data may be corrupted".to_string(),
+            )));
+        }
+        Ok(Some(old))
+    }
 
+    /// Commits the hash of `value`, already in the store, as the leaf of
+    /// `key` with `Command::Insert`, or `Command::Replace` when `replace`.
+    /// The result is returned without its `new_state`, now the database's.
+    async fn insert_stored(
+        &mut self,
+        key: &str,
+        value: &[u8],
+        replace: bool,
+        generate_proof: bool,
+    ) -> Result<ProvenQueryResult, DatabaseError> {
         // 2. Calculate hash for Merkle tree
-        let mut hasher = Sha256::new();
-        hasher.update(value);
-        let value_hash = hex::encode(hasher.finalize());
+        let value_hash = self.value_hash(value);
         debug!("PUT: Original value: {:?}", String::from_utf8_lossy(value));
         debug!("PUT: Calculated hash: {}", value_hash);
 
         // 3. Store hash in Merkle tree via SP1
-        let command = Command::Insert {
-            key: key.to_string(),
-            value: value_hash,
+        let (key_string, value_string) = (key.to_string(), value_hash);
+        let command = if replace {
+            Command::Replace {
+                key: key_string,
+                value: value_string,
+            }
+        } else {
+            Command::Insert {
+                key: key_string,
+                value: value_string,
+            }
         };
 
-        let result = self
-            .executor
-            .execute_query(&self.state, &command, generate_proof)?;
+        let result = self.run(&self.state, &command, generate_proof).await?;
+        self.commit_stored(key, value, &command, result).await
+    }
 
+    /// Commits `result`, that of running the `Insert` or `Replace`
+    /// `command` for `value`, and records the value in the key history.
+    async fn commit_stored(
+        &mut self,
+        key: &str,
+        value: &[u8],
+        command: &Command,
+        mut result: ProvenQueryResult,
+    ) -> Result<ProvenQueryResult, DatabaseError> {
         debug!("PUT: Result from executor: {:?}", result.data);
+        check_engine_error(&result.data, key)?;
 
         // update state
-        self.set_state(result.new_state);
+        let new_state = std::mem::take(&mut result.new_state);
+        self.commit(command, new_state, result.sp1_proof.as_ref())
+            .await?;
 
-        Ok(())
+        if self.key_history {
+            let version = KeyVersion {
+                version: 0,
+                value: value.to_vec(),
+                value_hash: self.value_hash(value),
+                root: self.current_root_hex(),
+                timestamp: self.clock.now_millis(),
+            };
+            versions::record(&*self.reserved, version, key, self.history_retention).await?;
+        }
+        Ok(result)
     }
 
-    #[instrument(skip(self))]
-    pub async fn get(&self, key: &str, generate_proof: bool) -> Result<Vec<u8>, DatabaseError> {
-        // 1. Get hash from Merkle tree for verification
-        let command = Command::Query {
+    /// Puts `value` under `key` without a proof and returns a future
+    /// resolving to the proof of the insert, generated on a blocking task
+    /// so that the database can be used meanwhile.
+    ///
+    /// The proof is of the very run that was committed, its inputs and
+    /// `OperationContext` included, against the state it was applied to;
+    /// later writes do not affect it.
+    #[instrument(skip(self, value))]
+    pub async fn put_async_proof(
+        &mut self,
+        key: &str,
+        value: &[u8],
+    ) -> Result<impl Future<Output = Result<ProvenOutput, DatabaseError>>, DatabaseError> {
+        self.ensure_writable("put")?;
+        self.validate_key(key)?;
+        self.limits.check_entry(key, value)?;
+        self.invalidate_cache(key);
+        self.store.put(key, value).await?;
+        let command = Command::Insert {
             key: key.to_string(),
+            value: self.value_hash(value),
         };
-        let result = self
-            .executor
-            .execute_query(&self.state, &command, generate_proof)?;
-        debug!("GET: Query Result: {:?}", result.data);
+        let stdin = self.executor.stdin(&self.state, &command);
+        let result = self.executor.execute_stdin(&command, stdin.clone())?;
+        self.commit_stored(key, value, &command, result).await?;
+        let job = self.executor.proof_job_for(&command, stdin);
+        let handle = tokio::task::spawn_blocking(job);
+        Ok(async move {
+            handle.await.map_err(|e| {
+                DatabaseError::ProofGenerationFailed(format!("proof task failed: {}", e))
+            })?
+        })
+    }
 
-        if result.data.get("error").is_some() {
-            return Err(DatabaseError::QueryExecutionFailed(format!(
-                "Query execution failed, error: {:?}",
-                result.data
-            )));
+    /// Stores `raw_value` under `key` and inserts `leaf_hash` into the tree
+    /// as is, e.g. to carry over leaves computed by another system.
+    ///
+    /// **Warning:** nothing checks that `leaf_hash` belongs to `raw_value`.
+    /// Unless it is `verify::hash_value(raw_value)`, `get` fails the
+    /// integrity check for `key` and proofs vouch for a value the store does
+    /// not hold. The key history is not updated.
+    #[cfg(feature = "admin")]
+    #[instrument(skip(self, raw_value))]
+    pub async fn put_raw_leaf(
+        &mut self,
+        key: &str,
+        raw_value: &[u8],
+        leaf_hash: [u8; 32],
+    ) -> Result<(), DatabaseError> {
+        self.ensure_writable("put_raw_leaf")?;
+        self.validate_key(key)?;
+        self.limits.check_entry(key, raw_value)?;
+        self.invalidate_cache(key);
+        self.store.put(key, raw_value).await?;
+
+        let command = Command::Insert {
+            key: key.to_string(),
+            value: hex::encode(leaf_hash),
+        };
+        let result = self.executor.execute_query(&self.state, &command, false)?;
+        debug!("put_raw_leaf: result from executor: {:?}", result.data);
+        check_engine_error(&result.data, key)?;
+        self.commit(&command, result.new_state, None).await
+    }
+
+    /// Imports newline-delimited JSON objects, reading the key from
+    /// `key_field` and the base64-encoded value from `value_field`.
+    ///
+    /// Values are stored as lines are read; the tree is updated with a
+    /// `BatchInsert` each time `DatabaseLimits::max_batch_entries` values
+    /// are pending, and once more at the end. Blank lines are ignored and
+    /// lines that fail to parse are counted as skipped.
+    #[instrument(skip(self, reader))]
+    pub async fn import_json_lines<R: AsyncBufRead + Unpin>(
+        &mut self,
+        reader: R,
+        key_field: &str,
+        value_field: &str,
+        generate_proof: bool,
+    ) -> Result<ImportReport, DatabaseError> {
+        self.ensure_writable("import")?;
+        let mut report = ImportReport::default();
+        let mut entries = Vec::new();
+        let mut lines = reader.lines();
+        let mut line_number = 0;
+        while let Some(line) = lines.next_line().await.map_err(|e| {
+            DatabaseError::QueryExecutionFailed(format!("Failed to read import: {}", e))
+        })? {
+            line_number += 1;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let parsed = import::parse_line(&line, key_field, value_field).and_then(
+                |(key, value)| match self
+                    .validate_key(&key)
+                    .and_then(|()| self.limits.check_entry(&key, &value))
+                {
+                    Ok(()) => Ok((key, value)),
+                    Err(e) => Err(e.to_string()),
+                },
+            );
+            match parsed {
+                Ok((key, value)) => {
+                    self.invalidate_cache(&key);
+                    self.store.put(&key, &value).await?;
+                    entries.push((key, self.value_hash(&value)));
+                    if entries.len() >= self.limits.max_batch_entries {
+                        report.imported += entries.len();
+                        self.import_batch(std::mem::take(&mut entries), generate_proof)
+                            .await?;
+                    }
+                }
+                Err(e) => {
+                    debug!(line_number, error = %e, "skipping import line");
+                    report.skipped += 1;
+                    report.errors.push(format!("line {}: {}", line_number, e));
+                }
+            }
         }
 
-        let merkle_hash = result
-            .data
-            .get("value")
-            .and_then(|v| v.as_str())
-            .ok_or_else(|| {
-                DatabaseError::QueryExecutionFailed("Invalid result format".to_string())
-            })?;
+        if !entries.is_empty() {
+            report.imported += entries.len();
+            self.import_batch(entries, generate_proof).await?;
+        }
+        Ok(report)
+    }
 
-        // 2. Get actual value from store
-        let value = self.store.get(key).await?;
-        debug!(
-            "GET: Retrieved value from store: {:?}",
-            String::from_utf8_lossy(&value)
+    /// Inserts the imported `entries`, whose values are already stored,
+    /// into the tree with one `BatchInsert`.
+    async fn import_batch(
+        &mut self,
+        entries: Vec<(String, String)>,
+        generate_proof: bool,
+    ) -> Result<(), DatabaseError> {
+        let command = Command::BatchInsert { entries };
+        let result = self.run(&self.state, &command, generate_proof).await?;
+        debug!("import: result from executor: {:?}", result.data);
+        check_engine_error(&result.data, "")?;
+
+        self.commit(&command, result.new_state, result.sp1_proof.as_ref())
+            .await
+    }
+
+    /// Imports every key-value pair of the RocksDB database at
+    /// `source_path`, such as a backup taken by another system, opening it
+    /// read-only.
+    ///
+    /// Keys that are not UTF-8 or fail validation are counted as skipped,
+    /// and so are keys the tree already holds unless `overwrite` is set.
+    /// Values are stored and the tree updated in chunks of at most
+    /// `DatabaseLimits::max_batch_entries`, each a `batch_put` followed by a
+    /// `BatchInsert`, as in `put_many`.
+    #[instrument(skip(self, source_path))]
+    pub async fn import_rocksdb_backup<P: AsRef<Path>>(
+        &mut self,
+        source_path: P,
+        overwrite: bool,
+        generate_proof: bool,
+    ) -> Result<ImportReport, DatabaseError> {
+        self.ensure_writable("import")?;
+        let pairs = zkdb_store::rocks::read_all(source_path)?;
+
+        let mut report = ImportReport::default();
+        let mut candidates = Vec::with_capacity(pairs.len());
+        for (key, value) in pairs {
+            let key = match String::from_utf8(key) {
+                Ok(key) => key,
+                Err(e) => {
+                    report.skipped += 1;
+                    report
+                        .errors
+                        .push(format!("key {}: not UTF-8", hex::encode(e.as_bytes())));
+                    continue;
+                }
+            };
+            match self
+                .validate_key(&key)
+                .and_then(|()| self.limits.check_entry(&key, &value))
+            {
+                Ok(()) => candidates.push((key, value)),
+                Err(e) => {
+                    debug!(key, error = %e, "skipping imported key");
+                    report.skipped += 1;
+                    report.errors.push(format!("key '{}': {}", key, e));
+                }
+            }
+        }
+        if !overwrite {
+            let keys: Vec<&str> = candidates.iter().map(|(key, _)| key.as_str()).collect();
+            let existing = self.committed_hashes(&keys)?;
+            let mut existing = existing.into_iter();
+            candidates.retain(|(key, _)| {
+                let held = existing.next().flatten().is_some();
+                if held {
+                    report.skipped += 1;
+                    report
+                        .errors
+                        .push(format!("key '{}': already in the database", key));
+                }
+                !held
+            });
+        }
+
+        for chunk in candidates.chunks(self.limits.max_batch_entries.max(1)) {
+            let values: Vec<(&str, &[u8])> = chunk
+                .iter()
+                .map(|(key, value)| (key.as_str(), value.as_slice()))
+                .collect();
+            for (key, _) in &values {
+                self.invalidate_cache(key);
+            }
+            self.store.batch_put(&values).await?;
+
+            let entries = chunk
+                .iter()
+                .map(|(key, value)| (key.clone(), self.value_hash(value)))
+                .collect();
+            let command = Command::BatchInsert { entries };
+            let result = self.run(&self.state, &command, generate_proof).await?;
+            debug!("import: result from executor: {:?}", result.data);
+            check_engine_error(&result.data, "")?;
+
+            self.commit(&command, result.new_state, result.sp1_proof.as_ref())
+                .await?;
+            report.imported += chunk.len();
+        }
+        Ok(report)
+    }
+
+    /// Merges the keys of `other_state`, a state of another database, into
+    /// this one, copying their values from `source`, the other database's
+    /// store. Keys under the other database's reserved prefix are left out.
+    ///
+    /// Keys held by both under different leaves are settled by `conflict`.
+    /// Every value is read and checked against its foreign leaf before
+    /// anything is written, so the leaves must have been committed the way
+    /// this database commits them, see `DatabaseBuilder::encryption`. The
+    /// tree is updated once with a single `BatchInsert`.
+    #[instrument(skip(self, other_state, source))]
+    pub async fn merge_from(
+        &mut self,
+        other_state: &[u8],
+        source: &dyn Store,
+        conflict: ConflictPolicy,
+        generate_proof: bool,
+    ) -> Result<MergeReport, DatabaseError> {
+        self.ensure_writable("merge")?;
+        self.ensure_raw_keys(&self.state, "merge")?;
+        self.ensure_raw_keys(other_state, "merge")?;
+        let other_prefix = (self.spec.reserved_prefix)(other_state)?;
+        let keys: Vec<String> = (self.spec.list_keys)(other_state, None)?
+            .into_iter()
+            .filter(|key| !key.starts_with(&other_prefix))
+            .collect();
+        let theirs = (self.spec.leaf_hashes)(other_state, &keys)?;
+        let ours = (self.spec.leaf_hashes)(&self.state, &keys)?;
+
+        let mut report = MergeReport::default();
+        let mut wanted = Vec::new();
+        for ((key, theirs), ours) in keys.into_iter().zip(theirs).zip(ours) {
+            let Some(theirs) = theirs else {
+                continue;
+            };
+            match ours {
+                None => wanted.push((key, theirs)),
+                Some(ours) if ours == theirs => report.skipped += 1,
+                Some(_) => {
+                    report.conflicts.push(key.clone());
+                    match conflict {
+                        ConflictPolicy::PreferLocal => report.skipped += 1,
+                        ConflictPolicy::PreferOther => wanted.push((key, theirs)),
+                        ConflictPolicy::Error => {}
+                    }
+                }
+            }
+        }
+        if conflict == ConflictPolicy::Error && !report.conflicts.is_empty() {
+            return Err(DatabaseError::MergeConflict(report.conflicts));
+        }
+        if wanted.is_empty() {
+            return Ok(report);
+        }
+        self.limits.check_batch(wanted.len())?;
+
+        let mut values = Vec::with_capacity(wanted.len());
+        for (key, leaf) in &wanted {
+            self.validate_key(key)?;
+            let value = source.get(key).await?;
+            if self.value_hash(&value) != *leaf {
+                return Err(DatabaseError::Store(StoreError::Storage(
+                    "Value hash mismatch - data may be corrupted".to_string(),
+                )));
+            }
+            self.limits.check_entry(key, &value)?;
+            values.push(value);
+        }
+
+        let batch: Vec<(&str, &[u8])> = wanted
+            .iter()
+            .zip(&values)
+            .map(|((key, _), value)| (key.as_str(), value.as_slice()))
+            .collect();
+        for (key, _) in &batch {
+            self.invalidate_cache(key);
+        }
+        self.store.batch_put(&batch).await?;
+
+        report.added = wanted.len();
+        let command = Command::BatchInsert { entries: wanted };
+        let result = self.run(&self.state, &command, generate_proof).await?;
+        debug!("merge: result from executor: {:?}", result.data);
+        check_engine_error(&result.data, "")?;
+
+        self.commit(&command, result.new_state, result.sp1_proof.as_ref())
+            .await?;
+        Ok(report)
+    }
+
+    /// Moves the keys starting with each of `prefixes` out of this database
+    /// into a new one per prefix, on the store `new_store` returns for it,
+    /// and returns the new databases by prefix. Afterwards this database
+    /// only holds the keys matching none of them.
+    ///
+    /// A key matching several prefixes goes to the first. The new databases
+    /// run the same engine with the same layout, reserved prefix, limits and
+    /// encryption, and commit the values as `put_many` does; key history
+    /// and metadata stay behind. Every new database is filled before any
+    /// key is removed from this one, which then removes them with a
+    /// `BatchDelete` per `DatabaseLimits::max_batch_entries` keys.
+    #[instrument(skip(self, new_store))]
+    pub async fn partition_by_prefix<F>(
+        &mut self,
+        prefixes: &[&str],
+        mut new_store: F,
+    ) -> Result<HashMap<String, Database>, DatabaseError>
+    where
+        F: FnMut(&str) -> Arc<dyn Store>,
+    {
+        self.ensure_writable("partition")?;
+        self.ensure_raw_keys(&self.state, "partition")?;
+        let mut moved: Vec<Vec<String>> = vec![Vec::new(); prefixes.len()];
+        for key in self.list_keys()? {
+            if let Some(position) = prefixes.iter().position(|prefix| key.starts_with(prefix)) {
+                moved[position].push(key);
+            }
+        }
+
+        let mut partitions = HashMap::new();
+        for (prefix, keys) in prefixes.iter().zip(&moved) {
+            if partitions.contains_key(*prefix) {
+                continue;
+            }
+            let mut builder = Database::builder(self.engine.clone(), new_store(prefix))
+                .reserved_prefix(self.reserved_prefix.clone())
+                .merkle_config(MerkleConfig {
+                    layout: self.layout(),
+                    key_derivation: self.key_derivation(),
+                })
+                .limits(self.limits);
+            if let Some(encryption) = &self.encryption {
+                builder = builder.encryption(encryption.clone());
+            }
+            let mut partition = builder.build().await?;
+            let mut entries = Vec::with_capacity(keys.len());
+            for key in keys {
+                entries.push((key.clone(), self.get(key, false).await?));
+            }
+            partition
+                .put_many(entries, self.limits.max_batch_entries, false)
+                .await?;
+            partitions.insert(prefix.to_string(), partition);
+        }
+
+        let moved: Vec<String> = moved.into_iter().flatten().collect();
+        for chunk in moved.chunks(self.limits.max_batch_entries.max(1)) {
+            let command = Command::BatchDelete {
+                keys: chunk.to_vec(),
+            };
+            let result = self.executor.execute_query(&self.state, &command, false)?;
+            debug!("partition: result from executor: {:?}", result.data);
+            check_engine_error(&result.data, "")?;
+            self.commit(&command, result.new_state, result.sp1_proof.as_ref())
+                .await?;
+        }
+        for key in &moved {
+            self.invalidate_cache(key);
+            match self.store.delete(key).await {
+                Ok(()) | Err(StoreError::NotFound(_)) => {}
+                Err(e) => return Err(e.into()),
+            }
+            meta::delete(&*self.reserved, key).await?;
+        }
+        info!(
+            moved = moved.len(),
+            partitions = partitions.len(),
+            "partitioned the database"
         );
+        Ok(partitions)
+    }
 
-        // 3. Verify hash matches
-        let mut hasher = Sha256::new();
-        hasher.update(&value);
-        let computed_hash = hex::encode(hasher.finalize());
-        debug!("GET: Computed hash of retrieved value: {}", computed_hash);
+    /// Stores entries in chunks of `chunk_size`, with one store batch and
+    /// one `BatchInsert` per chunk, and returns how many were inserted.
+    ///
+    /// Only one chunk of values is held in memory at a time, but the state
+    /// itself grows by a leaf and a key index per entry and is serialized
+    /// in full on every chunk, so larger chunks trade memory for fewer zkVM
+    /// executions. If a chunk fails, the chunks before it stay committed.
+    #[instrument(skip(self, entries))]
+    pub async fn put_many<I>(
+        &mut self,
+        entries: I,
+        chunk_size: usize,
+        generate_proof: bool,
+    ) -> Result<usize, DatabaseError>
+    where
+        I: IntoIterator<Item = (String, Vec<u8>)>,
+    {
+        self.ensure_writable("put_many")?;
+        if chunk_size == 0 {
+            return Err(DatabaseError::QueryExecutionFailed(
+                "chunk_size must be at least 1".to_string(),
+            ));
+        }
+        self.limits.check_batch(chunk_size)?;
+        let mut entries = entries.into_iter();
+        let mut inserted = 0;
+        loop {
+            let chunk: Vec<(String, Vec<u8>)> = entries.by_ref().take(chunk_size).collect();
+            if chunk.is_empty() {
+                return Ok(inserted);
+            }
+            for (key, value) in &chunk {
+                self.validate_key(key)?;
+                self.limits.check_entry(key, value)?;
+            }
 
-        if computed_hash != merkle_hash {
-            return Err(DatabaseError::Store(StoreError::Storage(
-                "Value hash mismatch - data may be corrupted".to_string(),
-            )));
+            let batch: Vec<(&str, &[u8])> = chunk
+                .iter()
+                .map(|(key, value)| (key.as_str(), value.as_slice()))
+                .collect();
+            for (key, _) in &batch {
+                self.invalidate_cache(key);
+            }
+            self.store.batch_put(&batch).await?;
+
+            let command = Command::BatchInsert {
+                entries: chunk
+                    .iter()
+                    .map(|(key, value)| (key.clone(), self.value_hash(value)))
+                    .collect(),
+            };
+            let result = self.run(&self.state, &command, generate_proof).await?;
+            debug!("put many: result from executor: {:?}", result.data);
+            check_engine_error(&result.data, "")?;
+
+            self.commit(&command, result.new_state, result.sp1_proof.as_ref())
+                .await?;
+            inserted += chunk.len();
         }
+    }
+
+    /// Copies the value of `src_key` to `dst_key` and commits the same leaf
+    /// hash under `dst_key`.
+    #[instrument(skip(self))]
+    pub async fn clone_key(
+        &mut self,
+        src_key: &str,
+        dst_key: &str,
+        generate_proof: bool,
+    ) -> Result<(), DatabaseError> {
+        self.ensure_writable("clone_key")?;
+        self.validate_key(dst_key)?;
+        self.limits.check_key(dst_key)?;
+        let value_hash = self.query_leaf(src_key)?;
+        self.invalidate_cache(dst_key);
+        self.store.copy(src_key, dst_key).await?;
+
+        let command = Command::Insert {
+            key: dst_key.to_string(),
+            value: value_hash,
+        };
+        let result = self.run(&self.state, &command, generate_proof).await?;
+        debug!("clone key: result from executor: {:?}", result.data);
+        check_engine_error(&result.data, dst_key)?;
+
+        self.commit(&command, result.new_state, result.sp1_proof.as_ref())
+            .await?;
+        Ok(())
+    }
+
+    /// Encodes `value` with the configured codec and stores it like `put`.
+    ///
+    /// The leaf is the hash of the encoded bytes, so proofs are unchanged.
+    #[instrument(skip(self, value))]
+    pub async fn put_typed<T: Serialize>(
+        &mut self,
+        key: &str,
+        value: &T,
+        generate_proof: bool,
+    ) -> Result<(), DatabaseError> {
+        let bytes = self.codec.encode(value)?;
+        self.put(key, &bytes, generate_proof).await
+    }
+
+    /// Reads a value like `get` and decodes it with the configured codec.
+    #[instrument(skip(self))]
+    pub async fn get_typed<T: DeserializeOwned>(
+        &self,
+        key: &str,
+        generate_proof: bool,
+    ) -> Result<T, DatabaseError> {
+        let bytes = self.get(key, generate_proof).await?;
+        self.codec.decode(&bytes)
+    }
+
+    /// Stores `value` like `put` and records `metadata` next to it.
+    ///
+    /// The leaf still commits to the raw value, so proofs are unchanged.
+    /// Timestamps in `metadata` are filled in; the returned copy has them.
+    #[instrument(skip(self, value, metadata))]
+    pub async fn put_with_meta(
+        &mut self,
+        key: &str,
+        value: &[u8],
+        metadata: Metadata,
+        generate_proof: bool,
+    ) -> Result<Metadata, DatabaseError> {
+        self.put(key, value, generate_proof).await?;
+        let now = self.clock.now_millis();
+        meta::write(&*self.reserved, key, self.value_hash(value), metadata, now).await
+    }
+
+    /// Stores `value` like `put`, to expire `ttl` from now.
+    ///
+    /// Reads of an expired key fail with `DatabaseError::Expired` until it is
+    /// removed by `sweep_expired` or overwritten. A `ttl` reaching past the
+    /// last millisecond a `u64` counts fails with
+    /// `DatabaseError::InvalidCommand`.
+    #[instrument(skip(self, value))]
+    pub async fn put_with_ttl(
+        &mut self,
+        key: &str,
+        value: &[u8],
+        ttl: Duration,
+        generate_proof: bool,
+    ) -> Result<Metadata, DatabaseError> {
+        let expires_at = u64::try_from(ttl.as_millis())
+            .ok()
+            .and_then(|ttl| self.clock.now_millis().checked_add(ttl))
+            .ok_or_else(|| {
+                DatabaseError::InvalidCommand(format!("TTL of {:?} is out of range", ttl))
+            })?;
+        let metadata = Metadata {
+            expires_at: Some(expires_at),
+            ..Metadata::default()
+        };
+        self.put_with_meta(key, value, metadata, generate_proof)
+            .await
+    }
+
+    /// Reads a value like `get`, along with its metadata if it was written
+    /// with `put_with_meta`.
+    #[instrument(skip(self))]
+    pub async fn get_with_meta(
+        &self,
+        key: &str,
+        generate_proof: bool,
+    ) -> Result<(Vec<u8>, Option<Metadata>), DatabaseError> {
+        let value = self.get(key, generate_proof).await?;
+        let metadata = meta::read(&*self.reserved, key, &self.value_hash(&value)).await?;
+        Ok((value, metadata))
+    }
+
+    /// Overwrites the value of a key that already exists.
+    #[instrument(skip(self, value))]
+    pub async fn update(
+        &mut self,
+        key: &str,
+        value: &[u8],
+        generate_proof: bool,
+    ) -> Result<(), DatabaseError> {
+        self.query_leaf(key)?;
+        self.put(key, value, generate_proof).await
+    }
+
+    /// Removes a key from the tree and its value from the store.
+    #[instrument(skip(self))]
+    pub async fn delete(&mut self, key: &str, generate_proof: bool) -> Result<(), DatabaseError> {
+        self.ensure_writable("delete")?;
+        let command = Command::Delete {
+            key: key.to_string(),
+        };
+        let result = self.run(&self.state, &command, generate_proof).await?;
+        debug!("delete: result from executor: {:?}", result.data);
+        check_engine_error(&result.data, key)?;
+
+        self.commit(&command, result.new_state, result.sp1_proof.as_ref())
+            .await?;
+        self.invalidate_cache(key);
+        // The tree no longer references the value, so a missing one is fine.
+        match self.store.delete(key).await {
+            Ok(()) | Err(StoreError::NotFound(_)) => {}
+            Err(e) => return Err(e.into()),
+        }
+        meta::delete(&*self.reserved, key).await
+    }
+
+    /// Removes every expired key from the tree, with a `BatchDelete` per
+    /// `DatabaseLimits::max_batch_entries` keys, and its value and metadata
+    /// from the store. Returns how many were removed.
+    #[instrument(skip(self))]
+    pub async fn sweep_expired(&mut self, generate_proof: bool) -> Result<usize, DatabaseError> {
+        self.ensure_writable("sweep_expired")?;
+        self.ensure_raw_keys(&self.state, "sweep_expired")?;
+        let keys = self.list_keys()?;
+        let expired = meta::expired(&*self.reserved, &keys, self.clock.now_millis(), |value| {
+            self.value_hash(value)
+        })
+        .await?;
+        if expired.is_empty() {
+            return Ok(0);
+        }
+        for chunk in expired.chunks(self.limits.max_batch_entries.max(1)) {
+            let command = Command::BatchDelete {
+                keys: chunk.to_vec(),
+            };
+            let result = self.run(&self.state, &command, generate_proof).await?;
+            debug!("sweep: result from executor: {:?}", result.data);
+            check_engine_error(&result.data, "")?;
+
+            self.commit(&command, result.new_state, result.sp1_proof.as_ref())
+                .await?;
+            for key in chunk {
+                self.invalidate_cache(key);
+                match self.store.delete(key).await {
+                    Ok(()) | Err(StoreError::NotFound(_)) => {}
+                    Err(e) => return Err(e.into()),
+                }
+                meta::delete(&*self.reserved, key).await?;
+            }
+        }
+        info!(removed = expired.len(), "swept expired keys");
+        Ok(expired.len())
+    }
+
+    /// Has the store reclaim the space left by deleted and overwritten
+    /// values. Stores that cannot report `CompactionStats::default()`.
+    #[instrument(skip(self))]
+    pub async fn compact(&self) -> Result<CompactionStats, DatabaseError> {
+        self.ensure_writable("compact")?;
+        let stats = self.store.compact().await?;
+        info!(
+            bytes_before = stats.bytes_before,
+            bytes_after = stats.bytes_after,
+            duration_ms = stats.duration_ms,
+            "compacted the store"
+        );
+        Ok(stats)
+    }
+
+    /// Makes every value written so far durable in the store, see
+    /// `Store::flush`.
+    ///
+    /// The state itself is kept in memory. It survives a crash when saved
+    /// with `save_state`, or when the operation log is enabled: its entries
+    /// are in the store, so `flush` makes them durable too and a fresh open
+    /// replays them.
+    #[instrument(skip(self))]
+    pub async fn flush(&self) -> Result<(), DatabaseError> {
+        self.store.flush().await?;
+        debug!("flushed the store");
+        Ok(())
+    }
+
+    /// Empties the tree in the zkVM, so that clearing can be proven.
+    ///
+    /// Values are left in the store; the tree no longer references them.
+    #[instrument(skip(self))]
+    pub async fn clear_tree(&mut self, generate_proof: bool) -> Result<(), DatabaseError> {
+        self.ensure_writable("clear_tree")?;
+        let command = Command::Clear;
+        let result = self.run(&self.state, &command, generate_proof).await?;
+        debug!("clear: result from executor: {:?}", result.data);
+        check_engine_error(&result.data, "")?;
+
+        self.commit(&command, result.new_state, result.sp1_proof.as_ref())
+            .await
+    }
+
+    /// Drops the leaves of the tree no key references in the zkVM, see
+    /// `Command::Compact`, and reports how many were dropped.
+    ///
+    /// Under `TreeLayout::Append` every overwrite and delete leaves a leaf
+    /// behind; compacting drops them and numbers the remaining leaves anew,
+    /// so the root changes and proofs generated before no longer verify
+    /// against the tree. Values are left in the store, see `compact` for
+    /// compacting it.
+    #[instrument(skip(self))]
+    pub async fn compact_tree(
+        &mut self,
+        generate_proof: bool,
+    ) -> Result<CompactResult, DatabaseError> {
+        self.ensure_writable("compact_tree")?;
+        let command = Command::Compact;
+        let result = self.run(&self.state, &command, generate_proof).await?;
+        debug!("compact: result from executor: {:?}", result.data);
+        check_engine_error(&result.data, "")?;
+        let compacted = result.as_compact()?;
+
+        self.commit(&command, result.new_state, result.sp1_proof.as_ref())
+            .await?;
+        Ok(compacted)
+    }
+
+    /// Returns the retained versions of `key` written by `put`, oldest first.
+    ///
+    /// History is kept only when enabled with `DatabaseBuilder::key_history`,
+    /// and survives `delete` until `purge_history` is called.
+    #[instrument(skip(self))]
+    pub async fn history(&self, key: &str) -> Result<Vec<KeyVersion>, DatabaseError> {
+        versions::list(&*self.reserved, key, self.history_retention).await
+    }
+
+    /// Returns version `version` of `key`, checking the value against the
+    /// hash it was committed with.
+    #[instrument(skip(self))]
+    pub async fn get_version(&self, key: &str, version: u64) -> Result<KeyVersion, DatabaseError> {
+        let entry = versions::read(&*self.reserved, key, version).await?;
+        if self.value_hash(&entry.value) != entry.value_hash {
+            return Err(DatabaseError::Store(StoreError::Storage(
+                "Value hash mismatch - data may be corrupted".to_string(),
+            )));
+        }
+        Ok(entry)
+    }
+
+    /// Removes every recorded version of `key` and returns how many there were.
+    #[instrument(skip(self))]
+    pub async fn purge_history(&mut self, key: &str) -> Result<usize, DatabaseError> {
+        self.ensure_writable("purge_history")?;
+        versions::purge(&*self.reserved, key).await
+    }
+
+    /// Returns the keys currently in the tree, in sorted order.
+    ///
+    /// Read from the host's copy of the state without running the zkVM.
+    /// Under `KeyDerivation::Sha256` the state only knows the keys' hashes,
+    /// so those are returned instead, as hex digests.
+    pub fn list_keys(&self) -> Result<Vec<String>, DatabaseError> {
+        (self.spec.list_keys)(&self.state, None)
+    }
+
+    /// Returns the keys currently in the tree as bytes, in sorted order,
+    /// including those written with `put_bytes` that `list_keys` leaves out.
+    /// Hashed keys are returned as `list_keys` does.
+    pub fn list_key_bytes(&self) -> Result<Vec<Vec<u8>>, DatabaseError> {
+        (self.spec.list_key_bytes)(&self.state, None)
+    }
+
+    /// A handle running every operation against the tree named `name`
+    /// instead of the root tree.
+    ///
+    /// Trees are independent: each has its own keys and root, and a named
+    /// tree's values are stored under `_tree/<name>/`. The tree is created
+    /// by its first write.
+    pub fn tree(&mut self, name: &str) -> Tree<'_> {
+        Tree::new(self, name)
+    }
+
+    #[instrument(skip(self))]
+    pub async fn get(&self, key: &str, generate_proof: bool) -> Result<Vec<u8>, DatabaseError> {
+        // 1. Get hash from Merkle tree for verification
+        let command = Command::Query {
+            key: key.to_string(),
+        };
+        let result = self.run(&self.state, &command, generate_proof).await?;
+        debug!("GET: Query Result: {:?}", result.data);
+        check_engine_error(&result.data, key)?;
+        let merkle_hash = result.as_query()?.value;
+        self.read_committed(key, &merkle_hash).await
+    }
+
+    /// Returns the value of `key` together with its inclusion proof and,
+    /// if `generate_proof`, the SP1 proof of the `Prove` command.
+    ///
+    /// A single `Prove` execution reports the leaf the value is checked
+    /// against, where `get` followed by `prove` would run the engine twice.
+    #[instrument(skip(self))]
+    pub async fn get_with_proof(
+        &self,
+        key: &str,
+        generate_proof: bool,
+    ) -> Result<(Vec<u8>, ProveResult, Option<ProvenOutput>), DatabaseError> {
+        let result = self.prove_async(key, generate_proof).await?;
+        let proof = result.as_proof()?;
+        // Under `TreeLayout::Sorted` the leaf also commits to the key.
+        let merkle_hash = match result.data["value_hash"].as_str() {
+            Some(value_hash) => value_hash.to_string(),
+            None => proof.leaf.clone(),
+        };
+        let value = self.read_committed(key, &merkle_hash).await?;
+        Ok((value, proof, result.sp1_proof))
+    }
+
+    /// Reads the value of `key` from the prefetch cache or the store,
+    /// checking it against `merkle_hash`, the value hash the tree commits
+    /// to.
+    async fn read_committed(&self, key: &str, merkle_hash: &str) -> Result<Vec<u8>, DatabaseError> {
+        if let Some(metadata) = meta::read(&*self.reserved, key, merkle_hash).await? {
+            if metadata.is_expired(self.clock.now_millis()) {
+                return Err(DatabaseError::Expired(key.to_string()));
+            }
+        }
+
+        // 2. Get actual value from the prefetch cache or the store
+        if let Some(value) = self.cache.get(key) {
+            if self.value_hash(&value) == merkle_hash {
+                debug!("GET: Served value from prefetch cache");
+                return Ok(value);
+            }
+            // The state changed without going through this database.
+            self.cache.invalidate(key);
+        }
+        let value = self.store.get(key).await?;
+        debug!(
+            "GET: Retrieved value from store: {:?}",
+            String::from_utf8_lossy(&value)
+        );
+
+        // 3. Verify hash matches
+        let computed_hash = self.value_hash(&value);
+        debug!("GET: Computed hash of retrieved value: {}", computed_hash);
+
+        if computed_hash != merkle_hash {
+            return Err(DatabaseError::Store(StoreError::Storage(
+                "Value hash mismatch - data may be corrupted".to_string(),
+            )));
+        }
+
+        // Return the actual value
+        Ok(value)
+    }
+
+    /// `put` with a key of arbitrary bytes, such as a hash.
+    ///
+    /// A UTF-8 key is the same key as the string it spells and goes through
+    /// `put`. The value of any other key is stored with `Store::put_bytes`
+    /// under the reserved prefix, and the key skips what is keyed by
+    /// strings: key history, metadata, the value index and `list_keys`.
+    #[instrument(skip(self, value))]
+    pub async fn put_bytes(
+        &mut self,
+        key: &[u8],
+        value: &[u8],
+        generate_proof: bool,
+    ) -> Result<(), DatabaseError> {
+        if let Ok(key) = std::str::from_utf8(key) {
+            return self.put(key, value, generate_proof).await;
+        }
+        self.ensure_writable("put")?;
+        zkdb_core::validate_key_bytes(key, &self.reserved_prefix)?;
+        self.limits.check_entry(key, value)?;
+        self.reserved.put_bytes(key, value).await?;
+
+        let command = Command::InsertBytes {
+            key: key.to_vec(),
+            value: self.value_hash(value),
+        };
+        let result = self.run(&self.state, &command, generate_proof).await?;
+        debug!("put_bytes: result from executor: {:?}", result.data);
+        check_engine_error(&result.data, &display_key(key))?;
+
+        self.commit(&command, result.new_state, result.sp1_proof.as_ref())
+            .await
+    }
+
+    /// `get` with a key of arbitrary bytes, as written by `put_bytes`.
+    #[instrument(skip(self))]
+    pub async fn get_bytes(
+        &self,
+        key: &[u8],
+        generate_proof: bool,
+    ) -> Result<Vec<u8>, DatabaseError> {
+        if let Ok(key) = std::str::from_utf8(key) {
+            return self.get(key, generate_proof).await;
+        }
+        let command = Command::QueryBytes { key: key.to_vec() };
+        let result = self.run(&self.state, &command, generate_proof).await?;
+        debug!("get_bytes: query result: {:?}", result.data);
+        check_engine_error(&result.data, &display_key(key))?;
+        let merkle_hash = result.as_query()?.value;
+
+        let value = self.reserved.get_bytes(key).await?;
+        if self.value_hash(&value) != merkle_hash {
+            return Err(DatabaseError::Store(StoreError::Storage(
+                "Value hash mismatch - data may be corrupted".to_string(),
+            )));
+        }
+        Ok(value)
+    }
+
+    /// `delete` with a key of arbitrary bytes, as written by `put_bytes`.
+    #[instrument(skip(self))]
+    pub async fn delete_bytes(
+        &mut self,
+        key: &[u8],
+        generate_proof: bool,
+    ) -> Result<(), DatabaseError> {
+        if let Ok(key) = std::str::from_utf8(key) {
+            return self.delete(key, generate_proof).await;
+        }
+        self.ensure_writable("delete")?;
+        let command = Command::DeleteBytes { key: key.to_vec() };
+        let result = self.run(&self.state, &command, generate_proof).await?;
+        debug!("delete_bytes: result from executor: {:?}", result.data);
+        check_engine_error(&result.data, &display_key(key))?;
+
+        self.commit(&command, result.new_state, result.sp1_proof.as_ref())
+            .await?;
+        // The tree no longer references the value, so a missing one is fine.
+        match self.reserved.delete_bytes(key).await {
+            Ok(()) | Err(StoreError::NotFound(_)) => Ok(()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Checks a value against the leaf committed for `key` without storing it.
+    ///
+    /// Returns `Ok(false)` when the value's hash differs from the committed leaf.
+    #[instrument(skip(self, value))]
+    pub fn verify_value(&self, key: &str, value: &[u8]) -> Result<bool, DatabaseError> {
+        let merkle_hash = self.query_leaf(key)?;
+        let computed_hash = self.value_hash(value);
+        debug!(%computed_hash, %merkle_hash, "verifying value against committed leaf");
+        Ok(computed_hash == merkle_hash)
+    }
+
+    /// Returns the hex-encoded leaf hash committed for `key`.
+    fn query_leaf(&self, key: &str) -> Result<String, DatabaseError> {
+        let command = Command::Query {
+            key: key.to_string(),
+        };
+        let result = self.executor.execute_query(&self.state, &command, false)?;
+        debug!("GET: Query Result: {:?}", result.data);
+        check_engine_error(&result.data, key)?;
+        Ok(result.as_query()?.value)
+    }
+
+    /// Generates a Merkle inclusion proof for `key`.
+    #[instrument(skip(self))]
+    pub fn prove(
+        &self,
+        key: &str,
+        generate_proof: bool,
+    ) -> Result<ProvenQueryResult, DatabaseError> {
+        let root = self.current_root_hex();
+        if let Some(result) = self.proofs.get(key, &root, generate_proof) {
+            debug!("prove: served from the proof cache");
+            return Ok(result);
+        }
+        let command = Command::Prove {
+            key: key.to_string(),
+        };
+        let result = self
+            .executor
+            .execute_query(&self.state, &command, generate_proof)?;
+        self.cache_proof(key, root, result)
+    }
+
+    /// `prove`, awaiting the permit to generate the proof.
+    async fn prove_async(
+        &self,
+        key: &str,
+        generate_proof: bool,
+    ) -> Result<ProvenQueryResult, DatabaseError> {
+        let root = self.current_root_hex();
+        if let Some(result) = self.proofs.get(key, &root, generate_proof) {
+            debug!("prove: served from the proof cache");
+            return Ok(result);
+        }
+        let command = Command::Prove {
+            key: key.to_string(),
+        };
+        let result = self.run(&self.state, &command, generate_proof).await?;
+        self.cache_proof(key, root, result)
+    }
+
+    /// Checks the result of proving `key` against `root` and caches it.
+    fn cache_proof(
+        &self,
+        key: &str,
+        root: Option<String>,
+        result: ProvenQueryResult,
+    ) -> Result<ProvenQueryResult, DatabaseError> {
+        debug!("prove: query result: {:?}", result.data);
+        check_engine_error(&result.data, key)?;
+        self.proofs.insert(key.to_string(), root, result.clone());
+        Ok(result)
+    }
+
+    /// Generates a Merkle inclusion proof for `key` against `state`, a
+    /// state this database had, e.g. one saved with `get_state`, rather
+    /// than the current one, so the proof is relative to the root of that
+    /// version. The current state is left untouched.
+    #[instrument(skip(self, state))]
+    pub fn prove_at(
+        &self,
+        key: &str,
+        state: &[u8],
+        generate_proof: bool,
+    ) -> Result<ProvenQueryResult, DatabaseError> {
+        let command = Command::Prove {
+            key: key.to_string(),
+        };
+        let result = self
+            .executor
+            .execute_query(state, &command, generate_proof)?;
+        debug!("prove_at: query result: {:?}", result.data);
+        check_engine_error(&result.data, key)?;
+        Ok(result)
+    }
+
+    /// Bundles the value of `key` with its inclusion proof, for
+    /// `verify::verify_bundle`. The bundle carries the sealed value when the
+    /// leaves commit to ciphertexts, see `DatabaseBuilder::encryption`, and
+    /// is signed when the database has a signer.
+    #[instrument(skip(self))]
+    pub async fn proof_bundle(
+        &self,
+        key: &str,
+        generate_proof: bool,
+    ) -> Result<verify::ProofBundle, DatabaseError> {
+        Ok(self.bundle(key, generate_proof).await?.0)
+    }
+
+    /// Bundles the value of `key` with its inclusion proof and, if
+    /// `generate_proof`, the SP1 proof and verifying key, so that
+    /// `ProofBundle::verify` checks it without the database.
+    #[instrument(skip(self))]
+    pub async fn prove_bundle(
+        &self,
+        key: &str,
+        generate_proof: bool,
+    ) -> Result<ProofBundle, DatabaseError> {
+        let (inclusion, sp1_proof) = self.bundle(key, generate_proof).await?;
+        Ok(ProofBundle {
+            vk: sp1_proof
+                .is_some()
+                .then(|| self.executor.verifying_key().clone()),
+            inclusion,
+            sp1_proof,
+        })
+    }
+
+    /// `proof_bundle`, with the SP1 proof its public values come from.
+    async fn bundle(
+        &self,
+        key: &str,
+        generate_proof: bool,
+    ) -> Result<(verify::ProofBundle, Option<ProvenOutput>), DatabaseError> {
+        let result = self.prove_async(key, generate_proof).await?;
+        let proof = result.as_proof()?;
+        let mut value = self.get(key, false).await?;
+        let commit_to = self.commit_to();
+        if let (Some(encryption), CommitTo::Ciphertext) = (&self.encryption, commit_to) {
+            value = encryption.seal(&value);
+        }
+        let public_values = result
+            .sp1_proof
+            .as_ref()
+            .map(|output| hex::encode(output.proof_data.public_values.as_slice()));
+        let mut bundle = verify::ProofBundle {
+            key: proof.key,
+            value: base64::encode(&value),
+            leaf: proof.leaf,
+            root: proof.root,
+            index: proof.index,
+            total_leaves: proof.total_leaves,
+            proof: proof.proof,
+            public_values,
+            commit_to,
+            signature: None,
+            layout: match self.layout() {
+                TreeLayout::Append => verify::LeafLayout::Append,
+                TreeLayout::Sorted => verify::LeafLayout::Sorted,
+            },
+        };
+        if let Some(signer) = &self.signer {
+            bundle.signature = Some(hex::encode(signer.sign(&bundle.signed_bytes())));
+        }
+        Ok((bundle, result.sp1_proof))
+    }
+
+    /// Generates the inclusion proof of `key` as `bytes32` words for an
+    /// Ethereum contract, see `SolidityProof`.
+    #[instrument(skip(self))]
+    pub fn serialize_for_solidity(&self, key: &str) -> Result<SolidityProof, DatabaseError> {
+        let result = self.prove(key, false)?;
+        let field = |name: &str| {
+            result.data.get(name).ok_or_else(|| {
+                DatabaseError::QueryExecutionFailed(format!("Prove result is missing {}", name))
+            })
+        };
+        let hash = |name: &str| -> Result<[u8; 32], DatabaseError> {
+            let hex = field(name)?.as_str().unwrap_or_default();
+            verify::decode_hash(name, hex).map_err(|e| DatabaseError::Codec(e.to_string()))
+        };
+        let count = |name: &str| -> Result<usize, DatabaseError> {
+            field(name)?
+                .as_u64()
+                .map(|n| n as usize)
+                .ok_or_else(|| DatabaseError::Codec(format!("{} is not a number", name)))
+        };
+
+        let proof = base64::decode(field("proof")?.as_str().unwrap_or_default())
+            .map_err(|e| DatabaseError::Codec(format!("invalid base64 proof: {}", e)))?;
+        let proof_hashes =
+            verify::proof_hashes(&proof).map_err(|e| DatabaseError::Codec(e.to_string()))?;
+        SolidityProof::new(
+            hash("root")?,
+            hash("leaf")?,
+            proof_hashes,
+            count("index")?,
+            count("total_leaves")?,
+        )
+    }
+
+    /// Checks an inclusion proof as returned by `prove` without a database
+    /// or the zkVM: `proof_b64` is its `proof` field, and the other
+    /// arguments its `root`, `leaf`, `index` and `total_leaves`.
+    ///
+    /// Returns `Ok(false)` for a proof that does not hold, and an error only
+    /// for input that cannot be decoded. Clients without the SP1 SDK can run
+    /// the same check with `verify::verify_inclusion`.
+    pub fn verify_inclusion_from_proof_bytes(
+        root_hex: &str,
+        leaf_hex: &str,
+        index: usize,
+        total_leaves: usize,
+        proof_b64: &str,
+    ) -> Result<bool, DatabaseError> {
+        let codec = |e: verify::VerifyError| DatabaseError::Codec(e.to_string());
+        let root = verify::decode_hash("root", root_hex).map_err(codec)?;
+        let leaf = verify::decode_hash("leaf", leaf_hex).map_err(codec)?;
+        let proof = base64::decode(proof_b64)
+            .map_err(|e| DatabaseError::Codec(format!("invalid base64 proof: {}", e)))?;
+        verify::verify_inclusion(root, leaf, index, total_leaves, &proof).map_err(codec)
+    }
+
+    /// Returns the source of a Solidity contract that checks SP1 proofs of
+    /// the engine, with its verifying key hash embedded as a `bytes32`
+    /// constant.
+    pub fn generate_solidity_verifier(&self) -> Result<String, DatabaseError> {
+        solidity::verifier_contract(&self.vk_hash())
+    }
+
+    /// Looks up the leaves committed for several keys in a single execution,
+    /// paying the zkVM's startup and state decoding once.
+    ///
+    /// Missing keys are reported with `found: false` rather than failing
+    /// the query; read the entries with `ProvenQueryResult::as_multi_query`.
+    #[instrument(skip(self))]
+    pub fn get_many_committed(
+        &self,
+        keys: &[&str],
+        generate_proof: bool,
+    ) -> Result<ProvenQueryResult, DatabaseError> {
+        let command = Command::MultiQuery {
+            keys: keys.iter().map(|key| key.to_string()).collect(),
+        };
+        let result = self
+            .executor
+            .execute_query(&self.state, &command, generate_proof)?;
+        debug!("get many committed: query result: {:?}", result.data);
+        check_engine_error(&result.data, "")?;
+        Ok(result)
+    }
+
+    /// Generates inclusion proofs for several keys in a single execution.
+    ///
+    /// The result holds a standalone proof per key under `proofs` and one
+    /// multiproof over all their leaves under `multiproof`.
+    #[instrument(skip(self))]
+    pub fn prove_many(
+        &self,
+        keys: &[&str],
+        generate_proof: bool,
+    ) -> Result<ProvenQueryResult, DatabaseError> {
+        let command = Command::MultiProve {
+            keys: keys.iter().map(|key| key.to_string()).collect(),
+        };
+        let result = self
+            .executor
+            .execute_query(&self.state, &command, generate_proof)?;
+        debug!("prove many: query result: {:?}", result.data);
+        check_engine_error(&result.data, "")?;
+        Ok(result)
+    }
+
+    /// Proves every key in `[start, end)` with a single multiproof.
+    ///
+    /// The result lists the covered keys and their leaves, in key order,
+    /// under `keys`.
+    #[instrument(skip(self))]
+    pub fn prove_range(
+        &self,
+        start: &str,
+        end: &str,
+        generate_proof: bool,
+    ) -> Result<ProvenQueryResult, DatabaseError> {
+        let command = Command::ProveRange {
+            start: start.to_string(),
+            end: end.to_string(),
+        };
+        let result = self
+            .executor
+            .execute_query(&self.state, &command, generate_proof)?;
+        debug!("prove range: query result: {:?}", result.data);
+        check_engine_error(&result.data, "")?;
+        Ok(result)
+    }
+
+    /// Reads up to `limit` keys in `key_range`, in key order, with their
+    /// values checked against their leaves and the leaves against the root
+    /// with the page's multiproof.
+    ///
+    /// Pass `RangePage::next` as the start of `key_range` for the next page.
+    /// Under `TreeLayout::Sorted` the page is also proven to hold every key
+    /// of the range before `next`, see `RangePage::complete`; see the
+    /// `range` module for what the multiproof does not prove otherwise.
+    #[instrument(skip(self))]
+    pub async fn range(
+        &self,
+        key_range: Range<&str>,
+        limit: usize,
+        generate_proof: bool,
+    ) -> Result<RangePage, DatabaseError> {
+        let command = Command::Range {
+            start: key_range.start.to_string(),
+            end: key_range.end.to_string(),
+            limit,
+        };
+        let result = self.run(&self.state, &command, generate_proof).await?;
+        debug!("range: query result: {:?}", result.data);
+        check_engine_error(&result.data, "")?;
+        (self.spec.check_range)(&self.state, &result.data, &key_range)?;
+        let output: range::RangeOutput = serde_json::from_value(result.data).map_err(|e| {
+            DatabaseError::QueryExecutionFailed(format!("Invalid range result format: {}", e))
+        })?;
+
+        let mut entries = Vec::with_capacity(output.keys.len());
+        for key in output.keys {
+            let value = if key.binary {
+                self.reserved.get_bytes(&key.bytes()?).await?
+            } else {
+                self.store.get(&key.key).await?
+            };
+            if self.value_hash(&value) != key.value_hash() {
+                return Err(DatabaseError::Store(StoreError::Storage(
+                    "Value hash mismatch - data may be corrupted".to_string(),
+                )));
+            }
+            entries.push(RangeEntry {
+                key: key.key,
+                binary: key.binary,
+                index: key.index,
+                value,
+            });
+        }
+        Ok(RangePage {
+            entries,
+            next: output.next,
+            complete: output.complete,
+        })
+    }
+
+    /// Proves that `key` is not in the tree, checked against the root before
+    /// returning: by the adjacent leaves of the keys around it, by its
+    /// empty leaf under `DatabaseType::SparseMerkle`, or by the empty slot
+    /// its search ends in under `DatabaseType::Iavl`.
+    ///
+    /// The Merkle engine can only for a database laid out as
+    /// `TreeLayout::Sorted` and fails the command otherwise. All fail it
+    /// for a key that is present.
+    #[instrument(skip(self))]
+    pub fn prove_absence(
+        &self,
+        key: &str,
+        generate_proof: bool,
+    ) -> Result<ProvenQueryResult, DatabaseError> {
+        let command = Command::ProveAbsence {
+            key: key.to_string(),
+        };
+        let result = self
+            .executor
+            .execute_query(&self.state, &command, generate_proof)?;
+        debug!("prove absence: query result: {:?}", result.data);
+        check_engine_error(&result.data, key)?;
+        (self.spec.check_absence)(&self.state, &result.data)?;
+        Ok(result)
+    }
+
+    /// Estimates the size of the inclusion proof for `key` without building
+    /// it. The result holds `sibling_count` and the serialized `bytes`.
+    #[instrument(skip(self))]
+    pub fn estimate_proof_size(&self, key: &str) -> Result<serde_json::Value, DatabaseError> {
+        let command = Command::ProofSize {
+            key: key.to_string(),
+        };
+        let result = self.executor.execute_query(&self.state, &command, false)?;
+        debug!("proof size: query result: {:?}", result.data);
+        check_engine_error(&result.data, key)?;
+        Ok(result.data)
+    }
+
+    /// Checks that the database is operational: looks up `HEALTHCHECK_KEY`
+    /// in the store, runs `Command::GetRoot` without a proof and decodes
+    /// the current state. A component failing is reported in the status
+    /// rather than as an error.
+    #[instrument(skip(self))]
+    pub async fn healthcheck(&self) -> Result<HealthStatus, DatabaseError> {
+        let store = ComponentStatus::measure_async(async {
+            self.store.exists(HEALTHCHECK_KEY).await?;
+            Ok::<_, DatabaseError>(Health::Ok)
+        })
+        .await;
+        let executor = ComponentStatus::measure(|| {
+            let result = self
+                .executor
+                .execute_query(&self.state, &Command::GetRoot, false)?;
+            check_engine_error(&result.data, "")?;
+            Ok(if self.executor.can_prove() {
+                Health::Ok
+            } else {
+                Health::Degraded
+            })
+        });
+        let state = ComponentStatus::measure(|| {
+            (self.spec.layout)(&self.state)?;
+            Ok(Health::Ok)
+        });
+        let status = HealthStatus {
+            store,
+            executor,
+            state,
+        };
+        if !status.is_ok() {
+            warn!(?status, "healthcheck found a component not ok");
+        }
+        Ok(status)
+    }
+
+    /// Returns the leaf, sibling hashes and path to the root for `key`.
+    #[instrument(skip(self))]
+    pub fn inspect(&self, key: &str) -> Result<serde_json::Value, DatabaseError> {
+        let command = Command::Inspect {
+            key: key.to_string(),
+        };
+        let result = self.executor.execute_query(&self.state, &command, false)?;
+        debug!("inspect: query result: {:?}", result.data);
+        check_engine_error(&result.data, key)?;
+        Ok(result.data)
+    }
+
+    /// Returns every value the key held before its current one, oldest first.
+    #[instrument(skip(self))]
+    pub fn get_history(&self, key: &str) -> Result<Vec<HistoryEntry>, DatabaseError> {
+        let command = Command::History {
+            key: key.to_string(),
+        };
+        let result = self.executor.execute_query(&self.state, &command, false)?;
+        debug!("history: query result: {:?}", result.data);
+        check_engine_error(&result.data, key)?;
+
+        serde_json::from_value(result.data["history"].clone()).map_err(|e| {
+            DatabaseError::QueryExecutionFailed(format!("Invalid history format: {}", e))
+        })
+    }
+
+    /// Describes the work `command` would trigger against the current state
+    /// without executing it in the zkVM.
+    pub fn explain(&self, command: &Command) -> ExplanationReport {
+        explain::explain(&self.state, command)
+    }
+
+    /// Sets the encoding the engine uses for its output. JSON is the default;
+    /// `OutputFormat::Binary` spends fewer cycles on large states.
+    pub fn set_output_format(&mut self, format: OutputFormat) {
+        self.executor.set_output_format(format);
+    }
+
+    /// Number of cycles `command` takes against the current state.
+    pub fn cycle_count(&self, command: &Command) -> Result<u64, DatabaseError> {
+        self.executor.cycle_count(&self.state, command)
+    }
+
+    /// Runs `command` against the current state and commits the state a
+    /// mutating command leaves, logged, indexed and checked against the
+    /// limits like any other mutation. A command the engine fails leaves
+    /// the state as it was, with the error in the output. The result is
+    /// returned without its `new_state`, moved into the database rather
+    /// than copied; read it with `get_state`.
+    #[instrument(skip(self, command))]
+    pub async fn execute_query(
+        &mut self,
+        command: Command,
+        generate_proof: bool,
+    ) -> Result<ProvenQueryResult, DatabaseError> {
+        if command.is_mutating() {
+            self.ensure_writable(command.kind())?;
+        }
+        debug!(?generate_proof, "Executing query");
+        let mut result = self.run(&self.state, &command, generate_proof).await?;
+        let new_state = std::mem::take(&mut result.new_state);
+        if command.is_mutating() && result.data.get("error").is_none() {
+            debug!("Query executed successfully, updating state");
+            self.commit(&command, new_state, result.sp1_proof.as_ref())
+                .await?;
+        }
+        Ok(result)
+    }
+
+    /// Runs `commands` in order in one zkVM execution, as a
+    /// `Command::Script`, and commits the state the last one leaves. Read
+    /// the output of each command with `ProvenQueryResult::as_script`; the
+    /// proof, if generated, covers them all.
+    ///
+    /// The first command to fail aborts the script with
+    /// `DatabaseError::ScriptFailed`, naming its index, and the state is
+    /// left unchanged. As with `execute_query`, the values inserted must
+    /// already be in the store, and the result comes without `new_state`.
+    #[instrument(skip(self, commands))]
+    pub async fn apply_commands(
+        &mut self,
+        commands: Vec<Command>,
+        generate_proof: bool,
+    ) -> Result<ProvenQueryResult, DatabaseError> {
+        let command = Command::Script { commands };
+        if command.is_mutating() {
+            self.ensure_writable(command.kind())?;
+        }
+        let mut result = self.run(&self.state, &command, generate_proof).await?;
+        debug!("script: result from executor: {:?}", result.data);
+        check_engine_error(&result.data, "")?;
+
+        if command.is_mutating() {
+            let new_state = std::mem::take(&mut result.new_state);
+            self.commit(&command, new_state, result.sp1_proof.as_ref())
+                .await?;
+        }
+        Ok(result)
+    }
+
+    /// Subscribes to changes of the committed state.
+    ///
+    /// Subscribers that fall more than `STATE_CHANGE_CHANNEL_CAPACITY` changes
+    /// behind miss the oldest ones and are told so by `RecvError::Lagged`.
+    pub fn subscribe(&self) -> broadcast::Receiver<StateChange> {
+        self.notifier.subscribe()
+    }
+
+    /// Registers a callback run synchronously after every committed change.
+    ///
+    /// Callbacks run on the mutating task and should return quickly.
+    pub fn on_state_change<F>(&mut self, callback: F)
+    where
+        F: Fn(&StateChange) + Send + Sync + 'static,
+    {
+        self.notifier.on_state_change(callback);
+    }
+
+    /// Returns a receiver holding the hex-encoded root, updated after every
+    /// change to the state. `None` stands for an empty tree.
+    pub fn watch_root(&mut self) -> watch::Receiver<Option<String>> {
+        self.notifier.watch_root(self.current_root_hex())
+    }
+
+    /// Hex-encoded root of the current state, `None` for an empty tree.
+    pub fn current_root_hex(&self) -> Option<String> {
+        (self.spec.state_root)(&self.state)
+    }
+
+    /// Runs `command` against `state`. A proof waits for its permit, see
+    /// `SP1Executor::with_max_concurrent_proofs`, by awaiting it rather
+    /// than parking a worker of the runtime.
+    pub(crate) async fn run(
+        &self,
+        state: &[u8],
+        command: &Command,
+        generate_proof: bool,
+    ) -> Result<ProvenQueryResult, DatabaseError> {
+        let permit = match generate_proof {
+            true => Some(self.executor.proof_permit_async().await?),
+            false => None,
+        };
+        self.executor.execute_query_with(state, command, permit)
+    }
+
+    /// Fails with `DatabaseError::ReadOnly` if the database was opened
+    /// read-only.
+    pub(crate) fn ensure_writable(&self, operation: &str) -> Result<(), DatabaseError> {
+        if self.read_only {
+            warn!(operation, "refused write to a read-only database");
+            return Err(DatabaseError::ReadOnly(operation.to_string()));
+        }
+        Ok(())
+    }
+
+    /// Fails unless the keys of `state` are stored as given. Under
+    /// `KeyDerivation::Sha256` the state only knows their hashes, so
+    /// `operation`, which needs the keys themselves, cannot run.
+    pub(crate) fn ensure_raw_keys(
+        &self,
+        state: &[u8],
+        operation: &str,
+    ) -> Result<(), DatabaseError> {
+        if (self.spec.key_derivation)(state)? != KeyDerivation::Raw {
+            return Err(DatabaseError::QueryExecutionFailed(format!(
+                "{} needs the keys, but the state stores their hashes",
+                operation
+            )));
+        }
+        Ok(())
+    }
+
+    /// Commits the result of a mutation, logging it first when the operation
+    /// log is enabled and updating the value index afterwards.
+    async fn commit(
+        &mut self,
+        command: &Command,
+        new_state: Vec<u8>,
+        proof: Option<&ProvenOutput>,
+    ) -> Result<(), DatabaseError> {
+        self.ensure_writable(command.kind())?;
+        self.limits.check_state(&new_state)?;
+        let index_changes = if self.value_index {
+            index::changes(&self.spec, &self.state, &new_state, command)?
+        } else {
+            Vec::new()
+        };
+        if self.operation_log {
+            self.commit_logged(command, new_state, proof).await?;
+        } else {
+            self.commit_state(command, new_state, proof)?;
+        }
+        index::apply(&*self.reserved, index_changes).await
+    }
+
+    async fn commit_logged(
+        &mut self,
+        command: &Command,
+        new_state: Vec<u8>,
+        proof: Option<&ProvenOutput>,
+    ) -> Result<(), DatabaseError> {
+        let mut entry = WalEntry {
+            seq: 0,
+            command: command.clone(),
+            key: command.key().map(str::to_string),
+            value_hash: match command {
+                Command::Insert { value, .. } | Command::Replace { value, .. } => {
+                    Some(value.clone())
+                }
+                _ => None,
+            },
+            prev_root: self.current_root_hex(),
+            new_root: (self.spec.state_root)(&new_state),
+            timestamp: self.clock.now_millis(),
+            proof_id: proof_id(proof),
+            committed: false,
+        };
+        wal::append(&*self.reserved, &mut entry).await?;
+        // Keep every inserted value so that `get_at` can read old versions.
+        for (key, value_hash) in inserted_values(command, &self.reserved_prefix) {
+            let value_key = wal::value_key(value_hash);
+            self.store
+                .copy(&key, &reserved::relocate(&self.reserved_prefix, &value_key))
+                .await?;
+        }
+        self.commit_state(command, new_state, proof)?;
+        entry.committed = true;
+        wal::write(&*self.reserved, &entry).await
+    }
+
+    /// Swaps in the state produced by `command` and notifies listeners.
+    fn commit_state(
+        &mut self,
+        command: &Command,
+        new_state: Vec<u8>,
+        proof: Option<&ProvenOutput>,
+    ) -> Result<(), DatabaseError> {
+        let notify = command.is_mutating() && self.notifier.has_listeners();
+        let prev_root = if notify {
+            self.current_root_hex()
+        } else {
+            None
+        };
+        let new_state = (self.spec.refresh_root)(new_state);
+        if let (true, Some(path)) = (command.is_mutating(), &self.root_history) {
+            let entry = RootEntry {
+                timestamp: self.clock.now_millis(),
+                root_hex: (self.spec.state_root)(&new_state).unwrap_or_default(),
+                operation: command.kind().to_string(),
+            };
+            roots::append(path, entry)?;
+        }
+        self.state = new_state;
+        if command.is_mutating() {
+            self.proofs.clear();
+            metrics::record_state(&self.state, self.spec.key_count);
+        }
+        if !notify {
+            return Ok(());
+        }
+
+        let change = StateChange {
+            command_kind: command.kind().to_string(),
+            key: command.key().map(str::to_string),
+            prev_root,
+            new_root: self.current_root_hex(),
+            timestamp: self.clock.now_millis(),
+            proof_id: proof_id(proof),
+        };
+        debug!(?change, "publishing state change");
+        self.notifier.publish(change);
+        Ok(())
+    }
+
+    /// Returns every root recorded in the root history, oldest first.
+    ///
+    /// Empty unless a file was set with `DatabaseBuilder::root_history`.
+    #[instrument(skip(self))]
+    pub fn get_root_history(&self) -> Result<Vec<RootEntry>, DatabaseError> {
+        match &self.root_history {
+            Some(path) => roots::read(path),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    /// Returns the hex-encoded root the state had at Unix time `ts` in
+    /// milliseconds, per the root history.
+    ///
+    /// `None` if no root was recorded by then or the tree was empty.
+    #[instrument(skip(self))]
+    pub fn root_at_timestamp(&self, ts: u64) -> Result<Option<String>, DatabaseError> {
+        let entries = self.get_root_history()?;
+        Ok(roots::at(&entries, ts)
+            .map(|entry| entry.root_hex.clone())
+            .filter(|root| !root.is_empty()))
+    }
+
+    /// Returns the hex-encoded root the root tree had after its `version`th
+    /// mutation, counting from 1, as recorded in the state.
+    ///
+    /// The state keeps the last `MAX_ROOT_VERSIONS` roots; older versions
+    /// and versions not reached yet fail with `DatabaseError::UnknownVersion`.
+    #[instrument(skip(self))]
+    pub fn root_at(&self, version: u64) -> Result<String, DatabaseError> {
+        let result =
+            self.executor
+                .execute_query(&self.state, &Command::RootAt { version }, false)?;
+        check_engine_error(&result.data, "")?;
+        result
+            .data
+            .get("root")
+            .and_then(|root| root.as_str())
+            .map(str::to_string)
+            .ok_or_else(|| DatabaseError::QueryExecutionFailed("Invalid result format".to_string()))
+    }
+
+    #[instrument(skip(self, proof))]
+    pub fn verify_proof(&self, proof: &ProvenOutput) -> Result<bool, DatabaseError> {
+        debug!("Verifying proof");
+        self.executor.verify_proof(proof)
+    }
+
+    /// `verify_proof` on a blocking task, so that verifying does not stall
+    /// the async runtime the database is used from.
+    #[instrument(skip(self, proof))]
+    pub async fn verify_proof_async(&self, proof: &ProvenOutput) -> Result<bool, DatabaseError> {
+        let executor = self.executor.clone();
+        let proof = proof.clone();
+        tokio::task::spawn_blocking(move || executor.verify_proof(&proof))
+            .await
+            .map_err(|e| {
+                DatabaseError::ProofVerificationFailed(format!("verification task failed: {}", e))
+            })?
+    }
+
+    /// Number of entries in the operation log.
+    pub async fn log_len(&self) -> Result<u64, DatabaseError> {
+        wal::len(&*self.reserved).await
+    }
+
+    /// Reads the log entries in `log_range`, failing on the first corrupt one.
+    pub async fn read_log(&self, log_range: Range<u64>) -> Result<Vec<WalEntry>, DatabaseError> {
+        let end = log_range.end.min(self.log_len().await?);
+        let mut entries = Vec::new();
+        for seq in log_range.start..end {
+            entries.push(wal::read(&*self.reserved, seq).await?);
+        }
+        Ok(entries)
+    }
+
+    /// Returns the keys of the root tree whose value has the hex-encoded
+    /// SHA-256 `hash`, in sorted order.
+    ///
+    /// Read from the value index, which is kept only when enabled with
+    /// `DatabaseBuilder::value_index`.
+    #[instrument(skip(self))]
+    pub async fn find_keys_by_hash(&self, hash: &str) -> Result<Vec<String>, DatabaseError> {
+        index::keys(&*self.reserved, &hash.to_ascii_lowercase()).await
+    }
+
+    /// Returns the keys of the root tree holding exactly `value`, as
+    /// `find_keys_by_hash`.
+    #[instrument(skip(self, value))]
+    pub async fn find_keys_by_value(&self, value: &[u8]) -> Result<Vec<String>, DatabaseError> {
+        self.find_keys_by_hash(&self.value_hash(value)).await
+    }
+
+    /// Rewrites the value index from the leaves of the current state,
+    /// dropping any entry the store holds that the state does not call for.
+    /// Returns the number of keys indexed.
+    #[instrument(skip(self))]
+    pub async fn rebuild_index(&mut self) -> Result<usize, DatabaseError> {
+        self.ensure_writable("rebuild_index")?;
+        self.ensure_raw_keys(&self.state, "rebuild_index")?;
+        let expected = index::expected(&self.spec, &self.state)?;
+        index::replace(&*self.reserved, &expected).await?;
+        let indexed = expected.values().map(Vec::len).sum();
+        info!(indexed, "rebuilt value index");
+        Ok(indexed)
+    }
+
+    /// Checks the value index against the leaves of the current state and
+    /// returns an error for each entry that differs, in hash order.
+    pub async fn verify_index(&self) -> Result<Vec<DatabaseError>, DatabaseError> {
+        self.ensure_raw_keys(&self.state, "verify_index")?;
+        let mut expected = index::expected(&self.spec, &self.state)?;
+        let mut mismatches = Vec::new();
+        for (hash, found) in index::stored(&*self.reserved).await? {
+            let wanted = expected.remove(&hash).unwrap_or_default();
+            if found != wanted {
+                mismatches.push((hash, wanted, found));
+            }
+        }
+        mismatches.extend(
+            expected
+                .into_iter()
+                .map(|(hash, wanted)| (hash, wanted, Vec::new())),
+        );
+        mismatches.sort();
+        Ok(mismatches
+            .into_iter()
+            .map(|(hash, expected, found)| DatabaseError::IndexMismatch {
+                hash,
+                expected,
+                found,
+            })
+            .collect())
+    }
+
+    /// Checks every log entry and returns the corrupt ones, in order.
+    pub async fn verify_log(&self) -> Result<Vec<DatabaseError>, DatabaseError> {
+        let mut corrupt = Vec::new();
+        for seq in 0..self.log_len().await? {
+            match wal::read(&*self.reserved, seq).await {
+                Ok(_) => {}
+                Err(e @ DatabaseError::CorruptLogEntry { .. }) => corrupt.push(e),
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(corrupt)
+    }
+
+    /// Rebuilds the state by re-executing the committed entries of the log
+    /// in the zkVM, checking the roots recorded before and after each one.
+    ///
+    /// The log holds no snapshots, so entries before `log_range` are
+    /// replayed as well to reach the state the range starts from.
+    #[instrument(skip(self))]
+    pub async fn replay(&self, log_range: Range<u64>) -> Result<ReplayReport, DatabaseError> {
+        let mut state = Vec::new();
+        let mut report = ReplayReport {
+            replayed: 0,
+            skipped: 0,
+            state: Vec::new(),
+            root: None,
+        };
+        for entry in self.read_log(0..log_range.end).await? {
+            let in_range = log_range.contains(&entry.seq);
+            if !entry.committed {
+                report.skipped += usize::from(in_range);
+                continue;
+            }
+
+            state = self.apply_entry(&state, &entry)?;
+            report.replayed += usize::from(in_range);
+        }
+
+        report.root = (self.spec.state_root)(&state);
+        report.state = state;
+        Ok(report)
+    }
+
+    /// Re-executes a log entry against `state`, checking the roots it
+    /// recorded, and returns the resulting state.
+    fn apply_entry(&self, state: &[u8], entry: &WalEntry) -> Result<Vec<u8>, DatabaseError> {
+        let prev_root = (self.spec.state_root)(state);
+        if prev_root != entry.prev_root {
+            return Err(DatabaseError::ReplayMismatch {
+                seq: entry.seq,
+                expected: entry.prev_root.clone(),
+                actual: prev_root,
+            });
+        }
+        let result = self.executor.execute_query(state, &entry.command, false)?;
+        check_engine_error(&result.data, entry.key.as_deref().unwrap_or_default())?;
+        let new_root = (self.spec.state_root)(&result.new_state);
+        if new_root != entry.new_root {
+            return Err(DatabaseError::ReplayMismatch {
+                seq: entry.seq,
+                expected: entry.new_root.clone(),
+                actual: new_root,
+            });
+        }
+        Ok((self.spec.refresh_root)(result.new_state))
+    }
+
+    /// Reads `key` as of the state with the hex-encoded `root`, rebuilding
+    /// that state from the operation log without touching the live one.
+    ///
+    /// A proof generated here commits to the historical root. Values are
+    /// read from the copies the log keeps of every inserted value, so only
+    /// states reached while the log was enabled can be read.
+    #[instrument(skip(self))]
+    pub async fn get_at(
+        &self,
+        key: &str,
+        root: &str,
+        generate_proof: bool,
+    ) -> Result<HistoricalValue, DatabaseError> {
+        let root = root.trim_start_matches("0x").to_ascii_lowercase();
+        let state = if self.current_root_hex().as_deref() == Some(root.as_str()) {
+            Cow::Borrowed(self.state.as_slice())
+        } else {
+            Cow::Owned(self.state_at_root(&root).await?)
+        };
+
+        let command = Command::Query {
+            key: key.to_string(),
+        };
+        let result = self.run(&state, &command, generate_proof).await?;
+        check_engine_error(&result.data, key)?;
+        let value_hash = result.as_query()?.value;
+
+        let value = match self.reserved.get(&wal::value_key(&value_hash)).await {
+            Ok(value) => value,
+            // Values inserted before the log was enabled have no copy.
+            Err(StoreError::NotFound(_)) => self.store.get(key).await?,
+            Err(e) => return Err(e.into()),
+        };
+        if self.value_hash(&value) != value_hash {
+            return Err(DatabaseError::Store(StoreError::Storage(
+                "Value hash mismatch - data may be corrupted".to_string(),
+            )));
+        }
+        Ok(HistoricalValue {
+            value,
+            root,
+            proof: result.sp1_proof,
+        })
+    }
+
+    /// Rebuilds the state with the hex-encoded `root` from the log.
+    async fn state_at_root(&self, root: &str) -> Result<Vec<u8>, DatabaseError> {
+        let mut state = Vec::new();
+        for entry in self.read_log(0..self.log_len().await?).await? {
+            if !entry.committed {
+                continue;
+            }
+            state = self.apply_entry(&state, &entry)?;
+            if entry.new_root.as_deref() == Some(root) {
+                return Ok(state);
+            }
+        }
+        Err(DatabaseError::UnknownRoot(root.to_string()))
+    }
+
+    /// Upgrades the state to the current layout if it was written by an
+    /// earlier release. The root is unchanged, so no change is published.
+    #[instrument(skip(self))]
+    pub fn auto_migrate(&mut self) -> Result<MigrationReport, DatabaseError> {
+        let (migrated, report) = migrate::migrate(&self.state)?;
+        if let Some(state) = migrated {
+            debug!(from = ?report.from, to = ?report.to, "migrated state");
+            self.state = state;
+        }
+        Ok(report)
+    }
+
+    /// Re-applies the log entries that the current state is missing, e.g.
+    /// because the process stopped before the state was saved.
+    ///
+    /// The state is matched to the latest entry that produced its root. An
+    /// uncommitted last entry is applied only if the store holds the values
+    /// it inserts, and is then marked committed.
+    #[instrument(skip(self))]
+    pub async fn recover(&mut self) -> Result<RecoveryReport, DatabaseError> {
+        self.ensure_writable("recover")?;
+        let mut report = RecoveryReport::default();
+        let len = self.log_len().await?;
+        let entries = self.read_log(0..len).await?;
+        let root = self.current_root_hex();
+        let start = match entries.iter().rposition(|entry| entry.new_root == root) {
+            Some(position) => position + 1,
+            None => match entries.first() {
+                Some(first) if first.prev_root != root => {
+                    return Err(DatabaseError::ReplayMismatch {
+                        seq: 0,
+                        expected: first.prev_root.clone(),
+                        actual: root,
+                    });
+                }
+                _ => 0,
+            },
+        };
+        // The entry that produced the current root took effect even if it
+        // was never marked.
+        if let Some(mut applied) = start.checked_sub(1).map(|i| entries[i].clone()) {
+            if !applied.committed {
+                applied.committed = true;
+                wal::write(&*self.reserved, &applied).await?;
+            }
+        }
+
+        for mut entry in entries.into_iter().skip(start) {
+            let is_last = entry.seq + 1 == len;
+            if !entry.committed && (!is_last || !self.store_holds_values(&entry.command).await?) {
+                debug!(seq = entry.seq, "discarding uncommitted log entry");
+                report.discarded += 1;
+                continue;
+            }
+
+            let new_state = self.apply_entry(&self.state, &entry)?;
+            if let Some(key) = &entry.key {
+                self.invalidate_cache(key);
+            }
+            self.commit_state(&entry.command, new_state, None)?;
+            if !entry.committed {
+                for (key, value_hash) in inserted_values(&entry.command, &self.reserved_prefix) {
+                    let value_key = wal::value_key(value_hash);
+                    self.store
+                        .copy(&key, &reserved::relocate(&self.reserved_prefix, &value_key))
+                        .await?;
+                }
+                entry.committed = true;
+                wal::write(&*self.reserved, &entry).await?;
+            }
+            report.reapplied += 1;
+        }
+
+        if report.reapplied > 0 || report.discarded > 0 {
+            info!(
+                reapplied = report.reapplied,
+                discarded = report.discarded,
+                "recovered state from the operation log"
+            );
+        }
+        Ok(report)
+    }
+
+    /// Whether the store holds every value `command` inserts.
+    async fn store_holds_values(&self, command: &Command) -> Result<bool, DatabaseError> {
+        for (key, value_hash) in inserted_values(command, &self.reserved_prefix) {
+            match self.store.get(&key).await {
+                Ok(value) if self.value_hash(&value) == value_hash => {}
+                Ok(_) | Err(StoreError::NotFound(_)) => return Ok(false),
+                Err(e) => return Err(e.into()),
+            }
+        }
+        Ok(true)
+    }
+
+    /// The `0x`-prefixed hash of the engine's verifying key.
+    pub fn vk_hash(&self) -> String {
+        self.executor.vk_hash()
+    }
+
+    /// Number of commands the zkVM has executed for this database.
+    pub fn execution_count(&self) -> u64 {
+        self.executor.execution_count()
+    }
 
-        // Return the actual value
-        Ok(value)
+    /// Instructions the zkVM has executed for this database, across every
+    /// command.
+    pub fn total_cycles(&self) -> u64 {
+        self.executor.total_cycles()
     }
 
-    #[instrument(skip(self, command))]
-    pub fn execute_query(
-        &mut self,
-        command: Command,
-        generate_proof: bool,
-    ) -> Result<ProvenQueryResult, DatabaseError> {
-        debug!(?generate_proof, "Executing query");
-        let result = self
-            .executor
-            .execute_query(&self.state, &command, generate_proof)?;
-        debug!("Query executed successfully, updating state");
-        self.state.clone_from(&result.new_state);
-        Ok(result)
+    /// Number of proofs being generated for this database right now, see
+    /// `DatabaseBuilder::max_concurrent_proofs`.
+    pub fn proofs_in_flight(&self) -> usize {
+        self.executor.proofs_in_flight()
     }
 
-    #[instrument(skip(self, proof))]
-    pub fn verify_proof(&self, proof: &ProvenOutput) -> Result<bool, DatabaseError> {
-        debug!("Verifying proof");
-        self.executor.verify_proof(proof)
+    /// Number of proofs that outlived `DatabaseBuilder::proof_timeout` and
+    /// are still running, see `SP1Executor::proofs_overdue`.
+    pub fn proofs_overdue(&self) -> usize {
+        self.executor.proofs_overdue()
     }
 
     #[instrument(skip(self))]
@@ -171,17 +2548,159 @@ impl Database {
 
     #[instrument(skip(self))]
     pub fn set_state(&mut self, state: Vec<u8>) {
-        self.state.clone_from(&state);
+        self.state = state;
+        self.adopt_reserved_prefix();
     }
 
     #[instrument(skip(self, path))]
     pub fn save_state(&self, path: &Path) -> Result<(), DatabaseError> {
+        self.ensure_writable("save_state")?;
         debug!(path = ?path, "Saving database state");
         fs::write(path, &self.state).map_err(|e| {
             error!(error = ?e, "Failed to save state");
             DatabaseError::QueryExecutionFailed(format!("Failed to save state: {}", e))
-        })
+        })?;
+        signing::sign_file(self.signer.as_deref(), path, &self.state)
+    }
+
+    /// Loads a state written by `save_state`, checking its signature as
+    /// configured with `DatabaseBuilder::verifying_key`.
+    #[instrument(skip(self, path))]
+    pub fn load_state(&mut self, path: &Path) -> Result<(), DatabaseError> {
+        debug!(path = ?path, "loading database state");
+        let state = fs::read(path).map_err(|e| {
+            error!(error = ?e, "failed to load state");
+            DatabaseError::QueryExecutionFailed(format!("Failed to load state: {}", e))
+        })?;
+        self.check_signature(path, &state)?;
+        self.set_state(state);
+        Ok(())
+    }
+
+    /// Loads a state from `reader`, e.g. one written by `save_state` and
+    /// sent over the network. A reader carries no signature, so this fails
+    /// when signatures are required.
+    #[instrument(skip(self, reader))]
+    pub fn load_state_from_reader<R: std::io::Read>(
+        &mut self,
+        mut reader: R,
+    ) -> Result<(), DatabaseError> {
+        if self.require_signatures {
+            return Err(DatabaseError::InvalidSignature(
+                "a state read from a reader is unsigned".to_string(),
+            ));
+        }
+        let mut state = Vec::new();
+        reader.read_to_end(&mut state).map_err(|e| {
+            error!(error = ?e, "failed to load state");
+            DatabaseError::QueryExecutionFailed(format!("Failed to load state: {}", e))
+        })?;
+        self.set_state(state);
+        Ok(())
     }
+
+    /// Saves the state framed with its length and CRC32 so that truncation or
+    /// corruption is caught by `load_state_checked`.
+    #[instrument(skip(self, path))]
+    pub fn save_state_checked(&self, path: &Path) -> Result<(), DatabaseError> {
+        self.ensure_writable("save_state")?;
+        debug!(path = ?path, "saving checked database state");
+        let framed = frame_state(&self.state);
+        fs::write(path, &framed).map_err(|e| {
+            error!(error = ?e, "failed to save state");
+            DatabaseError::QueryExecutionFailed(format!("Failed to save state: {}", e))
+        })?;
+        signing::sign_file(self.signer.as_deref(), path, &framed)
+    }
+
+    /// Loads a state written by `save_state_checked`, rejecting corrupt files
+    /// before the state reaches the zkVM. Signatures are checked as by
+    /// `load_state`.
+    #[instrument(skip(self, path))]
+    pub fn load_state_checked(&mut self, path: &Path) -> Result<(), DatabaseError> {
+        debug!(path = ?path, "loading checked database state");
+        let framed = fs::read(path).map_err(|e| {
+            error!(error = ?e, "failed to load state");
+            DatabaseError::QueryExecutionFailed(format!("Failed to load state: {}", e))
+        })?;
+        self.check_signature(path, &framed)?;
+        let state = unframe_state(&framed).ok_or_else(|| {
+            error!(path = ?path, "state file failed its integrity check");
+            DatabaseError::QueryExecutionFailed("corrupt state file".to_string())
+        })?;
+        self.set_state(state.to_vec());
+        Ok(())
+    }
+
+    /// Key signatures are checked against: the one set with
+    /// `DatabaseBuilder::verifying_key`, or the signer's own.
+    pub fn verifying_key(&self) -> Option<[u8; 32]> {
+        self.verifying_key
+            .or_else(|| self.signer.as_ref().map(|signer| signer.public_key()))
+    }
+
+    fn check_signature(&self, path: &Path, contents: &[u8]) -> Result<(), DatabaseError> {
+        signing::check_file(
+            self.verifying_key(),
+            self.require_signatures,
+            path,
+            contents,
+        )
+    }
+
+    /// Parses a JSON-encoded bundle, checking its signature against
+    /// `verifying_key`. Unsigned bundles are accepted unless signatures are
+    /// required.
+    pub fn load_bundle(&self, json: &str) -> Result<verify::ProofBundle, DatabaseError> {
+        let bundle: verify::ProofBundle = serde_json::from_str(json)
+            .map_err(|e| DatabaseError::Codec(format!("Invalid bundle JSON: {}", e)))?;
+        if bundle.signature.is_none() && !self.require_signatures {
+            return Ok(bundle);
+        }
+        let public_key = self.verifying_key().ok_or_else(|| {
+            DatabaseError::InvalidSignature(
+                "the bundle is signed but no verifying key is configured".to_string(),
+            )
+        })?;
+        bundle
+            .verify_signature(&public_key)
+            .map_err(|e| DatabaseError::InvalidSignature(e.to_string()))?;
+        Ok(bundle)
+    }
+}
+
+/// Size of the header written by `frame_state`: a u64 length and a u32 CRC32.
+const STATE_FRAME_HEADER_LEN: usize = 12;
+
+/// Prefixes a state with its little-endian length and CRC32.
+fn frame_state(state: &[u8]) -> Vec<u8> {
+    let mut framed = Vec::with_capacity(STATE_FRAME_HEADER_LEN + state.len());
+    framed.extend_from_slice(&(state.len() as u64).to_le_bytes());
+    framed.extend_from_slice(&crc32fast::hash(state).to_le_bytes());
+    framed.extend_from_slice(state);
+    framed
+}
+
+/// Returns the state inside a frame if its length and CRC32 check out.
+fn unframe_state(framed: &[u8]) -> Option<&[u8]> {
+    if framed.len() < STATE_FRAME_HEADER_LEN {
+        return None;
+    }
+    let (header, state) = framed.split_at(STATE_FRAME_HEADER_LEN);
+    let len = u64::from_le_bytes(header[..8].try_into().ok()?);
+    let crc = u32::from_le_bytes(header[8..].try_into().ok()?);
+    (len == state.len() as u64 && crc == crc32fast::hash(state)).then_some(state)
+}
+
+/// Kind of SP1 proof the executor generates.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum ProofMode {
+    /// A core proof: fastest to generate, megabytes in size.
+    #[default]
+    Core,
+    /// A core proof recursively compressed into one of kilobytes, at the
+    /// cost of extra proving time.
+    Compressed,
 }
 
 #[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
@@ -190,6 +2709,19 @@ pub struct ProvenOutput {
     pub vk: Vec<u8>,
 }
 
+impl ProvenOutput {
+    /// Mode the proof was generated in, read from the proof itself so that
+    /// outputs saved before modes existed keep their encoding. `None` for
+    /// kinds the executor does not generate, such as plonk.
+    pub fn proof_mode(&self) -> Option<ProofMode> {
+        match self.proof_data.proof {
+            SP1Proof::Core(_) => Some(ProofMode::Core),
+            SP1Proof::Compressed(_) => Some(ProofMode::Compressed),
+            _ => None,
+        }
+    }
+}
+
 #[derive(Error, Debug, serde::Serialize, serde::Deserialize)]
 pub enum DatabaseError {
     #[error("Query execution failed: {0}")]
@@ -200,31 +2732,721 @@ pub enum DatabaseError {
     ProofVerificationFailed(String),
     #[error("Store error: {0}")]
     Store(#[from] StoreError),
+    #[error("Key not found: {0}")]
+    KeyNotFound(String),
+    #[error("Key expired: {0}")]
+    Expired(String),
+    #[error("Tree is empty: no key can be proven")]
+    EmptyTree,
+    #[error("Value codec error: {0}")]
+    Codec(String),
+    #[error("Verifying key mismatch: expected {expected}, got {actual}")]
+    VkMismatch { expected: String, actual: String },
+    #[error("Operation log entry {seq} is corrupt: {reason}")]
+    CorruptLogEntry { seq: u64, reason: String },
+    #[error("Replay of log entry {seq} diverged: expected root {expected:?}, got {actual:?}")]
+    ReplayMismatch {
+        seq: u64,
+        expected: Option<String>,
+        actual: Option<String>,
+    },
+    #[error("State migration failed: {0}")]
+    MigrationFailed(String),
+    #[error("Proof generation timed out after {0:?}")]
+    ProofTimeout(Duration),
+    #[error("Database is read-only: {0} is not allowed")]
+    ReadOnly(String),
+    #[error("{which} limit exceeded: {actual} is over {limit}")]
+    LimitExceeded {
+        which: String,
+        limit: u64,
+        actual: u64,
+    },
+    #[error("Unknown root version: {0}")]
+    UnknownVersion(u64),
+    #[error("Invalid key: {0}")]
+    InvalidKey(String),
+    #[error("Unknown root: {0}")]
+    UnknownRoot(String),
+    #[error("Value index entry {hash} lists {found:?}, expected {expected:?}")]
+    IndexMismatch {
+        hash: String,
+        expected: Vec<String>,
+        found: Vec<String>,
+    },
+    #[error("ELF unavailable: {0}")]
+    ElfUnavailable(String),
+    #[error("ELF hash mismatch: expected {expected}, got {actual}")]
+    ElfHashMismatch { expected: String, actual: String },
+    /// The engine produced JSON output that is not UTF-8, which no engine
+    /// built from this workspace does: values reach it as hashes.
+    #[error("Engine output is not UTF-8: {0}")]
+    NonUtf8Output(String),
+    /// A state file or bundle is unsigned where signatures are required, or
+    /// its signature does not check out.
+    #[error("Invalid signature: {0}")]
+    InvalidSignature(String),
+    /// The state records an admin key and the mutation was not signed by
+    /// it, see `DatabaseBuilder::writer_key`.
+    #[error("Unauthorized: {0}")]
+    Unauthorized(String),
+    /// The command at `index` of a script failed with `error`, aborting
+    /// the script, see `Database::apply_commands`.
+    #[error("Command {index} of the script failed: {error}")]
+    ScriptFailed {
+        index: u64,
+        error: Box<DatabaseError>,
+    },
+    /// The engine could not decode the state, or found it inconsistent.
+    #[error("Invalid state: {0}")]
+    StateDecode(String),
+    /// The engine rejected the command as malformed or unsupported, e.g.
+    /// a range command the engine or the state's layout cannot run.
+    #[error("Invalid command: {0}")]
+    InvalidCommand(String),
+    /// `Database::merge_from` under `ConflictPolicy::Error` found keys held
+    /// by both databases under different leaves.
+    #[error("Merge conflicts on keys: {0:?}")]
+    MergeConflict(Vec<String>),
+    /// `error`, wrapped by `DatabaseError::context`.
+    #[error("{context}: {error}")]
+    Context {
+        context: String,
+        error: Box<DatabaseError>,
+    },
+}
+
+// Being a `std::error::Error` that is `Send + Sync + 'static`, a
+// `DatabaseError` converts into `Box<dyn std::error::Error>` and
+// `anyhow::Error` through their blanket `From` impls, so `?` works in
+// functions returning either.
+impl DatabaseError {
+    /// Wraps the error with `msg`, which its message is prefixed with.
+    pub fn context(self, msg: &str) -> DatabaseError {
+        DatabaseError::Context {
+            context: msg.to_string(),
+            error: Box::new(self),
+        }
+    }
+
+    /// Rewrites the `StoreError` of a `Store` error, including one wrapped
+    /// with `context`, and returns other errors unchanged.
+    pub fn map_store<F: FnOnce(StoreError) -> StoreError>(self, f: F) -> Self {
+        match self {
+            DatabaseError::Store(e) => DatabaseError::Store(f(e)),
+            DatabaseError::Context { context, error } => DatabaseError::Context {
+                context,
+                error: Box::new(error.map_store(f)),
+            },
+            other => other,
+        }
+    }
+}
+
+impl From<zkdb_core::DatabaseError> for DatabaseError {
+    fn from(err: zkdb_core::DatabaseError) -> Self {
+        match err {
+            zkdb_core::DatabaseError::QueryExecutionFailed(msg) => {
+                DatabaseError::QueryExecutionFailed(msg)
+            }
+            zkdb_core::DatabaseError::KeyNotFound(key) => DatabaseError::KeyNotFound(key),
+            zkdb_core::DatabaseError::EmptyTree => DatabaseError::EmptyTree,
+            zkdb_core::DatabaseError::UnknownVersion(version) => {
+                DatabaseError::UnknownVersion(version)
+            }
+            zkdb_core::DatabaseError::LimitExceeded {
+                which,
+                limit,
+                actual,
+            } => DatabaseError::LimitExceeded {
+                which,
+                limit,
+                actual,
+            },
+            zkdb_core::DatabaseError::InvalidKey(reason) => DatabaseError::InvalidKey(reason),
+            zkdb_core::DatabaseError::Unauthorized(reason) => DatabaseError::Unauthorized(reason),
+            zkdb_core::DatabaseError::ScriptFailed { index, error } => {
+                DatabaseError::ScriptFailed {
+                    index,
+                    error: Box::new((*error).into()),
+                }
+            }
+            zkdb_core::DatabaseError::StateDecode(reason) => DatabaseError::StateDecode(reason),
+            zkdb_core::DatabaseError::InvalidCommand(reason) => {
+                DatabaseError::InvalidCommand(reason)
+            }
+        }
+    }
+}
+
+/// Returns the hex-encoded SHA-256 hash committed to the tree for a value.
+fn hash_value(value: &[u8]) -> String {
+    zkdb_verify::hash_value_hex(value)
+}
+
+/// The store keys of the values `command` inserts, with their hex-encoded
+/// hashes. Values of named trees are kept under `reserved_prefix`.
+fn inserted_values<'a>(command: &'a Command, reserved_prefix: &str) -> Vec<(String, &'a str)> {
+    match command {
+        Command::Insert { key, value } | Command::Replace { key, value } => {
+            vec![(key.clone(), value.as_str())]
+        }
+        Command::InsertBytes { key, value } => {
+            // As `Database::put_bytes` stores them.
+            let store_key = match std::str::from_utf8(key) {
+                Ok(key) => key.to_string(),
+                Err(_) => {
+                    reserved::relocate(reserved_prefix, &zkdb_store::binary_key(key)).into_owned()
+                }
+            };
+            vec![(store_key, value.as_str())]
+        }
+        Command::BatchInsert { entries } => entries
+            .iter()
+            .map(|(key, value)| (key.clone(), value.as_str()))
+            .collect(),
+        Command::InTree { tree, command } => inserted_values(command, reserved_prefix)
+            .into_iter()
+            .map(|(key, value_hash)| {
+                let store_key = tree::store_key(tree, &key);
+                (
+                    reserved::relocate(reserved_prefix, &store_key).into_owned(),
+                    value_hash,
+                )
+            })
+            .collect(),
+        Command::Script { commands } => commands
+            .iter()
+            .flat_map(|command| inserted_values(command, reserved_prefix))
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// Hex-encoded hash of a proof, used to refer to it from notifications and
+/// the operation log.
+fn proof_id(proof: Option<&ProvenOutput>) -> Option<String> {
+    proof
+        .and_then(|proof| bincode::serialize(&proof.proof_data).ok())
+        .map(|bytes| hash_value(&bytes))
+}
+
+/// Lowercases a verifying key hash and makes sure it has a `0x` prefix.
+fn normalize_vk_hash(hash: &str) -> String {
+    let hash = hash.trim().to_lowercase();
+    match hash.strip_prefix("0x") {
+        Some(_) => hash,
+        None => format!("0x{}", hash),
+    }
+}
+
+/// Maps an error reported in the engine output to a `DatabaseError`.
+///
+/// `key` names the missing key when the engine does not report one.
+fn check_engine_error(data: &serde_json::Value, key: &str) -> Result<(), DatabaseError> {
+    match data.get("error") {
+        Some(error) => Err(engine_error(error, data, key)),
+        None => Ok(()),
+    }
+}
+
+/// Maps the `error` field of the engine output `data` to a `DatabaseError`,
+/// see `zkdb_core::DatabaseError::to_output`. Output of guests built before
+/// errors were reported `encoded` is read field by field.
+fn engine_error(error: &serde_json::Value, data: &serde_json::Value, key: &str) -> DatabaseError {
+    if let Ok(error) = serde_json::from_value::<zkdb_core::DatabaseError>(error["encoded"].clone())
+    {
+        return error.into();
+    }
+    match error["type"].as_str() {
+        Some("KeyNotFound") => {
+            DatabaseError::KeyNotFound(error["key"].as_str().unwrap_or(key).to_string())
+        }
+        Some("EmptyTree") => DatabaseError::EmptyTree,
+        Some("UnknownVersion") => {
+            DatabaseError::UnknownVersion(error["version"].as_u64().unwrap_or_default())
+        }
+        Some("LimitExceeded") => {
+            let limit = &error["limit"];
+            DatabaseError::LimitExceeded {
+                which: limit["which"].as_str().unwrap_or_default().to_string(),
+                limit: limit["limit"].as_u64().unwrap_or_default(),
+                actual: limit["actual"].as_u64().unwrap_or_default(),
+            }
+        }
+        Some("InvalidKey") => {
+            DatabaseError::InvalidKey(error["reason"].as_str().unwrap_or_default().to_string())
+        }
+        Some("Unauthorized") => {
+            DatabaseError::Unauthorized(error["reason"].as_str().unwrap_or_default().to_string())
+        }
+        Some("ScriptFailed") => DatabaseError::ScriptFailed {
+            index: error["index"].as_u64().unwrap_or_default(),
+            error: Box::new(engine_error(&error["cause"], data, key)),
+        },
+        _ => DatabaseError::QueryExecutionFailed(format!(
+            "Query execution failed, error: {:?}",
+            data
+        )),
+    }
 }
 
 pub struct SP1Executor {
-    client: ProverClient,
-    elf: &'static [u8],
-    pk: SP1ProvingKey,
-    vk: SP1VerifyingKey,
+    client: Arc<ProverClient>,
+    elf: Arc<[u8]>,
+    /// `None` for a verifier-only executor.
+    pk: Option<Arc<SP1ProvingKey>>,
+    vk: Arc<SP1VerifyingKey>,
+    output_format: OutputFormat,
+    proof_mode: ProofMode,
+    pinned_vk_hash: Option<String>,
+    proof_timeout: Option<Duration>,
+    execute_timeout: Option<Duration>,
+    proof_limiter: Arc<ProofLimiter>,
+    /// Tells the time sent to the engine with every command.
+    clock: Arc<dyn Clock>,
+    /// Who commands are run for, sent to the engine with every command.
+    actor: Option<String>,
+    /// Signs mutations for states that record an admin key.
+    writer: Option<Writer>,
+    executions: AtomicU64,
+    cycles: AtomicU64,
+}
+
+/// Clones share the prover, the keys and the limit on concurrent proofs,
+/// and start from the counts of the original.
+impl Clone for SP1Executor {
+    fn clone(&self) -> Self {
+        SP1Executor {
+            client: self.client.clone(),
+            elf: self.elf.clone(),
+            pk: self.pk.clone(),
+            vk: self.vk.clone(),
+            output_format: self.output_format,
+            proof_mode: self.proof_mode,
+            pinned_vk_hash: self.pinned_vk_hash.clone(),
+            proof_timeout: self.proof_timeout,
+            execute_timeout: self.execute_timeout,
+            proof_limiter: self.proof_limiter.clone(),
+            clock: self.clock.clone(),
+            actor: self.actor.clone(),
+            writer: self.writer.clone(),
+            executions: AtomicU64::new(self.executions.load(Ordering::Relaxed)),
+            cycles: AtomicU64::new(self.cycles.load(Ordering::Relaxed)),
+        }
+    }
+}
+
+/// Generates a proof with `pk` under `permit`, within what is left of
+/// `timeout` once the wait for the permit is taken off.
+fn prove_with(
+    pk: Arc<SP1ProvingKey>,
+    client: Arc<ProverClient>,
+    mode: ProofMode,
+    permit: ProofPermit,
+    timeout: Option<Duration>,
+    stdin: SP1Stdin,
+) -> Result<SP1ProofWithPublicValues, DatabaseError> {
+    let remaining = permit.remaining(timeout);
+    let overdue = permit.overdue().clone();
+    let expired = move |_| DatabaseError::ProofTimeout(timeout.unwrap_or_default());
+    deadline::run_with_deadline(remaining, Some(&overdue), expired, move || {
+        // Held by the task, which keeps running past its deadline.
+        let _permit = permit;
+        let prove = client.prove(&pk, stdin);
+        let proof = match mode {
+            ProofMode::Core => prove.run(),
+            ProofMode::Compressed => prove.compressed().run(),
+        };
+        proof.map_err(|e| {
+            error!(error = ?e, "Proof generation failed");
+            DatabaseError::ProofGenerationFailed(e.to_string())
+        })
+    })
 }
 
+/// Proving and verifying keys of a guest program.
+type ElfKeys = (Arc<SP1ProvingKey>, Arc<SP1VerifyingKey>);
+
+/// Proving and verifying keys of each guest program, keyed by the program's
+/// SHA-256 and set up once per process.
+static ELF_KEYS: OnceLock<Mutex<HashMap<String, ElfKeys>>> = OnceLock::new();
+
 impl SP1Executor {
     #[instrument(skip(elf))]
     pub fn new(elf: &'static [u8]) -> Self {
+        Self::from_elf(Cow::Borrowed(elf))
+    }
+
+    /// Creates an executor for a guest program loaded at runtime.
+    #[instrument(skip(elf))]
+    pub fn from_elf_bytes(elf: Vec<u8>) -> Self {
+        Self::from_elf(Cow::Owned(elf))
+    }
+
+    /// Creates an executor, reusing the keys of an earlier executor for the
+    /// same program.
+    fn from_elf(elf: Cow<'static, [u8]>) -> Self {
         debug!("Creating new SP1Executor");
         let client = ProverClient::new();
         debug!("Generated ProverClient");
-        let (pk, vk) = client.setup(elf);
-        debug!("Generated proving and verifying keys");
+        let (pk, vk) = ELF_KEYS
+            .get_or_init(Default::default)
+            .lock()
+            .unwrap()
+            .entry(hash_value(&elf))
+            .or_insert_with(|| {
+                debug!("Generating proving and verifying keys");
+                let (pk, vk) = client.setup(&elf);
+                (Arc::new(pk), Arc::new(vk))
+            })
+            .clone();
+        Self::with_keys(client, elf, Some(pk), vk)
+    }
+
+    /// Creates an executor with the keys cached in the directory
+    /// `cache_path` by an earlier run, setting them up and caching them
+    /// there if it holds none for this program.
+    ///
+    /// The keys are read from disk rather than shared with other executors
+    /// in the process. A cache that fails to load is set up again; one that
+    /// fails to save is reported in the log and left out.
+    #[instrument(skip(elf))]
+    pub fn with_pk_vk_cache(elf: &'static [u8], cache_path: &Path) -> Self {
+        let client = ProverClient::new();
+        let elf_hash = hash_value(elf);
+        let (pk, vk) = match key_cache::load(cache_path, &elf_hash) {
+            Some(keys) => {
+                debug!("loaded proving and verifying keys from the cache");
+                keys
+            }
+            None => {
+                debug!("Generating proving and verifying keys");
+                let (pk, vk) = client.setup(elf);
+                if let Err(e) = key_cache::save(cache_path, &elf_hash, &pk, &vk) {
+                    warn!(error = %e, "failed to cache proving and verifying keys");
+                }
+                (pk, vk)
+            }
+        };
+        Self::with_keys(client, Cow::Borrowed(elf), Some(Arc::new(pk)), Arc::new(vk))
+    }
+
+    /// Removes the keys cached in `cache_path` by `with_pk_vk_cache`, so
+    /// that the next executor sets them up again.
+    pub fn invalidate_pk_vk_cache(cache_path: &Path) -> Result<(), DatabaseError> {
+        key_cache::invalidate(cache_path)
+    }
+
+    /// Creates an executor that verifies proofs and executes commands but
+    /// cannot prove; `execute_query` with `generate_proof` fails.
+    ///
+    /// The proving key is dropped as soon as the verifying key is derived,
+    /// and the keys of an earlier executor for the same program are reused
+    /// when there are any.
+    #[instrument(skip(elf))]
+    pub fn new_verifier_only(elf: &'static [u8]) -> Self {
+        debug!("Creating new verifier-only SP1Executor");
+        let client = ProverClient::new();
+        let cached = ELF_KEYS
+            .get_or_init(Default::default)
+            .lock()
+            .unwrap()
+            .get(&hash_value(elf))
+            .map(|(_, vk)| vk.clone());
+        let vk = cached.unwrap_or_else(|| {
+            debug!("Generating verifying key");
+            Arc::new(client.setup(elf).1)
+        });
+        Self::with_keys(client, Cow::Borrowed(elf), None, vk)
+    }
+
+    fn with_keys(
+        client: ProverClient,
+        elf: Cow<'static, [u8]>,
+        pk: Option<Arc<SP1ProvingKey>>,
+        vk: Arc<SP1VerifyingKey>,
+    ) -> Self {
         SP1Executor {
-            client,
-            elf,
+            client: Arc::new(client),
+            elf: elf.into(),
             pk,
             vk,
+            output_format: OutputFormat::default(),
+            proof_mode: ProofMode::default(),
+            pinned_vk_hash: None,
+            proof_timeout: None,
+            execute_timeout: None,
+            proof_limiter: ProofLimiter::new(None),
+            clock: Arc::new(SystemClock),
+            actor: None,
+            writer: None,
+            executions: AtomicU64::new(0),
+            cycles: AtomicU64::new(0),
+        }
+    }
+
+    /// Reads the time sent to the engine from `clock`.
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Names `actor` in the `OperationContext` of every command.
+    pub fn with_actor(mut self, actor: impl Into<String>) -> Self {
+        self.actor = Some(actor.into());
+        self
+    }
+
+    /// Signs every mutation with `writer`, for states that only accept
+    /// mutations signed by their admin key.
+    pub fn with_writer(mut self, writer: Writer) -> Self {
+        self.writer = Some(writer);
+        self
+    }
+
+    /// Fails proof generation that takes longer than `duration` with
+    /// `DatabaseError::ProofTimeout`. Time spent waiting for a permit, see
+    /// `with_max_concurrent_proofs`, counts against it.
+    ///
+    /// The zkVM cannot be interrupted, so a proof that times out keeps
+    /// running, and holding its permit, until it finishes. Until then new
+    /// proofs fail with `DatabaseError::ProofGenerationFailed` instead of
+    /// piling up behind it, see `proofs_overdue`.
+    pub fn with_timeout(mut self, duration: Duration) -> Self {
+        self.proof_timeout = Some(duration);
+        self
+    }
+
+    /// Generates at most `limit` proofs at once, at least one; further
+    /// callers wait until one finishes, the `Database` methods by awaiting
+    /// their permit. The wait counts against `with_timeout`. Execution
+    /// without a proof is not limited.
+    pub fn with_max_concurrent_proofs(mut self, limit: usize) -> Self {
+        self.proof_limiter = ProofLimiter::new(Some(limit));
+        self
+    }
+
+    /// Cap set with `with_max_concurrent_proofs`, if any.
+    pub fn max_concurrent_proofs(&self) -> Option<usize> {
+        self.proof_limiter.limit()
+    }
+
+    /// Number of proofs being generated right now, including those that
+    /// outlived their timeout.
+    pub fn proofs_in_flight(&self) -> usize {
+        self.proof_limiter.in_flight()
+    }
+
+    /// Number of proofs that outlived their timeout and are still running.
+    /// New proofs are refused while it is not zero, see `with_timeout`.
+    pub fn proofs_overdue(&self) -> usize {
+        self.proof_limiter.overdue().count()
+    }
+
+    /// Generates proofs in `mode`.
+    pub fn with_proof_mode(mut self, mode: ProofMode) -> Self {
+        self.proof_mode = mode;
+        self
+    }
+
+    pub fn proof_mode(&self) -> ProofMode {
+        self.proof_mode
+    }
+
+    /// Fails execution without a proof that takes longer than `duration`.
+    ///
+    /// Execution is much faster than proving, so this is usually set far
+    /// below the proof timeout.
+    pub fn with_execute_timeout(mut self, duration: Duration) -> Self {
+        self.execute_timeout = Some(duration);
+        self
+    }
+
+    /// The `0x`-prefixed hash of the verifying key, as returned by
+    /// `bytes32()`.
+    pub fn vk_hash(&self) -> String {
+        self.vk.bytes32()
+    }
+
+    /// The key proofs of the guest program are checked against.
+    pub fn verifying_key(&self) -> &SP1VerifyingKey {
+        &self.vk
+    }
+
+    /// Pins the expected verifying key hash, failing if the loaded program
+    /// has a different one. Proofs are then also checked against the pin.
+    pub fn expect_vk_hash(&mut self, expected: &str) -> Result<(), DatabaseError> {
+        let expected = normalize_vk_hash(expected);
+        let actual = normalize_vk_hash(&self.vk_hash());
+        if actual != expected {
+            error!(%expected, %actual, "verifying key does not match the pinned hash");
+            return Err(DatabaseError::VkMismatch { expected, actual });
+        }
+        self.pinned_vk_hash = Some(expected);
+        Ok(())
+    }
+
+    /// Whether the executor has a proving key, see `new_verifier_only`.
+    pub fn can_prove(&self) -> bool {
+        self.pk.is_some()
+    }
+
+    /// The proving key, unless the executor is verifier-only.
+    fn proving_key(&self) -> Result<Arc<SP1ProvingKey>, DatabaseError> {
+        self.pk.clone().ok_or_else(|| {
+            DatabaseError::ProofGenerationFailed(
+                "proving is unavailable: the executor was created verifier-only".to_string(),
+            )
+        })
+    }
+
+    /// Waits for a permit to generate a proof, see
+    /// `with_max_concurrent_proofs`, blocking the calling thread.
+    fn proof_permit(&self) -> Result<ProofPermit, DatabaseError> {
+        self.proving_key()?;
+        self.proof_limiter.acquire(self.proof_timeout)
+    }
+
+    /// `proof_permit`, awaited instead of blocking the calling thread.
+    pub(crate) async fn proof_permit_async(&self) -> Result<ProofPermit, DatabaseError> {
+        self.proving_key()?;
+        self.proof_limiter.acquire_async(self.proof_timeout).await
+    }
+
+    /// Number of calls to `execute_query` so far.
+    pub fn execution_count(&self) -> u64 {
+        self.executions.load(Ordering::Relaxed)
+    }
+
+    /// Instructions executed by successful calls to `execute_query` so far.
+    pub fn total_cycles(&self) -> u64 {
+        self.cycles.load(Ordering::Relaxed)
+    }
+
+    /// Sets the encoding the engine uses for its output.
+    pub fn set_output_format(&mut self, format: OutputFormat) {
+        self.output_format = format;
+    }
+
+    pub fn output_format(&self) -> OutputFormat {
+        self.output_format
+    }
+
+    /// Executes a command without a proof and returns the number of cycles
+    /// it took.
+    pub fn cycle_count(&self, state: &[u8], command: &Command) -> Result<u64, DatabaseError> {
+        let stdin = self.stdin(state, command);
+        let (_, report) = self.execute(stdin)?;
+        Ok(report.total_instruction_count())
+    }
+
+    /// Executes the program without a proof, within the execute timeout.
+    fn execute(
+        &self,
+        stdin: SP1Stdin,
+    ) -> Result<(SP1PublicValues, ExecutionReport), DatabaseError> {
+        let client = self.client.clone();
+        let elf = self.elf.clone();
+        deadline::run_with_deadline(
+            self.execute_timeout,
+            None,
+            |deadline| {
+                DatabaseError::QueryExecutionFailed(format!(
+                    "execution timed out after {:?}",
+                    deadline
+                ))
+            },
+            move || {
+                client.execute(&elf, stdin).run().map_err(|e| {
+                    error!(error = ?e, "Query execution failed");
+                    DatabaseError::QueryExecutionFailed(format!("Failed to execute query: {}", e))
+                })
+            },
+        )
+    }
+
+    /// Generates a proof under `permit`, within the proof timeout.
+    fn prove(
+        &self,
+        stdin: SP1Stdin,
+        permit: ProofPermit,
+    ) -> Result<SP1ProofWithPublicValues, DatabaseError> {
+        prove_with(
+            self.proving_key()?,
+            self.client.clone(),
+            self.proof_mode,
+            permit,
+            self.proof_timeout,
+            stdin,
+        )
+    }
+
+    /// Returns a job proving `command` against `state`, to run away from
+    /// the executor, e.g. on a blocking task. The job verifies the proof it
+    /// generates, and counts against the cap on concurrent proofs.
+    pub fn proof_job(
+        &self,
+        state: &[u8],
+        command: &Command,
+    ) -> impl FnOnce() -> Result<ProvenOutput, DatabaseError> + Send + 'static {
+        self.proof_job_for(command, self.stdin(state, command))
+    }
+
+    /// `proof_job` for the inputs `stdin` of `command`, e.g. those of a run
+    /// that was already committed, see `execute_stdin`.
+    pub(crate) fn proof_job_for(
+        &self,
+        command: &Command,
+        stdin: SP1Stdin,
+    ) -> impl FnOnce() -> Result<ProvenOutput, DatabaseError> + Send + 'static {
+        let kind = command.kind();
+        let pk = self.proving_key();
+        let client = self.client.clone();
+        let mode = self.proof_mode;
+        let limiter = self.proof_limiter.clone();
+        let timeout = self.proof_timeout;
+        let vk = self.vk.clone();
+        move || {
+            let pk = pk?;
+            let permit = limiter.acquire(timeout)?;
+            let started = Instant::now();
+            let proof = prove_with(pk, client.clone(), mode, permit, timeout, stdin)?;
+            metrics::record_proof(
+                kind,
+                started.elapsed(),
+                bincode::serialized_size(&proof).unwrap_or_default() as usize,
+            );
+            client.verify(&proof, &vk).map_err(|e| {
+                error!(error = ?e, "Proof verification failed");
+                DatabaseError::ProofVerificationFailed(e.to_string())
+            })?;
+            Ok(ProvenOutput {
+                proof_data: proof,
+                vk: vk.bytes32().as_bytes().to_vec(),
+            })
         }
     }
 
+    /// Inputs running `command` against `state`, in the layout of
+    /// `zkdb_core::PROTOCOL_VERSION`.
+    pub(crate) fn stdin(&self, state: &[u8], command: &Command) -> SP1Stdin {
+        let mut stdin = SP1Stdin::new();
+        stdin.write_slice(state);
+        stdin.write_vec(zkdb_core::encode_command_input(command));
+        stdin.write(&self.output_format);
+        let signature = match &self.writer {
+            Some(writer) if command.is_mutating() => Some(writer.sign(state, command)),
+            _ => None,
+        };
+        stdin.write(&Some(OperationContext {
+            unix_millis: self.clock.now_millis(),
+            actor: self.actor.clone(),
+            signature,
+        }));
+        stdin
+    }
+
     #[instrument(skip(self, state, command))]
     pub fn execute_query(
         &self,
@@ -232,41 +3454,84 @@ impl SP1Executor {
         command: &Command,
         generate_proof: bool,
     ) -> Result<ProvenQueryResult, DatabaseError> {
-        debug!(?generate_proof, "Preparing query execution");
-        debug!(?command, "Command to execute");
+        self.counted(command, || {
+            let permit = match generate_proof {
+                true => Some(self.proof_permit()?),
+                false => None,
+            };
+            self.run_query(command, self.stdin(state, command), permit)
+        })
+    }
 
-        let mut stdin = SP1Stdin::new();
-        stdin.write(&state.to_vec());
-        stdin.write(command);
+    /// `execute_query` with the permit to generate a proof already held,
+    /// see `proof_permit_async`. Without one, nothing is proven.
+    pub(crate) fn execute_query_with(
+        &self,
+        state: &[u8],
+        command: &Command,
+        permit: Option<ProofPermit>,
+    ) -> Result<ProvenQueryResult, DatabaseError> {
+        self.counted(command, || {
+            self.run_query(command, self.stdin(state, command), permit)
+        })
+    }
+
+    /// Executes `command` from the inputs `stdin`, see `stdin`, without a
+    /// proof, so that the same inputs can be proven later with
+    /// `proof_job_for`.
+    pub(crate) fn execute_stdin(
+        &self,
+        command: &Command,
+        stdin: SP1Stdin,
+    ) -> Result<ProvenQueryResult, DatabaseError> {
+        self.counted(command, || self.run_query(command, stdin, None))
+    }
+
+    /// Runs `run`, counting it as an execution of `command`.
+    fn counted(
+        &self,
+        command: &Command,
+        run: impl FnOnce() -> Result<ProvenQueryResult, DatabaseError>,
+    ) -> Result<ProvenQueryResult, DatabaseError> {
+        let started = Instant::now();
+        self.executions.fetch_add(1, Ordering::Relaxed);
+        let result = run();
+        let ok = matches!(&result, Ok(result) if result.data.get("error").is_none());
+        metrics::record_operation(command.kind(), ok, started.elapsed());
+        result
+    }
+
+    fn run_query(
+        &self,
+        command: &Command,
+        stdin: SP1Stdin,
+        permit: Option<ProofPermit>,
+    ) -> Result<ProvenQueryResult, DatabaseError> {
+        debug!(
+            generate_proof = permit.is_some(),
+            "Preparing query execution"
+        );
+        debug!(?command, "Command to execute");
         debug!(?stdin, "Stdin prepared");
 
-        if generate_proof {
+        if let Some(permit) = permit {
             debug!("Generating proof");
-            let proof = self
-                .client
-                .prove(&self.pk, stdin.clone())
-                .run()
-                .map_err(|e| {
-                    error!(error = ?e, "Proof generation failed");
-                    DatabaseError::ProofGenerationFailed(e.to_string())
-                })?;
+            let proving_started = Instant::now();
+            let proof = self.prove(stdin.clone(), permit)?;
             debug!("Proof generated successfully");
+            metrics::record_proof(
+                command.kind(),
+                proving_started.elapsed(),
+                bincode::serialized_size(&proof).unwrap_or_default() as usize,
+            );
 
-            let (output, _) = self
-                .client
-                .execute(self.elf, stdin.clone())
-                .run()
-                .map_err(|e| {
-                    error!(error = ?e, "Query execution failed");
-                    DatabaseError::QueryExecutionFailed(format!(
-                        "Failed to execute query with proof: {}",
-                        e
-                    ))
-                })?;
+            let (output, report) = self.execute(stdin)?;
             debug!("Query executed with proof");
+            metrics::record_cycles(command.kind(), report.total_instruction_count());
 
             self.parse_output(
                 output,
+                report.total_instruction_count(),
                 Some(ProvenOutput {
                     proof_data: proof,
                     vk: self.vk.bytes32().as_bytes().to_vec(),
@@ -274,15 +3539,10 @@ impl SP1Executor {
             )
         } else {
             debug!("Executing query without proof");
-            let (output, _) = self.client.execute(self.elf, stdin).run().map_err(|e| {
-                error!(error = ?e, "Query execution failed");
-                DatabaseError::QueryExecutionFailed(format!(
-                    "Failed to execute query without proof: {}",
-                    e
-                ))
-            })?;
+            let (output, report) = self.execute(stdin)?;
             debug!("Query executed successfully");
-            self.parse_output(output, None)
+            metrics::record_cycles(command.kind(), report.total_instruction_count());
+            self.parse_output(output, report.total_instruction_count(), None)
         }
     }
 
@@ -290,45 +3550,62 @@ impl SP1Executor {
     fn parse_output(
         &self,
         output: SP1PublicValues,
+        cycles: u64,
         proof: Option<ProvenOutput>,
     ) -> Result<ProvenQueryResult, DatabaseError> {
         debug!("Parsing query output");
-        let output_str = String::from_utf8(output.to_vec()).map_err(|e| {
-            error!(error = ?e, "Failed to parse output as UTF-8");
-            DatabaseError::QueryExecutionFailed(format!("Failed to parse output as UTF-8: {}", e))
-        })?;
-
-        let output_json: serde_json::Value = serde_json::from_str(&output_str).map_err(|e| {
-            error!(error = ?e, "Failed to parse output as JSON");
-            DatabaseError::QueryExecutionFailed(format!("Failed to parse output as JSON: {}", e))
-        })?;
-
-        debug!(?output_json, "Parsed output JSON");
+        if !output
+            .as_slice()
+            .starts_with(&zkdb_core::BINARY_OUTPUT_MAGIC)
+        {
+            if let Err(e) = std::str::from_utf8(output.as_slice()) {
+                error!(error = %e, "engine output is not UTF-8");
+                return Err(DatabaseError::NonUtf8Output(e.to_string()));
+            }
+        }
+        let QueryResult { data, new_state } =
+            QueryResult::decode(output.as_slice()).map_err(|e| {
+                error!(error = ?e, "Failed to parse output");
+                DatabaseError::from(e)
+            })?;
 
-        let data = output_json["data"].clone();
-        let new_state = output_json["new_state"]
-            .as_array()
-            .unwrap()
-            .iter()
-            .map(|v| v.as_u64().unwrap() as u8)
-            .collect();
+        debug!(?data, "Parsed output data");
 
-        if let Some(proof) = proof.clone() {
+        if let Some(proof) = &proof {
             debug!("Verifying generated proof");
-            self.verify_proof(&proof)?;
+            self.verify_proof(proof)?;
             debug!("Proof verified successfully");
         }
 
+        self.cycles.fetch_add(cycles, Ordering::Relaxed);
         Ok(ProvenQueryResult {
             data,
             new_state,
             sp1_proof: proof,
+            cycles,
         })
     }
 
     #[instrument(skip(self, proof))]
     pub fn verify_proof(&self, proof: &ProvenOutput) -> Result<bool, DatabaseError> {
         debug!("Verifying proof");
+        if let Some(expected) = &self.pinned_vk_hash {
+            let actual = normalize_vk_hash(&String::from_utf8_lossy(&proof.vk));
+            if &actual != expected {
+                return Err(DatabaseError::VkMismatch {
+                    expected: expected.clone(),
+                    actual,
+                });
+            }
+        }
+        // The SDK verifies core and compressed proofs along separate paths,
+        // chosen from the proof itself; other kinds are never generated here.
+        let Some(mode) = proof.proof_mode() else {
+            return Err(DatabaseError::ProofVerificationFailed(
+                "only core and compressed proofs are supported".to_string(),
+            ));
+        };
+        debug!(?mode, "verifying proof in mode");
         self.client
             .verify(&proof.proof_data, &self.vk)
             .map(|_| {
@@ -340,4 +3617,22 @@ impl SP1Executor {
                 DatabaseError::ProofVerificationFailed(e.to_string())
             })
     }
+
+    /// `verify_proof` for each of `proofs`, with the results in the same
+    /// order. The proofs are checked in parallel on the rayon thread pool,
+    /// since verifying needs no more than shared access to the executor;
+    /// a single proof is checked on the calling thread.
+    #[instrument(skip(self, proofs), fields(count = proofs.len()))]
+    pub fn verify_many(&self, proofs: &[ProvenOutput]) -> Vec<Result<bool, DatabaseError>> {
+        if proofs.len() < 2 {
+            return proofs
+                .iter()
+                .map(|proof| self.verify_proof(proof))
+                .collect();
+        }
+        proofs
+            .par_iter()
+            .map(|proof| self.verify_proof(proof))
+            .collect()
+    }
 }
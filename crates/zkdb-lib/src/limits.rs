@@ -0,0 +1,64 @@
+//! Limits on what callers may write, checked before the store is touched.
+//!
+//! The guest enforces `zkdb_core::MAX_KEY_LEN` and
+//! `zkdb_core::MAX_STATE_BYTES` on its own, so raising those two limits
+//! here past the engine's only moves the failure into the zkVM.
+
+use serde::{Deserialize, Serialize};
+use zkdb_core::{MAX_KEY_LEN, MAX_STATE_BYTES};
+
+use crate::DatabaseError;
+
+/// Limits on the keys, values, batches and state a `Database` accepts,
+/// set with `DatabaseBuilder::limits`. Violations fail with
+/// `DatabaseError::LimitExceeded`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DatabaseLimits {
+    /// Longest key, in bytes.
+    pub max_key_len: usize,
+    /// Largest value, in bytes.
+    pub max_value_bytes: usize,
+    /// Most entries in one batch, e.g. a `put_many` chunk.
+    pub max_batch_entries: usize,
+    /// Largest serialized state a mutation may produce, in bytes.
+    pub max_state_bytes: usize,
+}
+
+impl Default for DatabaseLimits {
+    fn default() -> Self {
+        DatabaseLimits {
+            max_key_len: MAX_KEY_LEN,
+            max_value_bytes: 16 * 1024 * 1024,
+            max_batch_entries: 10_000,
+            max_state_bytes: MAX_STATE_BYTES,
+        }
+    }
+}
+
+impl DatabaseLimits {
+    /// Checks a key and its value.
+    pub(crate) fn check_entry(
+        &self,
+        key: impl AsRef<[u8]>,
+        value: &[u8],
+    ) -> Result<(), DatabaseError> {
+        self.check_key(key)?;
+        check("value_bytes", self.max_value_bytes, value.len())
+    }
+
+    pub(crate) fn check_key(&self, key: impl AsRef<[u8]>) -> Result<(), DatabaseError> {
+        check("key_len", self.max_key_len, key.as_ref().len())
+    }
+
+    pub(crate) fn check_batch(&self, entries: usize) -> Result<(), DatabaseError> {
+        check("batch_entries", self.max_batch_entries, entries)
+    }
+
+    pub(crate) fn check_state(&self, state: &[u8]) -> Result<(), DatabaseError> {
+        check("state_bytes", self.max_state_bytes, state.len())
+    }
+}
+
+fn check(which: &str, limit: usize, actual: usize) -> Result<(), DatabaseError> {
+    zkdb_core::DatabaseError::check_limit(which, limit, actual).map_err(DatabaseError::from)
+}
@@ -0,0 +1,43 @@
+//! Tracing subscriber setup shared by the CLI and embedders.
+
+use serde::{Deserialize, Serialize};
+use tracing::Subscriber;
+use tracing_subscriber::fmt::MakeWriter;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::EnvFilter;
+
+/// How log events and `#[instrument]` spans are rendered.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+pub enum LogFormat {
+    /// Human readable lines.
+    #[default]
+    Pretty,
+    /// One JSON object per line, for log collectors.
+    Json,
+}
+
+/// Builds a subscriber writing in `format` to `make_writer`.
+///
+/// The level filter is read from `RUST_LOG`, defaulting to `info`.
+pub fn build_subscriber<W>(format: LogFormat, make_writer: W) -> Box<dyn Subscriber + Send + Sync>
+where
+    W: for<'a> MakeWriter<'a> + Send + Sync + 'static,
+{
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
+    let builder = tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_writer(make_writer);
+    match format {
+        LogFormat::Pretty => Box::new(builder.finish()),
+        LogFormat::Json => Box::new(builder.json().with_current_span(true).finish()),
+    }
+}
+
+/// Installs a global subscriber writing to stdout in `format`.
+///
+/// Panics if a global subscriber is already set.
+pub fn init_tracing(format: LogFormat) {
+    build_subscriber(format, std::io::stdout)
+        .try_init()
+        .expect("Failed to install tracing subscriber");
+}
@@ -0,0 +1,28 @@
+//! Merging a foreign state into a database.
+
+use serde::{Deserialize, Serialize};
+
+/// What `Database::merge_from` does with a key held by both databases under
+/// different leaves.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+pub enum ConflictPolicy {
+    /// Keeps the local value.
+    PreferLocal,
+    /// Replaces the local value with the foreign one.
+    PreferOther,
+    /// Fails the merge before anything is written.
+    Error,
+}
+
+/// Outcome of `Database::merge_from`.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MergeReport {
+    /// Number of foreign keys inserted into the tree, including conflicts
+    /// resolved in favour of the foreign value.
+    pub added: usize,
+    /// Number of foreign keys left out: those already held with the same
+    /// leaf and conflicts resolved in favour of the local value.
+    pub skipped: usize,
+    /// Keys held by both databases under different leaves, in sorted order.
+    pub conflicts: Vec<String>,
+}
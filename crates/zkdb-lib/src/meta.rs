@@ -0,0 +1,132 @@
+//! Optional metadata stored next to values, under `_meta/`.
+//!
+//! Metadata is kept out of the value so that leaves keep committing to the
+//! raw bytes. Each record remembers the hash of the value it describes and
+//! is ignored once the value is overwritten without metadata.
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use zkdb_store::{Store, StoreError};
+
+use crate::DatabaseError;
+
+/// Prefix of every key the metadata writes to the store.
+pub const META_PREFIX: &str = "_meta/";
+
+/// Describes a value written with `Database::put_with_meta`.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Metadata {
+    /// MIME type of the value, e.g. `application/json`.
+    pub content_type: Option<String>,
+    /// Free-form tags set by the writer.
+    pub tags: BTreeMap<String, String>,
+    /// Unix time in milliseconds the key was first written with metadata.
+    /// Set by `put_with_meta`.
+    pub created_at: u64,
+    /// Unix time in milliseconds of the latest write. Set by `put_with_meta`.
+    pub updated_at: u64,
+    /// Unix time in milliseconds from which reads fail with
+    /// `DatabaseError::Expired`. Set by `put_with_ttl`.
+    #[serde(default)]
+    pub expires_at: Option<u64>,
+}
+
+impl Metadata {
+    /// Whether the value has expired at Unix time `now` in milliseconds.
+    pub fn is_expired(&self, now: u64) -> bool {
+        self.expires_at.is_some_and(|expires_at| expires_at <= now)
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct MetaRecord {
+    value_hash: String,
+    metadata: Metadata,
+}
+
+fn meta_key(key: &str) -> String {
+    format!("{}{}", META_PREFIX, key)
+}
+
+async fn read_record(store: &dyn Store, key: &str) -> Result<Option<MetaRecord>, DatabaseError> {
+    match store.get(&meta_key(key)).await {
+        Ok(bytes) => serde_json::from_slice(&bytes)
+            .map(Some)
+            .map_err(|e| DatabaseError::Codec(format!("failed to decode metadata: {}", e))),
+        Err(StoreError::NotFound(_)) => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Returns the metadata of `key` if it describes the value with the
+/// hex-encoded `value_hash`.
+pub(crate) async fn read(
+    store: &dyn Store,
+    key: &str,
+    value_hash: &str,
+) -> Result<Option<Metadata>, DatabaseError> {
+    Ok(read_record(store, key)
+        .await?
+        .filter(|record| record.value_hash == value_hash)
+        .map(|record| record.metadata))
+}
+
+/// Stores `metadata` for the value of `key` with the hex-encoded
+/// `value_hash` as written at `now`, keeping the creation time of earlier
+/// metadata.
+pub(crate) async fn write(
+    store: &dyn Store,
+    key: &str,
+    value_hash: String,
+    mut metadata: Metadata,
+    now: u64,
+) -> Result<Metadata, DatabaseError> {
+    metadata.created_at = match read_record(store, key).await? {
+        Some(previous) => previous.metadata.created_at,
+        None => now,
+    };
+    metadata.updated_at = now;
+    let record = MetaRecord {
+        value_hash,
+        metadata,
+    };
+    let encoded = serde_json::to_vec(&record)
+        .map_err(|e| DatabaseError::Codec(format!("failed to encode metadata: {}", e)))?;
+    store.put(&meta_key(key), &encoded).await?;
+    Ok(record.metadata)
+}
+
+/// Returns the keys among `keys` whose current value has expired at `now`.
+///
+/// Metadata left over from a value since overwritten is ignored, so a key
+/// rewritten with `put` no longer expires.
+pub(crate) async fn expired(
+    store: &dyn Store,
+    keys: &[String],
+    now: u64,
+    value_hash: impl Fn(&[u8]) -> String,
+) -> Result<Vec<String>, DatabaseError> {
+    let mut expired = Vec::new();
+    for key in keys {
+        let Some(record) = read_record(store, key).await? else {
+            continue;
+        };
+        if !record.metadata.is_expired(now) {
+            continue;
+        }
+        match store.get(key).await {
+            Ok(value) if value_hash(&value) == record.value_hash => expired.push(key.clone()),
+            Ok(_) | Err(StoreError::NotFound(_)) => {}
+            Err(e) => return Err(e.into()),
+        }
+    }
+    Ok(expired)
+}
+
+/// Removes the metadata of `key`, if any.
+pub(crate) async fn delete(store: &dyn Store, key: &str) -> Result<(), DatabaseError> {
+    match store.delete(&meta_key(key)).await {
+        Ok(()) | Err(StoreError::NotFound(_)) => Ok(()),
+        Err(e) => Err(e.into()),
+    }
+}
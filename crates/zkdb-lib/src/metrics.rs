@@ -0,0 +1,89 @@
+//! Metrics recorded for `Database` operations through the `metrics` facade.
+//!
+//! Recording is compiled in with the `metrics` feature, and any `metrics`
+//! recorder picks the values up. `install_prometheus_exporter` sets up a
+//! ready-made Prometheus endpoint.
+
+use std::time::Duration;
+
+/// Counter of executed commands, labelled by `command` and `outcome`.
+pub const OPERATIONS_TOTAL: &str = "zkdb_operations_total";
+/// Histogram of the time spent executing a command, labelled by `command`.
+pub const EXECUTE_DURATION_SECONDS: &str = "zkdb_execute_duration_seconds";
+/// Histogram of the time spent generating SP1 proofs, labelled by `command`.
+pub const PROVE_DURATION_SECONDS: &str = "zkdb_prove_duration_seconds";
+/// Histogram of serialized SP1 proof sizes, labelled by `command`.
+pub const PROOF_SIZE_BYTES: &str = "zkdb_proof_size_bytes";
+/// Histogram of zkVM cycles per execution, labelled by `command`.
+pub const CYCLES: &str = "zkdb_cycles";
+/// Gauge of the number of keys in the committed state, across all trees.
+pub const KEY_COUNT: &str = "zkdb_key_count";
+/// Gauge of the size of the committed state.
+pub const STATE_BYTES: &str = "zkdb_state_bytes";
+
+/// Label holding the command variant name.
+pub const LABEL_COMMAND: &str = "command";
+/// Label holding `OUTCOME_OK` or `OUTCOME_ERROR`.
+pub const LABEL_OUTCOME: &str = "outcome";
+pub const OUTCOME_OK: &str = "ok";
+pub const OUTCOME_ERROR: &str = "error";
+
+#[cfg(feature = "metrics")]
+pub(crate) fn record_operation(command: &'static str, ok: bool, elapsed: Duration) {
+    let outcome = if ok { OUTCOME_OK } else { OUTCOME_ERROR };
+    ::metrics::counter!(OPERATIONS_TOTAL, LABEL_COMMAND => command, LABEL_OUTCOME => outcome)
+        .increment(1);
+    ::metrics::histogram!(EXECUTE_DURATION_SECONDS, LABEL_COMMAND => command)
+        .record(elapsed.as_secs_f64());
+}
+
+#[cfg(feature = "metrics")]
+pub(crate) fn record_proof(command: &'static str, elapsed: Duration, size_bytes: usize) {
+    ::metrics::histogram!(PROVE_DURATION_SECONDS, LABEL_COMMAND => command)
+        .record(elapsed.as_secs_f64());
+    ::metrics::histogram!(PROOF_SIZE_BYTES, LABEL_COMMAND => command).record(size_bytes as f64);
+}
+
+#[cfg(feature = "metrics")]
+pub(crate) fn record_cycles(command: &'static str, cycles: u64) {
+    ::metrics::histogram!(CYCLES, LABEL_COMMAND => command).record(cycles as f64);
+}
+
+/// Records the size of the committed `state` and the number of keys it
+/// holds, as counted by the engine's `key_count`.
+#[cfg(feature = "metrics")]
+pub(crate) fn record_state(state: &[u8], key_count: fn(&[u8]) -> Option<usize>) {
+    ::metrics::gauge!(STATE_BYTES).set(state.len() as f64);
+    if let Some(keys) = key_count(state) {
+        ::metrics::gauge!(KEY_COUNT).set(keys as f64);
+    }
+}
+
+/// Serves every recorded metric in the Prometheus text format at
+/// `http://{addr}/metrics`.
+///
+/// Installs the global `metrics` recorder, so it can only be called once.
+#[cfg(feature = "metrics")]
+pub fn install_prometheus_exporter(addr: std::net::SocketAddr) -> Result<(), crate::DatabaseError> {
+    metrics_exporter_prometheus::PrometheusBuilder::new()
+        .with_http_listener(addr)
+        .install()
+        .map_err(|e| {
+            crate::DatabaseError::QueryExecutionFailed(format!(
+                "Failed to install Prometheus exporter: {}",
+                e
+            ))
+        })
+}
+
+#[cfg(not(feature = "metrics"))]
+pub(crate) fn record_operation(_command: &'static str, _ok: bool, _elapsed: Duration) {}
+
+#[cfg(not(feature = "metrics"))]
+pub(crate) fn record_proof(_command: &'static str, _elapsed: Duration, _size_bytes: usize) {}
+
+#[cfg(not(feature = "metrics"))]
+pub(crate) fn record_cycles(_command: &'static str, _cycles: u64) {}
+
+#[cfg(not(feature = "metrics"))]
+pub(crate) fn record_state(_state: &[u8], _key_count: fn(&[u8]) -> Option<usize>) {}
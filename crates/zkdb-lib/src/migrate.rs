@@ -0,0 +1,106 @@
+//! Upgrades of serialized state written by earlier releases.
+//!
+//! The engine still reads the legacy layout, but upgrading it once keeps the
+//! fallback off the hot path and lets the state be handed to tools that only
+//! understand the current layout.
+
+use serde::{Deserialize, Serialize};
+use zkdb_core::merkle::{split_version, LegacyMerkleState, MerkleState, STATE_VERSION};
+
+use crate::DatabaseError;
+
+/// Layout of a serialized state.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum StateVersion {
+    /// Leaves and key indices only, written before history was tracked.
+    V0,
+    /// `MerkleState` behind a version tag, see `MerkleState::to_bytes`.
+    V1,
+}
+
+impl StateVersion {
+    /// The layout the engine writes.
+    pub const CURRENT: StateVersion = StateVersion::V1;
+
+    /// Detects the layout of `state`. An empty state is current.
+    ///
+    /// A versioned state is identified by its tag; an unversioned one must
+    /// decode as a `V0` state.
+    pub fn detect(state: &[u8]) -> Result<StateVersion, DatabaseError> {
+        if state.is_empty() {
+            return Ok(StateVersion::CURRENT);
+        }
+        if let Some((version, _)) = split_version(state) {
+            return match version {
+                STATE_VERSION => Ok(StateVersion::V1),
+                version => Err(DatabaseError::MigrationFailed(format!(
+                    "unsupported state version {}",
+                    version
+                ))),
+            };
+        }
+        match bincode::deserialize::<LegacyMerkleState>(state) {
+            Ok(_) => Ok(StateVersion::V0),
+            Err(e) => Err(DatabaseError::MigrationFailed(format!(
+                "state matches no known layout: {}",
+                e
+            ))),
+        }
+    }
+}
+
+/// Outcome of `Database::auto_migrate`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MigrationReport {
+    /// Layout the state was in.
+    pub from: StateVersion,
+    /// Layout the state is in now.
+    pub to: StateVersion,
+    /// Names of the migrations applied, in order.
+    pub applied: Vec<String>,
+}
+
+/// Upgrades a `V0` state to the `V1` layout: the root tree, with an empty
+/// history, its root cached and no past roots, under the default settings.
+///
+/// Versions count from the upgrade: the roots the tree had before it cannot
+/// be recovered from its leaves.
+pub struct MigrationV0toV1;
+
+impl MigrationV0toV1 {
+    /// Name recorded in `MigrationReport::applied`.
+    pub const NAME: &'static str = "v0-to-v1";
+
+    /// Re-serializes a `V0` state in the `V1` layout.
+    pub fn migrate(bytes: &[u8]) -> Result<Vec<u8>, DatabaseError> {
+        let legacy: LegacyMerkleState = bincode::deserialize(bytes)
+            .map_err(|e| DatabaseError::MigrationFailed(format!("not a v0 state: {}", e)))?;
+        let mut state = MerkleState::from(legacy);
+        state.refresh_root();
+        Ok(state.to_bytes())
+    }
+}
+
+/// Applies every migration needed to bring `state` to the current layout.
+pub(crate) fn migrate(state: &[u8]) -> Result<(Option<Vec<u8>>, MigrationReport), DatabaseError> {
+    let from = StateVersion::detect(state)?;
+    let mut report = MigrationReport {
+        from,
+        to: from,
+        applied: Vec::new(),
+    };
+    let mut migrated: Option<Vec<u8>> = None;
+    if report.to == StateVersion::V0 {
+        migrated = Some(MigrationV0toV1::migrate(state)?);
+        report.applied.push(MigrationV0toV1::NAME.to_string());
+        report.to = StateVersion::V1;
+    }
+    Ok((migrated, report))
+}
+
+/// Brings a state written by any release to the current layout, returning
+/// it unchanged if it already is.
+pub fn migrate_state(state: &[u8]) -> Result<Vec<u8>, DatabaseError> {
+    let (migrated, _) = migrate(state)?;
+    Ok(migrated.unwrap_or_else(|| state.to_vec()))
+}
@@ -0,0 +1,87 @@
+//! Notifications published when the committed state of a `Database` changes.
+
+use serde::{Deserialize, Serialize};
+use tokio::sync::{broadcast, watch};
+use tracing::debug;
+
+/// Number of undelivered changes a subscriber may fall behind before it
+/// starts missing events and receives `RecvError::Lagged`.
+pub const STATE_CHANGE_CHANNEL_CAPACITY: usize = 256;
+
+/// A committed change to the state of a `Database`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StateChange {
+    /// Variant name of the command that produced the change.
+    pub command_kind: String,
+    /// Key the command operated on, if any.
+    pub key: Option<String>,
+    /// Hex-encoded root before the change, `None` for an empty tree.
+    pub prev_root: Option<String>,
+    /// Hex-encoded root after the change, `None` for an empty tree.
+    pub new_root: Option<String>,
+    /// Unix time of the change in milliseconds.
+    pub timestamp: u64,
+    /// Hex-encoded hash of the SP1 proof generated for the change, if any.
+    pub proof_id: Option<String>,
+}
+
+type StateChangeCallback = Box<dyn Fn(&StateChange) + Send + Sync>;
+
+/// Fans state changes out to broadcast subscribers, root watchers and
+/// registered callbacks.
+pub(crate) struct StateNotifier {
+    sender: broadcast::Sender<StateChange>,
+    root: watch::Sender<Option<String>>,
+    callbacks: Vec<StateChangeCallback>,
+}
+
+impl StateNotifier {
+    pub(crate) fn new() -> Self {
+        let (sender, _) = broadcast::channel(STATE_CHANGE_CHANNEL_CAPACITY);
+        let (root, _) = watch::channel(None);
+        StateNotifier {
+            sender,
+            root,
+            callbacks: Vec::new(),
+        }
+    }
+
+    /// Watches the root, starting from `current_root`.
+    pub(crate) fn watch_root(
+        &self,
+        current_root: Option<String>,
+    ) -> watch::Receiver<Option<String>> {
+        self.root.send_replace(current_root);
+        self.root.subscribe()
+    }
+
+    pub(crate) fn subscribe(&self) -> broadcast::Receiver<StateChange> {
+        self.sender.subscribe()
+    }
+
+    pub(crate) fn on_state_change<F>(&mut self, callback: F)
+    where
+        F: Fn(&StateChange) + Send + Sync + 'static,
+    {
+        self.callbacks.push(Box::new(callback));
+    }
+
+    /// Whether anyone would observe a published change.
+    pub(crate) fn has_listeners(&self) -> bool {
+        !self.callbacks.is_empty()
+            || self.sender.receiver_count() > 0
+            || self.root.receiver_count() > 0
+    }
+
+    /// Delivers a change without waiting on subscribers.
+    pub(crate) fn publish(&self, change: StateChange) {
+        for callback in &self.callbacks {
+            callback(&change);
+        }
+        self.root.send_replace(change.new_root.clone());
+        // Sending only fails when nobody is subscribed.
+        if self.sender.send(change).is_err() {
+            debug!("no state change subscribers");
+        }
+    }
+}
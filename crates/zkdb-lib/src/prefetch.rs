@@ -0,0 +1,49 @@
+//! Cache of store values loaded ahead of `Database::get`.
+
+use lru::LruCache;
+use std::num::NonZeroUsize;
+use std::sync::Mutex;
+
+/// LRU cache of store values, disabled when its capacity is zero.
+///
+/// Cached values still have their hash checked against the tree on every
+/// read; the cache only saves the round trip to the store.
+pub(crate) struct ValueCache {
+    entries: Option<Mutex<LruCache<String, Vec<u8>>>>,
+}
+
+impl ValueCache {
+    pub(crate) fn new(capacity: usize) -> Self {
+        ValueCache {
+            entries: NonZeroUsize::new(capacity)
+                .map(|capacity| Mutex::new(LruCache::new(capacity))),
+        }
+    }
+
+    /// Number of entries the cache holds at most, zero when disabled.
+    pub(crate) fn capacity(&self) -> usize {
+        self.entries
+            .as_ref()
+            .map_or(0, |entries| entries.lock().unwrap().cap().get())
+    }
+
+    pub(crate) fn is_enabled(&self) -> bool {
+        self.entries.is_some()
+    }
+
+    pub(crate) fn get(&self, key: &str) -> Option<Vec<u8>> {
+        self.entries.as_ref()?.lock().unwrap().get(key).cloned()
+    }
+
+    pub(crate) fn insert(&self, key: String, value: Vec<u8>) {
+        if let Some(entries) = &self.entries {
+            entries.lock().unwrap().put(key, value);
+        }
+    }
+
+    pub(crate) fn invalidate(&self, key: &str) {
+        if let Some(entries) = &self.entries {
+            entries.lock().unwrap().pop(key);
+        }
+    }
+}
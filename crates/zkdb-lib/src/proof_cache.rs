@@ -0,0 +1,61 @@
+//! Cache of `Database::prove` results for the current root.
+
+use lru::LruCache;
+use std::num::NonZeroUsize;
+use std::sync::Mutex;
+
+use crate::ProvenQueryResult;
+
+/// A key and the hex-encoded root it was proven against.
+type ProofKey = (String, Option<String>);
+
+/// LRU cache of inclusion proofs keyed by key and root, disabled when its
+/// capacity is zero.
+///
+/// A proof only depends on the tree, so an entry stays valid for as long as
+/// the root it was generated against; the whole cache is dropped on every
+/// mutation anyway to keep it from holding proofs of old roots.
+pub(crate) struct ProofCache {
+    entries: Option<Mutex<LruCache<ProofKey, ProvenQueryResult>>>,
+}
+
+impl ProofCache {
+    pub(crate) fn new(capacity: usize) -> Self {
+        ProofCache {
+            entries: NonZeroUsize::new(capacity)
+                .map(|capacity| Mutex::new(LruCache::new(capacity))),
+        }
+    }
+
+    /// Number of entries the cache holds at most, zero when disabled.
+    pub(crate) fn capacity(&self) -> usize {
+        self.entries
+            .as_ref()
+            .map_or(0, |entries| entries.lock().unwrap().cap().get())
+    }
+
+    /// Returns the cached proof of `key` against `root`, if it has an SP1
+    /// proof or none was asked for.
+    pub(crate) fn get(
+        &self,
+        key: &str,
+        root: &Option<String>,
+        generate_proof: bool,
+    ) -> Option<ProvenQueryResult> {
+        let mut entries = self.entries.as_ref()?.lock().unwrap();
+        let result = entries.get(&(key.to_string(), root.clone()))?;
+        (result.sp1_proof.is_some() || !generate_proof).then(|| result.clone())
+    }
+
+    pub(crate) fn insert(&self, key: String, root: Option<String>, result: ProvenQueryResult) {
+        if let Some(entries) = &self.entries {
+            entries.lock().unwrap().put((key, root), result);
+        }
+    }
+
+    pub(crate) fn clear(&self) {
+        if let Some(entries) = &self.entries {
+            entries.lock().unwrap().clear();
+        }
+    }
+}
@@ -0,0 +1,291 @@
+//! Paged reads of key ranges, checked against the root.
+//!
+//! `Command::Range` reports a page of keys with a multiproof over their
+//! leaves. The multiproof shows each leaf is in the tree, but leaves are
+//! laid out in insertion order and the mapping from keys to leaves is not
+//! part of the root, so it shows neither that the leaves belong to those
+//! keys nor that no key in the range was left out. Only a proof of the
+//! execution covers the page as a whole.
+//!
+//! Under `TreeLayout::Sorted` leaves commit to their keys and are ordered
+//! by them, so a page is a run of adjacent leaves and comes with the keys
+//! just before and after it. The multiproof over all of them then shows
+//! that no key of the range is missing from the page, and the same
+//! neighbours prove a key absent for `Command::ProveAbsence`.
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::ops::Range;
+use zkdb_core::backend::{RsMerkle, TreeBackend};
+use zkdb_verify::KeyedLeaf;
+
+use crate::{verify, DatabaseError};
+
+/// A key read by `Database::range`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RangeEntry {
+    /// The key, hex-encoded when `binary`.
+    pub key: String,
+    /// Whether the key is not UTF-8.
+    pub binary: bool,
+    /// Index of the key's leaf.
+    pub index: usize,
+    /// The value, checked against its leaf.
+    pub value: Vec<u8>,
+}
+
+/// One page of `Database::range`.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RangePage {
+    /// Keys of the page, in key order.
+    pub entries: Vec<RangeEntry>,
+    /// First key past the page, to pass as the start of the next one.
+    /// `None` once the range is exhausted.
+    pub next: Option<String>,
+    /// Whether the page was proven to hold every key of the range up to
+    /// `next`, which only `TreeLayout::Sorted` states allow.
+    pub complete: bool,
+}
+
+/// A key of the output of `Command::Range`. `value_hash` is reported under
+/// `TreeLayout::Sorted` only, the leaf being the hash otherwise.
+#[derive(Deserialize)]
+pub(crate) struct RangeKey {
+    pub key: String,
+    pub binary: bool,
+    pub index: usize,
+    pub leaf: String,
+    #[serde(default)]
+    pub value_hash: Option<String>,
+}
+
+impl RangeKey {
+    /// The key as bytes.
+    pub(crate) fn bytes(&self) -> Result<Vec<u8>, DatabaseError> {
+        if self.binary {
+            hex::decode(&self.key)
+                .map_err(|e| DatabaseError::Codec(format!("invalid binary key: {}", e)))
+        } else {
+            Ok(self.key.as_bytes().to_vec())
+        }
+    }
+
+    /// Hex-encoded hash of the value of the key.
+    pub(crate) fn value_hash(&self) -> &str {
+        self.value_hash.as_deref().unwrap_or(&self.leaf)
+    }
+
+    fn keyed_leaf(&self) -> Result<KeyedLeaf, DatabaseError> {
+        Ok(KeyedLeaf {
+            key: self.bytes()?,
+            value_hash: verify::decode_hash("value_hash", self.value_hash())
+                .map_err(|e| DatabaseError::Codec(e.to_string()))?,
+            index: self.index,
+        })
+    }
+}
+
+/// Output of `Command::Range`. The multiproof fields are absent from an
+/// empty page, unless it is `complete`.
+#[derive(Deserialize)]
+pub(crate) struct RangeOutput {
+    pub keys: Vec<RangeKey>,
+    pub next: Option<String>,
+    pub root: Option<String>,
+    pub total_leaves: Option<usize>,
+    #[serde(default)]
+    pub indices: Vec<usize>,
+    #[serde(default)]
+    pub leaves: Vec<String>,
+    pub multiproof: Option<String>,
+    #[serde(default)]
+    pub before: Option<RangeKey>,
+    #[serde(default)]
+    pub after: Option<RangeKey>,
+    #[serde(default)]
+    pub complete: bool,
+}
+
+impl RangeOutput {
+    /// Checks that the multiproof proves the leaf of every key of the page
+    /// against `root` and, for a `complete` page, that no key of
+    /// `key_range` up to `next` was left out.
+    pub(crate) fn check(
+        &self,
+        root: Option<&str>,
+        key_range: &Range<&str>,
+    ) -> Result<(), DatabaseError> {
+        if self.complete {
+            return self.check_complete(root, key_range);
+        }
+        if self.keys.is_empty() {
+            return Ok(());
+        }
+        let failed = |reason: &str| DatabaseError::ProofVerificationFailed(reason.to_string());
+        let codec = |e: verify::VerifyError| DatabaseError::Codec(e.to_string());
+        let reported = self
+            .root
+            .as_deref()
+            .ok_or_else(|| failed("range has no root"))?;
+        if root != Some(reported) {
+            return Err(failed("range was read against another root"));
+        }
+        let leaves = self
+            .leaves
+            .iter()
+            .map(|leaf| verify::decode_hash("leaf", leaf))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(codec)?;
+        let proven: HashMap<usize, &String> =
+            self.indices.iter().copied().zip(&self.leaves).collect();
+        if self
+            .keys
+            .iter()
+            .any(|key| proven.get(&key.index) != Some(&&key.leaf))
+        {
+            return Err(failed("range lists a leaf the multiproof does not cover"));
+        }
+        let proof = base64::decode(self.multiproof.as_deref().unwrap_or_default())
+            .map_err(|e| DatabaseError::Codec(format!("invalid base64 proof: {}", e)))?;
+        let valid = RsMerkle::verify(
+            verify::decode_hash("root", reported).map_err(codec)?,
+            &self.indices,
+            &leaves,
+            self.total_leaves.unwrap_or_default(),
+            &proof,
+        );
+        if !valid {
+            return Err(failed("range multiproof does not match the root"));
+        }
+        Ok(())
+    }
+
+    /// Checks a page of a sorted tree: its keys lie in `key_range` and form
+    /// a run of adjacent leaves with the keys around it, the key before
+    /// lies below the range, and the key after is `next` or lies past it.
+    fn check_complete(
+        &self,
+        root: Option<&str>,
+        key_range: &Range<&str>,
+    ) -> Result<(), DatabaseError> {
+        let failed = |reason: &str| DatabaseError::ProofVerificationFailed(reason.to_string());
+        let run = self
+            .keys
+            .iter()
+            .map(RangeKey::keyed_leaf)
+            .collect::<Result<Vec<_>, _>>()?;
+        let (start, end) = (key_range.start.as_bytes(), key_range.end.as_bytes());
+        if run
+            .iter()
+            .any(|entry| entry.key.as_slice() < start || entry.key.as_slice() >= end)
+        {
+            return Err(failed("range lists a key outside the range"));
+        }
+        let before = self.before.as_ref().map(RangeKey::keyed_leaf).transpose()?;
+        if before
+            .as_ref()
+            .is_some_and(|before| before.key.as_slice() >= start)
+        {
+            return Err(failed("range starts past a key of the range"));
+        }
+        let after = self.after.as_ref().map(RangeKey::keyed_leaf).transpose()?;
+        let bounded = match (&self.next, &after) {
+            (Some(next), Some(after)) => next.as_bytes() == after.key.as_slice(),
+            (Some(_), None) => false,
+            (None, Some(after)) => after.key.as_slice() >= end,
+            (None, None) => true,
+        };
+        if !bounded {
+            return Err(failed("range stops before the end of the range"));
+        }
+        check_run(self, root, before.as_ref(), &run, after.as_ref())
+    }
+}
+
+/// Output of `Command::ProveAbsence`. The multiproof fields are absent when
+/// the tree is empty.
+#[derive(Deserialize)]
+pub(crate) struct AbsenceOutput {
+    pub key: String,
+    pub root: Option<String>,
+    pub total_leaves: Option<usize>,
+    pub multiproof: Option<String>,
+    pub before: Option<RangeKey>,
+    pub after: Option<RangeKey>,
+}
+
+impl AbsenceOutput {
+    /// Checks that the keys around `key` are adjacent leaves under `root`
+    /// with `key` between them.
+    pub(crate) fn check(&self, root: Option<&str>) -> Result<(), DatabaseError> {
+        let failed = |reason: &str| DatabaseError::ProofVerificationFailed(reason.to_string());
+        let codec = |e: verify::VerifyError| DatabaseError::Codec(e.to_string());
+        if root != self.root.as_deref() {
+            return Err(failed("absence was proven against another root"));
+        }
+        let before = self.before.as_ref().map(RangeKey::keyed_leaf).transpose()?;
+        let after = self.after.as_ref().map(RangeKey::keyed_leaf).transpose()?;
+        let Some(root) = root else {
+            // Nothing is in an empty tree.
+            return if before.is_none() && after.is_none() {
+                Ok(())
+            } else {
+                Err(failed("absence proof lists keys of an empty tree"))
+            };
+        };
+        let proof = base64::decode(self.multiproof.as_deref().unwrap_or_default())
+            .map_err(|e| DatabaseError::Codec(format!("invalid base64 proof: {}", e)))?;
+        let valid = verify::verify_absence(
+            verify::decode_hash("root", root).map_err(codec)?,
+            self.total_leaves.unwrap_or_default(),
+            self.key.as_bytes(),
+            before.as_ref(),
+            after.as_ref(),
+            &proof,
+        )
+        .map_err(codec)?;
+        if !valid {
+            return Err(failed("absence proof does not hold"));
+        }
+        Ok(())
+    }
+}
+
+/// Checks the multiproof of `output` proves `run`, with `before` and
+/// `after` around it, as adjacent leaves under `root`. An empty tree has
+/// no root and no leaves.
+fn check_run(
+    output: &RangeOutput,
+    root: Option<&str>,
+    before: Option<&KeyedLeaf>,
+    run: &[KeyedLeaf],
+    after: Option<&KeyedLeaf>,
+) -> Result<(), DatabaseError> {
+    let failed = |reason: &str| DatabaseError::ProofVerificationFailed(reason.to_string());
+    if root != output.root.as_deref() {
+        return Err(failed("range was read against another root"));
+    }
+    let Some(root) = root else {
+        return if before.is_none() && run.is_empty() && after.is_none() {
+            Ok(())
+        } else {
+            Err(failed("range lists keys of an empty tree"))
+        };
+    };
+    let codec = |e: verify::VerifyError| DatabaseError::Codec(e.to_string());
+    let proof = base64::decode(output.multiproof.as_deref().unwrap_or_default())
+        .map_err(|e| DatabaseError::Codec(format!("invalid base64 proof: {}", e)))?;
+    let valid = verify::verify_sorted_run(
+        verify::decode_hash("root", root).map_err(codec)?,
+        output.total_leaves.unwrap_or_default(),
+        before,
+        run,
+        after,
+        &proof,
+    )
+    .map_err(codec)?;
+    if !valid {
+        return Err(failed("range multiproof does not prove adjacent leaves"));
+    }
+    Ok(())
+}
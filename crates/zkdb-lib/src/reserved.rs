@@ -0,0 +1,138 @@
+//! Keys reserved for bookkeeping.
+//!
+//! The operation log, key history, value index, metadata and named trees
+//! keep their entries in the `Store` under keys starting with
+//! `zkdb_core::DEFAULT_RESERVED_PREFIX`, e.g. `_wal/head`, and `put` rejects
+//! user keys starting with it. A database created with another reserved
+//! prefix, for users whose own keys start with `_`, keeps those entries
+//! under that prefix instead: `_wal/head` becomes `<prefix>wal/head`. The
+//! modules keep building `_` keys and go through `ReservedStore`, which
+//! moves them.
+
+use async_trait::async_trait;
+use std::borrow::Cow;
+use std::sync::Arc;
+use zkdb_core::DEFAULT_RESERVED_PREFIX;
+use zkdb_store::{CompactionStats, Store, StoreResult};
+
+use crate::DatabaseError;
+
+/// Fails with `DatabaseError::InvalidKey` unless `prefix` can hold the
+/// bookkeeping: it must be non-empty and shorter than a key.
+pub(crate) fn check_prefix(prefix: &str) -> Result<(), DatabaseError> {
+    if prefix.is_empty() || prefix.len() >= zkdb_core::MAX_KEY_LEN {
+        return Err(DatabaseError::InvalidKey(format!(
+            "reserved prefix {:?} must be between 1 and {} bytes",
+            prefix,
+            zkdb_core::MAX_KEY_LEN - 1
+        )));
+    }
+    Ok(())
+}
+
+/// Store key of the bookkeeping entry `key` under `prefix`.
+pub(crate) fn relocate<'a>(prefix: &str, key: &'a str) -> Cow<'a, str> {
+    match key.strip_prefix(DEFAULT_RESERVED_PREFIX) {
+        Some(rest) if prefix != DEFAULT_RESERVED_PREFIX => {
+            Cow::Owned(format!("{}{}", prefix, rest))
+        }
+        _ => Cow::Borrowed(key),
+    }
+}
+
+/// The store bookkeeping is written through for `prefix`: `store` itself
+/// under the default prefix.
+pub(crate) fn store_for(store: &Arc<dyn Store>, prefix: &str) -> Arc<dyn Store> {
+    if prefix == DEFAULT_RESERVED_PREFIX {
+        return store.clone();
+    }
+    Arc::new(ReservedStore {
+        inner: store.clone(),
+        prefix: prefix.to_string(),
+    })
+}
+
+/// Wraps a store and moves bookkeeping keys under another reserved prefix.
+struct ReservedStore {
+    inner: Arc<dyn Store>,
+    prefix: String,
+}
+
+impl ReservedStore {
+    fn relocate<'a>(&self, key: &'a str) -> Cow<'a, str> {
+        relocate(&self.prefix, key)
+    }
+}
+
+#[async_trait]
+impl Store for ReservedStore {
+    async fn put(&self, key: &str, value: &[u8]) -> StoreResult<()> {
+        self.inner.put(&self.relocate(key), value).await
+    }
+
+    async fn get(&self, key: &str) -> StoreResult<Vec<u8>> {
+        self.inner.get(&self.relocate(key)).await
+    }
+
+    async fn delete(&self, key: &str) -> StoreResult<()> {
+        self.inner.delete(&self.relocate(key)).await
+    }
+
+    async fn exists(&self, key: &str) -> StoreResult<bool> {
+        self.inner.exists(&self.relocate(key)).await
+    }
+
+    async fn keys_with_prefix(&self, prefix: &str) -> StoreResult<Vec<String>> {
+        let relocated = self.relocate(prefix);
+        // Hands the keys back as the modules built them, which keeps them
+        // sorted since they all share the relocated prefix.
+        let keys = self.inner.keys_with_prefix(&relocated).await?;
+        Ok(match relocated {
+            Cow::Borrowed(_) => keys,
+            Cow::Owned(_) => keys
+                .into_iter()
+                .map(|key| format!("{}{}", DEFAULT_RESERVED_PREFIX, &key[self.prefix.len()..]))
+                .collect(),
+        })
+    }
+
+    async fn batch_get(&self, keys: &[&str]) -> StoreResult<Vec<Vec<u8>>> {
+        let relocated: Vec<Cow<str>> = keys.iter().map(|key| self.relocate(key)).collect();
+        let keys: Vec<&str> = relocated.iter().map(|key| key.as_ref()).collect();
+        self.inner.batch_get(&keys).await
+    }
+
+    async fn batch_put(&self, entries: &[(&str, &[u8])]) -> StoreResult<()> {
+        let relocated: Vec<(Cow<str>, &[u8])> = entries
+            .iter()
+            .map(|(key, value)| (self.relocate(key), *value))
+            .collect();
+        let entries: Vec<(&str, &[u8])> = relocated
+            .iter()
+            .map(|(key, value)| (key.as_ref(), *value))
+            .collect();
+        self.inner.batch_put(&entries).await
+    }
+
+    async fn atomic_swap(&self, key: &str, new_value: &[u8]) -> StoreResult<Option<Vec<u8>>> {
+        self.inner.atomic_swap(&self.relocate(key), new_value).await
+    }
+
+    async fn copy(&self, src_key: &str, dst_key: &str) -> StoreResult<()> {
+        self.inner
+            .copy(&self.relocate(src_key), &self.relocate(dst_key))
+            .await
+    }
+
+    async fn compact(&self) -> StoreResult<CompactionStats> {
+        self.inner.compact().await
+    }
+
+    async fn flush(&self) -> StoreResult<()> {
+        self.inner.flush().await
+    }
+
+    async fn total_bytes(&self) -> StoreResult<u64> {
+        self.inner.total_bytes().await
+    }
+}
@@ -0,0 +1,210 @@
+//! Typed views of the `data` of a `ProvenQueryResult`, for the commands
+//! whose output callers read most.
+//!
+//! Hashes and roots stay hex-encoded, as the engine reports them.
+
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+use crate::{check_engine_error, DatabaseError, OperationContext, ProvenQueryResult};
+
+/// Output of `Command::Insert`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct InsertResult {
+    pub key: String,
+    /// Index of the new leaf.
+    pub index: usize,
+    /// Hex-encoded leaf, the hash of the value.
+    pub leaf: String,
+    /// Hex-encoded root of the tree after the insert.
+    pub root: String,
+    pub inserted: bool,
+}
+
+/// Output of `Command::Replace`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ReplaceResult {
+    pub key: String,
+    /// Hex-encoded leaf the key held before, `None` if it was absent.
+    pub old_leaf: Option<String>,
+    /// Hex-encoded hash of the value the key held before, `None` if it was
+    /// absent. The same as `old_leaf` unless leaves commit to their key.
+    pub old_value_hash: Option<String>,
+    /// Hex-encoded leaf the key holds now.
+    pub new_leaf: String,
+    /// Hex-encoded root of the tree after the replace.
+    pub root: String,
+}
+
+/// Output of `Command::BatchInsert`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BatchInsertResult {
+    /// Number of entries inserted.
+    pub inserted: usize,
+    /// Index of the leaf of the first entry; the rest follow in order.
+    pub first_index: usize,
+    pub total_leaves: usize,
+    /// Hex-encoded root of the tree after the batch.
+    pub root: String,
+}
+
+/// Output of `Command::Delete`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DeleteResult {
+    pub key: String,
+    /// Index of the leaf that was zeroed.
+    pub index: usize,
+    pub deleted: bool,
+    /// Hex-encoded root of the tree after the delete.
+    pub root: String,
+}
+
+/// Output of `Command::Query` for a key the tree holds.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct QueryHit {
+    pub key: String,
+    /// Hex-encoded leaf, the hash of the value.
+    pub value: String,
+    /// Index of the key's leaf, 0 under `DatabaseType::SparseMerkle` whose
+    /// leaves are not numbered.
+    #[serde(default)]
+    pub index: usize,
+}
+
+/// One key of the output of `Command::MultiQuery`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MultiQueryEntry {
+    pub key: String,
+    pub found: bool,
+    /// Hex-encoded leaf, the hash of the value, `None` for a missing key.
+    pub value: Option<String>,
+    pub index: Option<usize>,
+}
+
+/// Output of `Command::Prove`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProveResult {
+    pub key: String,
+    /// Hex-encoded root the proof was generated against.
+    pub root: String,
+    /// Base64-encoded sibling hashes, as `verify::verify_inclusion` takes
+    /// them once decoded.
+    pub proof: String,
+    pub index: usize,
+    /// Hex-encoded leaf of the key.
+    pub leaf: String,
+    pub total_leaves: usize,
+}
+
+/// Output of `Command::GetRoot`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RootResult {
+    /// Hex-encoded root, `None` while the tree is empty.
+    pub root: Option<String>,
+    pub leaf_count: usize,
+}
+
+/// Output of `Command::Compact`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CompactResult {
+    /// Number of leaves dropped.
+    pub leaves_pruned: usize,
+    /// Number of leaves left, one per key.
+    pub leaf_count: usize,
+    /// Hex-encoded root of the tree after compacting, `None` if no key is
+    /// left.
+    pub root: Option<String>,
+}
+
+impl ProvenQueryResult {
+    /// Reads the result of an insert.
+    pub fn as_insert(&self) -> Result<InsertResult, DatabaseError> {
+        self.parse("insert")
+    }
+
+    /// Reads the result of a replace.
+    pub fn as_replace(&self) -> Result<ReplaceResult, DatabaseError> {
+        self.parse("replace")
+    }
+
+    /// Reads the result of a batch insert.
+    pub fn as_batch_insert(&self) -> Result<BatchInsertResult, DatabaseError> {
+        self.parse("batch insert")
+    }
+
+    /// Reads the result of a delete.
+    pub fn as_delete(&self) -> Result<DeleteResult, DatabaseError> {
+        self.parse("delete")
+    }
+
+    /// Reads the result of a query. A missing key fails with
+    /// `DatabaseError::KeyNotFound`, as reported by the engine.
+    pub fn as_query(&self) -> Result<QueryHit, DatabaseError> {
+        self.parse("query")
+    }
+
+    /// Reads the result of a multi-key query, one entry per key in the
+    /// order they were asked for.
+    pub fn as_multi_query(&self) -> Result<Vec<MultiQueryEntry>, DatabaseError> {
+        self.parse("multi query")
+    }
+
+    /// Reads the result of a proof.
+    pub fn as_proof(&self) -> Result<ProveResult, DatabaseError> {
+        self.parse("prove")
+    }
+
+    /// Reads the result of a root lookup.
+    pub fn as_root(&self) -> Result<RootResult, DatabaseError> {
+        self.parse("get root")
+    }
+
+    /// Reads the result of a compaction.
+    pub fn as_compact(&self) -> Result<CompactResult, DatabaseError> {
+        self.parse("compact")
+    }
+
+    /// Reads the result of a script, one result per command in the order
+    /// they ran, each without state or proof so that it reads with the
+    /// other views.
+    pub fn as_script(&self) -> Result<Vec<ProvenQueryResult>, DatabaseError> {
+        #[derive(Deserialize)]
+        struct Script {
+            results: Vec<serde_json::Value>,
+        }
+        let script: Script = self.parse("script")?;
+        Ok(script
+            .results
+            .into_iter()
+            .map(|data| ProvenQueryResult {
+                data,
+                new_state: Vec::new(),
+                sp1_proof: None,
+                cycles: 0,
+            })
+            .collect())
+    }
+
+    /// The `OperationContext` the engine committed with a mutation, `None`
+    /// for other commands.
+    pub fn context(&self) -> Option<OperationContext> {
+        serde_json::from_value(self.data.get("context")?.clone()).ok()
+    }
+
+    /// The admin key the engine checked the signature of a mutation
+    /// against, `None` for unsigned commands.
+    pub fn signer(&self) -> Option<[u8; 32]> {
+        let mut key = [0u8; 32];
+        hex::decode_to_slice(self.data.get("signer")?.as_str()?, &mut key).ok()?;
+        Some(key)
+    }
+
+    /// Maps an engine error to its `DatabaseError`, or decodes `data` as a
+    /// `T`, failing if it lacks one of its fields.
+    fn parse<T: DeserializeOwned>(&self, kind: &str) -> Result<T, DatabaseError> {
+        check_engine_error(&self.data, "")?;
+        serde_json::from_value(self.data.clone()).map_err(|e| {
+            DatabaseError::QueryExecutionFailed(format!("Invalid {} result format: {}", kind, e))
+        })
+    }
+}
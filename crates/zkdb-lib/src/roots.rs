@@ -0,0 +1,51 @@
+//! Log of the roots the state has had, kept in a JSON file for auditing.
+//!
+//! The file holds a JSON array of `RootEntry`, one per mutation, oldest
+//! first. It is rewritten on every mutation, before the new state is
+//! swapped in.
+
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io::ErrorKind;
+use std::path::Path;
+use zkdb_store::StoreError;
+
+use crate::DatabaseError;
+
+/// A root the state had, as returned by `Database::get_root_history`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RootEntry {
+    /// Unix time in milliseconds the root was committed.
+    pub timestamp: u64,
+    /// Hex-encoded root, empty for an empty tree.
+    pub root_hex: String,
+    /// Kind of the command that produced the root, e.g. `Insert`.
+    pub operation: String,
+}
+
+/// Reads every entry in the file at `path`, an empty log if it does not
+/// exist yet.
+pub(crate) fn read(path: &Path) -> Result<Vec<RootEntry>, DatabaseError> {
+    let bytes = match fs::read(path) {
+        Ok(bytes) => bytes,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(DatabaseError::Store(StoreError::from(e))),
+    };
+    serde_json::from_slice(&bytes)
+        .map_err(|e| DatabaseError::Codec(format!("failed to decode root history: {}", e)))
+}
+
+/// Appends `entry` to the file at `path`.
+pub(crate) fn append(path: &Path, entry: RootEntry) -> Result<(), DatabaseError> {
+    let mut entries = read(path)?;
+    entries.push(entry);
+    let encoded = serde_json::to_vec(&entries)
+        .map_err(|e| DatabaseError::Codec(format!("failed to encode root history: {}", e)))?;
+    fs::write(path, encoded).map_err(|e| DatabaseError::Store(StoreError::from(e)))
+}
+
+/// The root committed last at or before `timestamp`.
+pub(crate) fn at(entries: &[RootEntry], timestamp: u64) -> Option<&RootEntry> {
+    let after = entries.partition_point(|entry| entry.timestamp <= timestamp);
+    after.checked_sub(1).map(|index| &entries[index])
+}
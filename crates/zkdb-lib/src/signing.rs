@@ -0,0 +1,144 @@
+//! Signatures over state files and proof bundles.
+//!
+//! A database given a `Signer` with `DatabaseBuilder::signing_key` or
+//! `DatabaseBuilder::signer` signs what it hands out: `Database::save_state`
+//! and `Database::save_state_checked` write a detached ed25519 signature
+//! over the file's bytes next to it, at `signature_path`, and
+//! `Database::proof_bundle` signs the bundle. Loads check signatures
+//! against the verifying key, see `DatabaseBuilder::verifying_key`.
+//!
+//! A `Writer` signs mutations instead, for states whose engine only accepts
+//! those signed by an admin key, see `DatabaseBuilder::writer_key`.
+
+use ed25519_dalek::SigningKey;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use zkdb_core::merkle::ROOT_TREE;
+use zkdb_core::{write_message, Command};
+
+use crate::{verify, DatabaseError};
+
+/// Signs with an ed25519 key the library never reads itself, e.g. one held
+/// by a hardware module.
+pub trait Signer: Send + Sync {
+    /// The key signatures verify against.
+    fn public_key(&self) -> [u8; 32];
+
+    /// Signs `message`.
+    fn sign(&self, message: &[u8]) -> [u8; 64];
+}
+
+impl Signer for SigningKey {
+    fn public_key(&self) -> [u8; 32] {
+        self.verifying_key().to_bytes()
+    }
+
+    fn sign(&self, message: &[u8]) -> [u8; 64] {
+        ed25519_dalek::Signer::sign(self, message).to_bytes()
+    }
+}
+
+/// Signs the mutations an executor sends to the engine with the admin key
+/// of the state, see `zkdb_core::write_message`.
+#[derive(Clone)]
+pub struct Writer {
+    signer: Arc<dyn Signer>,
+    tree_root: fn(&[u8], &str) -> Option<String>,
+}
+
+impl Writer {
+    /// Signs with `signer`, reading the roots the signatures bind from a
+    /// serialized state with `tree_root`, as `EngineSpec::tree_root`.
+    pub fn new(signer: Arc<dyn Signer>, tree_root: fn(&[u8], &str) -> Option<String>) -> Self {
+        Writer { signer, tree_root }
+    }
+
+    /// The admin key signatures verify against.
+    pub fn public_key(&self) -> [u8; 32] {
+        self.signer.public_key()
+    }
+
+    /// Signs `command`, to be run against `state`.
+    pub fn sign(&self, state: &[u8], command: &Command) -> Vec<u8> {
+        let tree = match command {
+            Command::InTree { tree, .. } => tree.as_str(),
+            _ => ROOT_TREE,
+        };
+        let mut prev_root = [0u8; 32];
+        if let Some(root) = (self.tree_root)(state, tree) {
+            hex::decode_to_slice(root, &mut prev_root).expect("roots are 32 hex-encoded bytes");
+        }
+        self.signer
+            .sign(&write_message(&prev_root, command))
+            .to_vec()
+    }
+}
+
+/// Where the signature of the state file at `path` is kept: `path` with
+/// `.sig` appended.
+pub fn signature_path(path: &Path) -> PathBuf {
+    let mut path = path.as_os_str().to_owned();
+    path.push(".sig");
+    PathBuf::from(path)
+}
+
+/// Signs `contents`, just written to `path`, or removes the signature of
+/// the file it replaced, which no longer matches.
+pub(crate) fn sign_file(
+    signer: Option<&dyn Signer>,
+    path: &Path,
+    contents: &[u8],
+) -> Result<(), DatabaseError> {
+    let sig_path = signature_path(path);
+    let written = match signer {
+        Some(signer) => fs::write(&sig_path, signer.sign(contents)),
+        None => match fs::remove_file(&sig_path) {
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+            result => result,
+        },
+    };
+    written.map_err(|e| {
+        DatabaseError::QueryExecutionFailed(format!("Failed to write state signature: {}", e))
+    })
+}
+
+/// Checks the signature of the state file at `path`, whose bytes are
+/// `contents`, against `public_key`.
+///
+/// An unsigned file passes unless `required`; a signed one fails without a
+/// key to check it against.
+pub(crate) fn check_file(
+    public_key: Option<[u8; 32]>,
+    required: bool,
+    path: &Path,
+    contents: &[u8],
+) -> Result<(), DatabaseError> {
+    let signature = match fs::read(signature_path(path)) {
+        Ok(signature) => signature,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            if required {
+                return Err(DatabaseError::InvalidSignature(format!(
+                    "{} is unsigned",
+                    path.display()
+                )));
+            }
+            return Ok(());
+        }
+        Err(e) => {
+            return Err(DatabaseError::QueryExecutionFailed(format!(
+                "Failed to read state signature: {}",
+                e
+            )))
+        }
+    };
+    let public_key = public_key.ok_or_else(|| {
+        DatabaseError::InvalidSignature(format!(
+            "{} is signed but no verifying key is configured",
+            path.display()
+        ))
+    })?;
+    verify::verify_signature(&public_key, contents, &signature)
+        .map_err(|e| DatabaseError::InvalidSignature(format!("{}: {}", path.display(), e)))
+}
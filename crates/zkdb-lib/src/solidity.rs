@@ -0,0 +1,137 @@
+//! Ethereum support: inclusion proofs laid out for contracts, returned by
+//! `Database::serialize_for_solidity`, and the verifier contract returned by
+//! `Database::generate_solidity_verifier`.
+//!
+//! The tree hashes each pair with SHA-256 in position order and promotes
+//! the odd node out of a layer unchanged, so a contract checks the proof
+//! with OpenZeppelin's `MerkleProof.processProof` loop, swapping its
+//! sorted-pair `keccak256` for the `sha256` precompile and taking the order
+//! of each pair from `path`.
+
+use ethabi::{Token, Uint};
+use serde::{Deserialize, Serialize};
+
+use crate::DatabaseError;
+
+/// Contract checking SP1 proofs of the engine through an `ISP1Verifier`
+/// gateway, as SP1's plonk and groth16 verifiers are deployed. `{vkey}` is
+/// replaced with the program's verifying key hash.
+const VERIFIER_TEMPLATE: &str = r#"// SPDX-License-Identifier: MIT
+// Generated by zkdb. Do not edit.
+pragma solidity ^0.8.20;
+
+/// @notice The SP1 verifier gateway, e.g. SP1VerifierGateway from sp1-contracts.
+interface ISP1Verifier {
+    function verifyProof(
+        bytes32 programVKey,
+        bytes calldata publicValues,
+        bytes calldata proofBytes
+    ) external view;
+}
+
+/// @notice Verifies SP1 proofs of zkDB commands.
+contract ZkdbVerifier {
+    /// @notice Verifying key hash of the zkDB engine program.
+    bytes32 public constant PROGRAM_VKEY = {vkey};
+
+    ISP1Verifier public immutable verifier;
+
+    constructor(address _verifier) {
+        verifier = ISP1Verifier(_verifier);
+    }
+
+    /// @notice Reverts unless `proof` proves `publicValues` for the engine.
+    function verify(bytes calldata proof, bytes memory publicValues) public view {
+        verifier.verifyProof(PROGRAM_VKEY, publicValues, proof);
+    }
+
+    /// @notice As `verify`, with the public values as 32-byte words. Their
+    /// length must be a multiple of 32 bytes, since the words are hashed as
+    /// they are.
+    function verify(bytes calldata proof, bytes32[] calldata publicValues) external view {
+        verify(proof, abi.encodePacked(publicValues));
+    }
+}
+"#;
+
+/// Fills in the verifier contract for the `0x`-prefixed verifying key hash
+/// `vk_hash`.
+pub(crate) fn verifier_contract(vk_hash: &str) -> Result<String, DatabaseError> {
+    let hex = vk_hash.strip_prefix("0x").unwrap_or(vk_hash);
+    if hex.len() != 64 || !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return Err(DatabaseError::Codec(format!(
+            "verifying key hash {:?} is not 32 bytes of hex",
+            vk_hash
+        )));
+    }
+    Ok(VERIFIER_TEMPLATE.replace("{vkey}", &format!("0x{}", hex.to_ascii_lowercase())))
+}
+
+/// An inclusion proof as `bytes32` words.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SolidityProof {
+    /// Root the proof was generated against.
+    pub root: [u8; 32],
+    /// Leaf of the key, the SHA-256 of its value.
+    pub leaf: [u8; 32],
+    /// Sibling hashes, ordered from the leaf up to the root.
+    pub proof_hashes: Vec<[u8; 32]>,
+    /// Bit `i` is set when `proof_hashes[i]` is the left node of its pair.
+    pub path: u64,
+}
+
+impl SolidityProof {
+    /// Builds the proof for the leaf at `index` of a tree of `total_leaves`
+    /// leaves from its sibling hashes.
+    pub(crate) fn new(
+        root: [u8; 32],
+        leaf: [u8; 32],
+        proof_hashes: Vec<[u8; 32]>,
+        index: usize,
+        total_leaves: usize,
+    ) -> Result<Self, DatabaseError> {
+        // Walks the layers as the engine's proof_size does: a node has a
+        // sibling unless it is the odd node out of its layer.
+        let mut path = 0u64;
+        let mut siblings = 0;
+        let mut position = index;
+        let mut layer_len = total_leaves;
+        while layer_len > 1 {
+            if position ^ 1 < layer_len {
+                if position % 2 == 1 {
+                    path |= 1 << siblings;
+                }
+                siblings += 1;
+            }
+            layer_len = layer_len.div_ceil(2);
+            position /= 2;
+        }
+        if siblings != proof_hashes.len() {
+            return Err(DatabaseError::Codec(format!(
+                "proof has {} hashes, expected {} for leaf {} of {}",
+                proof_hashes.len(),
+                siblings,
+                index,
+                total_leaves
+            )));
+        }
+        Ok(SolidityProof {
+            root,
+            leaf,
+            proof_hashes,
+            path,
+        })
+    }
+
+    /// ABI-encodes the proof as `(bytes32 root, bytes32 leaf,
+    /// bytes32[] proofHashes, uint256 path)`, as `abi.encode` would.
+    pub fn to_abi_bytes(&self) -> Vec<u8> {
+        let word = |hash: &[u8; 32]| Token::FixedBytes(hash.to_vec());
+        ethabi::encode(&[Token::Tuple(vec![
+            word(&self.root),
+            word(&self.leaf),
+            Token::Array(self.proof_hashes.iter().map(word).collect()),
+            Token::Uint(Uint::from(self.path)),
+        ])])
+    }
+}
@@ -0,0 +1,143 @@
+//! A cap on the number of proofs generated at once.
+//!
+//! Proving is bound by CPU and memory, so proofs beyond what the machine
+//! can run side by side only slow every other one down. Callers past the
+//! cap wait until a proof finishes, async ones by awaiting a permit that
+//! is waited for on a blocking thread; execution without a proof is never
+//! held back. While a proof that missed its deadline is still running, new
+//! ones are refused rather than queued behind it.
+
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
+use tracing::debug;
+
+use crate::deadline::Overdue;
+use crate::DatabaseError;
+
+/// Counting semaphore over proof generation.
+pub(crate) struct ProofLimiter {
+    /// `None` for no cap.
+    limit: Option<usize>,
+    in_flight: Mutex<usize>,
+    released: Condvar,
+    /// Proofs that timed out and are still running.
+    overdue: Arc<Overdue>,
+}
+
+impl ProofLimiter {
+    /// Lets up to `limit` proofs run at once, at least one.
+    pub(crate) fn new(limit: Option<usize>) -> Arc<Self> {
+        Arc::new(ProofLimiter {
+            limit: limit.map(|limit| limit.max(1)),
+            in_flight: Mutex::new(0),
+            released: Condvar::new(),
+            overdue: Overdue::new(),
+        })
+    }
+
+    /// Waits for a proof to be allowed to start, failing with
+    /// `DatabaseError::ProofTimeout` if `timeout` passes first. Asked for
+    /// while a proof that timed out is still running, it fails at once with
+    /// `DatabaseError::ProofGenerationFailed`. It counts as running until
+    /// the permit is dropped.
+    pub(crate) fn acquire(
+        self: &Arc<Self>,
+        timeout: Option<Duration>,
+    ) -> Result<ProofPermit, DatabaseError> {
+        let requested = Instant::now();
+        self.ensure_none_overdue()?;
+        let mut in_flight = self.in_flight.lock().unwrap();
+        if let Some(limit) = self.limit {
+            if *in_flight >= limit {
+                debug!(limit, "waiting for a running proof to finish");
+            }
+            while *in_flight >= limit {
+                in_flight = match timeout {
+                    Some(timeout) => {
+                        let left = timeout.saturating_sub(requested.elapsed());
+                        if left.is_zero() {
+                            return Err(DatabaseError::ProofTimeout(timeout));
+                        }
+                        self.released.wait_timeout(in_flight, left).unwrap().0
+                    }
+                    None => self.released.wait(in_flight).unwrap(),
+                };
+            }
+        }
+        *in_flight += 1;
+        Ok(ProofPermit {
+            limiter: self.clone(),
+            requested,
+        })
+    }
+
+    /// `acquire` run on a blocking thread, so that the caller awaits its
+    /// permit instead of parking a worker of the runtime.
+    pub(crate) async fn acquire_async(
+        self: &Arc<Self>,
+        timeout: Option<Duration>,
+    ) -> Result<ProofPermit, DatabaseError> {
+        let limiter = self.clone();
+        tokio::task::spawn_blocking(move || limiter.acquire(timeout))
+            .await
+            .map_err(|e| {
+                DatabaseError::ProofGenerationFailed(format!("permit task failed: {}", e))
+            })?
+    }
+
+    /// Fails while a proof that missed its deadline is still running: the
+    /// zkVM cannot be stopped, so it holds its permit and the machine until
+    /// it finishes.
+    fn ensure_none_overdue(&self) -> Result<(), DatabaseError> {
+        match self.overdue.count() {
+            0 => Ok(()),
+            overdue => {
+                debug!(overdue, "refusing a proof while a timed-out one is running");
+                Err(DatabaseError::ProofGenerationFailed(format!(
+                    "{} timed-out proof(s) still running",
+                    overdue
+                )))
+            }
+        }
+    }
+
+    /// Proofs that timed out and are still running.
+    pub(crate) fn overdue(&self) -> &Arc<Overdue> {
+        &self.overdue
+    }
+
+    pub(crate) fn limit(&self) -> Option<usize> {
+        self.limit
+    }
+
+    pub(crate) fn in_flight(&self) -> usize {
+        *self.in_flight.lock().unwrap()
+    }
+}
+
+/// A running proof, see `ProofLimiter::acquire`.
+pub(crate) struct ProofPermit {
+    limiter: Arc<ProofLimiter>,
+    /// When the permit was asked for.
+    requested: Instant,
+}
+
+impl ProofPermit {
+    /// What is left of `timeout` once the wait for the permit is taken off,
+    /// so that waiting and proving share one deadline.
+    pub(crate) fn remaining(&self, timeout: Option<Duration>) -> Option<Duration> {
+        timeout.map(|timeout| timeout.saturating_sub(self.requested.elapsed()))
+    }
+
+    /// Where a proof run under this permit is counted if it times out.
+    pub(crate) fn overdue(&self) -> &Arc<Overdue> {
+        self.limiter.overdue()
+    }
+}
+
+impl Drop for ProofPermit {
+    fn drop(&mut self) {
+        *self.limiter.in_flight.lock().unwrap() -= 1;
+        self.limiter.released.notify_one();
+    }
+}
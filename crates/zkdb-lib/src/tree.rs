@@ -0,0 +1,154 @@
+//! Handles scoping operations to a named tree, returned by `Database::tree`.
+
+use tracing::{debug, instrument};
+use zkdb_core::Command;
+use zkdb_store::StoreError;
+
+use crate::{check_engine_error, Database, DatabaseError, ProvenQueryResult};
+
+/// Prefix of every key the values of named trees are stored under.
+pub const TREE_PREFIX: &str = "_tree/";
+
+/// Store key of the value of `key` in the tree named `tree`.
+pub(crate) fn store_key(tree: &str, key: &str) -> String {
+    format!("{}{}/{}", TREE_PREFIX, tree, key)
+}
+
+/// A named tree of a `Database`.
+///
+/// Mirrors the key-value methods of `Database`, with every command run
+/// through `Command::in_tree`.
+pub struct Tree<'a> {
+    db: &'a mut Database,
+    name: String,
+}
+
+impl<'a> Tree<'a> {
+    pub(crate) fn new(db: &'a mut Database, name: &str) -> Self {
+        Tree {
+            db,
+            name: name.to_string(),
+        }
+    }
+
+    /// Name of the tree.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Hex-encoded root of the tree, `None` while it is empty.
+    pub fn root(&self) -> Option<String> {
+        (self.db.spec.tree_root)(&self.db.state, &self.name)
+    }
+
+    /// Returns the keys in the tree, in sorted order.
+    pub fn list_keys(&self) -> Result<Vec<String>, DatabaseError> {
+        (self.db.spec.list_keys)(&self.db.state, Some(&self.name))
+    }
+
+    #[instrument(skip(self, value), fields(tree = %self.name))]
+    pub async fn put(
+        &mut self,
+        key: &str,
+        value: &[u8],
+        generate_proof: bool,
+    ) -> Result<(), DatabaseError> {
+        self.db.ensure_writable("put")?;
+        self.db.validate_key(key)?;
+        self.db.limits.check_entry(key, value)?;
+        self.db.reserved.put(&self.store_key(key), value).await?;
+        let command = self.scope(Command::Insert {
+            key: key.to_string(),
+            value: self.db.value_hash(value),
+        });
+        let result = self.execute(&command, key, generate_proof).await?;
+        self.db
+            .commit(&command, result.new_state, result.sp1_proof.as_ref())
+            .await
+    }
+
+    #[instrument(skip(self), fields(tree = %self.name))]
+    pub async fn get(&self, key: &str, generate_proof: bool) -> Result<Vec<u8>, DatabaseError> {
+        let command = self.scope(Command::Query {
+            key: key.to_string(),
+        });
+        let merkle_hash = self
+            .execute(&command, key, generate_proof)
+            .await?
+            .as_query()?
+            .value;
+
+        let value = self.db.reserved.get(&self.store_key(key)).await?;
+        if self.db.value_hash(&value) != merkle_hash {
+            return Err(DatabaseError::Store(StoreError::Storage(
+                "Value hash mismatch - data may be corrupted".to_string(),
+            )));
+        }
+        Ok(value)
+    }
+
+    /// Removes a key from the tree and its value from the store.
+    #[instrument(skip(self), fields(tree = %self.name))]
+    pub async fn delete(&mut self, key: &str, generate_proof: bool) -> Result<(), DatabaseError> {
+        let command = self.scope(Command::Delete {
+            key: key.to_string(),
+        });
+        let result = self.execute(&command, key, generate_proof).await?;
+        self.db
+            .commit(&command, result.new_state, result.sp1_proof.as_ref())
+            .await?;
+        match self.db.reserved.delete(&self.store_key(key)).await {
+            Ok(()) | Err(StoreError::NotFound(_)) => Ok(()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Generates a Merkle inclusion proof for `key` against the tree's root.
+    #[instrument(skip(self), fields(tree = %self.name))]
+    pub fn prove(
+        &self,
+        key: &str,
+        generate_proof: bool,
+    ) -> Result<ProvenQueryResult, DatabaseError> {
+        let command = self.scope(Command::Prove {
+            key: key.to_string(),
+        });
+        let result = self
+            .db
+            .executor
+            .execute_query(&self.db.state, &command, generate_proof)?;
+        self.checked(&command, key, result)
+    }
+
+    fn scope(&self, command: Command) -> Command {
+        command.in_tree(self.name.as_str())
+    }
+
+    fn store_key(&self, key: &str) -> String {
+        store_key(&self.name, key)
+    }
+
+    async fn execute(
+        &self,
+        command: &Command,
+        key: &str,
+        generate_proof: bool,
+    ) -> Result<ProvenQueryResult, DatabaseError> {
+        let result = self.db.run(&self.db.state, command, generate_proof).await?;
+        self.checked(command, key, result)
+    }
+
+    fn checked(
+        &self,
+        command: &Command,
+        key: &str,
+        result: ProvenQueryResult,
+    ) -> Result<ProvenQueryResult, DatabaseError> {
+        debug!(
+            kind = command.kind(),
+            "result from executor: {:?}", result.data
+        );
+        check_engine_error(&result.data, key)?;
+        Ok(result)
+    }
+}
@@ -0,0 +1,148 @@
+//! Past values of each key, kept in the `Store` under `_hist/`.
+//!
+//! Version `n` of a key lives at `_hist/<key>/<n>` and the number of the
+//! latest version at `_hist/<key>/head`. Versions are numbered from 1 and
+//! keep counting when older ones fall out of the retention limit; only
+//! purging a key's history starts it over.
+
+use serde::{Deserialize, Serialize};
+use zkdb_store::{Store, StoreError};
+
+use crate::DatabaseError;
+
+/// Prefix of every key the version history writes to the store.
+pub const HISTORY_PREFIX: &str = "_hist/";
+
+/// A value a key held, as returned by `Database::history`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KeyVersion {
+    /// Version number, starting at 1.
+    pub version: u64,
+    /// The value written.
+    pub value: Vec<u8>,
+    /// Hex-encoded hash of the value, as committed in the tree.
+    pub value_hash: String,
+    /// Hex-encoded root of the state the value was committed in.
+    pub root: Option<String>,
+    /// Unix time of the write in milliseconds.
+    pub timestamp: u64,
+}
+
+fn version_key(key: &str, version: u64) -> String {
+    format!("{}{}/{}", HISTORY_PREFIX, key, version)
+}
+
+fn head_key(key: &str) -> String {
+    format!("{}{}/head", HISTORY_PREFIX, key)
+}
+
+/// Number of the latest version of `key`, 0 if it has none.
+async fn head(store: &dyn Store, key: &str) -> Result<u64, DatabaseError> {
+    match store.get(&head_key(key)).await {
+        Ok(bytes) => {
+            let bytes: [u8; 8] = bytes.as_slice().try_into().map_err(|_| {
+                DatabaseError::Store(StoreError::Storage(format!(
+                    "version head of {} is corrupt",
+                    key
+                )))
+            })?;
+            Ok(u64::from_be_bytes(bytes))
+        }
+        Err(StoreError::NotFound(_)) => Ok(0),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Versions of `key` that can still be held, given the latest one.
+fn retained(head: u64, retention: Option<usize>) -> std::ops::RangeInclusive<u64> {
+    let oldest = match retention {
+        Some(limit) => head.saturating_sub(limit as u64) + 1,
+        None => 1,
+    };
+    oldest..=head
+}
+
+/// Records `version` as the next version of `key`, dropping the versions
+/// beyond `retention`.
+pub(crate) async fn record(
+    store: &dyn Store,
+    mut version: KeyVersion,
+    key: &str,
+    retention: Option<usize>,
+) -> Result<u64, DatabaseError> {
+    let previous = head(store, key).await?;
+    version.version = previous + 1;
+    let encoded = bincode::serialize(&version)
+        .map_err(|e| DatabaseError::Codec(format!("failed to encode version: {}", e)))?;
+    store
+        .put(&version_key(key, version.version), &encoded)
+        .await?;
+    store
+        .put(&head_key(key), &version.version.to_be_bytes())
+        .await?;
+
+    // Only the version that just fell out needs dropping; older ones went
+    // on earlier writes.
+    let oldest = *retained(version.version, retention).start();
+    if oldest > 1 {
+        match store.delete(&version_key(key, oldest - 1)).await {
+            Ok(()) | Err(StoreError::NotFound(_)) => {}
+            Err(e) => return Err(e.into()),
+        }
+    }
+    Ok(version.version)
+}
+
+/// Reads version `version` of `key`.
+pub(crate) async fn read(
+    store: &dyn Store,
+    key: &str,
+    version: u64,
+) -> Result<KeyVersion, DatabaseError> {
+    let encoded = match store.get(&version_key(key, version)).await {
+        Ok(encoded) => encoded,
+        Err(StoreError::NotFound(_)) => {
+            return Err(DatabaseError::KeyNotFound(format!(
+                "{} version {}",
+                key, version
+            )))
+        }
+        Err(e) => return Err(e.into()),
+    };
+    bincode::deserialize(&encoded)
+        .map_err(|e| DatabaseError::Codec(format!("failed to decode version: {}", e)))
+}
+
+/// Reads every retained version of `key`, oldest first.
+pub(crate) async fn list(
+    store: &dyn Store,
+    key: &str,
+    retention: Option<usize>,
+) -> Result<Vec<KeyVersion>, DatabaseError> {
+    let mut versions = Vec::new();
+    for version in retained(head(store, key).await?, retention) {
+        match read(store, key, version).await {
+            Ok(entry) => versions.push(entry),
+            // Dropped under an earlier, smaller retention limit.
+            Err(DatabaseError::KeyNotFound(_)) => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(versions)
+}
+
+/// Removes every version of `key` and returns how many were removed.
+pub(crate) async fn purge(store: &dyn Store, key: &str) -> Result<usize, DatabaseError> {
+    let mut removed = 0;
+    for version in 1..=head(store, key).await? {
+        match store.delete(&version_key(key, version)).await {
+            Ok(()) => removed += 1,
+            Err(StoreError::NotFound(_)) => {}
+            Err(e) => return Err(e.into()),
+        }
+    }
+    match store.delete(&head_key(key)).await {
+        Ok(()) | Err(StoreError::NotFound(_)) => Ok(removed),
+        Err(e) => Err(e.into()),
+    }
+}
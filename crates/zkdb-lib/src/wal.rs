@@ -0,0 +1,157 @@
+//! Append-only log of state changes, kept in the `Store` under `_wal/`.
+//!
+//! Every mutation made through the async `Database` methods is appended
+//! before the state is swapped and marked committed afterwards, so an entry
+//! left uncommitted never took effect. Entries are framed with a length and
+//! CRC32 like checked state files, so truncation and tampering are detected
+//! per entry. `Database::execute_query` is synchronous and is not logged.
+//!
+//! A database opened with the log enabled first re-applies the entries its
+//! state is missing, so a mutation survives a crash before the state was
+//! saved. Entries are kept after recovery; they are what `replay` and
+//! `get_at` rebuild past states from, alongside a copy of every inserted
+//! value under `_wal/values/`.
+
+use serde::{Deserialize, Serialize};
+use zkdb_core::Command;
+use zkdb_store::{Store, StoreError};
+
+use crate::{frame_state, unframe_state, DatabaseError, ProvenOutput};
+
+/// Prefix of every key the log writes to the store.
+pub const WAL_PREFIX: &str = "_wal/";
+
+/// Key holding the number of entries in the log.
+const HEAD_KEY: &str = "_wal/head";
+
+/// Prefix of the copies kept of inserted values, keyed by their hash.
+const VALUES_PREFIX: &str = "_wal/values/";
+
+/// A logged state change.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct WalEntry {
+    /// Position of the entry in the log, starting at 0.
+    pub seq: u64,
+    /// The command that was executed.
+    pub command: Command,
+    /// Key the command operated on, if any.
+    pub key: Option<String>,
+    /// Hex-encoded hash of the inserted value, for `Insert`.
+    pub value_hash: Option<String>,
+    /// Hex-encoded root before the change, `None` for an empty tree.
+    pub prev_root: Option<String>,
+    /// Hex-encoded root after the change, `None` for an empty tree.
+    pub new_root: Option<String>,
+    /// Unix time of the change in milliseconds.
+    pub timestamp: u64,
+    /// Hex-encoded hash of the SP1 proof generated for the change, if any.
+    pub proof_id: Option<String>,
+    /// Whether the state was swapped after the entry was written.
+    pub committed: bool,
+}
+
+impl WalEntry {
+    /// Store key of the entry at `seq`.
+    pub fn store_key(seq: u64) -> String {
+        format!("{}{:020}", WAL_PREFIX, seq)
+    }
+}
+
+/// A value read by `Database::get_at`.
+#[derive(Debug)]
+pub struct HistoricalValue {
+    /// The value the key held.
+    pub value: Vec<u8>,
+    /// Hex-encoded root of the state the value was read from.
+    pub root: String,
+    /// Proof of the read against `root`, if one was requested.
+    pub proof: Option<ProvenOutput>,
+}
+
+/// Outcome of `Database::replay`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ReplayReport {
+    /// Number of committed entries re-executed.
+    pub replayed: usize,
+    /// Number of uncommitted entries passed over.
+    pub skipped: usize,
+    /// State rebuilt from the log.
+    pub state: Vec<u8>,
+    /// Hex-encoded root of the rebuilt state.
+    pub root: Option<String>,
+}
+
+/// Outcome of `Database::recover`.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RecoveryReport {
+    /// Number of entries re-applied to the state.
+    pub reapplied: usize,
+    /// Number of uncommitted entries dropped because the store does not
+    /// hold the values they refer to.
+    pub discarded: usize,
+}
+
+/// Store key of the copy kept of the value with the hex-encoded `value_hash`.
+pub(crate) fn value_key(value_hash: &str) -> String {
+    format!("{}{}", VALUES_PREFIX, value_hash)
+}
+
+/// Number of entries in the log.
+pub(crate) async fn len(store: &dyn Store) -> Result<u64, DatabaseError> {
+    match store.get(HEAD_KEY).await {
+        Ok(bytes) => {
+            let bytes: [u8; 8] =
+                bytes
+                    .as_slice()
+                    .try_into()
+                    .map_err(|_| DatabaseError::CorruptLogEntry {
+                        seq: 0,
+                        reason: "log head is corrupt".to_string(),
+                    })?;
+            Ok(u64::from_be_bytes(bytes))
+        }
+        Err(StoreError::NotFound(_)) => Ok(0),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Appends `entry` at the end of the log, setting its `seq`.
+pub(crate) async fn append(store: &dyn Store, entry: &mut WalEntry) -> Result<(), DatabaseError> {
+    entry.seq = len(store).await?;
+    write(store, entry).await?;
+    // An entry past the head is ignored, so a crash here loses nothing.
+    store.put(HEAD_KEY, &(entry.seq + 1).to_be_bytes()).await?;
+    Ok(())
+}
+
+/// Rewrites an entry in place, e.g. to mark it committed.
+pub(crate) async fn write(store: &dyn Store, entry: &WalEntry) -> Result<(), DatabaseError> {
+    let encoded = bincode::serialize(entry).map_err(|e| DatabaseError::CorruptLogEntry {
+        seq: entry.seq,
+        reason: format!("failed to encode entry: {}", e),
+    })?;
+    store
+        .put(&WalEntry::store_key(entry.seq), &frame_state(&encoded))
+        .await?;
+    Ok(())
+}
+
+/// Reads the entry at `seq`, checking its frame.
+pub(crate) async fn read(store: &dyn Store, seq: u64) -> Result<WalEntry, DatabaseError> {
+    let corrupt = |reason: &str| DatabaseError::CorruptLogEntry {
+        seq,
+        reason: reason.to_string(),
+    };
+    let framed = match store.get(&WalEntry::store_key(seq)).await {
+        Ok(framed) => framed,
+        Err(StoreError::NotFound(_)) => return Err(corrupt("entry is missing")),
+        Err(e) => return Err(e.into()),
+    };
+    let encoded = unframe_state(&framed).ok_or_else(|| corrupt("checksum mismatch"))?;
+    let entry: WalEntry =
+        bincode::deserialize(encoded).map_err(|_| corrupt("entry cannot be decoded"))?;
+    if entry.seq != seq {
+        return Err(corrupt("entry is out of place"));
+    }
+    Ok(entry)
+}
@@ -0,0 +1,39 @@
+use assert_cmd::Command;
+use predicates::prelude::*;
+
+fn cli(data_dir: &std::path::Path) -> Command {
+    let mut cmd = Command::cargo_bin("cli").unwrap();
+    cmd.arg("--data-dir")
+        .arg(data_dir)
+        .arg("--state-file")
+        .arg(data_dir.join("state.bin"));
+    cmd
+}
+
+#[test]
+fn test_show_cycles() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let cycles_line = predicate::str::is_match("(?m)^Cycles: [0-9]+$").unwrap();
+
+    cli(temp_dir.path())
+        .args(["--show-cycles", "put", "key", "value"])
+        .assert()
+        .success()
+        .stdout(cycles_line.clone());
+    cli(temp_dir.path())
+        .args(["--show-cycles", "get", "key"])
+        .assert()
+        .success()
+        .stdout(cycles_line.clone());
+    cli(temp_dir.path())
+        .args(["--show-cycles", "prove", "key"])
+        .assert()
+        .success()
+        .stdout(cycles_line.clone());
+
+    cli(temp_dir.path())
+        .args(["get", "key"])
+        .assert()
+        .success()
+        .stdout(cycles_line.not());
+}
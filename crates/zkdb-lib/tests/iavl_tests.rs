@@ -0,0 +1,195 @@
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use zkdb_core::iavl::{IavlState, Link};
+use zkdb_lib::verify;
+
+/// Deterministic xorshift, so failures replay.
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn below(&mut self, n: u64) -> u64 {
+        self.next() % n
+    }
+}
+
+fn value_hash(value: &str) -> [u8; 32] {
+    Sha256::digest(value.as_bytes()).into()
+}
+
+/// Height of the subtree at `link`, asserting every node below is balanced
+/// and records its height, size and hash.
+fn check_node(link: &Link) -> u8 {
+    let Some(node) = link else {
+        return 0;
+    };
+    let left = check_node(&node.left);
+    let right = check_node(&node.right);
+    assert!(left.abs_diff(right) <= 1, "unbalanced at {:?}", node.key);
+    assert_eq!(node.height, 1 + left.max(right));
+    let size = |link: &Link| link.as_ref().map_or(0, |node| node.size);
+    assert_eq!(node.size, 1 + size(&node.left) + size(&node.right));
+    let hash = |link: &Link| link.as_ref().map_or([0u8; 32], |node| node.hash);
+    assert_eq!(
+        node.hash,
+        verify::iavl_node_hash(
+            &node.key,
+            &node.value_hash,
+            node.height,
+            node.size,
+            &hash(&node.left),
+            &hash(&node.right),
+        )
+    );
+    node.height
+}
+
+fn proof(iavl: &IavlState, key: &[u8]) -> verify::IavlProof {
+    serde_json::from_value(serde_json::to_value(iavl.prove(key)).unwrap()).unwrap()
+}
+
+/// Runs random inserts, updates and deletes against both the tree and a
+/// `BTreeMap`, comparing them after every step.
+fn run_ops(seed: u64, ops: usize, key_space: u64) -> (IavlState, BTreeMap<Vec<u8>, [u8; 32]>) {
+    let mut rng = Rng(seed);
+    let mut iavl = IavlState::new();
+    let mut model = BTreeMap::new();
+    for step in 0..ops {
+        let key = format!("key{:03}", rng.below(key_space)).into_bytes();
+        if rng.below(3) == 0 {
+            assert_eq!(iavl.remove(&key), model.remove(&key), "step {}", step);
+        } else {
+            let hash = value_hash(&format!("value{}", step));
+            assert_eq!(
+                iavl.insert(&key, hash),
+                model.insert(key, hash),
+                "step {}",
+                step
+            );
+        }
+        assert_eq!(iavl.len(), model.len());
+        check_node(&iavl.root);
+    }
+    (iavl, model)
+}
+
+#[test]
+fn test_iavl_matches_btreemap() {
+    for seed in 1..=20u64 {
+        let (iavl, model) = run_ops(seed * 0x9e37_79b9, 400, 64);
+        let expected: Vec<_> = model.iter().map(|(k, v)| (k.clone(), *v)).collect();
+        assert_eq!(iavl.entries(), expected);
+        for i in 0..64 {
+            let key = format!("key{:03}", i).into_bytes();
+            assert_eq!(iavl.get(&key), model.get(&key).copied());
+            assert_eq!(iavl.rank(&key), model.range(..key.clone()).count());
+        }
+
+        let mut rng = Rng(seed);
+        for _ in 0..20 {
+            let (a, b) = (rng.below(70), rng.below(70));
+            let start = format!("key{:03}", a.min(b)).into_bytes();
+            let end = format!("key{:03}", a.max(b) + 1).into_bytes();
+            let limit = 1 + rng.below(10) as usize;
+            let expected: Vec<_> = model
+                .range(start.clone()..end.clone())
+                .take(limit)
+                .map(|(k, v)| (k.clone(), *v))
+                .collect();
+            assert_eq!(iavl.range(&start, &end, limit), expected);
+        }
+
+        // The height stays logarithmic.
+        let height = iavl.root.as_ref().map_or(0, |node| node.height) as f64;
+        assert!(height <= 1.45 * ((model.len() + 2) as f64).log2());
+
+        let decoded = IavlState::from_bytes(&iavl.to_bytes()).unwrap();
+        assert_eq!(decoded, iavl);
+        assert_eq!(decoded.root_hash(), iavl.root_hash());
+    }
+}
+
+#[test]
+fn test_iavl_root_is_deterministic() {
+    let (first, _) = run_ops(7, 300, 40);
+    let (second, _) = run_ops(7, 300, 40);
+    assert_eq!(first.root_hash(), second.root_hash());
+    let (other, _) = run_ops(8, 300, 40);
+    assert_ne!(first.root_hash(), other.root_hash());
+
+    let mut iavl = IavlState::new();
+    assert_eq!(iavl.root_hash(), None);
+    iavl.insert(b"a", value_hash("a"));
+    let root = iavl.root_hash();
+    iavl.insert(b"b", value_hash("b"));
+    assert_eq!(iavl.remove(b"b"), Some(value_hash("b")));
+    assert_eq!(iavl.root_hash(), root);
+    assert_eq!(iavl.remove(b"a"), Some(value_hash("a")));
+    assert!(iavl.is_empty());
+    assert_eq!(iavl.to_bytes(), IavlState::new().to_bytes());
+}
+
+#[test]
+fn test_iavl_proofs_verify() {
+    let (iavl, model) = run_ops(42, 300, 50);
+    let root = iavl.root_hash().unwrap();
+
+    for i in 0..55 {
+        let key = format!("key{:03}", i).into_bytes();
+        let held = model.get(&key);
+        let proof = proof(&iavl, &key);
+        assert_eq!(proof.node.is_some(), held.is_some());
+        assert!(verify::verify_iavl_proof(root, &key, held, &proof).unwrap());
+        assert_eq!(iavl.prove(&key).root(&key, held), Some(root));
+        match held {
+            Some(_) => {
+                let forged = value_hash("forged");
+                assert!(!verify::verify_iavl_proof(root, &key, Some(&forged), &proof).unwrap());
+                assert!(verify::verify_iavl_proof(root, &key, None, &proof).is_err());
+            }
+            None => {
+                let claimed = value_hash("claimed");
+                assert!(verify::verify_iavl_proof(root, &key, Some(&claimed), &proof).is_err());
+            }
+        }
+    }
+
+    // A proof does not carry over to another key, even a neighbouring one.
+    let (present, _) = model.iter().next().unwrap();
+    let mut moved = proof(&iavl, present);
+    moved.node = None;
+    assert!(!verify::verify_iavl_proof(root, b"key999", None, &moved).unwrap());
+    let absent = b"key0005";
+    let mut absence = proof(&iavl, absent);
+    assert!(verify::verify_iavl_proof(root, absent, None, &absence).unwrap());
+    absence.path[0].from_left = !absence.path[0].from_left;
+    assert!(!verify::verify_iavl_proof(root, absent, None, &absence).unwrap());
+
+    // The empty tree proves every key absent.
+    let empty = IavlState::new();
+    assert!(verify::verify_iavl_proof([0u8; 32], b"key", None, &proof(&empty, b"key")).unwrap());
+}
+
+#[test]
+fn test_corrupted_iavl_state_is_rejected() {
+    let (mut iavl, _) = run_ops(3, 100, 30);
+    let bytes = iavl.to_bytes();
+    assert!(IavlState::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+    assert!(IavlState::from_bytes(b"ZKAV\x02\x00").is_err());
+    assert!(IavlState::from_bytes(b"ZKSM\x01\x00").is_err());
+
+    // Swapping the children of the root breaks the key order.
+    let root = iavl.root.as_mut().unwrap();
+    std::mem::swap(&mut root.left, &mut root.right);
+    assert!(IavlState::from_bytes(&iavl.to_bytes()).is_err());
+    let root = iavl.root.as_mut().unwrap();
+    std::mem::swap(&mut root.left, &mut root.right);
+    root.size += 1;
+    assert!(IavlState::from_bytes(&iavl.to_bytes()).is_err());
+}
@@ -0,0 +1,43 @@
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+use tracing::{info, info_span};
+use zkdb_lib::{build_subscriber, LogFormat};
+
+#[derive(Clone, Default)]
+struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+impl Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn test_json_log_format() {
+    let buffer = SharedBuffer::default();
+    let writer = buffer.clone();
+    let subscriber = build_subscriber(LogFormat::Json, move || writer.clone());
+
+    tracing::subscriber::with_default(subscriber, || {
+        let span = info_span!("put", key = "key1");
+        let _guard = span.enter();
+        info!(leaf_count = 1, "inserted key");
+        info!("second event");
+    });
+
+    let output = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+    let lines: Vec<serde_json::Value> = output
+        .lines()
+        .map(|line| serde_json::from_str(line).expect("log line is not valid JSON"))
+        .collect();
+    assert_eq!(lines.len(), 2);
+    assert_eq!(lines[0]["level"], "INFO");
+    assert_eq!(lines[0]["fields"]["message"], "inserted key");
+    assert_eq!(lines[0]["fields"]["leaf_count"], 1);
+    assert_eq!(lines[0]["span"]["name"], "put");
+    assert_eq!(lines[0]["span"]["key"], "key1");
+}
@@ -1,10 +1,22 @@
 use hex;
+use rs_merkle::algorithms::Sha256 as MerkleSha256;
+use rs_merkle::proof_serializers::ReverseHashesOrder;
+use rs_merkle::MerkleProof;
 use serial_test::serial;
 use sha2::{Digest, Sha256};
+use sp1_sdk::SP1ProofWithPublicValues;
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 use tempfile;
-use zkdb_lib::{Command, Database, DatabaseType};
+use zkdb_core::backend::{RsMerkle, TreeBackend};
+use zkdb_core::merkle::{hashed_key, MerkleState, TreeData, ROOT_TREE};
+use zkdb_lib::{
+    get_elf_for, verify, Command, ConflictPolicy, Database, DatabaseError, DatabaseType,
+    InsertResult, KeyDerivation, MerkleConfig, MultiQueryEntry, ProofMode, ProvenOutput,
+    SP1Executor, TreeLayout,
+};
 use zkdb_store::file::FileStore;
+use zkdb_store::Store;
 
 fn init() {
     let _ = tracing_subscriber::fmt()
@@ -44,7 +56,7 @@ async fn test_insert_and_get() {
     };
 
     tracing::debug!("Executing insert command");
-    let insert_result = db.execute_query(insert_command, false).unwrap();
+    let insert_result = db.execute_query(insert_command, false).await.unwrap();
     tracing::debug!("Insert result: {:?}", insert_result.data);
     assert!(insert_result.data["inserted"].as_bool().unwrap());
 
@@ -54,7 +66,7 @@ async fn test_insert_and_get() {
     };
 
     tracing::debug!("Executing query command");
-    let get_result = db.execute_query(get_command, false).unwrap();
+    let get_result = db.execute_query(get_command, false).await.unwrap();
     tracing::debug!("Query result: {:?}", get_result.data);
     assert!(get_result.data["found"].as_bool().unwrap());
 
@@ -82,7 +94,7 @@ async fn test_proof_generation_and_verification() {
         key: key.to_string(),
         value: value_hash, // Send the hex-encoded hash
     };
-    let insert_result = db.execute_query(insert_command, true).unwrap();
+    let insert_result = db.execute_query(insert_command, true).await.unwrap();
     tracing::debug!("Insert with proof result: {:?}", insert_result.data);
     assert!(insert_result.sp1_proof.is_some());
 
@@ -91,7 +103,7 @@ async fn test_proof_generation_and_verification() {
     let prove_command = Command::Prove {
         key: key.to_string(),
     };
-    let prove_result = db.execute_query(prove_command, true).unwrap();
+    let prove_result = db.execute_query(prove_command, true).await.unwrap();
     tracing::debug!("Proof generation result: {:?}", prove_result.data);
 
     // Verify proof exists
@@ -99,6 +111,265 @@ async fn test_proof_generation_and_verification() {
     assert!(prove_result.data["root"].is_string());
 }
 
+#[tokio::test]
+#[serial]
+async fn test_typed_results() {
+    init();
+    let (mut db, _store) = setup_database().await;
+    let value_hash = verify::hash_value_hex(b"value");
+
+    let inserted = db
+        .execute_query(
+            Command::Insert {
+                key: "a".to_string(),
+                value: value_hash.clone(),
+            },
+            false,
+        )
+        .await
+        .unwrap()
+        .as_insert()
+        .unwrap();
+    assert_eq!(
+        inserted,
+        InsertResult {
+            key: "a".to_string(),
+            index: 0,
+            leaf: value_hash.clone(),
+            root: db.current_root_hex().unwrap(),
+            inserted: true,
+        }
+    );
+
+    let batch = db
+        .execute_query(
+            Command::BatchInsert {
+                entries: vec![
+                    ("b".to_string(), value_hash.clone()),
+                    ("c".to_string(), value_hash.clone()),
+                ],
+            },
+            false,
+        )
+        .await
+        .unwrap()
+        .as_batch_insert()
+        .unwrap();
+    assert_eq!(
+        (batch.inserted, batch.first_index, batch.total_leaves),
+        (2, 1, 3)
+    );
+    assert_eq!(Some(batch.root), db.current_root_hex());
+
+    let hit = db
+        .execute_query(
+            Command::Query {
+                key: "c".to_string(),
+            },
+            false,
+        )
+        .await
+        .unwrap()
+        .as_query()
+        .unwrap();
+    assert_eq!((hit.value.as_str(), hit.index), (value_hash.as_str(), 2));
+
+    let proof = db.prove("b", false).unwrap().as_proof().unwrap();
+    assert_eq!((proof.index, proof.total_leaves), (1, 3));
+    assert!(Database::verify_inclusion_from_proof_bytes(
+        &proof.root,
+        &proof.leaf,
+        proof.index,
+        proof.total_leaves,
+        &proof.proof
+    )
+    .unwrap());
+
+    let deleted = db
+        .execute_query(
+            Command::Delete {
+                key: "a".to_string(),
+            },
+            false,
+        )
+        .await
+        .unwrap()
+        .as_delete()
+        .unwrap();
+    assert!(deleted.deleted);
+    assert_eq!(Some(deleted.root.clone()), db.current_root_hex());
+
+    let root = db
+        .execute_query(Command::GetRoot, false)
+        .await
+        .unwrap()
+        .as_root()
+        .unwrap();
+    assert_eq!((root.root, root.leaf_count), (Some(deleted.root), 3));
+
+    // Engine errors come back as their `DatabaseError`, and output of
+    // another command as a format error.
+    let missing = db
+        .execute_query(
+            Command::Query {
+                key: "a".to_string(),
+            },
+            false,
+        )
+        .await
+        .unwrap();
+    assert!(matches!(missing.as_query(), Err(DatabaseError::KeyNotFound(key)) if key == "a"));
+    let query = db
+        .execute_query(
+            Command::Query {
+                key: "b".to_string(),
+            },
+            false,
+        )
+        .await
+        .unwrap();
+    assert!(matches!(
+        query.as_insert(),
+        Err(DatabaseError::QueryExecutionFailed(_))
+    ));
+}
+
+#[tokio::test]
+#[serial]
+async fn test_verifier_only_executor() {
+    init();
+    let (mut db, _store) = setup_database().await;
+    db.put("key", b"value", false).await.unwrap();
+    let proven = db
+        .execute_query(
+            Command::Query {
+                key: "key".to_string(),
+            },
+            true,
+        )
+        .await
+        .unwrap();
+    let proof = proven.sp1_proof.unwrap();
+
+    let elf = get_elf_for(&DatabaseType::Merkle).expect("merkle ELF is embedded");
+    let verifier = SP1Executor::new_verifier_only(elf);
+    assert!(!verifier.can_prove());
+    assert_eq!(verifier.vk_hash(), db.vk_hash());
+    assert!(verifier.verify_proof(&proof).unwrap());
+
+    // Executing still works; only proving is refused.
+    let state = db.get_state().to_vec();
+    let query = Command::Query {
+        key: "key".to_string(),
+    };
+    let executed = verifier.execute_query(&state, &query, false).unwrap();
+    assert_eq!(executed.data, proven.data);
+    match verifier.execute_query(&state, &query, true) {
+        Err(DatabaseError::ProofGenerationFailed(reason)) => {
+            assert!(reason.contains("verifier-only"), "{}", reason)
+        }
+        other => panic!(
+            "expected proving to be refused, got {:?}",
+            other.map(|r| r.data)
+        ),
+    }
+}
+
+#[test]
+#[serial]
+fn test_pk_vk_cache_speeds_up_startup() {
+    init();
+    let elf = get_elf_for(&DatabaseType::Merkle).expect("merkle ELF is embedded");
+    let cache = tempfile::tempdir().unwrap();
+
+    let started = Instant::now();
+    let generated = SP1Executor::with_pk_vk_cache(elf, cache.path());
+    let uncached = started.elapsed();
+    assert!(cache.path().join("pk.bin").exists());
+    assert!(cache.path().join("vk.bin").exists());
+
+    let started = Instant::now();
+    let loaded = SP1Executor::with_pk_vk_cache(elf, cache.path());
+    let cached = started.elapsed();
+    assert_eq!(loaded.vk_hash(), generated.vk_hash());
+    assert!(
+        cached * 2 <= uncached,
+        "cached startup took {:?}, uncached {:?}",
+        cached,
+        uncached
+    );
+
+    SP1Executor::invalidate_pk_vk_cache(cache.path()).unwrap();
+    assert!(!cache.path().join("pk.bin").exists());
+    let regenerated = SP1Executor::with_pk_vk_cache(elf, cache.path());
+    assert_eq!(regenerated.vk_hash(), generated.vk_hash());
+    assert!(cache.path().join("pk.bin").exists());
+}
+
+#[tokio::test]
+#[serial]
+async fn test_compressed_proof_round_trip() {
+    init();
+    let temp_dir = tempfile::tempdir().unwrap();
+    let store = Arc::new(FileStore::new(temp_dir.path().join("db")).await.unwrap());
+    let mut db = Database::builder(DatabaseType::Merkle, store)
+        .proof_mode(ProofMode::Compressed)
+        .build()
+        .await
+        .unwrap();
+    db.put("key", b"value", false).await.unwrap();
+    let proof = db
+        .execute_query(
+            Command::Query {
+                key: "key".to_string(),
+            },
+            true,
+        )
+        .await
+        .unwrap()
+        .sp1_proof
+        .unwrap();
+    assert_eq!(proof.proof_mode(), Some(ProofMode::Compressed));
+
+    let path = temp_dir.path().join("query.proof");
+    proof.proof_data.save(&path).unwrap();
+    let loaded = ProvenOutput {
+        proof_data: SP1ProofWithPublicValues::load(&path).unwrap(),
+        vk: proof.vk.clone(),
+    };
+    assert_eq!(loaded.proof_mode(), Some(ProofMode::Compressed));
+    assert!(db.verify_proof(&loaded).unwrap());
+}
+
+#[tokio::test]
+#[serial]
+async fn test_put_async_proof() {
+    init();
+    let (mut db, _store) = setup_database().await;
+
+    let pending = db
+        .put_async_proof("async_key", b"async_value")
+        .await
+        .unwrap();
+    // The insert is applied before its proof is ready.
+    assert_eq!(db.get("async_key", false).await.unwrap(), b"async_value");
+    for i in 0..3 {
+        db.put(
+            &format!("key_{}", i),
+            format!("value_{}", i).as_bytes(),
+            false,
+        )
+        .await
+        .unwrap();
+    }
+
+    let proof = pending.await.unwrap();
+    assert!(db.verify_proof(&proof).unwrap());
+    let data = verify::decode_public_values(proof.proof_data.public_values.as_slice()).unwrap();
+    assert_eq!(data["key"], "async_key");
+    assert_eq!(data["leaf"], verify::hash_value_hex(b"async_value"));
+}
+
 #[tokio::test]
 #[serial]
 async fn test_multiple_operations() {
@@ -122,7 +393,7 @@ async fn test_multiple_operations() {
         };
 
         tracing::debug!("Inserting key-value pair {}", i);
-        let result = db.execute_query(insert_command, false).unwrap();
+        let result = db.execute_query(insert_command, false).await.unwrap();
         tracing::debug!("Insert result for pair {}: {:?}", i, result.data);
         assert!(result.data["inserted"].as_bool().unwrap());
     }
@@ -133,7 +404,7 @@ async fn test_multiple_operations() {
         let get_command = Command::Query { key: key.clone() };
 
         tracing::debug!("Querying key {}", key);
-        let result = db.execute_query(get_command, false).unwrap();
+        let result = db.execute_query(get_command, false).await.unwrap();
         tracing::debug!("Query result for key {}: {:?}", key, result.data);
         assert!(result.data["found"].as_bool().unwrap());
     }
@@ -161,7 +432,7 @@ async fn test_merkle_tree_properties() {
             key: key.clone(),
             value: value_hash.clone(), // Send the hex-encoded hash
         };
-        let result = db.execute_query(insert_command, false).unwrap();
+        let result = db.execute_query(insert_command, false).await.unwrap();
         value_hashes.push(result.data["leaf"].as_str().unwrap().to_string());
     }
 
@@ -171,7 +442,7 @@ async fn test_merkle_tree_properties() {
         let prove_command = Command::Prove { key: key.clone() };
 
         tracing::debug!("Generating proof for key {}", key);
-        let result = db.execute_query(prove_command, false).unwrap();
+        let result = db.execute_query(prove_command, false).await.unwrap();
         tracing::debug!("Proof result for key {}: {:?}", key, result.data);
 
         // Verify proof contains necessary components
@@ -201,7 +472,7 @@ async fn test_state_consistency() {
         key: key.to_string(),
         value: value_hash, // Send the hex-encoded hash
     };
-    db.execute_query(insert_command, false).unwrap();
+    db.execute_query(insert_command, false).await.unwrap();
 
     // Get current state
     let state = db.get_state().to_vec();
@@ -217,7 +488,1314 @@ async fn test_state_consistency() {
     };
 
     tracing::debug!("Querying value from new database instance");
-    let result = new_db.execute_query(get_command, false).unwrap();
+    let result = new_db.execute_query(get_command, false).await.unwrap();
     tracing::debug!("Query result from new instance: {:?}", result.data);
     assert!(result.data["found"].as_bool().unwrap());
 }
+
+#[tokio::test]
+#[serial]
+async fn test_explain() {
+    init();
+    let (mut db, _store) = setup_database().await;
+
+    let mut hasher = Sha256::new();
+    hasher.update(b"explained_value");
+    let value_hash = hex::encode(hasher.finalize());
+    for i in 0..3 {
+        let insert_command = Command::Insert {
+            key: format!("key_{}", i),
+            value: value_hash.clone(),
+        };
+        db.execute_query(insert_command, false).await.unwrap();
+    }
+    let state_before = db.get_state().to_vec();
+
+    let insert = db.explain(&Command::Insert {
+        key: "key_3".to_string(),
+        value: value_hash.clone(),
+    });
+    assert!(insert.is_mutating);
+    // Appending is cheap, but the new root is recorded as a version.
+    assert!(insert.requires_tree_rebuild);
+    assert!(insert.description.contains("as version 4"));
+    assert_eq!(insert.estimated_leaves_affected, 1);
+
+    let query = db.explain(&Command::Query {
+        key: "key_1".to_string(),
+    });
+    assert!(!query.is_mutating);
+    assert!(!query.requires_tree_rebuild);
+    assert_eq!(query.estimated_leaves_affected, 1);
+
+    let missing = db.explain(&Command::Query {
+        key: "missing".to_string(),
+    });
+    assert_eq!(missing.estimated_leaves_affected, 0);
+
+    let prove = db.explain(&Command::Prove {
+        key: "key_1".to_string(),
+    });
+    assert!(!prove.is_mutating);
+    assert!(prove.requires_tree_rebuild);
+    assert_eq!(prove.estimated_leaves_affected, 3);
+
+    let history = db.explain(&Command::History {
+        key: "key_1".to_string(),
+    });
+    assert!(!history.is_mutating);
+    assert!(!history.requires_tree_rebuild);
+    assert_eq!(history.estimated_leaves_affected, 0);
+
+    // Explaining never touches the state.
+    assert_eq!(db.get_state(), state_before.as_slice());
+}
+
+#[tokio::test]
+#[serial]
+async fn test_prove_empty_tree() {
+    init();
+    let (db, _store) = setup_database().await;
+
+    let result = db.prove("any_key", false);
+    assert!(matches!(result, Err(DatabaseError::EmptyTree)));
+}
+
+#[tokio::test]
+#[serial]
+async fn test_inspect_path_matches_root() {
+    init();
+    let (mut db, _store) = setup_database().await;
+
+    // An odd leaf count exercises levels where a node has no sibling.
+    for i in 0..5 {
+        let mut hasher = Sha256::new();
+        hasher.update(format!("value_{}", i).as_bytes());
+        let insert_command = Command::Insert {
+            key: format!("key_{}", i),
+            value: hex::encode(hasher.finalize()),
+        };
+        db.execute_query(insert_command, false).await.unwrap();
+    }
+
+    let root_result = db.execute_query(Command::GetRoot, false).await.unwrap();
+    let root = root_result.data["root"].as_str().unwrap().to_string();
+
+    for i in 0..5 {
+        let inspection = db.inspect(&format!("key_{}", i)).unwrap();
+        let mut index = inspection["leaf_index"].as_u64().unwrap();
+        let mut node = hex::decode(inspection["leaf_hex"].as_str().unwrap()).unwrap();
+
+        // Fold the siblings back up to the root.
+        for sibling in inspection["sibling_hashes"].as_array().unwrap() {
+            if let Some(sibling) = sibling.as_str() {
+                let sibling = hex::decode(sibling).unwrap();
+                let mut hasher = Sha256::new();
+                if index % 2 == 0 {
+                    hasher.update(&node);
+                    hasher.update(&sibling);
+                } else {
+                    hasher.update(&sibling);
+                    hasher.update(&node);
+                }
+                node = hasher.finalize().to_vec();
+            }
+            index /= 2;
+        }
+        assert_eq!(hex::encode(&node), root);
+
+        let path = inspection["path_to_root"].as_array().unwrap();
+        assert_eq!(path.last().unwrap().as_str().unwrap(), root);
+    }
+}
+
+#[tokio::test]
+#[serial]
+async fn test_get_with_proof() {
+    init();
+    let (mut db, _store) = setup_database().await;
+
+    for i in 0..3 {
+        db.put(
+            &format!("key{}", i),
+            format!("value{}", i).as_bytes(),
+            false,
+        )
+        .await
+        .unwrap();
+    }
+    let executions = db.execution_count();
+    let (value, proof, sp1_proof) = db.get_with_proof("key1", false).await.unwrap();
+    assert_eq!(db.execution_count(), executions + 1);
+    assert_eq!(value, b"value1");
+    assert!(sp1_proof.is_none());
+
+    // The proof leads from the hash of the returned value to the root.
+    let leaf = verify::decode_hash("leaf", &verify::hash_value_hex(&value)).unwrap();
+    let root = verify::decode_hash("root", &proof.root).unwrap();
+    let bytes = base64::decode(&proof.proof).unwrap();
+    assert!(verify::verify_inclusion(root, leaf, proof.index, proof.total_leaves, &bytes).unwrap());
+
+    assert!(matches!(
+        db.get_with_proof("missing", false).await,
+        Err(DatabaseError::KeyNotFound(_))
+    ));
+}
+
+#[tokio::test]
+#[serial]
+async fn test_multi_prove() {
+    init();
+    let (mut db, _store) = setup_database().await;
+
+    for i in 0..6 {
+        let mut hasher = Sha256::new();
+        hasher.update(format!("value_{}", i).as_bytes());
+        let insert_command = Command::Insert {
+            key: format!("key_{}", i),
+            value: hex::encode(hasher.finalize()),
+        };
+        db.execute_query(insert_command, false).await.unwrap();
+    }
+
+    let keys = ["key_4", "key_0", "key_2", "key_5"];
+    let result = db.prove_many(&keys, false).unwrap();
+    let root = verify::decode_hash("root", result.data["root"].as_str().unwrap()).unwrap();
+    let total_leaves = result.data["total_leaves"].as_u64().unwrap() as usize;
+
+    // Every key gets a standalone proof against the shared root.
+    let proofs = result.data["proofs"].as_array().unwrap();
+    assert_eq!(proofs.len(), keys.len());
+    for (proof, key) in proofs.iter().zip(keys) {
+        assert_eq!(proof["key"].as_str().unwrap(), key);
+        let leaf = verify::decode_hash("leaf", proof["leaf"].as_str().unwrap()).unwrap();
+        let bytes = base64::decode(proof["proof"].as_str().unwrap()).unwrap();
+        let index = proof["index"].as_u64().unwrap() as usize;
+        assert!(verify::verify_inclusion(root, leaf, index, total_leaves, &bytes).unwrap());
+    }
+
+    // The multiproof covers all of them at once.
+    let indices: Vec<usize> = result.data["indices"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|index| index.as_u64().unwrap() as usize)
+        .collect();
+    let leaves: Vec<[u8; 32]> = result.data["leaves"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|leaf| verify::decode_hash("leaf", leaf.as_str().unwrap()).unwrap())
+        .collect();
+    let multiproof = base64::decode(result.data["multiproof"].as_str().unwrap()).unwrap();
+    let multiproof =
+        MerkleProof::<MerkleSha256>::deserialize::<ReverseHashesOrder>(&multiproof).unwrap();
+    assert_eq!(indices, vec![0, 2, 4, 5]);
+    assert!(multiproof.verify(root, &indices, &leaves, total_leaves));
+
+    assert!(matches!(
+        db.prove_many(&["key_0", "missing"], false),
+        Err(DatabaseError::KeyNotFound(key)) if key == "missing"
+    ));
+}
+
+#[tokio::test]
+#[serial]
+async fn test_multi_query() {
+    init();
+    let (mut db, _store) = setup_database().await;
+
+    let mut leaves = Vec::new();
+    for i in 0..3 {
+        let mut hasher = Sha256::new();
+        hasher.update(format!("value_{}", i).as_bytes());
+        let leaf = hex::encode(hasher.finalize());
+        let insert_command = Command::Insert {
+            key: format!("key_{}", i),
+            value: leaf.clone(),
+        };
+        db.execute_query(insert_command, false).await.unwrap();
+        leaves.push(leaf);
+    }
+
+    let keys = ["key_2", "missing", "key_0", "key_1", "also_missing"];
+    let result = db.get_many_committed(&keys, false).unwrap();
+    let hit = |key: &str, index: usize| MultiQueryEntry {
+        key: key.to_string(),
+        found: true,
+        value: Some(leaves[index].clone()),
+        index: Some(index),
+    };
+    let miss = |key: &str| MultiQueryEntry {
+        key: key.to_string(),
+        found: false,
+        value: None,
+        index: None,
+    };
+    assert_eq!(
+        result.as_multi_query().unwrap(),
+        vec![
+            hit("key_2", 2),
+            miss("missing"),
+            hit("key_0", 0),
+            hit("key_1", 1),
+            miss("also_missing"),
+        ]
+    );
+}
+
+#[tokio::test]
+#[serial]
+async fn test_prove_range() {
+    init();
+    let (mut db, _store) = setup_database().await;
+
+    // Insert out of key order so leaf indices differ from key order.
+    for i in [3, 7, 0, 9, 5, 1, 8, 2, 6, 4] {
+        let mut hasher = Sha256::new();
+        hasher.update(format!("value_{}", i).as_bytes());
+        let insert_command = Command::Insert {
+            key: format!("key_{}", i),
+            value: hex::encode(hasher.finalize()),
+        };
+        db.execute_query(insert_command, false).await.unwrap();
+    }
+
+    let result = db.prove_range("key_3", "key_6", false).unwrap();
+    let root = verify::decode_hash("root", result.data["root"].as_str().unwrap()).unwrap();
+    let total_leaves = result.data["total_leaves"].as_u64().unwrap() as usize;
+    assert_eq!(total_leaves, 10);
+
+    let keys: Vec<&str> = result.data["keys"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|entry| entry["key"].as_str().unwrap())
+        .collect();
+    assert_eq!(keys, vec!["key_3", "key_4", "key_5"]);
+
+    let indices: Vec<usize> = result.data["indices"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|index| index.as_u64().unwrap() as usize)
+        .collect();
+    let leaves: Vec<[u8; 32]> = result.data["leaves"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|leaf| verify::decode_hash("leaf", leaf.as_str().unwrap()).unwrap())
+        .collect();
+    assert_eq!(indices, vec![0, 4, 9]);
+
+    // The multiproof and leaves alone are enough to rebuild the root.
+    let multiproof = base64::decode(result.data["multiproof"].as_str().unwrap()).unwrap();
+    let multiproof =
+        MerkleProof::<MerkleSha256>::deserialize::<ReverseHashesOrder>(&multiproof).unwrap();
+    assert_eq!(
+        multiproof.root(&indices, &leaves, total_leaves).unwrap(),
+        root
+    );
+
+    assert!(matches!(
+        db.prove_range("key_a", "key_z", false),
+        Err(DatabaseError::QueryExecutionFailed(_))
+    ));
+}
+
+#[tokio::test]
+#[serial]
+async fn test_range_pages() {
+    init();
+    let (mut db, store) = setup_database().await;
+
+    // Insert out of key order so leaf indices differ from key order.
+    for i in [3, 7, 0, 9, 5, 1, 8, 2, 6, 4] {
+        db.put(
+            &format!("key_{}", i),
+            format!("value_{}", i).as_bytes(),
+            false,
+        )
+        .await
+        .unwrap();
+    }
+
+    let mut pages = Vec::new();
+    let mut start = "key_2".to_string();
+    loop {
+        let page = db.range(start.as_str().."key_8", 2, false).await.unwrap();
+        pages.push(
+            page.entries
+                .iter()
+                .map(|entry| {
+                    assert_eq!(
+                        entry.value,
+                        format!("value_{}", &entry.key[4..]).into_bytes()
+                    );
+                    entry.key.clone()
+                })
+                .collect::<Vec<_>>(),
+        );
+        match page.next {
+            Some(next) => start = next,
+            None => break,
+        }
+    }
+    assert_eq!(
+        pages,
+        vec![
+            vec!["key_2", "key_3"],
+            vec!["key_4", "key_5"],
+            vec!["key_6", "key_7"],
+        ]
+    );
+
+    let empty = db.range("key_a".."key_z", 10, false).await.unwrap();
+    assert!(empty.entries.is_empty());
+    assert_eq!(empty.next, None);
+
+    // A value that no longer matches its leaf fails the page.
+    store.put("key_4", b"tampered").await.unwrap();
+    assert!(db.range("key_4".."key_5", 1, false).await.is_err());
+}
+
+#[tokio::test]
+#[serial]
+async fn test_sorted_layout_proves_completeness() {
+    init();
+    let temp_dir = tempfile::tempdir().unwrap();
+    let store = Arc::new(FileStore::new(temp_dir.path()).await.unwrap());
+    let mut db = Database::builder(DatabaseType::Merkle, store.clone())
+        .merkle_config(MerkleConfig {
+            layout: TreeLayout::Sorted,
+            ..MerkleConfig::default()
+        })
+        .build()
+        .await
+        .unwrap();
+    assert_eq!(db.layout(), TreeLayout::Sorted);
+
+    // An empty tree holds nothing.
+    db.prove_absence("key_0", false).unwrap();
+
+    for i in [3, 7, 0, 9, 5, 1, 8, 2, 6, 4] {
+        db.put(
+            &format!("key_{}", i),
+            format!("value_{}", i).as_bytes(),
+            false,
+        )
+        .await
+        .unwrap();
+    }
+    db.put("key_5", b"value_5", false).await.unwrap();
+    db.delete("key_6", false).await.unwrap();
+
+    // Leaves follow key order whatever the order of the writes.
+    let proof = db.prove("key_4", false).unwrap();
+    assert_eq!(proof.data["index"], 4);
+    assert_eq!(proof.data["total_leaves"], 9);
+    let bundle = db.proof_bundle("key_4", false).await.unwrap();
+    assert_eq!(bundle.layout, verify::LeafLayout::Sorted);
+    assert!(verify::verify_bundle(&bundle).unwrap().valid);
+
+    let first = db.range("key_2".."key_8", 3, false).await.unwrap();
+    assert!(first.complete);
+    let keys: Vec<_> = first
+        .entries
+        .iter()
+        .map(|entry| entry.key.as_str())
+        .collect();
+    assert_eq!(keys, ["key_2", "key_3", "key_4"]);
+    assert_eq!(first.next.as_deref(), Some("key_5"));
+    let second = db.range("key_5".."key_8", 3, false).await.unwrap();
+    let keys: Vec<_> = second
+        .entries
+        .iter()
+        .map(|entry| entry.key.as_str())
+        .collect();
+    assert_eq!(keys, ["key_5", "key_7"]);
+    assert_eq!(second.next, None);
+
+    // A page of no keys proves the gap it found.
+    let empty = db.range("key_a".."key_z", 10, false).await.unwrap();
+    assert!(empty.complete && empty.entries.is_empty());
+
+    db.prove_absence("key_6", false).unwrap();
+    db.prove_absence("a", false).unwrap();
+    db.prove_absence("z", false).unwrap();
+    assert!(db.prove_absence("key_5", false).is_err());
+
+    // The layout is kept by the state and cannot change once written.
+    let reopened = Database::builder(DatabaseType::Merkle, store.clone())
+        .state(db.get_state().to_vec())
+        .build()
+        .await
+        .unwrap();
+    assert_eq!(reopened.layout(), TreeLayout::Sorted);
+    assert_eq!(reopened.get("key_7", false).await.unwrap(), b"value_7");
+    assert!(Database::builder(DatabaseType::Merkle, store)
+        .state(db.get_state().to_vec())
+        .merkle_config(MerkleConfig::default())
+        .build()
+        .await
+        .is_err());
+
+    // The append layout cannot prove absence, nor complete pages.
+    let (mut append, _store) = setup_database().await;
+    assert_eq!(append.layout(), TreeLayout::Append);
+    append.put("key_1", b"value_1", false).await.unwrap();
+    assert!(append.prove_absence("key_0", false).is_err());
+    assert!(
+        !append
+            .range("key_0".."key_9", 10, false)
+            .await
+            .unwrap()
+            .complete
+    );
+}
+
+#[tokio::test]
+#[serial]
+async fn test_root_at_version() {
+    init();
+    let (mut db, _store) = setup_database().await;
+    assert!(matches!(
+        db.root_at(1),
+        Err(DatabaseError::UnknownVersion(1))
+    ));
+
+    let mut roots = Vec::new();
+    for i in 0..4 {
+        db.put(
+            &format!("key_{}", i),
+            format!("value_{}", i).as_bytes(),
+            false,
+        )
+        .await
+        .unwrap();
+        roots.push(db.current_root_hex().unwrap());
+    }
+    db.delete("key_0", false).await.unwrap();
+    roots.push(db.current_root_hex().unwrap());
+
+    for (i, root) in roots.iter().enumerate() {
+        assert_eq!(&db.root_at(i as u64 + 1).unwrap(), root);
+    }
+    assert!(matches!(
+        db.root_at(0),
+        Err(DatabaseError::UnknownVersion(0))
+    ));
+    assert!(matches!(
+        db.root_at(6),
+        Err(DatabaseError::UnknownVersion(6))
+    ));
+
+    // Reads do not add versions, and named trees count their own.
+    db.get("key_1", false).await.unwrap();
+    db.tree("other").put("key", b"value", false).await.unwrap();
+    assert!(matches!(
+        db.root_at(6),
+        Err(DatabaseError::UnknownVersion(6))
+    ));
+    let state = MerkleState::from_bytes(db.get_state()).unwrap();
+    assert_eq!(state.tree(ROOT_TREE).unwrap().latest_version(), 5);
+    assert_eq!(state.tree("other").unwrap().latest_version(), 1);
+}
+
+#[tokio::test]
+#[serial]
+async fn test_generate_solidity_verifier() {
+    init();
+    let (db, _store) = setup_database().await;
+
+    let contract = db.generate_solidity_verifier().unwrap();
+    let vk_hash = db.vk_hash();
+    assert!(contract.contains(&format!(
+        "bytes32 public constant PROGRAM_VKEY = {};",
+        vk_hash.to_lowercase()
+    )));
+    assert!(
+        contract.contains("function verify(bytes calldata proof, bytes32[] calldata publicValues)")
+    );
+
+    #[cfg(feature = "solidity-test")]
+    {
+        let dir = tempfile::tempdir().unwrap();
+        let source = dir.path().join("ZkdbVerifier.sol");
+        std::fs::write(&source, &contract).unwrap();
+        let output = std::process::Command::new("solc")
+            .arg("--bin")
+            .arg(&source)
+            .output()
+            .expect("solc is on the PATH");
+        assert!(
+            output.status.success(),
+            "solc failed: {}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+}
+
+/// OpenZeppelin's `MerkleProof.verify`, with pairs hashed by SHA-256 in the
+/// order `path` gives rather than sorted.
+fn openzeppelin_verify(proof: &[[u8; 32]], path: u64, root: [u8; 32], leaf: [u8; 32]) -> bool {
+    let mut computed_hash = leaf;
+    for (i, proof_element) in proof.iter().enumerate() {
+        let (left, right) = if path >> i & 1 == 1 {
+            (proof_element, &computed_hash)
+        } else {
+            (&computed_hash, proof_element)
+        };
+        let mut hasher = Sha256::new();
+        hasher.update(left);
+        hasher.update(right);
+        computed_hash = hasher.finalize().into();
+    }
+    computed_hash == root
+}
+
+#[tokio::test]
+#[serial]
+async fn test_serialize_for_solidity() {
+    init();
+    let (mut db, _store) = setup_database().await;
+
+    // An odd number of leaves, so some nodes are promoted without a sibling.
+    for i in 0..7 {
+        db.put(
+            &format!("key_{}", i),
+            format!("value_{}", i).as_bytes(),
+            false,
+        )
+        .await
+        .unwrap();
+    }
+    let root = verify::decode_hash("root", &db.current_root_hex().unwrap()).unwrap();
+
+    for i in 0..7 {
+        let proof = db.serialize_for_solidity(&format!("key_{}", i)).unwrap();
+        assert_eq!(proof.root, root);
+        assert_eq!(
+            proof.leaf,
+            verify::hash_value(format!("value_{}", i).as_bytes())
+        );
+        assert!(openzeppelin_verify(
+            &proof.proof_hashes,
+            proof.path,
+            proof.root,
+            proof.leaf
+        ));
+        assert!(!openzeppelin_verify(
+            &proof.proof_hashes,
+            proof.path,
+            proof.root,
+            verify::hash_value(b"forged")
+        ));
+
+        let decoded = ethabi::decode(
+            &[ethabi::ParamType::Tuple(vec![
+                ethabi::ParamType::FixedBytes(32),
+                ethabi::ParamType::FixedBytes(32),
+                ethabi::ParamType::Array(Box::new(ethabi::ParamType::FixedBytes(32))),
+                ethabi::ParamType::Uint(256),
+            ])],
+            &proof.to_abi_bytes(),
+        )
+        .unwrap();
+        let ethabi::Token::Tuple(fields) = &decoded[0] else {
+            panic!("expected a tuple, got {:?}", decoded);
+        };
+        assert_eq!(fields[0], ethabi::Token::FixedBytes(proof.root.to_vec()));
+        assert_eq!(fields[1], ethabi::Token::FixedBytes(proof.leaf.to_vec()));
+        assert_eq!(
+            fields[2],
+            ethabi::Token::Array(
+                proof
+                    .proof_hashes
+                    .iter()
+                    .map(|hash| ethabi::Token::FixedBytes(hash.to_vec()))
+                    .collect()
+            )
+        );
+        assert_eq!(fields[3], ethabi::Token::Uint(proof.path.into()));
+    }
+
+    assert!(matches!(
+        db.serialize_for_solidity("missing"),
+        Err(DatabaseError::KeyNotFound(_))
+    ));
+}
+
+#[test]
+fn test_verify_inclusion_from_proof_bytes_without_a_database() {
+    // Built with rs_merkle as the engine builds it, with no zkVM involved.
+    let leaves: Vec<[u8; 32]> = (0..5)
+        .map(|i| verify::hash_value(format!("value_{}", i).as_bytes()))
+        .collect();
+    let tree = rs_merkle::MerkleTree::<MerkleSha256>::from_leaves(&leaves);
+    let root = hex::encode(tree.root().unwrap());
+    for (index, leaf) in leaves.iter().enumerate() {
+        let proof = base64::encode(tree.proof(&[index]).serialize::<ReverseHashesOrder>());
+        assert!(Database::verify_inclusion_from_proof_bytes(
+            &root,
+            &hex::encode(leaf),
+            index,
+            leaves.len(),
+            &proof
+        )
+        .unwrap());
+    }
+}
+
+#[test]
+fn test_tree_backend_matches_rs_merkle() {
+    let leaves: Vec<[u8; 32]> = (0..7)
+        .map(|i| verify::hash_value(format!("value_{}", i).as_bytes()))
+        .collect();
+    let tree = rs_merkle::MerkleTree::<MerkleSha256>::from_leaves(&leaves);
+
+    // Inserted one at a time, as the engine appends leaves.
+    let mut backend = RsMerkle::from_leaves(&[]);
+    assert_eq!(backend.root(), None);
+    for leaf in &leaves {
+        backend.insert(*leaf);
+    }
+    assert_eq!(backend.root(), tree.root());
+    assert_eq!(
+        RsMerkle::from_leaves(&leaves).root(),
+        TreeData {
+            leaves: leaves.clone(),
+            ..TreeData::default()
+        }
+        .compute_root()
+    );
+
+    let root = backend.root().unwrap();
+    for (index, leaf) in leaves.iter().enumerate() {
+        let proof = backend.prove(&[index]);
+        assert_eq!(
+            proof,
+            tree.proof(&[index]).serialize::<ReverseHashesOrder>()
+        );
+        assert!(RsMerkle::verify(
+            root,
+            &[index],
+            &[*leaf],
+            leaves.len(),
+            &proof
+        ));
+        assert!(verify::verify_inclusion(root, *leaf, index, leaves.len(), &proof).unwrap());
+        assert!(!RsMerkle::verify(
+            root,
+            &[index],
+            &[[0; 32]],
+            leaves.len(),
+            &proof
+        ));
+        // The path ends at the root, with a sibling at every level but where
+        // the odd node out is promoted.
+        let path = backend.path(index);
+        assert_eq!(path.last().unwrap().1, root);
+        assert_eq!(
+            path.iter().filter(|(sibling, _)| sibling.is_some()).count() * RsMerkle::HASH_SIZE,
+            proof.len()
+        );
+    }
+
+    let multiproof = backend.prove(&[1, 4, 6]);
+    assert!(RsMerkle::verify(
+        root,
+        &[1, 4, 6],
+        &[leaves[1], leaves[4], leaves[6]],
+        leaves.len(),
+        &multiproof
+    ));
+    assert!(!RsMerkle::verify(
+        root,
+        &[0],
+        &[leaves[0]],
+        leaves.len(),
+        b"garbage"
+    ));
+}
+
+#[tokio::test]
+#[serial]
+async fn test_verify_inclusion_from_proof_bytes() {
+    init();
+    let (mut db, _store) = setup_database().await;
+    for i in 0..6 {
+        db.put(
+            &format!("key_{}", i),
+            format!("value_{}", i).as_bytes(),
+            false,
+        )
+        .await
+        .unwrap();
+    }
+    let result = db.prove("key_2", false).unwrap();
+    let field = |name: &str| result.data[name].as_str().unwrap().to_string();
+    let (root, leaf, proof) = (field("root"), field("leaf"), field("proof"));
+    let index = result.data["index"].as_u64().unwrap() as usize;
+    let total_leaves = result.data["total_leaves"].as_u64().unwrap() as usize;
+    let check = |root: &str, leaf: &str, index: usize, total_leaves: usize, proof: &str| {
+        Database::verify_inclusion_from_proof_bytes(root, leaf, index, total_leaves, proof)
+    };
+
+    assert!(check(&root, &leaf, index, total_leaves, &proof).unwrap());
+
+    // A flipped bit anywhere in the sibling hashes breaks the proof.
+    let bytes = base64::decode(&proof).unwrap();
+    for position in [0, bytes.len() / 2, bytes.len() - 1] {
+        let mut tampered = bytes.clone();
+        tampered[position] ^= 1;
+        let tampered = base64::encode(tampered);
+        assert!(!check(&root, &leaf, index, total_leaves, &tampered).unwrap());
+    }
+
+    let other_root = hex::encode(verify::hash_value(b"another tree"));
+    assert!(!check(&other_root, &leaf, index, total_leaves, &proof).unwrap());
+    let other_leaf = verify::hash_value_hex(b"forged");
+    assert!(!check(&root, &other_leaf, index, total_leaves, &proof).unwrap());
+    assert!(!check(&root, &leaf, index + 1, total_leaves, &proof).unwrap());
+    assert!(!check(&root, &leaf, total_leaves, total_leaves, &proof).unwrap());
+    assert!(!check(&root, &leaf, index, total_leaves + 3, &proof).unwrap());
+
+    // Input that cannot be decoded is an error rather than a failed proof.
+    for (root, leaf, proof) in [
+        ("not hex", leaf.as_str(), proof.as_str()),
+        (root.as_str(), &leaf[..62], proof.as_str()),
+        (root.as_str(), leaf.as_str(), "not base64!"),
+    ] {
+        match check(root, leaf, index, total_leaves, proof) {
+            Err(DatabaseError::Codec(_)) => {}
+            other => panic!("expected a codec error, got {:?}", other),
+        }
+    }
+    let truncated = base64::encode(&bytes[..bytes.len() - 1]);
+    assert!(check(&root, &leaf, index, total_leaves, &truncated).is_err());
+}
+
+#[tokio::test]
+#[serial]
+async fn test_proof_size_matches_prove() {
+    init();
+    let (mut db, _store) = setup_database().await;
+
+    // An odd leaf count makes the last leaf skip a level.
+    for i in 0..7 {
+        let mut hasher = Sha256::new();
+        hasher.update(format!("value_{}", i).as_bytes());
+        let insert_command = Command::Insert {
+            key: format!("key_{}", i),
+            value: hex::encode(hasher.finalize()),
+        };
+        db.execute_query(insert_command, false).await.unwrap();
+    }
+
+    for i in 0..7 {
+        let key = format!("key_{}", i);
+        let estimate = db.estimate_proof_size(&key).unwrap();
+        let proof = db.prove(&key, false).unwrap();
+        let proof_bytes = base64::decode(proof.data["proof"].as_str().unwrap()).unwrap();
+
+        assert_eq!(estimate["kind"], "single");
+        assert_eq!(
+            estimate["bytes"].as_u64().unwrap() as usize,
+            proof_bytes.len()
+        );
+        assert_eq!(
+            estimate["sibling_count"].as_u64().unwrap() as usize,
+            proof_bytes.len() / 32
+        );
+    }
+
+    assert!(matches!(
+        db.estimate_proof_size("missing"),
+        Err(DatabaseError::KeyNotFound(_))
+    ));
+}
+
+#[tokio::test]
+#[serial]
+async fn test_cached_root_tracks_mutations() {
+    init();
+    let (mut db, _store) = setup_database().await;
+
+    for i in 0..5 {
+        db.put(
+            &format!("key{}", i),
+            format!("value{}", i).as_bytes(),
+            false,
+        )
+        .await
+        .unwrap();
+        let state = MerkleState::from_bytes(db.get_state()).unwrap();
+        let tree = state.tree(ROOT_TREE).unwrap();
+        assert!(!tree.root_dirty);
+        assert_eq!(tree.cached_root, tree.compute_root());
+    }
+    db.delete("key2", false).await.unwrap();
+
+    let state = MerkleState::from_bytes(db.get_state()).unwrap();
+    let tree = state.tree(ROOT_TREE).unwrap();
+    assert!(!tree.root_dirty);
+    assert_eq!(tree.cached_root, tree.compute_root());
+    let result = db.execute_query(Command::GetRoot, false).await.unwrap();
+    assert_eq!(
+        result.data["root"].as_str(),
+        tree.cached_root.map(hex::encode).as_deref()
+    );
+
+    // A stale cache is never served.
+    let mut stale = tree.clone();
+    stale.leaves.push([7u8; 32]);
+    stale.invalidate_root();
+    assert_ne!(stale.root(), tree.root());
+    assert_eq!(stale.root(), stale.compute_root());
+}
+
+#[tokio::test]
+#[serial]
+async fn test_proof_cache_reuses_proofs_for_unchanged_root() {
+    init();
+    let temp_dir = tempfile::tempdir().unwrap();
+    let store = Arc::new(FileStore::new(temp_dir.path()).await.unwrap());
+    let mut db = Database::builder(DatabaseType::Merkle, store)
+        .proof_cache_capacity(16)
+        .build()
+        .await
+        .unwrap();
+    db.put("key1", b"value1", false).await.unwrap();
+
+    let executions = db.execution_count();
+    let first = db.prove("key1", false).unwrap();
+    let second = db.prove("key1", false).unwrap();
+    assert_eq!(db.execution_count(), executions + 1);
+    assert_eq!(first.data, second.data);
+
+    // A write changes the root, so the next proof is generated again.
+    db.put("key2", b"value2", false).await.unwrap();
+    let executions = db.execution_count();
+    let third = db.prove("key1", false).unwrap();
+    assert_eq!(db.execution_count(), executions + 1);
+    assert_ne!(third.data["root"], first.data["root"]);
+}
+
+#[tokio::test]
+#[serial]
+async fn test_put_if_changed_skips_unchanged_values() {
+    init();
+    let (mut db, _store) = setup_database().await;
+
+    let executions = db.execution_count();
+    assert!(db.put_if_changed("key1", b"value1", false).await.unwrap());
+    assert_eq!(db.execution_count(), executions + 1);
+    let root = db.current_root_hex();
+    assert!(!db.put_if_changed("key1", b"value1", false).await.unwrap());
+    assert_eq!(db.execution_count(), executions + 1);
+    assert_eq!(db.current_root_hex(), root);
+
+    assert!(db.put_if_changed("key1", b"value2", false).await.unwrap());
+    assert_ne!(db.current_root_hex(), root);
+    assert_eq!(db.get("key1", false).await.unwrap(), b"value2");
+}
+
+#[tokio::test]
+#[serial]
+async fn test_prove_at_a_snapshot() {
+    init();
+    let (mut db, _store) = setup_database().await;
+
+    db.put("key1", b"value1", false).await.unwrap();
+    let snapshot = db.get_state().to_vec();
+    let old_root = db.current_root_hex().unwrap();
+
+    db.put("key1", b"value2", false).await.unwrap();
+    db.put("key2", b"value2", false).await.unwrap();
+    let state = db.get_state().to_vec();
+
+    let proof = db
+        .prove_at("key1", &snapshot, false)
+        .unwrap()
+        .as_proof()
+        .unwrap();
+    assert_eq!(proof.root, old_root);
+    assert_eq!(proof.total_leaves, 1);
+    assert_eq!(proof.leaf, verify::hash_value_hex(b"value1"));
+    assert!(matches!(
+        db.prove_at("key2", &snapshot, false),
+        Err(DatabaseError::KeyNotFound(_))
+    ));
+    assert_eq!(db.get_state(), state.as_slice());
+    assert_ne!(db.current_root_hex().unwrap(), old_root);
+}
+
+#[tokio::test]
+#[serial]
+async fn test_named_trees_have_independent_roots() {
+    init();
+    let temp_dir = tempfile::tempdir().unwrap();
+    let store = Arc::new(FileStore::new(temp_dir.path()).await.unwrap());
+    let mut db = Database::new(DatabaseType::Merkle, store, None)
+        .await
+        .unwrap();
+
+    db.tree("users").put("key", b"alice", false).await.unwrap();
+    db.tree("orders")
+        .put("key", b"order-1", false)
+        .await
+        .unwrap();
+
+    let users_root = db.tree("users").root().unwrap();
+    let orders_root = db.tree("orders").root().unwrap();
+    assert_ne!(users_root, orders_root);
+    assert_eq!(db.tree("users").get("key", false).await.unwrap(), b"alice");
+    assert_eq!(
+        db.tree("orders").get("key", false).await.unwrap(),
+        b"order-1"
+    );
+
+    // The root tree is untouched.
+    assert_eq!(db.current_root_hex(), None);
+    assert!(matches!(
+        db.get("key", false).await,
+        Err(DatabaseError::KeyNotFound(_))
+    ));
+
+    // Writing one tree leaves the other's root alone.
+    db.tree("users").put("other", b"bob", false).await.unwrap();
+    assert_ne!(db.tree("users").root().unwrap(), users_root);
+    assert_eq!(db.tree("orders").root().unwrap(), orders_root);
+    assert_eq!(db.tree("users").list_keys().unwrap(), vec!["key", "other"]);
+    assert_eq!(db.tree("orders").list_keys().unwrap(), vec!["key"]);
+
+    // Proofs verify against their own tree's root.
+    let proof = db.tree("orders").prove("key", false).unwrap();
+    assert_eq!(proof.data["root"].as_str(), Some(orders_root.as_str()));
+
+    db.tree("orders").delete("key", false).await.unwrap();
+    assert!(db.tree("orders").list_keys().unwrap().is_empty());
+    assert_eq!(db.tree("users").get("key", false).await.unwrap(), b"alice");
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+#[serial]
+async fn test_verify_proof_async() {
+    init();
+    let (mut db, _store) = setup_database().await;
+    db.put("key", b"value", false).await.unwrap();
+    let proof = db
+        .execute_query(
+            Command::Prove {
+                key: "key".to_string(),
+            },
+            true,
+        )
+        .await
+        .unwrap()
+        .sp1_proof
+        .unwrap();
+
+    // Other work keeps running on the runtime while the proof is verified.
+    let ticker = tokio::spawn(async {
+        let mut ticks = 0;
+        for _ in 0..10 {
+            tokio::time::sleep(std::time::Duration::from_millis(1)).await;
+            ticks += 1;
+        }
+        ticks
+    });
+    let (verified, reads) = tokio::join!(db.verify_proof_async(&proof), db.get("key", false));
+    assert!(verified.unwrap());
+    assert_eq!(reads.unwrap(), b"value");
+    assert_eq!(ticker.await.unwrap(), 10);
+}
+
+#[tokio::test]
+#[serial]
+async fn test_verify_many_flags_the_corrupted_proof() {
+    init();
+    let (mut db, _store) = setup_database().await;
+    let mut proofs = Vec::new();
+    for i in 0..3 {
+        let key = format!("key{}", i);
+        db.put(&key, b"value", false).await.unwrap();
+        let proven = db
+            .execute_query(Command::Prove { key }, true)
+            .await
+            .unwrap();
+        proofs.push(proven.sp1_proof.unwrap());
+    }
+    // The proof of one command no longer matches the public values of another.
+    proofs[1].proof_data.public_values = proofs[2].proof_data.public_values.clone();
+
+    let elf = get_elf_for(&DatabaseType::Merkle).expect("merkle ELF is embedded");
+    let results = SP1Executor::new_verifier_only(elf).verify_many(&proofs);
+    assert_eq!(results.len(), 3);
+    assert!(matches!(results[0], Ok(true)));
+    assert!(matches!(
+        results[1],
+        Err(DatabaseError::ProofVerificationFailed(_))
+    ));
+    assert!(matches!(results[2], Ok(true)));
+}
+
+/// A state holding `keys` in the root tree under `TreeLayout::Append`, in
+/// the order given, with its root recorded as version 1.
+fn append_state(keys: &[&str]) -> MerkleState {
+    let mut tree = TreeData::default();
+    for key in keys {
+        tree.key_indices
+            .insert(key.as_bytes().to_vec(), tree.leaves.len());
+        tree.leaves.push(verify::hash_value(key.as_bytes()));
+    }
+    tree.record_root();
+    let mut state = MerkleState::new();
+    state.trees.insert(ROOT_TREE.to_string(), tree);
+    state
+}
+
+#[test]
+fn test_canonical_bytes_ignore_caches_and_blank_trees() {
+    let state = append_state(&["a", "b", "c"]);
+    let bytes = state.canonical_bytes();
+    assert_eq!(bytes, state.to_bytes());
+    assert_eq!(MerkleState::from_bytes(&bytes).unwrap().to_bytes(), bytes);
+
+    // A stale cache and a tree left blank change the bytes `to_bytes`
+    // writes, not the canonical ones.
+    let mut cluttered = state.clone();
+    let tree = cluttered.tree_mut(ROOT_TREE);
+    tree.cached_root = None;
+    tree.invalidate_root();
+    cluttered.tree_mut("blank");
+    assert_ne!(cluttered.to_bytes(), bytes);
+    assert_eq!(cluttered.canonical_bytes(), bytes);
+    assert_eq!(cluttered.digest(), state.digest());
+
+    // Under `TreeLayout::Append` the leaves keep the order of the inserts.
+    let reordered = append_state(&["c", "a", "b"]);
+    assert_ne!(reordered.root(), state.root());
+    assert_ne!(reordered.digest(), state.digest());
+}
+
+/// Catches changes to the encoding of states, which would make the same
+/// state hash differently across versions of the engine.
+#[test]
+fn test_state_encoding_is_pinned() {
+    let mut state = append_state(&["a"]);
+    let named = state.tree_mut("named");
+    named.leaves.push([1u8; 32]);
+    named.key_indices.insert(vec![0xff], 0);
+    named.record_root();
+    assert_eq!(
+        hex::encode(state.canonical_bytes()),
+        concat!(
+            "5a4b44530a00020000000000000000000000000000000100000000000000ca97",
+            "8112ca1bbdcafac231b39a23dc4da786eff8147c4e72b9807785afee48bb0100",
+            "0000000000000100000000000000610000000000000000000000000000000001",
+            "ca978112ca1bbdcafac231b39a23dc4da786eff8147c4e72b9807785afee48bb",
+            "000100000000000000ca978112ca1bbdcafac231b39a23dc4da786eff8147c4e",
+            "72b9807785afee48bb0000000000000000000000000000000005000000000000",
+            "006e616d65640100000000000000010101010101010101010101010101010101",
+            "010101010101010101010101010101000000000000000100000000000000ff00",
+            "0000000000000000000000000000000101010101010101010101010101010101",
+            "0101010101010101010101010101010100010000000000000001010101010101",
+            "0101010101010101010101010101010101010101010101010100000000000000",
+            "00000000000000000001000000000000005f000000000000000000",
+        )
+    );
+}
+
+#[tokio::test]
+#[serial]
+async fn test_hashed_keys_resolve_by_the_original_key() {
+    init();
+    let temp_dir = tempfile::tempdir().unwrap();
+    let store = Arc::new(FileStore::new(temp_dir.path()).await.unwrap());
+    let mut db = Database::builder(DatabaseType::Merkle, store.clone())
+        .merkle_config(MerkleConfig {
+            key_derivation: KeyDerivation::Sha256,
+            ..MerkleConfig::default()
+        })
+        .build()
+        .await
+        .unwrap();
+    assert_eq!(db.key_derivation(), KeyDerivation::Sha256);
+
+    let key = "a_rather_long_and_secret_key_name";
+    db.put(key, b"value", false).await.unwrap();
+    assert_eq!(db.get(key, false).await.unwrap(), b"value");
+    let proof = db.prove(key, false).unwrap();
+    assert_eq!(proof.data["key"], key);
+    assert!(
+        verify::verify_bundle(&db.proof_bundle(key, false).await.unwrap())
+            .unwrap()
+            .valid
+    );
+    assert!(matches!(
+        db.prove("missing", false),
+        Err(DatabaseError::KeyNotFound(missing)) if missing == "missing"
+    ));
+
+    // The state holds the key's hash, never the key.
+    let state = MerkleState::from_bytes(db.get_state()).unwrap();
+    let stored: Vec<_> = state.tree(ROOT_TREE).unwrap().key_indices.keys().collect();
+    assert_eq!(stored, [hashed_key(key.as_bytes()).as_bytes()]);
+    assert!(!db
+        .get_state()
+        .windows(key.len())
+        .any(|window| window == key.as_bytes()));
+    assert!(db.range("a".."z", 10, false).await.is_err());
+}
+
+async fn hashed_key_database(store: Arc<FileStore>) -> Database {
+    Database::builder(DatabaseType::Merkle, store)
+        .merkle_config(MerkleConfig {
+            key_derivation: KeyDerivation::Sha256,
+            ..MerkleConfig::default()
+        })
+        .build()
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+#[serial]
+async fn test_sweep_expired_refuses_hashed_keys() {
+    init();
+    let temp_dir = tempfile::tempdir().unwrap();
+    let store = Arc::new(FileStore::new(temp_dir.path()).await.unwrap());
+    let mut db = hashed_key_database(store).await;
+    db.put_with_ttl("short", b"a", Duration::ZERO, false)
+        .await
+        .unwrap();
+
+    // The state lists digests, under which no expiry is recorded.
+    assert_eq!(db.list_keys().unwrap(), [hashed_key(b"short")]);
+    let root = db.current_root_hex();
+    assert!(matches!(
+        db.sweep_expired(false).await,
+        Err(DatabaseError::QueryExecutionFailed(_))
+    ));
+    assert_eq!(db.current_root_hex(), root);
+}
+
+#[tokio::test]
+#[serial]
+async fn test_merge_from_refuses_hashed_keys() {
+    init();
+    let temp_dir = tempfile::tempdir().unwrap();
+    let hashed_store = Arc::new(
+        FileStore::new(temp_dir.path().join("hashed"))
+            .await
+            .unwrap(),
+    );
+    let mut hashed = hashed_key_database(hashed_store.clone()).await;
+    hashed.put("alpha", b"a", false).await.unwrap();
+    let raw_store = Arc::new(FileStore::new(temp_dir.path().join("raw")).await.unwrap());
+    let mut raw = Database::new(DatabaseType::Merkle, raw_store.clone(), None)
+        .await
+        .unwrap();
+    raw.put("beta", b"b", false).await.unwrap();
+
+    let hashed_state = hashed.get_state().to_vec();
+    let raw_state = raw.get_state().to_vec();
+    assert!(matches!(
+        raw.merge_from(&hashed_state, &*hashed_store, ConflictPolicy::Error, false)
+            .await,
+        Err(DatabaseError::QueryExecutionFailed(_))
+    ));
+    assert_eq!(raw.get_state(), raw_state.as_slice());
+    assert!(matches!(
+        hashed
+            .merge_from(&raw_state, &*raw_store, ConflictPolicy::Error, false)
+            .await,
+        Err(DatabaseError::QueryExecutionFailed(_))
+    ));
+    assert_eq!(hashed.get_state(), hashed_state.as_slice());
+}
+
+#[tokio::test]
+#[serial]
+async fn test_value_index_refuses_hashed_keys() {
+    init();
+    let temp_dir = tempfile::tempdir().unwrap();
+    let store = Arc::new(FileStore::new(temp_dir.path()).await.unwrap());
+    let mut db = hashed_key_database(store.clone()).await;
+    db.put("alpha", b"a", false).await.unwrap();
+
+    assert!(matches!(
+        db.rebuild_index().await,
+        Err(DatabaseError::QueryExecutionFailed(_))
+    ));
+    assert!(store.keys_with_prefix("_idx/").await.unwrap().is_empty());
+    assert!(matches!(
+        db.verify_index().await,
+        Err(DatabaseError::QueryExecutionFailed(_))
+    ));
+}
+
+#[tokio::test]
+#[serial]
+async fn test_insertion_order_under_sorted_layout() {
+    init();
+    let entries: Vec<(String, String)> = (0..8)
+        .map(|i| {
+            let key = format!("key{}", i);
+            let value = verify::hash_value_hex(key.as_bytes());
+            (key, value)
+        })
+        .collect();
+    let mut orders = vec![entries.clone()];
+    orders.push(entries.iter().rev().cloned().collect());
+    for step in [3, 5] {
+        orders.push(
+            (0..entries.len())
+                .map(|i| entries[i * step % 8].clone())
+                .collect(),
+        );
+    }
+
+    let mut states = Vec::new();
+    for (layout, entries) in [TreeLayout::Sorted, TreeLayout::Append]
+        .into_iter()
+        .flat_map(|layout| orders.iter().map(move |entries| (layout, entries)))
+    {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let store = Arc::new(FileStore::new(temp_dir.path()).await.unwrap());
+        let mut db = Database::builder(DatabaseType::Merkle, store)
+            .merkle_config(MerkleConfig {
+                layout,
+                ..MerkleConfig::default()
+            })
+            .build()
+            .await
+            .unwrap();
+        db.execute_query(
+            Command::BatchInsert {
+                entries: entries.clone(),
+            },
+            false,
+        )
+        .await
+        .unwrap();
+        states.push((db.current_root_hex(), db.get_state().to_vec()));
+    }
+
+    // Sorted trees only depend on the keys they hold; appended ones on the
+    // order they were inserted in too.
+    let (sorted, append) = states.split_at(orders.len());
+    for (root, state) in sorted {
+        assert_eq!(*root, sorted[0].0);
+        assert_eq!(*state, sorted[0].1);
+        assert_eq!(
+            MerkleState::from_bytes(state).unwrap().canonical_bytes(),
+            *state
+        );
+    }
+    for (root, _) in &append[1..] {
+        assert_ne!(*root, append[0].0);
+    }
+}
@@ -0,0 +1,64 @@
+#![cfg(feature = "metrics")]
+
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::Arc;
+use zkdb_lib::metrics::{self, install_prometheus_exporter};
+use zkdb_lib::{Database, DatabaseType};
+use zkdb_store::file::FileStore;
+
+/// A port nothing listens on, picked by the OS, so that parallel test runs
+/// do not collide.
+fn free_addr() -> SocketAddr {
+    TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+}
+
+fn scrape(addr: SocketAddr) -> String {
+    let mut stream = TcpStream::connect(addr).unwrap();
+    stream
+        .write_all(b"GET /metrics HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+        .unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    response
+}
+
+#[tokio::test]
+async fn test_prometheus_endpoint_reports_operations() {
+    let addr = free_addr();
+    install_prometheus_exporter(addr).unwrap();
+
+    let temp_dir = tempfile::tempdir().unwrap();
+    let store = FileStore::new(temp_dir.path()).await.unwrap();
+    let mut db = Database::new(DatabaseType::Merkle, Arc::new(store), None)
+        .await
+        .unwrap();
+
+    db.put("metered_1", b"one", false).await.unwrap();
+    db.put("metered_2", b"two", false).await.unwrap();
+    db.get("metered_1", false).await.unwrap();
+    assert!(db.get("missing", false).await.is_err());
+
+    let body = tokio::task::spawn_blocking(move || scrape(addr))
+        .await
+        .unwrap();
+    assert!(body.contains(&format!(
+        "{}{{{}=\"Insert\",{}=\"{}\"}} 2",
+        metrics::OPERATIONS_TOTAL,
+        metrics::LABEL_COMMAND,
+        metrics::LABEL_OUTCOME,
+        metrics::OUTCOME_OK
+    )));
+    assert!(body.contains(&format!(
+        "{}{{{}=\"Query\",{}=\"{}\"}} 1",
+        metrics::OPERATIONS_TOTAL,
+        metrics::LABEL_COMMAND,
+        metrics::LABEL_OUTCOME,
+        metrics::OUTCOME_ERROR
+    )));
+    assert!(body.contains(&format!("{} 2", metrics::KEY_COUNT)));
+    assert!(body.contains(metrics::CYCLES));
+}
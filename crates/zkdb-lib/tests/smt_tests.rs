@@ -0,0 +1,176 @@
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use zkdb_core::smt::{key_path, SmtState};
+use zkdb_lib::verify;
+
+/// A sparse Merkle tree computed from scratch: every node is rehashed from
+/// the leaves under it on each call.
+struct ReferenceSmt {
+    leaves: BTreeMap<[u8; 32], [u8; 32]>,
+}
+
+impl ReferenceSmt {
+    fn new() -> Self {
+        ReferenceSmt {
+            leaves: BTreeMap::new(),
+        }
+    }
+
+    fn insert(&mut self, key: &[u8], value_hash: [u8; 32]) {
+        let path: [u8; 32] = Sha256::digest(key).into();
+        let mut hasher = Sha256::new();
+        hasher.update([0u8]);
+        hasher.update(path);
+        hasher.update(value_hash);
+        self.leaves.insert(path, hasher.finalize().into());
+    }
+
+    fn remove(&mut self, key: &[u8]) {
+        self.leaves.remove(&<[u8; 32]>::from(Sha256::digest(key)));
+    }
+
+    fn root(&self) -> [u8; 32] {
+        let leaves: Vec<_> = self.leaves.iter().collect();
+        subtree(&leaves, 0)
+    }
+
+    /// Siblings of the leaf of `key`, from the leaf up.
+    fn siblings(&self, key: &[u8]) -> Vec<[u8; 32]> {
+        let path: [u8; 32] = Sha256::digest(key).into();
+        let mut siblings = Vec::new();
+        let mut leaves: Vec<_> = self.leaves.iter().collect();
+        for depth in 0..256 {
+            let (same, other): (Vec<_>, Vec<_>) = leaves
+                .into_iter()
+                .partition(|(leaf_path, _)| bit(leaf_path, depth) == bit(&path, depth));
+            siblings.push(subtree(&other, depth + 1));
+            leaves = same;
+        }
+        siblings.reverse();
+        siblings
+    }
+}
+
+fn bit(path: &[u8; 32], depth: usize) -> bool {
+    path[depth / 8] & (0x80 >> (depth % 8)) != 0
+}
+
+/// Hash of the subtree at `depth` below the root holding `leaves`.
+fn subtree(leaves: &[(&[u8; 32], &[u8; 32])], depth: usize) -> [u8; 32] {
+    if leaves.is_empty() {
+        return [0u8; 32];
+    }
+    if depth == 256 {
+        return *leaves[0].1;
+    }
+    let (left, right): (Vec<_>, Vec<_>) = leaves.iter().partition(|(path, _)| !bit(path, depth));
+    let mut hasher = Sha256::new();
+    hasher.update([1u8]);
+    hasher.update(subtree(&left, depth + 1));
+    hasher.update(subtree(&right, depth + 1));
+    hasher.finalize().into()
+}
+
+fn value_hash(value: &str) -> [u8; 32] {
+    Sha256::digest(value.as_bytes()).into()
+}
+
+#[test]
+fn test_smt_matches_reference() {
+    let mut smt = SmtState::new();
+    let mut reference = ReferenceSmt::new();
+    assert_eq!(smt.root(), [0u8; 32]);
+
+    for i in 0..40 {
+        let key = format!("key{}", i);
+        smt.insert(key.as_bytes(), value_hash(&format!("value{}", i)));
+        reference.insert(key.as_bytes(), value_hash(&format!("value{}", i)));
+        assert_eq!(smt.root(), reference.root(), "after inserting {}", key);
+    }
+    // Overwriting a key replaces its leaf.
+    for i in (0..40).step_by(3) {
+        let key = format!("key{}", i);
+        let old = smt.insert(key.as_bytes(), value_hash("updated"));
+        assert_eq!(old, Some(value_hash(&format!("value{}", i))));
+        reference.insert(key.as_bytes(), value_hash("updated"));
+    }
+    assert_eq!(smt.root(), reference.root());
+    for i in (0..40).step_by(2) {
+        let key = format!("key{}", i);
+        assert!(smt.remove(key.as_bytes()).is_some());
+        reference.remove(key.as_bytes());
+        assert_eq!(smt.root(), reference.root(), "after removing {}", key);
+    }
+    assert_eq!(smt.remove(b"key0"), None);
+
+    let decoded = SmtState::from_bytes(&smt.to_bytes()).unwrap();
+    assert_eq!(decoded.root(), reference.root());
+    assert_eq!(decoded.get(b"key1"), Some(value_hash("value1")));
+
+    // Removing every key leaves nothing behind.
+    for i in (1..40).step_by(2) {
+        smt.remove(format!("key{}", i).as_bytes());
+    }
+    assert!(smt.is_empty());
+    assert_eq!(smt.root(), [0u8; 32]);
+    assert_eq!(smt.to_bytes(), SmtState::new().to_bytes());
+}
+
+#[test]
+fn test_smt_proofs_match_reference() {
+    let mut smt = SmtState::new();
+    let mut reference = ReferenceSmt::new();
+    for i in 0..25 {
+        let key = format!("user:{}", i);
+        smt.insert(key.as_bytes(), value_hash(&key));
+        reference.insert(key.as_bytes(), value_hash(&key));
+    }
+    let root = reference.root();
+
+    for key in ["user:0", "user:7", "user:24", "missing", "user:25"] {
+        let proof = smt.prove(key.as_bytes());
+        let siblings: Vec<_> = reference
+            .siblings(key.as_bytes())
+            .into_iter()
+            .filter(|sibling| *sibling != [0u8; 32])
+            .collect();
+        assert_eq!(proof.siblings, siblings, "siblings of {}", key);
+        // Only the levels near the root have siblings.
+        assert!(proof.siblings.len() < 16);
+
+        let bytes = proof.to_bytes();
+        let present = smt.get(key.as_bytes());
+        assert!(verify::verify_smt_proof(root, key.as_bytes(), present.as_ref(), &bytes).unwrap());
+        assert_eq!(
+            proof.root(
+                &key_path(key.as_bytes()),
+                smt.leaf(&key_path(key.as_bytes()))
+            ),
+            Some(root)
+        );
+        // The same proof does not show the opposite.
+        let other = match present {
+            Some(_) => None,
+            None => Some(value_hash(key)),
+        };
+        assert!(!verify::verify_smt_proof(root, key.as_bytes(), other.as_ref(), &bytes).unwrap());
+    }
+
+    let proof = smt.prove(b"user:3").to_bytes();
+    assert!(
+        !verify::verify_smt_proof(root, b"user:3", Some(&value_hash("forged")), &proof).unwrap()
+    );
+    assert!(verify::verify_smt_proof(root, b"user:3", None, &proof[..proof.len() - 32]).is_err());
+    assert!(verify::verify_smt_proof(root, b"user:3", None, &proof[..31]).is_err());
+}
+
+#[test]
+fn test_smt_state_keeps_few_nodes() {
+    let mut smt = SmtState::new();
+    for i in 0..200 {
+        smt.insert(format!("key{}", i).as_bytes(), value_hash("value"));
+    }
+    // Storing every node on the path of each key would take over 3 MB.
+    assert!(smt.to_bytes().len() < 100 * 1024);
+    assert!(SmtState::from_bytes(b"ZKDS\x08\x00").is_err());
+}
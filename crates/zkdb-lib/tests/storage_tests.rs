@@ -1,6 +1,21 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
 use std::sync::Arc;
-use zkdb_lib::{Database, DatabaseType};
+use std::time::{Duration, Instant};
+use zkdb_core::merkle::{LegacyMerkleState, MerkleState, ROOT_TREE, STATE_MAGIC};
+use zkdb_core::MAX_KEY_LEN;
+use zkdb_lib::deadline::{run_with_deadline, Overdue};
+use zkdb_lib::{
+    migrate_state, verify, Clock, Codec, Command, CommitTo, ConcurrentDatabase, ConflictPolicy,
+    Database, DatabaseError, DatabaseLimits, DatabaseType, EncryptionConfig, MergeReport, Metadata,
+    MigrationV0toV1, OperationContext, OutputFormat, ProofBundle, QueryResult, RecoveryReport,
+    StateVersion, TreeLayout, WalEntry, DEFAULT_RESERVED_PREFIX, HEALTHCHECK_KEY,
+};
+use zkdb_store::bounded::BoundedStore;
 use zkdb_store::file::FileStore;
+use zkdb_store::mem::MemStore;
+use zkdb_store::rocks::RocksStore;
+use zkdb_store::{Store, StoreError};
 
 // Add this function to set up logging for tests
 fn init() {
@@ -34,3 +49,2914 @@ async fn test_storage_integration() {
     let retrieved = db.get(key, false).await.unwrap();
     assert_eq!(&retrieved, value);
 }
+
+#[tokio::test]
+async fn test_get_history() {
+    init();
+
+    let temp_dir = tempfile::tempdir().unwrap();
+    let store = FileStore::new(temp_dir.path()).await.unwrap();
+    let mut db = Database::new(DatabaseType::Merkle, Arc::new(store), None)
+        .await
+        .unwrap();
+
+    // Write the key once, then update it three times.
+    let key = "audited_key";
+    for i in 0..4 {
+        let value = format!("value_{}", i);
+        db.put(key, value.as_bytes(), false).await.unwrap();
+    }
+
+    let history = db.get_history(key).unwrap();
+    assert_eq!(history.len(), 3);
+
+    // Every superseded value has its own hash and leaf.
+    for (i, entry) in history.iter().enumerate() {
+        assert_eq!(entry.leaf_index, i);
+        for other in &history[i + 1..] {
+            assert_ne!(entry.value_hash, other.value_hash);
+        }
+    }
+
+    // The current value is still readable.
+    let retrieved = db.get(key, false).await.unwrap();
+    assert_eq!(retrieved, b"value_3");
+}
+
+#[tokio::test]
+async fn test_verify_value() {
+    init();
+
+    let temp_dir = tempfile::tempdir().unwrap();
+    let store = FileStore::new(temp_dir.path()).await.unwrap();
+    let mut db = Database::new(DatabaseType::Merkle, Arc::new(store), None)
+        .await
+        .unwrap();
+
+    db.put("verified_key", b"original", false).await.unwrap();
+
+    assert!(db.verify_value("verified_key", b"original").unwrap());
+    assert!(!db.verify_value("verified_key", b"tampered").unwrap());
+    assert!(matches!(
+        db.verify_value("missing_key", b"original"),
+        Err(DatabaseError::KeyNotFound(_))
+    ));
+}
+
+#[tokio::test]
+async fn test_non_utf8_values_round_trip() {
+    init();
+
+    let temp_dir = tempfile::tempdir().unwrap();
+    let store = FileStore::new(temp_dir.path()).await.unwrap();
+    let mut db = Database::new(DatabaseType::Merkle, Arc::new(store), None)
+        .await
+        .unwrap();
+
+    // Not UTF-8: 0xff never appears in it, and 0xc3 must start a pair.
+    let value = [0xff, 0xfe, 0x00, 0xc3, 0x28];
+    db.put("binary", &value, false).await.unwrap();
+    assert_eq!(db.get("binary", false).await.unwrap(), value);
+    assert!(db.verify_value("binary", &value).unwrap());
+
+    // The engine only ever sees the hash.
+    let result = db.prove("binary", false).unwrap();
+    assert_eq!(
+        result.as_proof().unwrap().leaf,
+        verify::hash_value_hex(&value)
+    );
+}
+
+#[tokio::test]
+async fn test_state_change_notifications() {
+    init();
+
+    let temp_dir = tempfile::tempdir().unwrap();
+    let store = FileStore::new(temp_dir.path()).await.unwrap();
+    let mut db = Database::new(DatabaseType::Merkle, Arc::new(store), None)
+        .await
+        .unwrap();
+
+    let mut receiver = db.subscribe();
+    let callback_count = Arc::new(AtomicUsize::new(0));
+    let counter = callback_count.clone();
+    db.on_state_change(move |_| {
+        counter.fetch_add(1, Ordering::SeqCst);
+    });
+
+    db.put("first", b"one", false).await.unwrap();
+    db.put("second", b"two", false).await.unwrap();
+    // Reads do not publish changes.
+    db.get("first", false).await.unwrap();
+
+    let first = receiver.recv().await.unwrap();
+    assert_eq!(first.command_kind, "Insert");
+    assert_eq!(first.key.as_deref(), Some("first"));
+    assert_eq!(first.prev_root, None);
+    assert!(first.new_root.is_some());
+
+    let second = receiver.recv().await.unwrap();
+    assert_eq!(second.key.as_deref(), Some("second"));
+    assert_eq!(second.prev_root, first.new_root);
+    assert_ne!(second.new_root, first.new_root);
+
+    assert!(receiver.try_recv().is_err());
+    assert_eq!(callback_count.load(Ordering::SeqCst), 2);
+}
+
+#[tokio::test]
+async fn test_bundle_matches_native_database() {
+    init();
+
+    let temp_dir = tempfile::tempdir().unwrap();
+    let store = FileStore::new(temp_dir.path()).await.unwrap();
+    let mut db = Database::new(DatabaseType::Merkle, Arc::new(store), None)
+        .await
+        .unwrap();
+
+    db.put("a", b"alpha", false).await.unwrap();
+    db.put("b", b"beta", false).await.unwrap();
+
+    let proof = db.prove("a", false).unwrap();
+    let value = db.get("a", false).await.unwrap();
+    let bundle = verify::ProofBundle {
+        key: "a".to_string(),
+        value: base64::encode(&value),
+        leaf: proof.data["leaf"].as_str().unwrap().to_string(),
+        root: proof.data["root"].as_str().unwrap().to_string(),
+        index: proof.data["index"].as_u64().unwrap() as usize,
+        total_leaves: proof.data["total_leaves"].as_u64().unwrap() as usize,
+        proof: proof.data["proof"].as_str().unwrap().to_string(),
+        public_values: None,
+        commit_to: verify::CommitTo::Plaintext,
+        signature: None,
+        layout: verify::LeafLayout::Append,
+    };
+    assert!(verify::verify_bundle(&bundle).unwrap().valid);
+
+    // The browser tests in zkdb-verify check the same bundle.
+    let fixture_path = std::path::Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("../zkdb-verify/tests/fixtures/bundle.json");
+    if std::env::var("ZKDB_UPDATE_FIXTURES").is_ok() {
+        let json = serde_json::to_string_pretty(&bundle).unwrap();
+        std::fs::write(&fixture_path, json + "\n").unwrap();
+    }
+    let fixture: verify::ProofBundle =
+        serde_json::from_str(&std::fs::read_to_string(&fixture_path).unwrap()).unwrap();
+    assert_eq!(fixture, bundle);
+}
+
+#[tokio::test]
+async fn test_prove_bundle_verifies_on_its_own() {
+    init();
+
+    let temp_dir = tempfile::tempdir().unwrap();
+    let store = FileStore::new(temp_dir.path()).await.unwrap();
+    let mut db = Database::new(DatabaseType::Merkle, Arc::new(store), None)
+        .await
+        .unwrap();
+    db.put("a", b"alpha", false).await.unwrap();
+    db.put("b", b"beta", false).await.unwrap();
+
+    let bundle = db.prove_bundle("a", true).await.unwrap();
+    assert!(bundle.sp1_proof.is_some() && bundle.vk.is_some());
+    drop(db);
+    let decoded = ProofBundle::from_bytes(&bundle.to_bytes()).unwrap();
+    decoded.verify().unwrap();
+
+    let mut tampered = decoded.clone();
+    tampered.inclusion.value = base64::encode(b"omega");
+    assert!(matches!(
+        tampered.verify(),
+        Err(DatabaseError::ProofVerificationFailed(_))
+    ));
+    let mut tampered = decoded.clone();
+    tampered.vk = None;
+    assert!(tampered.verify().is_err());
+    let mut tampered = decoded;
+    tampered.inclusion.public_values = None;
+    assert!(tampered.verify().is_err());
+    assert!(ProofBundle::from_bytes(b"not a bundle").is_err());
+}
+
+#[tokio::test]
+async fn test_prove_bundle_under_binary_output() {
+    init();
+
+    let temp_dir = tempfile::tempdir().unwrap();
+    let store = FileStore::new(temp_dir.path()).await.unwrap();
+    let mut db = Database::new(DatabaseType::Merkle, Arc::new(store), None)
+        .await
+        .unwrap();
+    db.set_output_format(OutputFormat::Binary);
+    db.put("a", b"alpha", false).await.unwrap();
+
+    let bundle = db.prove_bundle("a", true).await.unwrap();
+    let public_values = bundle.inclusion.public_values.as_deref().unwrap();
+    assert!(public_values.starts_with(&hex::encode(zkdb_core::BINARY_OUTPUT_MAGIC)));
+    assert!(verify::verify_bundle(&bundle.inclusion).unwrap().valid);
+    ProofBundle::from_bytes(&bundle.to_bytes())
+        .unwrap()
+        .verify()
+        .unwrap();
+}
+
+#[test]
+fn test_public_values_decode_in_either_output_format() {
+    let result = QueryResult {
+        data: serde_json::json!({ "root": "ab", "index": 3 }),
+        new_state: vec![1, 2, 3],
+    };
+    for format in [OutputFormat::Json, OutputFormat::Binary] {
+        let encoded = result.encode(format);
+        assert_eq!(verify::decode_public_values(&encoded).unwrap(), result.data);
+    }
+    let mut truncated = result.encode(OutputFormat::Binary);
+    truncated.truncate(10);
+    assert!(verify::decode_public_values(&truncated).is_err());
+}
+
+#[tokio::test]
+async fn test_state_round_trips_through_file() {
+    init();
+
+    let temp_dir = tempfile::tempdir().unwrap();
+    let store = FileStore::new(temp_dir.path().join("data")).await.unwrap();
+    let mut db = Database::new(DatabaseType::Merkle, Arc::new(store), None)
+        .await
+        .unwrap();
+    db.put("saved_key", b"saved_value", false).await.unwrap();
+
+    let state_path = temp_dir.path().join("state.bin");
+    db.save_state(&state_path).unwrap();
+    let saved_state = db.get_state().to_vec();
+
+    let store = FileStore::new(temp_dir.path().join("data")).await.unwrap();
+    let mut loaded = Database::new(DatabaseType::Merkle, Arc::new(store), None)
+        .await
+        .unwrap();
+    loaded.load_state(&state_path).unwrap();
+    assert_eq!(loaded.get_state(), saved_state.as_slice());
+    assert_eq!(
+        loaded.get("saved_key", false).await.unwrap(),
+        b"saved_value"
+    );
+
+    loaded.set_state(Vec::new());
+    loaded
+        .load_state_from_reader(std::fs::File::open(&state_path).unwrap())
+        .unwrap();
+    assert_eq!(loaded.get_state(), saved_state.as_slice());
+}
+
+#[tokio::test]
+async fn test_checked_state_detects_corruption() {
+    init();
+
+    let temp_dir = tempfile::tempdir().unwrap();
+    let store = FileStore::new(temp_dir.path().join("data")).await.unwrap();
+    let mut db = Database::new(DatabaseType::Merkle, Arc::new(store), None)
+        .await
+        .unwrap();
+    db.put("checked_key", b"checked_value", false)
+        .await
+        .unwrap();
+
+    let state_path = temp_dir.path().join("state.bin");
+    db.save_state_checked(&state_path).unwrap();
+
+    // An intact file round-trips.
+    let saved_state = db.get_state().to_vec();
+    db.set_state(Vec::new());
+    db.load_state_checked(&state_path).unwrap();
+    assert_eq!(db.get_state(), saved_state.as_slice());
+
+    // A flipped byte is rejected and the state is left untouched.
+    let mut framed = std::fs::read(&state_path).unwrap();
+    let last = framed.len() - 1;
+    framed[last] ^= 0xff;
+    std::fs::write(&state_path, &framed).unwrap();
+    let err = db.load_state_checked(&state_path).unwrap_err();
+    assert!(err.to_string().contains("corrupt state file"));
+    assert_eq!(db.get_state(), saved_state.as_slice());
+
+    // So is a truncated one.
+    std::fs::write(&state_path, &framed[..framed.len() / 2]).unwrap();
+    assert!(db.load_state_checked(&state_path).is_err());
+}
+
+#[tokio::test]
+async fn test_bounded_store_evicts_least_recently_used() {
+    init();
+
+    let temp_dir = tempfile::tempdir().unwrap();
+    let inner = FileStore::new(temp_dir.path()).await.unwrap();
+    let store = Arc::new(BoundedStore::new(Arc::new(inner), 25));
+    let mut db = Database::new(DatabaseType::Merkle, store.clone(), None)
+        .await
+        .unwrap();
+
+    db.put("oldest", b"0123456789", false).await.unwrap();
+    db.put("middle", b"abcdefghij", false).await.unwrap();
+    // Reading keeps "middle" ahead of "oldest" in the eviction order.
+    db.get("middle", false).await.unwrap();
+    db.put("newest", b"ABCDEFGHIJ", false).await.unwrap();
+    assert!(store.tracked_bytes() <= 25);
+
+    assert!(matches!(
+        db.get("oldest", false).await,
+        Err(DatabaseError::Store(StoreError::NotFound(_)))
+    ));
+    assert_eq!(db.get("middle", false).await.unwrap(), b"abcdefghij");
+    assert_eq!(db.get("newest", false).await.unwrap(), b"ABCDEFGHIJ");
+
+    // The tree still commits to the evicted value.
+    assert!(db.verify_value("oldest", b"0123456789").unwrap());
+    let proof = db.prove("oldest", false).unwrap();
+    assert!(proof.data["proof"].is_string());
+}
+
+#[tokio::test]
+async fn test_clone_key() {
+    init();
+
+    let temp_dir = tempfile::tempdir().unwrap();
+    let store = FileStore::new(temp_dir.path()).await.unwrap();
+    let mut db = Database::new(DatabaseType::Merkle, Arc::new(store), None)
+        .await
+        .unwrap();
+
+    db.put("template", b"template_value", false).await.unwrap();
+    db.clone_key("template", "instance", false).await.unwrap();
+
+    assert_eq!(
+        db.get("template", false).await.unwrap(),
+        db.get("instance", false).await.unwrap()
+    );
+    let template_proof = db.prove("template", false).unwrap();
+    let instance_proof = db.prove("instance", false).unwrap();
+    assert_eq!(template_proof.data["leaf"], instance_proof.data["leaf"]);
+    assert_eq!(template_proof.data["root"], instance_proof.data["root"]);
+
+    assert!(matches!(
+        db.clone_key("missing", "other", false).await,
+        Err(DatabaseError::KeyNotFound(_))
+    ));
+}
+
+#[tokio::test]
+async fn test_swap_returns_replaced_value() {
+    init();
+
+    let temp_dir = tempfile::tempdir().unwrap();
+    let store = Arc::new(FileStore::new(temp_dir.path()).await.unwrap());
+    let mut db = Database::new(DatabaseType::Merkle, store.clone(), None)
+        .await
+        .unwrap();
+
+    assert_eq!(db.swap("key", b"first", false).await.unwrap(), None);
+    assert_eq!(
+        db.swap("key", b"second", false).await.unwrap(),
+        Some(b"first".to_vec())
+    );
+    assert_eq!(db.get("key", false).await.unwrap(), b"second");
+
+    // A value the tree never committed to is not handed back.
+    store.put("orphan", b"stale").await.unwrap();
+    assert_eq!(db.swap("orphan", b"fresh", false).await.unwrap(), None);
+    assert_eq!(db.get("orphan", false).await.unwrap(), b"fresh");
+}
+
+#[tokio::test]
+async fn test_replace_returns_prior_hash() {
+    init();
+
+    let temp_dir = tempfile::tempdir().unwrap();
+    let store = Arc::new(FileStore::new(temp_dir.path()).await.unwrap());
+    let mut db = Database::new(DatabaseType::Merkle, store, None)
+        .await
+        .unwrap();
+
+    assert_eq!(db.replace("key", b"first").await.unwrap(), None);
+    assert_eq!(
+        db.replace("key", b"second").await.unwrap(),
+        Some(verify::hash_value_hex(b"first"))
+    );
+    assert_eq!(db.get("key", false).await.unwrap(), b"second");
+
+    let result = db
+        .execute_query(
+            Command::Replace {
+                key: "key".to_string(),
+                value: verify::hash_value_hex(b"third"),
+            },
+            false,
+        )
+        .await
+        .unwrap()
+        .as_replace()
+        .unwrap();
+    assert_eq!(result.old_leaf, Some(verify::hash_value_hex(b"second")));
+    assert_eq!(result.new_leaf, verify::hash_value_hex(b"third"));
+}
+
+#[tokio::test]
+async fn test_merge_from() {
+    init();
+
+    let temp_dir = tempfile::tempdir().unwrap();
+    let other_store = FileStore::new(temp_dir.path().join("other")).await.unwrap();
+    let mut other = Database::new(DatabaseType::Merkle, Arc::new(other_store), None)
+        .await
+        .unwrap();
+    other.put("shared", b"same", false).await.unwrap();
+    other.put("contested", b"theirs", false).await.unwrap();
+    other
+        .put("only_other", b"other_value", false)
+        .await
+        .unwrap();
+    let other_state = other.get_state().to_vec();
+    let source = FileStore::new(temp_dir.path().join("other")).await.unwrap();
+
+    for (i, policy) in [
+        ConflictPolicy::Error,
+        ConflictPolicy::PreferLocal,
+        ConflictPolicy::PreferOther,
+    ]
+    .into_iter()
+    .enumerate()
+    {
+        let store = FileStore::new(temp_dir.path().join(format!("local{}", i)))
+            .await
+            .unwrap();
+        let mut db = Database::new(DatabaseType::Merkle, Arc::new(store), None)
+            .await
+            .unwrap();
+        db.put("shared", b"same", false).await.unwrap();
+        db.put("contested", b"ours", false).await.unwrap();
+        db.put("only_local", b"local_value", false).await.unwrap();
+        let before = db.get_state().to_vec();
+
+        let merged = db.merge_from(&other_state, &source, policy, false).await;
+        let expected_contested: &[u8] = match policy {
+            ConflictPolicy::Error => {
+                assert!(matches!(
+                    merged,
+                    Err(DatabaseError::MergeConflict(keys)) if keys == ["contested"]
+                ));
+                assert_eq!(db.get_state(), before.as_slice());
+                assert!(db.get("only_other", false).await.is_err());
+                continue;
+            }
+            ConflictPolicy::PreferLocal => {
+                assert_eq!(
+                    merged.unwrap(),
+                    MergeReport {
+                        added: 1,
+                        skipped: 2,
+                        conflicts: vec!["contested".to_string()],
+                    }
+                );
+                b"ours"
+            }
+            ConflictPolicy::PreferOther => {
+                assert_eq!(
+                    merged.unwrap(),
+                    MergeReport {
+                        added: 2,
+                        skipped: 1,
+                        conflicts: vec!["contested".to_string()],
+                    }
+                );
+                b"theirs"
+            }
+        };
+
+        assert_eq!(
+            db.get("contested", false).await.unwrap(),
+            expected_contested
+        );
+        for (key, value) in [
+            ("shared", &b"same"[..]),
+            ("only_local", b"local_value"),
+            ("only_other", b"other_value"),
+        ] {
+            assert_eq!(db.get(key, false).await.unwrap(), value);
+            let bundle = db.proof_bundle(key, false).await.unwrap();
+            assert!(verify::verify_bundle(&bundle).unwrap().valid);
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_binary_output_matches_json() {
+    init();
+
+    let temp_dir = tempfile::tempdir().unwrap();
+    let store = FileStore::new(temp_dir.path()).await.unwrap();
+    let mut db = Database::new(DatabaseType::Merkle, Arc::new(store), None)
+        .await
+        .unwrap();
+
+    db.put("key1", b"value1", false).await.unwrap();
+    db.put("key2", b"value2", false).await.unwrap();
+
+    let commands = [
+        Command::Query {
+            key: "key1".to_string(),
+        },
+        Command::Prove {
+            key: "key2".to_string(),
+        },
+        Command::Insert {
+            key: "key3".to_string(),
+            value: verify::hash_value_hex(b"value3"),
+        },
+    ];
+    for command in commands {
+        let state = db.get_state().to_vec();
+
+        db.set_output_format(OutputFormat::Json);
+        let json = db.execute_query(command.clone(), false).await.unwrap();
+        db.set_state(state.clone());
+
+        db.set_output_format(OutputFormat::Binary);
+        let binary = db.execute_query(command, false).await.unwrap();
+        db.set_state(state);
+
+        assert_eq!(
+            QueryResult {
+                data: json.data,
+                new_state: json.new_state
+            },
+            QueryResult {
+                data: binary.data,
+                new_state: binary.new_state
+            }
+        );
+    }
+}
+
+#[test]
+fn test_engine_input_protocol_versions() {
+    let state = MerkleState::new().to_bytes();
+    let command = Command::Insert {
+        key: "key".to_string(),
+        value: verify::hash_value_hex(b"value"),
+    };
+
+    let input = zkdb_core::encode_command_input(&command);
+    assert!(input.starts_with(&zkdb_core::COMMAND_INPUT_MAGIC));
+    let (decoded, decoded_command) = zkdb_core::decode_input(state.clone(), &input).unwrap();
+    assert_eq!(decoded, state);
+    assert_eq!(
+        serde_json::to_value(&decoded_command).unwrap(),
+        serde_json::to_value(&command).unwrap()
+    );
+
+    // Version 1 hosts wrote both inputs with serde.
+    let legacy_state = bincode::serialize(&state).unwrap();
+    let legacy_command = bincode::serialize(&command).unwrap();
+    let (decoded, decoded_command) =
+        zkdb_core::decode_input(legacy_state, &legacy_command).unwrap();
+    assert_eq!(decoded, state);
+    assert_eq!(
+        serde_json::to_value(&decoded_command).unwrap(),
+        serde_json::to_value(&command).unwrap()
+    );
+
+    let mut future = zkdb_core::COMMAND_INPUT_MAGIC.to_vec();
+    future.extend_from_slice(&(zkdb_core::PROTOCOL_VERSION + 1).to_le_bytes());
+    future.extend_from_slice(&input[8..]);
+    assert!(matches!(
+        zkdb_core::decode_input(state, &future),
+        Err(zkdb_core::DatabaseError::InvalidCommand(reason)) if reason.contains("protocol version")
+    ));
+}
+
+#[tokio::test]
+async fn test_import_json_lines() {
+    init();
+
+    let temp_dir = tempfile::tempdir().unwrap();
+    let store = FileStore::new(temp_dir.path()).await.unwrap();
+    let mut db = Database::new(DatabaseType::Merkle, Arc::new(store), None)
+        .await
+        .unwrap();
+
+    let input = format!(
+        "{}\n{}\nnot json\n\n{}\n{}\n",
+        serde_json::json!({"id": "user:1", "blob": base64::encode(b"alice")}),
+        serde_json::json!({"id": "user:2", "blob": base64::encode(b"bob")}),
+        serde_json::json!({"blob": base64::encode(b"no key")}),
+        serde_json::json!({"id": "user:1", "blob": base64::encode(b"alice v2")}),
+    );
+    let report = db
+        .import_json_lines(input.as_bytes(), "id", "blob", false)
+        .await
+        .unwrap();
+
+    assert_eq!(report.imported, 3);
+    assert_eq!(report.skipped, 2);
+    assert_eq!(report.errors.len(), 2);
+    assert!(report.errors[0].starts_with("line 3:"));
+    assert!(report.errors[1].starts_with("line 5:"));
+
+    assert_eq!(db.get("user:1", false).await.unwrap(), b"alice v2");
+    assert_eq!(db.get("user:2", false).await.unwrap(), b"bob");
+    assert_eq!(db.get_history("user:1").unwrap().len(), 1);
+}
+
+#[tokio::test]
+async fn test_import_and_sweep_in_batches() {
+    init();
+
+    let temp_dir = tempfile::tempdir().unwrap();
+    let store = FileStore::new(temp_dir.path()).await.unwrap();
+    let clock = Arc::new(ManualClock(AtomicU64::new(1_000)));
+    let limits = DatabaseLimits {
+        max_batch_entries: 2,
+        ..DatabaseLimits::default()
+    };
+    let mut db = Database::builder(DatabaseType::Merkle, Arc::new(store))
+        .limits(limits)
+        .clock(clock.clone())
+        .operation_log(true)
+        .build()
+        .await
+        .unwrap();
+
+    let input: String = (0..5)
+        .map(|i| {
+            let line = serde_json::json!({"id": format!("key{}", i), "blob": base64::encode(b"v")});
+            format!("{}\n", line)
+        })
+        .collect();
+    let report = db
+        .import_json_lines(input.as_bytes(), "id", "blob", false)
+        .await
+        .unwrap();
+    assert_eq!(report.imported, 5);
+    // Batches of 2, 2 and 1 entries.
+    assert_eq!(db.log_len().await.unwrap(), 3);
+
+    for i in 0..5 {
+        db.put_with_ttl(&format!("ttl{}", i), b"t", Duration::from_secs(1), false)
+            .await
+            .unwrap();
+    }
+    clock.0.store(10_000, Ordering::SeqCst);
+    let logged = db.log_len().await.unwrap();
+    assert_eq!(db.sweep_expired(false).await.unwrap(), 5);
+    assert_eq!(db.log_len().await.unwrap(), logged + 3);
+    for entry in db.read_log(0..logged + 3).await.unwrap() {
+        match &entry.command {
+            Command::BatchInsert { entries } => assert!(entries.len() <= 2),
+            Command::BatchDelete { keys } => assert!(keys.len() <= 2),
+            _ => {}
+        }
+    }
+    assert_eq!(db.list_keys().unwrap().len(), 5);
+}
+
+#[tokio::test]
+async fn test_import_rocksdb_backup() {
+    init();
+
+    let source_dir = tempfile::tempdir().unwrap();
+    {
+        let source = RocksStore::new(source_dir.path()).unwrap();
+        for i in 0..10 {
+            source
+                .put(&format!("key{}", i), format!("value{}", i).as_bytes())
+                .await
+                .unwrap();
+        }
+    }
+
+    let temp_dir = tempfile::tempdir().unwrap();
+    let store = FileStore::new(temp_dir.path()).await.unwrap();
+    let mut db = Database::new(DatabaseType::Merkle, Arc::new(store), None)
+        .await
+        .unwrap();
+    db.put("key0", b"local", false).await.unwrap();
+
+    let report = db
+        .import_rocksdb_backup(source_dir.path(), false, false)
+        .await
+        .unwrap();
+    assert_eq!(report.imported, 9);
+    assert_eq!(report.skipped, 1);
+    assert!(report.errors[0].starts_with("key 'key0':"));
+    assert_eq!(db.get("key0", false).await.unwrap(), b"local");
+
+    let report = db
+        .import_rocksdb_backup(source_dir.path(), true, false)
+        .await
+        .unwrap();
+    assert_eq!(report.imported, 10);
+    for i in 0..10 {
+        let key = format!("key{}", i);
+        let value = format!("value{}", i);
+        assert_eq!(db.get(&key, false).await.unwrap(), value.as_bytes());
+        let result = db
+            .execute_query(Command::Query { key: key.clone() }, false)
+            .await
+            .unwrap();
+        assert_eq!(
+            result.data["value"],
+            verify::hash_value_hex(value.as_bytes())
+        );
+    }
+}
+
+#[tokio::test]
+async fn test_import_rocksdb_backup_in_batches() {
+    init();
+
+    let source_dir = tempfile::tempdir().unwrap();
+    {
+        let source = RocksStore::new(source_dir.path()).unwrap();
+        for i in 0..10 {
+            source
+                .put(&format!("key{}", i), format!("value{}", i).as_bytes())
+                .await
+                .unwrap();
+        }
+    }
+
+    let temp_dir = tempfile::tempdir().unwrap();
+    let store = FileStore::new(temp_dir.path()).await.unwrap();
+    let limits = DatabaseLimits {
+        max_batch_entries: 4,
+        ..DatabaseLimits::default()
+    };
+    let mut db = Database::builder(DatabaseType::Merkle, Arc::new(store))
+        .limits(limits)
+        .operation_log(true)
+        .build()
+        .await
+        .unwrap();
+
+    let report = db
+        .import_rocksdb_backup(source_dir.path(), false, false)
+        .await
+        .unwrap();
+    assert_eq!(report.imported, 10);
+    // Chunks of 4, 4 and 2 entries.
+    assert_eq!(db.log_len().await.unwrap(), 3);
+    for entry in db.read_log(0..3).await.unwrap() {
+        let Command::BatchInsert { entries } = &entry.command else {
+            panic!("expected a BatchInsert, got {:?}", entry.command);
+        };
+        assert!(entries.len() <= 4);
+    }
+    assert_eq!(db.list_keys().unwrap().len(), 10);
+}
+
+#[tokio::test]
+async fn test_delete_and_update() {
+    init();
+
+    let temp_dir = tempfile::tempdir().unwrap();
+    let store = FileStore::new(temp_dir.path()).await.unwrap();
+    let mut db = Database::new(DatabaseType::Merkle, Arc::new(store), None)
+        .await
+        .unwrap();
+
+    assert!(matches!(
+        db.update("key1", b"value1", false).await,
+        Err(DatabaseError::KeyNotFound(_))
+    ));
+
+    db.put("key1", b"value1", false).await.unwrap();
+    db.put("key2", b"value2", false).await.unwrap();
+    db.update("key1", b"value1 v2", false).await.unwrap();
+    assert_eq!(db.get("key1", false).await.unwrap(), b"value1 v2");
+    assert_eq!(db.list_keys().unwrap(), vec!["key1", "key2"]);
+
+    db.delete("key1", false).await.unwrap();
+    assert_eq!(db.list_keys().unwrap(), vec!["key2"]);
+    assert!(matches!(
+        db.get("key1", false).await,
+        Err(DatabaseError::KeyNotFound(_))
+    ));
+    assert_eq!(db.get("key2", false).await.unwrap(), b"value2");
+    assert!(matches!(
+        db.delete("key1", false).await,
+        Err(DatabaseError::KeyNotFound(_))
+    ));
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn test_concurrent_readers_see_committed_values() {
+    init();
+
+    let temp_dir = tempfile::tempdir().unwrap();
+    let store = FileStore::new(temp_dir.path()).await.unwrap();
+    let db = Database::new(DatabaseType::Merkle, Arc::new(store), None)
+        .await
+        .unwrap();
+    let db = ConcurrentDatabase::new(db);
+    db.put("counter", b"0", false).await.unwrap();
+
+    const WRITES: usize = 5;
+    let writer = {
+        let db = db.clone();
+        tokio::spawn(async move {
+            for i in 1..=WRITES {
+                db.put("counter", i.to_string().as_bytes(), false)
+                    .await
+                    .unwrap();
+            }
+        })
+    };
+
+    let readers: Vec<_> = (0..50)
+        .map(|_| {
+            let db = db.clone();
+            tokio::spawn(async move {
+                // A read between the store write and the tree update would
+                // fail the hash check in `get`.
+                let value = db.get("counter", false).await.unwrap();
+                let value: usize = String::from_utf8(value).unwrap().parse().unwrap();
+                assert!(value <= WRITES);
+            })
+        })
+        .collect();
+
+    writer.await.unwrap();
+    for reader in readers {
+        reader.await.unwrap();
+    }
+
+    assert_eq!(db.get("counter", false).await.unwrap(), b"5");
+    assert_eq!(
+        db.with_read(|db| db.get_history("counter").unwrap().len())
+            .await,
+        WRITES
+    );
+}
+
+#[tokio::test]
+async fn test_prefetch_serves_cached_values() {
+    init();
+
+    let temp_dir = tempfile::tempdir().unwrap();
+    let store = Arc::new(FileStore::new(temp_dir.path()).await.unwrap());
+    let mut db = Database::new(DatabaseType::Merkle, store.clone(), None)
+        .await
+        .unwrap()
+        .with_prefetch_capacity(2);
+
+    db.put("key1", b"value1", false).await.unwrap();
+    db.put("key2", b"value2", false).await.unwrap();
+    db.prefetch(&["key1", "key2"]).await.unwrap();
+
+    // Cached values no longer need the store.
+    store.delete("key1").await.unwrap();
+    assert_eq!(db.get("key1", false).await.unwrap(), b"value1");
+
+    // Writing a key drops its cached value.
+    db.put("key2", b"value2 v2", false).await.unwrap();
+    assert_eq!(db.get("key2", false).await.unwrap(), b"value2 v2");
+
+    assert!(matches!(
+        db.prefetch(&["missing"]).await,
+        Err(DatabaseError::Store(StoreError::NotFound(_)))
+    ));
+}
+
+#[tokio::test]
+async fn test_runtime_elf_is_hash_checked() {
+    init();
+
+    let temp_dir = tempfile::tempdir().unwrap();
+    let store = Arc::new(FileStore::new(temp_dir.path().join("db")).await.unwrap());
+    let elf_path = temp_dir.path().join("zkdb_merkle");
+
+    let mut elf = zkdb_lib::get_elf().to_vec();
+    std::fs::write(&elf_path, &elf).unwrap();
+    let mut db = Database::with_elf_path(DatabaseType::Merkle, store.clone(), None, &elf_path)
+        .await
+        .unwrap();
+    db.put("key1", b"value1", false).await.unwrap();
+    assert_eq!(db.get("key1", false).await.unwrap(), b"value1");
+
+    let last = elf.len() - 1;
+    elf[last] ^= 0xff;
+    std::fs::write(&elf_path, &elf).unwrap();
+    assert!(matches!(
+        Database::with_elf_path(DatabaseType::Merkle, store, None, &elf_path).await,
+        Err(DatabaseError::ElfHashMismatch { .. })
+    ));
+}
+
+#[tokio::test]
+async fn test_rocks_optimistic_put_conflict() {
+    init();
+
+    let temp_dir = tempfile::tempdir().unwrap();
+    let store = Arc::new(RocksStore::new(temp_dir.path()).unwrap());
+    assert_eq!(store.version_of("key1").await.unwrap(), 0);
+
+    // Both writers read version 0; only one of them may win.
+    let (first, second) = tokio::join!(
+        {
+            let store = store.clone();
+            tokio::spawn(async move { store.optimistic_put("key1", 0, b"first").await })
+        },
+        {
+            let store = store.clone();
+            tokio::spawn(async move { store.optimistic_put("key1", 0, b"second").await })
+        }
+    );
+    let results = [first.unwrap(), second.unwrap()];
+    assert_eq!(results.iter().filter(|result| result.is_ok()).count(), 1);
+    assert_eq!(
+        results
+            .iter()
+            .filter(|result| matches!(result, Err(StoreError::Locked(_))))
+            .count(),
+        1
+    );
+
+    let winner: &[u8] = if results[0].is_ok() {
+        b"first"
+    } else {
+        b"second"
+    };
+    assert_eq!(store.version_of("key1").await.unwrap(), 1);
+    assert_eq!(store.get("key1").await.unwrap(), winner);
+    assert_eq!(store.optimistic_put("key1", 1, b"third").await.unwrap(), 2);
+}
+
+#[derive(Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+struct Account {
+    owner: String,
+    balance: u64,
+    tags: Vec<String>,
+}
+
+#[tokio::test]
+async fn test_typed_values_round_trip() {
+    init();
+
+    let account = Account {
+        owner: "alice".to_string(),
+        balance: 42,
+        tags: vec!["admin".to_string()],
+    };
+
+    for codec in [Codec::Bincode, Codec::Json] {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let store = FileStore::new(temp_dir.path()).await.unwrap();
+        let mut db = Database::builder(DatabaseType::Merkle, Arc::new(store))
+            .codec(codec)
+            .build()
+            .await
+            .unwrap();
+
+        db.put_typed("account", &account, false).await.unwrap();
+        let read: Account = db.get_typed("account", false).await.unwrap();
+        assert_eq!(read, account);
+
+        // The leaf commits to the encoded bytes.
+        let encoded = codec.encode(&account).unwrap();
+        assert!(db.verify_value("account", &encoded).unwrap());
+        assert!(matches!(
+            db.get_typed::<Vec<u64>>("account", false).await,
+            Err(DatabaseError::Codec(_))
+        ));
+    }
+}
+
+#[tokio::test]
+async fn test_pinned_vk_hash() {
+    init();
+
+    let temp_dir = tempfile::tempdir().unwrap();
+    let store: Arc<dyn zkdb_store::Store> =
+        Arc::new(FileStore::new(temp_dir.path()).await.unwrap());
+    let vk_hash = Database::new(DatabaseType::Merkle, store.clone(), None)
+        .await
+        .unwrap()
+        .vk_hash();
+
+    let db = Database::builder(DatabaseType::Merkle, store.clone())
+        .expect_vk_hash(vk_hash.to_uppercase().trim_start_matches("0X"))
+        .build()
+        .await;
+    assert!(db.is_ok());
+
+    let wrong = format!("0x{}", "00".repeat(32));
+    match Database::builder(DatabaseType::Merkle, store)
+        .expect_vk_hash(wrong.clone())
+        .build()
+        .await
+    {
+        Err(DatabaseError::VkMismatch { expected, actual }) => {
+            assert_eq!(expected, wrong);
+            assert_eq!(actual, vk_hash.to_lowercase());
+        }
+        _ => panic!("expected a verifying key mismatch"),
+    }
+}
+
+#[tokio::test]
+async fn test_watch_root() {
+    init();
+
+    let temp_dir = tempfile::tempdir().unwrap();
+    let store = FileStore::new(temp_dir.path()).await.unwrap();
+    let mut db = Database::new(DatabaseType::Merkle, Arc::new(store), None)
+        .await
+        .unwrap();
+
+    let mut roots = db.watch_root();
+    assert_eq!(*roots.borrow_and_update(), None);
+
+    let mut seen = Vec::new();
+    for i in 0..5 {
+        db.put(
+            &format!("key{}", i),
+            format!("value{}", i).as_bytes(),
+            false,
+        )
+        .await
+        .unwrap();
+        assert!(roots.has_changed().unwrap());
+        let root = roots.borrow_and_update().clone().unwrap();
+        assert_eq!(Some(&root), db.current_root_hex().as_ref());
+        assert!(!seen.contains(&root));
+        seen.push(root);
+    }
+    assert_eq!(seen.len(), 5);
+
+    // Reads leave the root alone.
+    db.get("key0", false).await.unwrap();
+    assert!(!roots.has_changed().unwrap());
+}
+
+#[tokio::test]
+async fn test_operation_log_replay() {
+    init();
+
+    let temp_dir = tempfile::tempdir().unwrap();
+    let store = Arc::new(FileStore::new(temp_dir.path()).await.unwrap());
+    let mut db = Database::builder(DatabaseType::Merkle, store.clone())
+        .operation_log(true)
+        .build()
+        .await
+        .unwrap();
+
+    for i in 0..4 {
+        db.put(
+            &format!("key{}", i),
+            format!("value{}", i).as_bytes(),
+            false,
+        )
+        .await
+        .unwrap();
+    }
+    db.delete("key2", false).await.unwrap();
+
+    let len = db.log_len().await.unwrap();
+    assert_eq!(len, 5);
+    let entries = db.read_log(0..len).await.unwrap();
+    assert!(entries.iter().all(|entry| entry.committed));
+    assert_eq!(entries[4].key.as_deref(), Some("key2"));
+
+    let report = db.replay(0..len).await.unwrap();
+    assert_eq!(report.replayed, 5);
+    assert_eq!(report.root, db.current_root_hex());
+    assert_eq!(report.state, db.get_state());
+    assert!(db.verify_log().await.unwrap().is_empty());
+
+    // Flip a byte in the second entry.
+    let entry_key = WalEntry::store_key(1);
+    let mut bytes = store.get(&entry_key).await.unwrap();
+    let last = bytes.len() - 1;
+    bytes[last] ^= 0xff;
+    store.put(&entry_key, &bytes).await.unwrap();
+
+    let corrupt = db.verify_log().await.unwrap();
+    assert_eq!(corrupt.len(), 1);
+    assert!(matches!(
+        corrupt[0],
+        DatabaseError::CorruptLogEntry { seq: 1, .. }
+    ));
+    assert!(matches!(
+        db.replay(0..len).await,
+        Err(DatabaseError::CorruptLogEntry { seq: 1, .. })
+    ));
+}
+
+#[tokio::test]
+async fn test_auto_migrate_v0_state() {
+    init();
+
+    #[derive(serde::Serialize)]
+    struct V0State {
+        leaves: Vec<[u8; 32]>,
+        key_indices: BTreeMap<String, usize>,
+    }
+
+    let temp_dir = tempfile::tempdir().unwrap();
+    let store = Arc::new(FileStore::new(temp_dir.path()).await.unwrap());
+    let mut v0 = V0State {
+        leaves: Vec::new(),
+        key_indices: BTreeMap::new(),
+    };
+    for (i, key) in ["alpha", "beta"].iter().enumerate() {
+        let value = format!("{}-value", key);
+        store.put(key, value.as_bytes()).await.unwrap();
+        let leaf = verify::decode_hash("leaf", &verify::hash_value_hex(value.as_bytes())).unwrap();
+        v0.leaves.push(leaf);
+        v0.key_indices.insert(key.to_string(), i);
+    }
+    let v0_bytes = bincode::serialize(&v0).unwrap();
+    assert_eq!(StateVersion::detect(&v0_bytes).unwrap(), StateVersion::V0);
+
+    let mut db = Database::builder(DatabaseType::Merkle, store)
+        .state(v0_bytes)
+        .build()
+        .await
+        .unwrap();
+    let root = db.current_root_hex();
+
+    let report = db.auto_migrate().unwrap();
+    assert_eq!(report.from, StateVersion::V0);
+    assert_eq!(report.to, StateVersion::CURRENT);
+    assert_eq!(report.applied, vec![MigrationV0toV1::NAME.to_string()]);
+    assert_eq!(
+        StateVersion::detect(db.get_state()).unwrap(),
+        StateVersion::CURRENT
+    );
+    assert_eq!(db.current_root_hex(), root);
+
+    assert_eq!(db.get("alpha", false).await.unwrap(), b"alpha-value");
+    assert_eq!(db.get("beta", false).await.unwrap(), b"beta-value");
+    db.put("gamma", b"gamma-value", false).await.unwrap();
+    assert_eq!(db.list_keys().unwrap(), vec!["alpha", "beta", "gamma"]);
+
+    // A current state is left alone.
+    let report = db.auto_migrate().unwrap();
+    assert_eq!(report.from, StateVersion::CURRENT);
+    assert!(report.applied.is_empty());
+}
+
+#[tokio::test]
+async fn test_recover_from_operation_log() {
+    init();
+
+    let temp_dir = tempfile::tempdir().unwrap();
+    let state_path = temp_dir.path().join("state.bin");
+    let store = Arc::new(FileStore::new(temp_dir.path().join("data")).await.unwrap());
+
+    let mut db = Database::builder(DatabaseType::Merkle, store.clone())
+        .operation_log(true)
+        .build()
+        .await
+        .unwrap();
+    db.put("saved", b"saved-value", false).await.unwrap();
+    db.save_state(&state_path).unwrap();
+    db.put("unsaved", b"unsaved-value", false).await.unwrap();
+    let root = db.current_root_hex();
+    // Simulate a crash: the last insert never reaches the state file.
+    drop(db);
+
+    let mut db = Database::builder(DatabaseType::Merkle, store.clone())
+        .state(std::fs::read(&state_path).unwrap())
+        .operation_log(true)
+        .build()
+        .await
+        .unwrap();
+    assert_eq!(db.current_root_hex(), root);
+    assert_eq!(db.get("unsaved", false).await.unwrap(), b"unsaved-value");
+    assert_eq!(db.get("saved", false).await.unwrap(), b"saved-value");
+
+    // Recovering again finds nothing to do.
+    let report = db.recover().await.unwrap();
+    assert_eq!(report, RecoveryReport::default());
+}
+
+#[tokio::test]
+async fn test_get_at_historical_root() {
+    init();
+
+    let temp_dir = tempfile::tempdir().unwrap();
+    let store = FileStore::new(temp_dir.path()).await.unwrap();
+    let mut db = Database::builder(DatabaseType::Merkle, Arc::new(store))
+        .operation_log(true)
+        .build()
+        .await
+        .unwrap();
+
+    db.put("doc", b"first", false).await.unwrap();
+    db.put("other", b"unrelated", false).await.unwrap();
+    let checkpoint = db.current_root_hex().unwrap();
+    db.put("doc", b"second", false).await.unwrap();
+    let head = db.current_root_hex().unwrap();
+
+    let old = db.get_at("doc", &checkpoint, false).await.unwrap();
+    assert_eq!(old.value, b"first");
+    assert_eq!(old.root, checkpoint);
+    let new = db
+        .get_at("doc", &format!("0x{}", head), false)
+        .await
+        .unwrap();
+    assert_eq!(new.value, b"second");
+    assert_eq!(new.root, head);
+
+    // The live state is untouched.
+    assert_eq!(db.current_root_hex().as_ref(), Some(&head));
+    assert_eq!(db.get("doc", false).await.unwrap(), b"second");
+
+    let unknown = "00".repeat(32);
+    assert!(matches!(
+        db.get_at("doc", &unknown, false).await,
+        Err(DatabaseError::UnknownRoot(root)) if root == unknown
+    ));
+}
+
+#[test]
+fn test_proof_timeout() {
+    init();
+
+    let overdue = Overdue::new();
+    let timeout = Duration::from_millis(500);
+
+    // A mock proof that takes far longer than 500 ms.
+    let started = Instant::now();
+    let result: Result<(), _> = run_with_deadline(
+        Some(timeout),
+        Some(&overdue),
+        DatabaseError::ProofTimeout,
+        || {
+            std::thread::sleep(Duration::from_secs(2));
+            Ok(())
+        },
+    );
+    assert!(matches!(result, Err(DatabaseError::ProofTimeout(d)) if d == timeout));
+    assert!(started.elapsed() < Duration::from_secs(1));
+
+    // The abandoned task keeps running and counts as overdue until it ends.
+    assert_eq!(overdue.count(), 1);
+    std::thread::sleep(Duration::from_secs(2));
+    assert_eq!(overdue.count(), 0);
+
+    // A task that beats its deadline returns its result.
+    let result = run_with_deadline(
+        Some(timeout),
+        Some(&overdue),
+        DatabaseError::ProofTimeout,
+        || Ok(7),
+    );
+    assert_eq!(result.unwrap(), 7);
+    assert_eq!(overdue.count(), 0);
+}
+
+#[tokio::test]
+async fn test_max_concurrent_proofs() {
+    init();
+
+    let temp_dir = tempfile::tempdir().unwrap();
+    let store = FileStore::new(temp_dir.path()).await.unwrap();
+    let mut db = Database::builder(DatabaseType::Merkle, Arc::new(store))
+        .max_concurrent_proofs(2)
+        .build()
+        .await
+        .unwrap();
+    db.put("key", b"value", false).await.unwrap();
+
+    let done = AtomicBool::new(false);
+    let peak = AtomicUsize::new(0);
+    std::thread::scope(|scope| {
+        scope.spawn(|| {
+            while !done.load(Ordering::SeqCst) {
+                peak.fetch_max(db.proofs_in_flight(), Ordering::SeqCst);
+                // Reads without a proof are not held back by the proofs.
+                db.prove("key", false).unwrap();
+            }
+        });
+        let provers: Vec<_> = (0..5)
+            .map(|_| scope.spawn(|| db.prove("key", true).unwrap()))
+            .collect();
+        for prover in provers {
+            assert!(prover.join().unwrap().sp1_proof.is_some());
+        }
+        done.store(true, Ordering::SeqCst);
+    });
+    assert!(peak.load(Ordering::SeqCst) <= 2);
+    assert_eq!(db.proofs_in_flight(), 0);
+}
+
+#[tokio::test]
+async fn test_clear_tree() {
+    init();
+
+    let temp_dir = tempfile::tempdir().unwrap();
+    let store = FileStore::new(temp_dir.path()).await.unwrap();
+    let mut db = Database::new(DatabaseType::Merkle, Arc::new(store), None)
+        .await
+        .unwrap();
+
+    for i in 0..3 {
+        db.put(
+            &format!("key{}", i),
+            format!("value{}", i).as_bytes(),
+            false,
+        )
+        .await
+        .unwrap();
+    }
+    db.clear_tree(false).await.unwrap();
+
+    assert!(db.list_keys().unwrap().is_empty());
+    for i in 0..3 {
+        assert!(matches!(
+            db.get(&format!("key{}", i), false).await,
+            Err(DatabaseError::KeyNotFound(_))
+        ));
+    }
+    // An empty tree has no root.
+    assert_eq!(db.current_root_hex(), None);
+    let result = db.execute_query(Command::GetRoot, false).await.unwrap();
+    assert!(result.data["root"].is_null());
+    assert_eq!(result.data["leaf_count"], 0);
+}
+
+#[tokio::test]
+async fn test_compact_tree_prunes_orphaned_leaves() {
+    init();
+
+    let temp_dir = tempfile::tempdir().unwrap();
+    let store = FileStore::new(temp_dir.path()).await.unwrap();
+    let mut db = Database::new(DatabaseType::Merkle, Arc::new(store), None)
+        .await
+        .unwrap();
+
+    for round in 0..4 {
+        for i in 0..3 {
+            db.put(
+                &format!("key{}", i),
+                format!("value{}-{}", i, round).as_bytes(),
+                false,
+            )
+            .await
+            .unwrap();
+        }
+    }
+    db.delete("key2", false).await.unwrap();
+    let root_before = db.current_root_hex();
+
+    let compacted = db.compact_tree(false).await.unwrap();
+    assert_eq!(compacted.leaves_pruned, 10);
+    assert_eq!(compacted.leaf_count, 2);
+    assert_ne!(db.current_root_hex(), root_before);
+
+    let state = MerkleState::from_bytes(db.get_state()).unwrap();
+    let tree = &state.trees[ROOT_TREE];
+    assert_eq!(tree.leaves.len(), tree.key_indices.len());
+    assert_eq!(tree.leaves.len(), 2);
+    // History keeps the indices of the superseded leaves as recorded, though
+    // those leaves are gone.
+    let history = &tree.history[b"key0".as_slice()];
+    let recorded: Vec<usize> = history.iter().map(|entry| entry.leaf_index).collect();
+    assert_eq!(recorded, vec![0, 3, 6]);
+    assert!(recorded.iter().all(|&index| index >= tree.leaves.len()));
+    // The live values survive, under their renumbered leaves.
+    for i in 0..2 {
+        let value = db.get(&format!("key{}", i), false).await.unwrap();
+        assert_eq!(value, format!("value{}-3", i).as_bytes());
+    }
+}
+
+#[tokio::test]
+async fn test_key_version_history() {
+    init();
+
+    let temp_dir = tempfile::tempdir().unwrap();
+    let store = Arc::new(FileStore::new(temp_dir.path()).await.unwrap());
+    let mut db = Database::builder(DatabaseType::Merkle, store.clone())
+        .key_history(true)
+        .build()
+        .await
+        .unwrap();
+
+    let mut roots = Vec::new();
+    for value in ["one", "two", "three"] {
+        db.put("doc", value.as_bytes(), false).await.unwrap();
+        roots.push(db.current_root_hex());
+    }
+
+    let history = db.history("doc").await.unwrap();
+    assert_eq!(history.len(), 3);
+    assert_eq!(
+        history.iter().map(|v| v.version).collect::<Vec<_>>(),
+        vec![1, 2, 3]
+    );
+    assert_eq!(
+        history.iter().map(|v| v.root.clone()).collect::<Vec<_>>(),
+        roots
+    );
+
+    let middle = db.get_version("doc", 2).await.unwrap();
+    assert_eq!(middle.value, b"two");
+    assert_eq!(middle.value_hash, verify::hash_value_hex(b"two"));
+    assert!(matches!(
+        db.get_version("doc", 4).await,
+        Err(DatabaseError::KeyNotFound(_))
+    ));
+
+    // Deleting the key keeps its history until it is purged.
+    db.delete("doc", false).await.unwrap();
+    assert_eq!(db.history("doc").await.unwrap().len(), 3);
+    assert_eq!(db.purge_history("doc").await.unwrap(), 3);
+    assert!(db.history("doc").await.unwrap().is_empty());
+
+    // With a retention limit only the latest versions are kept.
+    let mut db = Database::builder(DatabaseType::Merkle, store)
+        .history_retention(2)
+        .build()
+        .await
+        .unwrap();
+    for value in ["a", "b", "c"] {
+        db.put("limited", value.as_bytes(), false).await.unwrap();
+    }
+    let history = db.history("limited").await.unwrap();
+    assert_eq!(
+        history.iter().map(|v| v.value.clone()).collect::<Vec<_>>(),
+        vec![b"b".to_vec(), b"c".to_vec()]
+    );
+    assert!(db.get_version("limited", 1).await.is_err());
+}
+
+#[tokio::test]
+async fn test_value_metadata() {
+    init();
+
+    let temp_dir = tempfile::tempdir().unwrap();
+    let store = FileStore::new(temp_dir.path()).await.unwrap();
+    let mut db = Database::new(DatabaseType::Merkle, Arc::new(store), None)
+        .await
+        .unwrap();
+
+    // Values written without metadata read back without it.
+    db.put("plain", b"bytes", false).await.unwrap();
+    assert_eq!(
+        db.get_with_meta("plain", false).await.unwrap(),
+        (b"bytes".to_vec(), None)
+    );
+
+    let metadata = Metadata {
+        content_type: Some("application/json".to_string()),
+        tags: [("owner".to_string(), "alice".to_string())].into(),
+        ..Metadata::default()
+    };
+    let written = db
+        .put_with_meta("doc", br#"{"a":1}"#, metadata, false)
+        .await
+        .unwrap();
+    assert!(written.created_at > 0);
+
+    // The leaf commits to the raw value and `get` returns it unchanged.
+    assert_eq!(db.get("doc", false).await.unwrap(), br#"{"a":1}"#);
+    assert!(db.verify_value("doc", br#"{"a":1}"#).unwrap());
+    let (value, read) = db.get_with_meta("doc", false).await.unwrap();
+    assert_eq!(value, br#"{"a":1}"#);
+    assert_eq!(read.as_ref(), Some(&written));
+
+    // Rewriting keeps the creation time.
+    let rewritten = db
+        .put_with_meta("doc", br#"{"a":2}"#, Metadata::default(), false)
+        .await
+        .unwrap();
+    assert_eq!(rewritten.created_at, written.created_at);
+    assert!(rewritten.updated_at >= written.updated_at);
+
+    // A plain put leaves metadata that no longer describes the value.
+    db.put("doc", b"raw", false).await.unwrap();
+    assert_eq!(db.get_with_meta("doc", false).await.unwrap().1, None);
+}
+
+/// A clock that only moves when told to.
+struct ManualClock(AtomicU64);
+
+impl Clock for ManualClock {
+    fn now_millis(&self) -> u64 {
+        self.0.load(Ordering::SeqCst)
+    }
+}
+
+#[tokio::test]
+async fn test_ttl_expiry_and_sweep() {
+    init();
+
+    let temp_dir = tempfile::tempdir().unwrap();
+    let store = FileStore::new(temp_dir.path()).await.unwrap();
+    let clock = Arc::new(ManualClock(AtomicU64::new(1_000)));
+    let mut db = Database::builder(DatabaseType::Merkle, Arc::new(store))
+        .clock(clock.clone())
+        .build()
+        .await
+        .unwrap();
+
+    let written = db
+        .put_with_ttl("short", b"a", Duration::from_secs(10), false)
+        .await
+        .unwrap();
+    assert_eq!(written.expires_at, Some(11_000));
+    assert!(matches!(
+        db.put_with_ttl("never", b"x", Duration::MAX, false).await,
+        Err(DatabaseError::InvalidCommand(_))
+    ));
+    assert!(matches!(
+        db.put_with_ttl("never", b"x", Duration::from_millis(u64::MAX), false)
+            .await,
+        Err(DatabaseError::InvalidCommand(_))
+    ));
+    db.put_with_ttl("long", b"b", Duration::from_secs(60), false)
+        .await
+        .unwrap();
+    db.put_with_ttl("rewritten", b"c", Duration::from_secs(10), false)
+        .await
+        .unwrap();
+    db.put("forever", b"d", false).await.unwrap();
+
+    assert_eq!(db.get("short", false).await.unwrap(), b"a");
+    assert_eq!(db.sweep_expired(false).await.unwrap(), 0);
+
+    // A plain put drops the expiry of the previous value.
+    db.put("rewritten", b"e", false).await.unwrap();
+
+    clock.0.store(11_000, Ordering::SeqCst);
+    assert!(matches!(
+        db.get("short", false).await,
+        Err(DatabaseError::Expired(key)) if key == "short"
+    ));
+    assert!(matches!(
+        db.get_with_meta("short", false).await,
+        Err(DatabaseError::Expired(_))
+    ));
+    assert_eq!(db.get("long", false).await.unwrap(), b"b");
+    assert_eq!(db.get("rewritten", false).await.unwrap(), b"e");
+
+    let root_before = db.current_root_hex();
+    assert_eq!(db.sweep_expired(false).await.unwrap(), 1);
+    assert_ne!(db.current_root_hex(), root_before);
+    assert!(matches!(
+        db.get("short", false).await,
+        Err(DatabaseError::KeyNotFound(_))
+    ));
+    assert_eq!(
+        db.list_keys().unwrap(),
+        vec!["forever", "long", "rewritten"]
+    );
+
+    clock.0.store(61_000, Ordering::SeqCst);
+    assert_eq!(db.sweep_expired(false).await.unwrap(), 1);
+    assert_eq!(db.list_keys().unwrap(), vec!["forever", "rewritten"]);
+    assert_eq!(db.sweep_expired(false).await.unwrap(), 0);
+}
+
+#[tokio::test]
+async fn test_root_history() {
+    init();
+
+    let temp_dir = tempfile::tempdir().unwrap();
+    let store = FileStore::new(temp_dir.path()).await.unwrap();
+    let clock = Arc::new(ManualClock(AtomicU64::new(0)));
+    let history_file = temp_dir.path().join("roots.json");
+    let mut db = Database::builder(DatabaseType::Merkle, Arc::new(store))
+        .clock(clock.clone())
+        .root_history(&history_file)
+        .build()
+        .await
+        .unwrap();
+    assert_eq!(db.root_at_timestamp(5_000).unwrap(), None);
+
+    // One insert per second, starting at 1s.
+    let mut roots = Vec::new();
+    for i in 1..=5u64 {
+        clock.0.store(i * 1_000, Ordering::SeqCst);
+        db.put(&format!("key{}", i), b"value", false).await.unwrap();
+        roots.push(db.current_root_hex().unwrap());
+    }
+
+    let history = db.get_root_history().unwrap();
+    assert_eq!(history.len(), 5);
+    assert!(history.iter().all(|entry| entry.operation == "Insert"));
+    assert_eq!(
+        history
+            .iter()
+            .map(|entry| &entry.root_hex)
+            .collect::<Vec<_>>(),
+        roots.iter().collect::<Vec<_>>()
+    );
+
+    assert_eq!(db.root_at_timestamp(999).unwrap(), None);
+    for (i, root) in roots.iter().enumerate() {
+        let committed = (i as u64 + 1) * 1_000;
+        assert_eq!(
+            db.root_at_timestamp(committed).unwrap().as_ref(),
+            Some(root)
+        );
+        assert_eq!(
+            db.root_at_timestamp(committed + 999).unwrap().as_ref(),
+            Some(root)
+        );
+    }
+
+    // Reads do not grow the history, and it outlives the database.
+    db.get("key1", false).await.unwrap();
+    drop(db);
+    let store = FileStore::new(temp_dir.path()).await.unwrap();
+    let db = Database::builder(DatabaseType::Merkle, Arc::new(store))
+        .root_history(&history_file)
+        .build()
+        .await
+        .unwrap();
+    assert_eq!(db.get_root_history().unwrap(), history);
+}
+
+#[tokio::test]
+async fn test_execute_query_is_logged_and_indexed() {
+    init();
+
+    let temp_dir = tempfile::tempdir().unwrap();
+    let store = Arc::new(FileStore::new(temp_dir.path()).await.unwrap());
+    let mut db = Database::builder(DatabaseType::Merkle, store.clone())
+        .operation_log(true)
+        .value_index(true)
+        .build()
+        .await
+        .unwrap();
+    db.put("a", b"first", false).await.unwrap();
+    store.put("b", b"second").await.unwrap();
+    db.execute_query(
+        Command::Insert {
+            key: "b".to_string(),
+            value: verify::hash_value_hex(b"second"),
+        },
+        false,
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(db.log_len().await.unwrap(), 2);
+    assert!(db.verify_log().await.unwrap().is_empty());
+    assert!(db.verify_index().await.unwrap().is_empty());
+    assert_eq!(db.find_keys_by_value(b"second").await.unwrap(), vec!["b"]);
+    let report = db.replay(0..2).await.unwrap();
+    assert_eq!(report.root, db.current_root_hex());
+
+    // Reads commit nothing.
+    db.execute_query(
+        Command::Query {
+            key: "b".to_string(),
+        },
+        false,
+    )
+    .await
+    .unwrap();
+    assert_eq!(db.log_len().await.unwrap(), 2);
+}
+
+#[tokio::test]
+async fn test_value_index() {
+    init();
+
+    let temp_dir = tempfile::tempdir().unwrap();
+    let store = FileStore::new(temp_dir.path()).await.unwrap();
+    let mut db = Database::builder(DatabaseType::Merkle, Arc::new(store))
+        .value_index(true)
+        .build()
+        .await
+        .unwrap();
+
+    db.put("b", b"shared", false).await.unwrap();
+    db.put("a", b"shared", false).await.unwrap();
+    db.put("c", b"other", false).await.unwrap();
+    let shared = zkdb_verify::hash_value_hex(b"shared");
+    assert_eq!(db.find_keys_by_hash(&shared).await.unwrap(), vec!["a", "b"]);
+    assert_eq!(db.find_keys_by_value(b"other").await.unwrap(), vec!["c"]);
+
+    // Overwrites move the key, deletes drop it.
+    db.put("b", b"other", false).await.unwrap();
+    db.delete("a", false).await.unwrap();
+    assert!(db.find_keys_by_hash(&shared).await.unwrap().is_empty());
+    assert_eq!(
+        db.find_keys_by_value(b"other").await.unwrap(),
+        vec!["b", "c"]
+    );
+
+    db.put_many(
+        vec![
+            ("d".to_string(), b"shared".to_vec()),
+            ("c".to_string(), b"shared".to_vec()),
+        ],
+        10,
+        false,
+    )
+    .await
+    .unwrap();
+    assert_eq!(db.find_keys_by_hash(&shared).await.unwrap(), vec!["c", "d"]);
+    assert!(db.verify_index().await.unwrap().is_empty());
+
+    // A database opened without the index, on a fresh state, leaves the
+    // stored entries stale until rebuilt.
+    drop(db);
+    let store = FileStore::new(temp_dir.path()).await.unwrap();
+    let mut db = Database::builder(DatabaseType::Merkle, Arc::new(store))
+        .build()
+        .await
+        .unwrap();
+    db.put("e", b"shared", false).await.unwrap();
+    let mismatches = db.verify_index().await.unwrap();
+    assert!(!mismatches.is_empty());
+    assert!(mismatches
+        .iter()
+        .all(|e| matches!(e, DatabaseError::IndexMismatch { .. })));
+
+    assert_eq!(db.rebuild_index().await.unwrap(), 1);
+    assert_eq!(db.find_keys_by_hash(&shared).await.unwrap(), vec!["e"]);
+    assert!(db.find_keys_by_value(b"other").await.unwrap().is_empty());
+    assert!(db.verify_index().await.unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn test_read_only_alongside_writer() {
+    init();
+
+    let temp_dir = tempfile::tempdir().unwrap();
+    let store = Arc::new(FileStore::new(temp_dir.path()).await.unwrap());
+    let mut writer = Database::new(DatabaseType::Merkle, store.clone(), None)
+        .await
+        .unwrap();
+    writer.put("a", b"one", false).await.unwrap();
+    writer.put("b", b"two", false).await.unwrap();
+
+    let state_file = temp_dir.path().join("state.bin");
+    writer.save_state(&state_file).unwrap();
+    let reader_store = FileStore::new(temp_dir.path()).await.unwrap();
+    let mut reader = Database::builder(DatabaseType::Merkle, Arc::new(reader_store))
+        .state(std::fs::read(&state_file).unwrap())
+        .operation_log(true)
+        .read_only(true)
+        .build()
+        .await
+        .unwrap();
+
+    assert_eq!(reader.get("a", false).await.unwrap(), b"one");
+    assert_eq!(reader.list_keys().unwrap(), vec!["a", "b"]);
+    assert_eq!(reader.current_root_hex(), writer.current_root_hex());
+    assert!(reader.prove("b", false).unwrap().data["proof"].is_string());
+
+    let refused = |result: Result<(), DatabaseError>| {
+        assert!(
+            matches!(result, Err(DatabaseError::ReadOnly(_))),
+            "expected ReadOnly, got {:?}",
+            result
+        )
+    };
+    refused(reader.put("a", b"changed", false).await);
+    // Deletes are refused before the engine runs.
+    let executions = reader.execution_count();
+    refused(reader.delete("b", false).await);
+    refused(reader.delete_bytes(&[0xff], false).await);
+    refused(reader.sweep_expired(false).await.map(|_| ()));
+    refused(reader.clear_tree(false).await);
+    refused(reader.compact_tree(false).await.map(|_| ()));
+    refused(reader.compact().await.map(|_| ()));
+    assert_eq!(reader.execution_count(), executions);
+    refused(
+        reader
+            .put_many(vec![("c".to_string(), b"three".to_vec())], 10, false)
+            .await
+            .map(|_| ()),
+    );
+    refused(
+        reader
+            .execute_query(Command::Clear, false)
+            .await
+            .map(|_| ()),
+    );
+    refused(reader.save_state(&state_file));
+    assert_eq!(std::fs::read(&state_file).unwrap(), writer.get_state());
+
+    // Reads of the reader are unaffected, and the writer keeps writing.
+    assert_eq!(store.get("a").await.unwrap(), b"one");
+    assert!(reader
+        .execute_query(
+            Command::Query {
+                key: "b".to_string()
+            },
+            false
+        )
+        .await
+        .is_ok());
+    writer.put("c", b"three", false).await.unwrap();
+    assert_eq!(reader.get("a", false).await.unwrap(), b"one");
+}
+
+fn assert_limit_exceeded<T: std::fmt::Debug>(
+    result: Result<T, DatabaseError>,
+    expected_which: &str,
+    expected_limit: u64,
+    expected_actual: u64,
+) {
+    match result {
+        Err(DatabaseError::LimitExceeded {
+            which,
+            limit,
+            actual,
+        }) => assert_eq!(
+            (which.as_str(), limit, actual),
+            (expected_which, expected_limit, expected_actual)
+        ),
+        other => panic!(
+            "expected {} to be exceeded, got {:?}",
+            expected_which, other
+        ),
+    }
+}
+
+#[tokio::test]
+async fn test_limits_checked_before_the_store() {
+    init();
+
+    let temp_dir = tempfile::tempdir().unwrap();
+    let store = Arc::new(FileStore::new(temp_dir.path()).await.unwrap());
+    let limits = DatabaseLimits {
+        max_key_len: 8,
+        max_value_bytes: 16,
+        max_batch_entries: 2,
+        ..DatabaseLimits::default()
+    };
+    let mut db = Database::builder(DatabaseType::Merkle, store.clone())
+        .limits(limits)
+        .build()
+        .await
+        .unwrap();
+
+    assert_limit_exceeded(db.put("too_long_key", b"v", false).await, "key_len", 8, 12);
+    assert_limit_exceeded(
+        db.put("key", &[0u8; 17], false).await,
+        "value_bytes",
+        16,
+        17,
+    );
+    assert_limit_exceeded(
+        db.put_many(vec![("a".to_string(), b"v".to_vec())], 3, false)
+            .await,
+        "batch_entries",
+        2,
+        3,
+    );
+    assert_limit_exceeded(
+        db.put_many(vec![("a".to_string(), vec![0u8; 20])], 2, false)
+            .await,
+        "value_bytes",
+        16,
+        20,
+    );
+    assert!(!store.exists("too_long_key").await.unwrap());
+    assert!(!store.exists("key").await.unwrap());
+    assert!(!store.exists("a").await.unwrap());
+    assert!(db.list_keys().unwrap().is_empty());
+
+    db.put("key", &[0u8; 16], false).await.unwrap();
+    db.put_many(
+        vec![
+            ("a".to_string(), b"1".to_vec()),
+            ("b".to_string(), b"2".to_vec()),
+        ],
+        2,
+        false,
+    )
+    .await
+    .unwrap();
+    assert_eq!(db.list_keys().unwrap(), vec!["a", "b", "key"]);
+
+    // The state check runs on the result, so the state is left as it was.
+    let state = db.get_state().to_vec();
+    let mut db = Database::builder(DatabaseType::Merkle, store.clone())
+        .state(state.clone())
+        .limits(DatabaseLimits {
+            max_state_bytes: state.len(),
+            ..DatabaseLimits::default()
+        })
+        .build()
+        .await
+        .unwrap();
+    match db.put("c", b"3", false).await {
+        Err(DatabaseError::LimitExceeded { which, limit, .. }) => {
+            assert_eq!((which.as_str(), limit), ("state_bytes", state.len() as u64))
+        }
+        other => panic!("expected state_bytes to be exceeded, got {:?}", other),
+    }
+    assert_eq!(db.get_state(), state.as_slice());
+}
+
+#[tokio::test]
+async fn test_engine_enforces_key_limit() {
+    init();
+
+    let temp_dir = tempfile::tempdir().unwrap();
+    let store = FileStore::new(temp_dir.path()).await.unwrap();
+    // A host allowing longer keys than the engine does.
+    let mut db = Database::builder(DatabaseType::Merkle, Arc::new(store))
+        .limits(DatabaseLimits {
+            max_key_len: MAX_KEY_LEN * 2,
+            ..DatabaseLimits::default()
+        })
+        .build()
+        .await
+        .unwrap();
+
+    let key = "k".repeat(MAX_KEY_LEN + 1);
+    assert_limit_exceeded(
+        db.put(&key, b"value", false).await,
+        "key_len",
+        MAX_KEY_LEN as u64,
+        MAX_KEY_LEN as u64 + 1,
+    );
+    let result = db
+        .execute_query(
+            Command::Insert {
+                key: key.clone(),
+                value: verify::hash_value_hex(b"value"),
+            },
+            false,
+        )
+        .await
+        .unwrap();
+    assert_eq!(result.data["error"]["type"], "LimitExceeded");
+    assert_eq!(result.data["error"]["limit"]["which"], "key_len");
+    assert!(db.list_keys().unwrap().is_empty());
+
+    db.put(&"k".repeat(MAX_KEY_LEN), b"value", false)
+        .await
+        .unwrap();
+}
+
+fn assert_invalid_key<T: std::fmt::Debug>(result: Result<T, DatabaseError>, reason: &str) {
+    match result {
+        Err(DatabaseError::InvalidKey(actual)) => {
+            assert!(
+                actual.contains(reason),
+                "{:?} does not mention {:?}",
+                actual,
+                reason
+            )
+        }
+        other => panic!("expected an invalid key, got {:?}", other),
+    }
+}
+
+#[tokio::test]
+async fn test_invalid_keys_checked_before_the_store() {
+    init();
+
+    let temp_dir = tempfile::tempdir().unwrap();
+    let store = Arc::new(FileStore::new(temp_dir.path()).await.unwrap());
+    let mut db = Database::builder(DatabaseType::Merkle, store.clone())
+        .operation_log(true)
+        .build()
+        .await
+        .unwrap();
+    assert_eq!(db.reserved_prefix(), DEFAULT_RESERVED_PREFIX);
+
+    assert_invalid_key(db.put("", b"value", false).await, "empty");
+    assert_invalid_key(db.put("_wal/head", b"value", false).await, "reserved");
+    assert_invalid_key(
+        db.put_many(
+            vec![
+                ("a".to_string(), b"1".to_vec()),
+                ("_b".to_string(), b"2".to_vec()),
+            ],
+            2,
+            false,
+        )
+        .await,
+        "reserved",
+    );
+    assert_invalid_key(db.tree("users").put("", b"value", false).await, "empty");
+    // The log's head is untouched by the rejected write.
+    assert_eq!(db.log_len().await.unwrap(), 0);
+    assert!(!store.exists("a").await.unwrap());
+    assert!(db.list_keys().unwrap().is_empty());
+
+    db.put("a", b"1", false).await.unwrap();
+    assert_invalid_key(db.clone_key("a", "_a", false).await, "reserved");
+    // Only the prefix is reserved: underscores elsewhere are fine.
+    db.put("a_b", b"2", false).await.unwrap();
+
+    let input = format!(
+        "{}\n{}\n",
+        serde_json::json!({"id": "_meta/a", "blob": base64::encode(b"forged")}),
+        serde_json::json!({"id": "c", "blob": base64::encode(b"3")}),
+    );
+    let report = db
+        .import_json_lines(input.as_bytes(), "id", "blob", false)
+        .await
+        .unwrap();
+    assert_eq!((report.imported, report.skipped), (1, 1));
+    assert!(report.errors[0].contains("reserved"), "{:?}", report.errors);
+    assert_eq!(db.list_keys().unwrap(), vec!["a", "a_b", "c"]);
+}
+
+#[tokio::test]
+async fn test_engine_rejects_invalid_keys() {
+    init();
+
+    let temp_dir = tempfile::tempdir().unwrap();
+    let store = FileStore::new(temp_dir.path()).await.unwrap();
+    let mut db = Database::new(DatabaseType::Merkle, Arc::new(store), None)
+        .await
+        .unwrap();
+
+    // Commands sent straight to the engine skip the host's checks.
+    for (entries, reason) in [
+        (vec![("", "value")], "empty"),
+        (vec![("_idx/hash/x", "value")], "reserved"),
+        (vec![("a", "value"), ("_a", "value")], "reserved"),
+    ] {
+        let result = db
+            .execute_query(
+                Command::BatchInsert {
+                    entries: entries
+                        .iter()
+                        .map(|(key, value)| {
+                            (key.to_string(), verify::hash_value_hex(value.as_bytes()))
+                        })
+                        .collect(),
+                },
+                false,
+            )
+            .await
+            .unwrap();
+        assert_eq!(result.data["error"]["type"], "InvalidKey");
+        let details = result.data["error"]["reason"].as_str().unwrap();
+        assert!(details.contains(reason), "{:?}", details);
+    }
+    assert!(db.list_keys().unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn test_reserved_prefix_escape_hatch() {
+    init();
+
+    let temp_dir = tempfile::tempdir().unwrap();
+    let store = Arc::new(FileStore::new(temp_dir.path()).await.unwrap());
+    assert_invalid_key(
+        Database::builder(DatabaseType::Merkle, store.clone())
+            .reserved_prefix("")
+            .build()
+            .await
+            .map(|_| ()),
+        "reserved prefix",
+    );
+
+    let mut db = Database::builder(DatabaseType::Merkle, store.clone())
+        .reserved_prefix("~zkdb/")
+        .operation_log(true)
+        .value_index(true)
+        .build()
+        .await
+        .unwrap();
+    assert_eq!(db.reserved_prefix(), "~zkdb/");
+
+    // Keys the default layout reserves are ordinary keys here, and the
+    // bookkeeping moves out of their way.
+    db.put("_wal/head", b"user value", false).await.unwrap();
+    db.put("_tree/a", b"another", false).await.unwrap();
+    db.tree("users").put("alice", b"1", false).await.unwrap();
+    assert_invalid_key(db.put("~zkdb/wal/head", b"forged", false).await, "reserved");
+    assert_eq!(store.get("_wal/head").await.unwrap(), b"user value");
+    assert!(store.exists("~zkdb/wal/head").await.unwrap());
+    assert!(store.exists("~zkdb/tree/users/alice").await.unwrap());
+    assert_eq!(db.log_len().await.unwrap(), 3);
+    let hash = verify::hash_value_hex(b"user value");
+    assert_eq!(
+        db.find_keys_by_hash(&hash).await.unwrap(),
+        vec!["_wal/head"]
+    );
+
+    // The prefix travels with the state.
+    let state = db.get_state().to_vec();
+    let reopened = Database::builder(DatabaseType::Merkle, store.clone())
+        .state(state.clone())
+        .operation_log(true)
+        .build()
+        .await
+        .unwrap();
+    assert_eq!(reopened.reserved_prefix(), "~zkdb/");
+    assert_eq!(
+        reopened.get("_wal/head", false).await.unwrap(),
+        b"user value"
+    );
+    assert_eq!(reopened.log_len().await.unwrap(), 3);
+
+    // It cannot change once the state holds anything.
+    assert_invalid_key(
+        Database::builder(DatabaseType::Merkle, store)
+            .state(state)
+            .reserved_prefix(DEFAULT_RESERVED_PREFIX)
+            .build()
+            .await
+            .map(|_| ()),
+        "empty state",
+    );
+}
+
+#[tokio::test]
+async fn test_byte_keys() {
+    init();
+
+    let temp_dir = tempfile::tempdir().unwrap();
+    let store = Arc::new(FileStore::new(temp_dir.path()).await.unwrap());
+    let mut db = Database::builder(DatabaseType::Merkle, store.clone())
+        .operation_log(true)
+        .build()
+        .await
+        .unwrap();
+
+    let key = [0x00, 0xff, b'/', 0x80];
+    db.put_bytes(&key, b"binary", false).await.unwrap();
+    assert_eq!(db.get_bytes(&key, false).await.unwrap(), b"binary");
+    assert!(store.exists(&zkdb_store::binary_key(&key)).await.unwrap());
+    // A UTF-8 key is the string key it spells.
+    db.put_bytes(b"text", b"utf-8", false).await.unwrap();
+    assert_eq!(db.get("text", false).await.unwrap(), b"utf-8");
+    assert_eq!(db.list_keys().unwrap(), vec!["text"]);
+    assert_eq!(
+        db.list_key_bytes().unwrap(),
+        vec![key.to_vec(), b"text".to_vec()]
+    );
+    assert_invalid_key(db.put_bytes(b"_\xff", b"forged", false).await, "reserved");
+
+    db.delete_bytes(&key, false).await.unwrap();
+    assert!(matches!(
+        db.get_bytes(&key, false).await,
+        Err(DatabaseError::KeyNotFound(missing)) if missing == "00ff2f80"
+    ));
+    assert!(!store.exists(&zkdb_store::binary_key(&key)).await.unwrap());
+}
+
+#[tokio::test]
+async fn test_legacy_state_migrates_to_versioned_layout() {
+    init();
+
+    let temp_dir = tempfile::tempdir().unwrap();
+    let store = Arc::new(FileStore::new(temp_dir.path()).await.unwrap());
+    let mut v0 = LegacyMerkleState::default();
+    for (i, key) in ["alpha", "beta"].iter().enumerate() {
+        let value = format!("{}-value", key);
+        store.put(key, value.as_bytes()).await.unwrap();
+        let leaf = verify::decode_hash("leaf", &verify::hash_value_hex(value.as_bytes())).unwrap();
+        v0.leaves.push(leaf);
+        v0.key_indices.insert(key.to_string(), i);
+    }
+    let v0_bytes = bincode::serialize(&v0).unwrap();
+    assert_eq!(StateVersion::detect(&v0_bytes).unwrap(), StateVersion::V0);
+
+    // The engine reads the old layout as is.
+    let state = MerkleState::from_bytes(&v0_bytes).unwrap();
+    assert_eq!(
+        state.tree("").unwrap().key_indices.get(&b"beta"[..]),
+        Some(&1)
+    );
+
+    let migrated = migrate_state(&v0_bytes).unwrap();
+    assert!(migrated.starts_with(&STATE_MAGIC));
+    assert_eq!(
+        StateVersion::detect(&migrated).unwrap(),
+        StateVersion::CURRENT
+    );
+    assert_eq!(migrate_state(&migrated).unwrap(), migrated);
+
+    let mut db = Database::builder(DatabaseType::Merkle, store)
+        .state(migrated)
+        .build()
+        .await
+        .unwrap();
+    assert_eq!(db.get("alpha", false).await.unwrap(), b"alpha-value");
+    assert_eq!(db.get("beta", false).await.unwrap(), b"beta-value");
+    db.put("gamma", b"gamma-value", false).await.unwrap();
+    assert_eq!(
+        StateVersion::detect(db.get_state()).unwrap(),
+        StateVersion::CURRENT
+    );
+
+    // A version from the future is refused rather than misread.
+    let mut future = STATE_MAGIC.to_vec();
+    future.extend_from_slice(&u16::MAX.to_le_bytes());
+    assert!(StateVersion::detect(&future).is_err());
+    assert!(MerkleState::from_bytes(&future).is_err());
+}
+
+#[test]
+fn test_corrupted_state_is_rejected() {
+    let corrupted = |state: &[u8]| {
+        matches!(
+            MerkleState::from_bytes(state),
+            Err(zkdb_core::DatabaseError::StateDecode(reason)) if reason == "corrupted state"
+        )
+    };
+    let mut state = MerkleState::new();
+    let tree = state.tree_mut("");
+    for (i, key) in ["alpha", "beta", "gamma"].iter().enumerate() {
+        tree.leaves.push([i as u8 + 1; 32]);
+        tree.key_indices.insert(key.as_bytes().to_vec(), i);
+    }
+    let valid = state.to_bytes();
+    assert!(MerkleState::from_bytes(&valid).is_ok());
+
+    // Keys pointing past the leaves or sharing a leaf decode but are refused.
+    let mut past_end = state.clone();
+    past_end
+        .tree_mut("")
+        .key_indices
+        .insert(b"beta".to_vec(), 3);
+    assert!(corrupted(&past_end.to_bytes()));
+    let mut shared = state.clone();
+    shared.tree_mut("").key_indices.insert(b"beta".to_vec(), 0);
+    assert!(corrupted(&shared.to_bytes()));
+    let mut sorted = state.clone();
+    sorted.layout = TreeLayout::Sorted;
+    assert!(corrupted(&sorted.to_bytes()));
+
+    // Truncated bytes never decode.
+    for len in 1..valid.len() {
+        assert!(MerkleState::from_bytes(&valid[..len]).is_err(), "{}", len);
+    }
+
+    // Whatever random damage decodes still points every key at a leaf of
+    // its own.
+    let mut seed = 0x2545_f491_4f6c_dd1du64;
+    let mut next = move || {
+        seed ^= seed << 13;
+        seed ^= seed >> 7;
+        seed ^= seed << 17;
+        seed
+    };
+    for _ in 0..2000 {
+        let mut bytes = valid.clone();
+        for _ in 0..1 + next() % 4 {
+            let at = (next() % bytes.len() as u64) as usize;
+            bytes[at] ^= 1 + (next() % 255) as u8;
+        }
+        if let Ok(decoded) = MerkleState::from_bytes(&bytes) {
+            for tree in decoded.trees.values() {
+                let indices: BTreeSet<_> = tree.key_indices.values().collect();
+                assert_eq!(indices.len(), tree.key_indices.len());
+                assert!(indices.iter().all(|&&index| index < tree.leaves.len()));
+            }
+        }
+    }
+}
+
+#[test]
+fn test_engine_errors_keep_their_structure() {
+    let error = zkdb_core::DatabaseError::ScriptFailed {
+        index: 2,
+        error: Box::new(zkdb_core::DatabaseError::LimitExceeded {
+            which: "key_len".into(),
+            limit: 4,
+            actual: 5,
+        }),
+    };
+    assert_eq!(
+        error.to_string(),
+        "Command 2 of the script failed: key_len limit exceeded: 5 is over 4"
+    );
+    let boxed: Box<dyn std::error::Error> = Box::new(error.clone());
+    assert_eq!(boxed.to_string(), error.to_string());
+
+    // The guests' output carries the error whole, and readable by parts.
+    let output = error.to_output(10);
+    assert_eq!(output["type"], "ScriptFailed");
+    assert_eq!(output["details"], error.to_string());
+    assert_eq!(output["cause"]["limit"]["which"], "key_len");
+    let decoded: zkdb_core::DatabaseError =
+        serde_json::from_value(output["encoded"].clone()).unwrap();
+    assert_eq!(decoded, error);
+
+    // The host sees the same variants, with the same messages.
+    let host = DatabaseError::from(decoded);
+    assert_eq!(host.to_string(), error.to_string());
+    assert!(matches!(
+        host,
+        DatabaseError::ScriptFailed { index: 2, error }
+            if matches!(*error, DatabaseError::LimitExceeded { limit: 4, actual: 5, .. })
+    ));
+    for (core, expected) in [
+        (
+            zkdb_core::DatabaseError::StateDecode("corrupted state".into()),
+            "Invalid state: corrupted state",
+        ),
+        (
+            zkdb_core::DatabaseError::InvalidCommand("Invalid range [b, a)".into()),
+            "Invalid command: Invalid range [b, a)",
+        ),
+        (
+            zkdb_core::DatabaseError::KeyNotFound("missing".into()),
+            "Key not found: missing",
+        ),
+    ] {
+        assert_eq!(core.to_string(), expected);
+        assert_eq!(DatabaseError::from(core).to_string(), expected);
+    }
+    let mut future = STATE_MAGIC.to_vec();
+    future.extend_from_slice(&u16::MAX.to_le_bytes());
+    assert!(matches!(
+        MerkleState::from_bytes(&future),
+        Err(zkdb_core::DatabaseError::StateDecode(_))
+    ));
+}
+
+#[tokio::test]
+async fn test_put_many_from_generator() {
+    init();
+
+    let temp_dir = tempfile::tempdir().unwrap();
+    let store = FileStore::new(temp_dir.path()).await.unwrap();
+    let mut db = Database::new(DatabaseType::Merkle, Arc::new(store), None)
+        .await
+        .unwrap();
+
+    let mut next = 0;
+    let entries = std::iter::from_fn(|| {
+        if next == 1000 {
+            return None;
+        }
+        next += 1;
+        Some((
+            format!("key{:04}", next),
+            format!("value{}", next).into_bytes(),
+        ))
+    });
+    let inserted = db.put_many(entries, 128, false).await.unwrap();
+    assert_eq!(inserted, 1000);
+
+    assert_eq!(db.list_keys().unwrap().len(), 1000);
+    for i in [1, 128, 129, 500, 1000] {
+        assert_eq!(
+            db.get(&format!("key{:04}", i), false).await.unwrap(),
+            format!("value{}", i).into_bytes()
+        );
+    }
+    assert!(db.put_many(Vec::new(), 0, false).await.is_err());
+}
+
+#[test]
+fn test_database_error_conversions() {
+    fn boxed() -> Result<(), Box<dyn std::error::Error>> {
+        Err(DatabaseError::KeyNotFound("missing".into()))?
+    }
+    fn with_anyhow() -> anyhow::Result<()> {
+        Err(DatabaseError::EmptyTree)?
+    }
+    assert_eq!(boxed().unwrap_err().to_string(), "Key not found: missing");
+    let error = with_anyhow().unwrap_err();
+    assert!(matches!(
+        error.downcast_ref::<DatabaseError>(),
+        Some(DatabaseError::EmptyTree)
+    ));
+
+    let error = DatabaseError::KeyNotFound("missing".into()).context("loading the profile");
+    assert_eq!(
+        error.to_string(),
+        "loading the profile: Key not found: missing"
+    );
+
+    // Store errors are rewritten through the context; others are left alone.
+    let error = DatabaseError::Store(StoreError::NotFound("a".into()))
+        .context("reading")
+        .map_store(|e| StoreError::Storage(format!("primary: {}", e)));
+    assert_eq!(
+        error.to_string(),
+        "reading: Store error: Storage error: primary: Value not found for key: a"
+    );
+    let error = DatabaseError::EmptyTree.map_store(|_| unreachable!());
+    assert!(matches!(error, DatabaseError::EmptyTree));
+}
+
+#[tokio::test]
+async fn test_encryption_commits_to_either_hash() {
+    init();
+
+    for commit_to in [CommitTo::Plaintext, CommitTo::Ciphertext] {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let store = Arc::new(FileStore::new(temp_dir.path()).await.unwrap());
+        let mut db = Database::builder(DatabaseType::Merkle, store.clone())
+            .encryption(EncryptionConfig {
+                key: [7; 32],
+                commit_to,
+            })
+            .operation_log(true)
+            .build()
+            .await
+            .unwrap();
+
+        db.put("secret", b"plaintext", false).await.unwrap();
+        assert_eq!(db.get("secret", false).await.unwrap(), b"plaintext");
+        // Values and bookkeeping both reach the store sealed.
+        let sealed = store.get("secret").await.unwrap();
+        assert!(!sealed.windows(9).any(|window| window == b"plaintext"));
+        assert_ne!(store.get("_wal/head").await.unwrap().len(), 8);
+
+        let leaf = db.prove("secret", false).unwrap().as_proof().unwrap().leaf;
+        let expected = match commit_to {
+            CommitTo::Plaintext => verify::hash_value_hex(b"plaintext"),
+            CommitTo::Ciphertext => verify::hash_value_hex(&sealed),
+        };
+        assert_eq!(leaf, expected);
+
+        let bundle = db.proof_bundle("secret", false).await.unwrap();
+        assert_eq!(bundle.commit_to, commit_to);
+        assert!(verify::verify_bundle(&bundle).unwrap().valid);
+    }
+}
+
+#[tokio::test]
+async fn test_encryption_rejects_wrong_key_and_tampering() {
+    init();
+
+    let temp_dir = tempfile::tempdir().unwrap();
+    let store: Arc<dyn Store> = Arc::new(FileStore::new(temp_dir.path()).await.unwrap());
+    let config = EncryptionConfig {
+        key: [7; 32],
+        commit_to: CommitTo::Ciphertext,
+    };
+    let mut db = Database::builder(DatabaseType::Merkle, store.clone())
+        .encryption(config.clone())
+        .build()
+        .await
+        .unwrap();
+    db.put("secret", b"plaintext", false).await.unwrap();
+    db.put("other", b"another value", false).await.unwrap();
+
+    let wrong_key = Database::builder(DatabaseType::Merkle, store.clone())
+        .state(db.get_state().to_vec())
+        .encryption(EncryptionConfig {
+            key: [8; 32],
+            ..config.clone()
+        })
+        .build()
+        .await
+        .unwrap();
+    assert!(matches!(
+        wrong_key.get("secret", false).await,
+        Err(DatabaseError::Store(StoreError::Storage(message))) if message.contains("wrong key")
+    ));
+
+    // A flipped byte fails authentication.
+    let mut sealed = store.get("secret").await.unwrap();
+    let last = sealed.len() - 1;
+    sealed[last] ^= 0xff;
+    store.put("secret", &sealed).await.unwrap();
+    assert!(matches!(
+        db.get("secret", false).await,
+        Err(DatabaseError::Store(StoreError::Storage(_)))
+    ));
+
+    // A value sealed under the right key but for another key fails the leaf.
+    store.copy("other", "secret").await.unwrap();
+    assert!(db.get("secret", false).await.is_err());
+}
+
+#[cfg(feature = "admin")]
+#[tokio::test]
+async fn test_put_raw_leaf() {
+    init();
+
+    let temp_dir = tempfile::tempdir().unwrap();
+    let store = Arc::new(FileStore::new(temp_dir.path()).await.unwrap());
+    let mut db = Database::new(DatabaseType::Merkle, store.clone(), None)
+        .await
+        .unwrap();
+
+    let leaf_hash = [0xab; 32];
+    db.put_raw_leaf("migrated", b"raw value", leaf_hash)
+        .await
+        .unwrap();
+    assert_eq!(store.get("migrated").await.unwrap(), b"raw value");
+
+    let hit = db
+        .execute_query(
+            Command::Query {
+                key: "migrated".to_string(),
+            },
+            false,
+        )
+        .await
+        .unwrap()
+        .as_query()
+        .unwrap();
+    assert_eq!(hit.value, hex::encode(leaf_hash));
+    // The leaf is not the hash of the value, which `get` reports.
+    assert!(db.get("migrated", false).await.is_err());
+}
+
+#[tokio::test]
+async fn test_signed_state_files() {
+    init();
+
+    let temp_dir = tempfile::tempdir().unwrap();
+    let store: Arc<dyn Store> =
+        Arc::new(FileStore::new(temp_dir.path().join("data")).await.unwrap());
+    let secret_key = [1; 32];
+    let mut db = Database::builder(DatabaseType::Merkle, store.clone())
+        .signing_key(secret_key)
+        .require_signatures(true)
+        .build()
+        .await
+        .unwrap();
+    db.put("signed", b"value", false).await.unwrap();
+    let public_key = db.verifying_key().unwrap();
+
+    let state_path = temp_dir.path().join("state.bin");
+    let checked_path = temp_dir.path().join("state.checked");
+    db.save_state(&state_path).unwrap();
+    db.save_state_checked(&checked_path).unwrap();
+    assert!(zkdb_lib::signature_path(&state_path).exists());
+    let saved_state = db.get_state().to_vec();
+    db.load_state(&state_path).unwrap();
+    db.load_state_checked(&checked_path).unwrap();
+    assert_eq!(db.get_state(), saved_state.as_slice());
+
+    let bundle = db.proof_bundle("signed", false).await.unwrap();
+    bundle.verify_signature(&public_key).unwrap();
+    let json = serde_json::to_string(&bundle).unwrap();
+    assert_eq!(db.load_bundle(&json).unwrap(), bundle);
+
+    // A partner holding only the public key verifies; another key fails.
+    let partner = |public_key| {
+        Database::builder(DatabaseType::Merkle, store.clone())
+            .verifying_key(public_key)
+            .require_signatures(true)
+            .build()
+    };
+    let mut verifier = partner(public_key).await.unwrap();
+    verifier.load_state(&state_path).unwrap();
+    let mut wrong_key = partner([9; 32]).await.unwrap();
+    assert!(matches!(
+        wrong_key.load_state(&state_path),
+        Err(DatabaseError::InvalidSignature(_))
+    ));
+
+    // A tampered payload fails, whether a state file or a bundle.
+    let mut state = std::fs::read(&state_path).unwrap();
+    state.push(0);
+    std::fs::write(&state_path, &state).unwrap();
+    assert!(matches!(
+        verifier.load_state(&state_path),
+        Err(DatabaseError::InvalidSignature(_))
+    ));
+    let mut tampered = bundle.clone();
+    tampered.leaf = tampered.root.clone();
+    assert!(matches!(
+        verifier.load_bundle(&serde_json::to_string(&tampered).unwrap()),
+        Err(DatabaseError::InvalidSignature(_))
+    ));
+
+    // Unsigned artifacts load only where signatures are not required.
+    let mut unsigned = Database::new(DatabaseType::Merkle, store.clone(), None)
+        .await
+        .unwrap();
+    unsigned.set_state(saved_state);
+    unsigned.save_state(&state_path).unwrap();
+    assert!(!zkdb_lib::signature_path(&state_path).exists());
+    unsigned.load_state(&state_path).unwrap();
+    assert!(matches!(
+        verifier.load_state(&state_path),
+        Err(DatabaseError::InvalidSignature(_))
+    ));
+    let unsigned_bundle = verify::ProofBundle {
+        signature: None,
+        ..bundle
+    };
+    let json = serde_json::to_string(&unsigned_bundle).unwrap();
+    unsigned.load_bundle(&json).unwrap();
+    assert!(verifier.load_bundle(&json).is_err());
+}
+
+#[tokio::test]
+async fn test_healthcheck_reports_ok() {
+    init();
+
+    let temp_dir = tempfile::tempdir().unwrap();
+    let store = Arc::new(FileStore::new(temp_dir.path()).await.unwrap());
+    let mut db = Database::new(DatabaseType::Merkle, store, None)
+        .await
+        .unwrap();
+    db.put("key", b"value", false).await.unwrap();
+
+    let status = db.healthcheck().await.unwrap();
+    assert!(status.is_ok(), "{:?}", status);
+    let json = serde_json::to_value(&status).unwrap();
+    for component in ["store", "executor", "state"] {
+        assert_eq!(json[component]["status"], "ok");
+        assert!(json[component]["error"].is_null());
+    }
+    // The probe leaves nothing behind.
+    assert!(!db
+        .list_keys()
+        .unwrap()
+        .contains(&HEALTHCHECK_KEY.to_string()));
+}
+
+#[tokio::test]
+async fn test_mutations_commit_operation_context() {
+    init();
+
+    let temp_dir = tempfile::tempdir().unwrap();
+    let store = FileStore::new(temp_dir.path()).await.unwrap();
+    let clock = Arc::new(ManualClock(AtomicU64::new(1_700_000_000_000)));
+    let mut db = Database::builder(DatabaseType::Merkle, Arc::new(store))
+        .clock(clock.clone())
+        .actor("auditor")
+        .build()
+        .await
+        .unwrap();
+
+    let insert = db
+        .execute_query(
+            Command::Insert {
+                key: "key".to_string(),
+                value: verify::hash_value_hex(b"value"),
+            },
+            false,
+        )
+        .await
+        .unwrap();
+    assert_eq!(
+        insert.context(),
+        Some(OperationContext {
+            unix_millis: 1_700_000_000_000,
+            actor: Some("auditor".to_string()),
+            signature: None,
+        })
+    );
+
+    // Reads commit no context.
+    let query = db
+        .execute_query(
+            Command::Query {
+                key: "key".to_string(),
+            },
+            false,
+        )
+        .await
+        .unwrap();
+    assert_eq!(query.context(), None);
+}
+
+/// A clock that moves on by a millisecond every time it is read.
+struct TickingClock(AtomicU64);
+
+impl Clock for TickingClock {
+    fn now_millis(&self) -> u64 {
+        self.0.fetch_add(1, Ordering::SeqCst)
+    }
+}
+
+#[tokio::test]
+async fn test_async_proof_is_of_the_committed_run() {
+    init();
+
+    let temp_dir = tempfile::tempdir().unwrap();
+    let store = FileStore::new(temp_dir.path()).await.unwrap();
+    let clock = Arc::new(TickingClock(AtomicU64::new(1_700_000_000_000)));
+    let mut db = Database::builder(DatabaseType::Merkle, Arc::new(store))
+        .clock(clock.clone())
+        .build()
+        .await
+        .unwrap();
+
+    let read_at = clock.0.load(Ordering::SeqCst);
+    let pending = db.put_async_proof("key", b"value").await.unwrap();
+    let proof = pending.await.unwrap();
+    let data = verify::decode_public_values(proof.proof_data.public_values.as_slice()).unwrap();
+    // The proof carries the context the committed insert ran under, not a
+    // fresh reading of the clock.
+    assert_eq!(data["context"]["unix_millis"], read_at);
+}
+
+#[tokio::test]
+async fn test_writer_key_authorizes_mutations() {
+    init();
+
+    let temp_dir = tempfile::tempdir().unwrap();
+    let store: Arc<dyn Store> = Arc::new(FileStore::new(temp_dir.path()).await.unwrap());
+    let secret_key = [7; 32];
+    let mut writer = Database::builder(DatabaseType::Merkle, store.clone())
+        .writer_key(secret_key)
+        .build()
+        .await
+        .unwrap();
+    let admin = ed25519_dalek::SigningKey::from_bytes(&secret_key)
+        .verifying_key()
+        .to_bytes();
+    let insert = writer
+        .execute_query(
+            Command::Insert {
+                key: "owned".to_string(),
+                value: verify::hash_value_hex(b"value"),
+            },
+            false,
+        )
+        .await
+        .unwrap();
+    assert_eq!(insert.signer(), Some(admin));
+    writer.put("signed", b"value", false).await.unwrap();
+
+    // Without the key, the state can be read but not written.
+    let mut reader = Database::builder(DatabaseType::Merkle, store.clone())
+        .state(writer.get_state().to_vec())
+        .build()
+        .await
+        .unwrap();
+    assert_eq!(reader.get("signed", false).await.unwrap(), b"value");
+    assert!(matches!(
+        reader.put("forged", b"value", false).await,
+        Err(DatabaseError::Unauthorized(_))
+    ));
+    assert!(matches!(
+        reader.delete("signed", false).await,
+        Err(DatabaseError::Unauthorized(_))
+    ));
+
+    // Another key is refused up front.
+    assert!(matches!(
+        Database::builder(DatabaseType::Merkle, store)
+            .state(writer.get_state().to_vec())
+            .writer_key([8; 32])
+            .build()
+            .await,
+        Err(DatabaseError::Unauthorized(_))
+    ));
+}
+
+#[tokio::test]
+async fn test_cloned_databases_diverge() {
+    init();
+
+    let temp_dir = tempfile::tempdir().unwrap();
+    let store = FileStore::new(temp_dir.path()).await.unwrap();
+    let mut original = Database::new(DatabaseType::Merkle, Arc::new(store), None)
+        .await
+        .unwrap();
+    for i in 0..5 {
+        let key = format!("shared{}", i);
+        original.put(&key, key.as_bytes(), false).await.unwrap();
+    }
+
+    let mut clone = original.clone();
+    assert_eq!(clone.get_state(), original.get_state());
+    assert_eq!(clone.vk_hash(), original.vk_hash());
+
+    for i in 0..2 {
+        let key = format!("original{}", i);
+        original.put(&key, key.as_bytes(), false).await.unwrap();
+        let key = format!("clone{}", i);
+        clone.put(&key, key.as_bytes(), false).await.unwrap();
+    }
+    assert_ne!(clone.get_state(), original.get_state());
+    assert_ne!(clone.current_root_hex(), original.current_root_hex());
+    assert_eq!(original.list_keys().unwrap().len(), 7);
+    assert!(original
+        .list_keys()
+        .unwrap()
+        .contains(&"original1".to_string()));
+    assert!(!clone
+        .list_keys()
+        .unwrap()
+        .contains(&"original1".to_string()));
+    assert!(clone.list_keys().unwrap().contains(&"clone1".to_string()));
+    assert_eq!(clone.get("shared4", false).await.unwrap(), b"shared4");
+}
+
+#[tokio::test]
+async fn test_apply_commands_runs_a_script() {
+    init();
+
+    let temp_dir = tempfile::tempdir().unwrap();
+    let store = FileStore::new(temp_dir.path()).await.unwrap();
+    let mut db = Database::new(DatabaseType::Merkle, Arc::new(store), None)
+        .await
+        .unwrap();
+    db.put("existing", b"existing", false).await.unwrap();
+
+    let insert = |key: &str| Command::Insert {
+        key: key.to_string(),
+        value: verify::hash_value_hex(key.as_bytes()),
+    };
+    let result = db
+        .apply_commands(
+            vec![
+                insert("first"),
+                insert("second"),
+                Command::Query {
+                    key: "first".to_string(),
+                },
+                Command::Delete {
+                    key: "existing".to_string(),
+                },
+            ],
+            false,
+        )
+        .await
+        .unwrap();
+    let results = result.as_script().unwrap();
+    assert_eq!(results.len(), 4);
+    assert_eq!(results[1].as_insert().unwrap().key, "second");
+    assert_eq!(
+        results[2].as_query().unwrap().value,
+        verify::hash_value_hex(b"first")
+    );
+    assert!(results[3].as_delete().unwrap().deleted);
+    // The state moved into the database rather than being copied.
+    assert!(result.new_state.is_empty());
+    let keys = db.list_keys().unwrap();
+    assert!(keys.contains(&"second".to_string()));
+    assert!(!keys.contains(&"existing".to_string()));
+
+    // A failing command aborts the whole script.
+    let state = db.get_state().to_vec();
+    let err = db
+        .apply_commands(
+            vec![
+                insert("third"),
+                Command::Delete {
+                    key: "missing".to_string(),
+                },
+                insert("fourth"),
+            ],
+            false,
+        )
+        .await
+        .unwrap_err();
+    match err {
+        DatabaseError::ScriptFailed { index, error } => {
+            assert_eq!(index, 1);
+            assert!(matches!(*error, DatabaseError::KeyNotFound(_)));
+        }
+        other => panic!("expected ScriptFailed, got {:?}", other),
+    }
+    assert_eq!(db.get_state(), state);
+
+    // Scripts nest, but only so deep.
+    let mut nested = insert("deep");
+    for _ in 0..zkdb_core::MAX_SCRIPT_DEPTH + 1 {
+        nested = Command::Script {
+            commands: vec![nested],
+        };
+    }
+    assert!(db.apply_commands(vec![nested], false).await.is_err());
+    assert_eq!(db.get_state(), state);
+}
+
+#[tokio::test]
+async fn test_partition_by_prefix() {
+    init();
+
+    let temp_dir = tempfile::tempdir().unwrap();
+    let store = FileStore::new(temp_dir.path()).await.unwrap();
+    let mut db = Database::new(DatabaseType::Merkle, Arc::new(store), None)
+        .await
+        .unwrap();
+    for i in 0..20 {
+        let key = match i % 4 {
+            0 | 1 => format!("user/{}", i),
+            2 => format!("tx/{}", i),
+            _ => format!("other/{}", i),
+        };
+        db.put(&key, key.as_bytes(), false).await.unwrap();
+    }
+
+    let partitions = db
+        .partition_by_prefix(&["user/", "tx/"], |_| Arc::new(MemStore::new()))
+        .await
+        .unwrap();
+    assert_eq!(partitions.len(), 2);
+    let users = &partitions["user/"];
+    assert_eq!(users.list_keys().unwrap().len(), 10);
+    assert_eq!(users.get("user/4", false).await.unwrap(), b"user/4");
+    let txs = &partitions["tx/"];
+    assert_eq!(txs.list_keys().unwrap().len(), 5);
+    assert_eq!(txs.get("tx/6", false).await.unwrap(), b"tx/6");
+
+    let keys = db.list_keys().unwrap();
+    assert_eq!(keys.len(), 5);
+    assert!(keys.iter().all(|key| key.starts_with("other/")));
+    assert!(matches!(
+        db.get("user/4", false).await,
+        Err(DatabaseError::KeyNotFound(_))
+    ));
+    assert_eq!(db.get("other/3", false).await.unwrap(), b"other/3");
+}
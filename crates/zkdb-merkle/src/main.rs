@@ -1,43 +1,58 @@
 //! A SP1 program for Merkle tree-based database operations.
 //!
-//! Supports `insert`, `query`, and `prove` commands.
-//! State is managed by passing the Merkle tree in and out as serialized data.
+//! Supports `insert`, `batch_insert`, `delete`, `batch_delete`, `query`,
+//! `multi_query`, `range`, `prove`, `history`, `inspect`, `get_root`,
+//! `multi_prove`, `prove_range`, `proof_size`, `clear`, `compact`,
+//! `root_at`, `prove_absence` and `replace` commands, against the root tree
+//! or, scoped with `in_tree`, a named one. `insert_bytes`, `query_bytes`,
+//! `delete_bytes` and `prove_bytes` take keys of arbitrary bytes, which
+//! output reports as strings when they are UTF-8 and hex-encoded otherwise.
+//! State is managed by passing the Merkle trees in and out as serialized data;
+//! a state that decodes but is inconsistent fails, see `MerkleState::validate`.
+//! Mutations write the state out in canonical form, see
+//! `MerkleState::canonical_bytes`, so that equal states chain as equal bytes.
+//! Every mutation records the tree's new root as its next version and
+//! reports it under `root`, with the `OperationContext` the host sent
+//! under `context`.
+//! Keys longer than `MAX_KEY_LEN` and states larger than `MAX_STATE_BYTES`
+//! are rejected, so proofs enforce the limits whatever the host allows.
+//! So are inserts of empty keys and of keys under the state's reserved
+//! prefix.
+//! Under `TreeLayout::Sorted`, leaves commit to their key and are kept in
+//! key order, so `range` pages and `prove_absence` come with the
+//! neighbouring leaves that show no key was left out; query output then
+//! reports a key's value hash under `value` and its keyed leaf under `leaf`.
+//! A state that records an admin key only accepts mutations signed by it,
+//! see `write_message`; the output of a signed mutation names the key under
+//! `signer`, and any other mutation fails with `Unauthorized`.
+//! A `script` runs its commands in order against the same state, reporting
+//! their outputs under `results`; the first to fail aborts it, and the
+//! output names the command under `index` and its error under `cause`.
+//! Under `KeyDerivation::Sha256`, commands name keys as given but the trees
+//! store their hashes, see `hashed_key`; output and errors report the keys
+//! as given, and `range`, `prove_range` and `prove_absence` fail.
 
 sp1_zkvm::entrypoint!(main);
 
 extern crate alloc;
 
+use alloc::boxed::Box;
 use alloc::collections::BTreeMap;
 use alloc::string::String;
 use alloc::string::ToString;
 use alloc::vec::Vec;
-use rs_merkle::proof_serializers;
-use rs_merkle::{algorithms::Sha256, MerkleTree};
-use serde::{Deserialize, Serialize};
+use core::ops::Bound;
+use ed25519_dalek::{Signature, VerifyingKey};
 use sp1_zkvm::io;
-use zkdb_core::{Command, DatabaseEngine, DatabaseError, QueryResult};
-
-/// Key-value pair type.
-type Key = String;
-// type Value = String;
-
-/// Serializable state of the Merkle tree.
-#[derive(Serialize, Deserialize)]
-struct MerkleState {
-    /// The list of leaves in the Merkle tree.
-    leaves: Vec<[u8; 32]>,
-    /// Map from keys to leaf indices.
-    key_indices: BTreeMap<Key, usize>,
-}
-
-impl MerkleState {
-    fn new() -> Self {
-        MerkleState {
-            leaves: Vec::new(),
-            key_indices: BTreeMap::new(),
-        }
-    }
-}
+use zkdb_core::backend::{RsMerkle, TreeBackend};
+use zkdb_core::merkle::{
+    hashed_key, keyed_leaf, KeyDerivation, MerkleState, TreeData, TreeLayout, ROOT_TREE,
+};
+use zkdb_core::{
+    decode_input, display_key, validate_key_bytes, write_message, Command, DatabaseEngine,
+    DatabaseError, HistoryEntry, OperationContext, OutputFormat, QueryResult, MAX_KEY_LEN,
+    MAX_SCRIPT_DEPTH, MAX_STATE_BYTES,
+};
 
 pub struct MerkleEngine;
 
@@ -47,124 +62,1026 @@ impl DatabaseEngine for MerkleEngine {
         state: &[u8],
         command: &Command,
     ) -> Result<QueryResult, DatabaseError> {
-        main_internal(state, command)
+        main_internal::<RsMerkle>(state, command, None)
     }
 }
 
 pub fn main() {
-    let state: Vec<u8> = io::read::<Vec<u8>>();
-    let command: Command = io::read::<Command>();
-
-    let result = main_internal(&state, &command).unwrap_or_else(|e| QueryResult {
-        data: serde_json::json!({
-            "error": {
-                "type": "QueryExecutionFailed",
-                "state_len": state.len(),
-                "details": format!("{:?}", e),
-            }
-        }),
-        new_state: state,
-    });
+    let state: Vec<u8> = io::read_vec();
+    let input: Vec<u8> = io::read_vec();
+    let format: OutputFormat = io::read::<OutputFormat>();
+    let context: Option<OperationContext> = io::read::<Option<OperationContext>>();
+
+    let (state, command) = match decode_input(state, &input) {
+        Ok(decoded) => decoded,
+        Err(e) => {
+            let result = QueryResult {
+                data: serde_json::json!({ "error": e.to_output(0) }),
+                new_state: Vec::new(),
+            };
+            sp1_zkvm::io::commit_slice(&result.encode(format));
+            return;
+        }
+    };
+
+    let signature = context
+        .as_ref()
+        .and_then(|context| context.signature.clone());
+    let result = main_internal::<RsMerkle>(&state, &command, signature.as_deref())
+        .map(|result| result.with_context(&command, context))
+        .unwrap_or_else(|e| QueryResult {
+            data: serde_json::json!({ "error": e.to_output(state.len()) }),
+            new_state: state,
+        });
 
-    let output = serde_json::to_vec(&result).expect("Failed to serialize output");
+    let output = result.encode(format);
     sp1_zkvm::io::commit_slice(&output);
 }
 
-fn main_internal(state: &[u8], command: &Command) -> Result<QueryResult, DatabaseError> {
+/// Runs `command` against `state`, building trees and proofs with `B`.
+///
+/// `signature` authorizes a mutation of a state that records an admin key.
+fn main_internal<B: TreeBackend>(
+    state: &[u8],
+    command: &Command,
+    signature: Option<&[u8]>,
+) -> Result<QueryResult, DatabaseError> {
     // if the state is empty, initialize it
-    let mut merkle_state: MerkleState = if state.is_empty() {
-        MerkleState::new()
+    let mut merkle_state = MerkleState::from_bytes(state)?;
+
+    let name = match command {
+        Command::InTree { tree, .. } => tree.as_str(),
+        _ => ROOT_TREE,
+    };
+    let signer = match merkle_state.admin_pubkey {
+        Some(admin) if command.is_mutating() => {
+            let prev_root = merkle_state
+                .tree(name)
+                .and_then(|tree| tree.compute_root_with::<B>())
+                .unwrap_or([0u8; 32]);
+            authorize(&admin, &prev_root, command, signature)?;
+            Some(admin)
+        }
+        _ => None,
+    };
+    let mut data = apply::<B>(&mut merkle_state, command, 0)?;
+    // Reads hand back the state they were given, without reserializing it.
+    let new_state = if command.is_mutating() {
+        if let Some(admin) = signer {
+            data["signer"] = serde_json::json!(hex::encode(admin));
+        }
+        let new_state = merkle_state.canonical_bytes();
+        DatabaseError::check_limit("state_bytes", MAX_STATE_BYTES, new_state.len())?;
+        new_state
     } else {
-        bincode::deserialize(state).map_err(|e| {
-            DatabaseError::QueryExecutionFailed(format!("Failed to deserialize state: {}", e))
-        })?
+        state.to_vec()
+    };
+    Ok(QueryResult { data, new_state })
+}
+
+/// Runs `command` against `merkle_state` in place, inside scripts nested
+/// `depth` deep, recording the new root of the tree a mutation changed.
+fn apply<B: TreeBackend>(
+    merkle_state: &mut MerkleState,
+    command: &Command,
+    depth: usize,
+) -> Result<serde_json::Value, DatabaseError> {
+    let (name, command) = match command {
+        Command::InTree { tree, command } => (tree.as_str(), command.as_ref()),
+        Command::Script { commands } => return script::<B>(merkle_state, commands, depth + 1),
+        command => (ROOT_TREE, command),
+    };
+    if merkle_state.key_derivation == KeyDerivation::Sha256 {
+        let (hashed, keys) = hash_keys(command, &merkle_state.reserved_prefix)?;
+        let restore = |key: &mut serde_json::Value| {
+            if let Some(given) = key.as_str().and_then(|stored| keys.get(stored)) {
+                *key = serde_json::json!(given);
+            }
+        };
+        let mut data = dispatch::<B>(merkle_state, name, &hashed).map_err(|e| match e {
+            DatabaseError::KeyNotFound(stored) => {
+                DatabaseError::KeyNotFound(keys.get(&stored).cloned().unwrap_or(stored))
+            }
+            e => e,
+        })?;
+        // Single-key output names it under `key`, `multi_query` and
+        // `multi_prove` under `key` of each entry, `batch_delete` under `keys`.
+        let entries = match &mut data {
+            serde_json::Value::Array(entries) => entries.iter_mut().collect(),
+            data => vec![data],
+        };
+        for entry in entries {
+            if let Some(key) = entry.get_mut("key") {
+                restore(key);
+            }
+            if let Some(serde_json::Value::Array(stored)) = entry.get_mut("keys") {
+                stored.iter_mut().for_each(restore);
+            }
+        }
+        return Ok(data);
+    }
+    dispatch::<B>(merkle_state, name, command)
+}
+
+/// Runs `command`, which holds no scope or script, against the tree named
+/// `name`, recording its new root if `command` changed it.
+fn dispatch<B: TreeBackend>(
+    merkle_state: &mut MerkleState,
+    name: &str,
+    command: &Command,
+) -> Result<serde_json::Value, DatabaseError> {
+    let reserved_prefix = merkle_state.reserved_prefix.clone();
+    let layout = merkle_state.layout;
+    let tree = merkle_state.tree_mut(name);
+    let mut data = match command {
+        Command::Insert { key, value } => {
+            insert(tree, layout, &reserved_prefix, key.as_bytes(), value)?
+        }
+        Command::Query { key } => query(tree, key.as_bytes())?,
+        Command::Prove { key } => prove::<B>(tree, key.as_bytes())?,
+        Command::History { key } => history(tree, key.as_bytes())?,
+        Command::Inspect { key } => inspect::<B>(tree, key.as_bytes())?,
+        Command::GetRoot => get_root::<B>(tree)?,
+        Command::MultiProve { keys } => multi_prove::<B>(tree, keys)?,
+        Command::BatchInsert { entries } => batch_insert(tree, layout, &reserved_prefix, entries)?,
+        Command::Delete { key } => delete(tree, layout, key.as_bytes())?,
+        Command::BatchDelete { keys } => batch_delete(tree, layout, keys)?,
+        Command::ProveRange { start, end } => prove_range::<B>(tree, start, end)?,
+        Command::ProofSize { key } => proof_size::<B>(tree, key.as_bytes())?,
+        Command::Clear => clear(merkle_state, name)?,
+        Command::Compact => compact(tree),
+        Command::RootAt { version } => root_at(tree, *version)?,
+        Command::InsertBytes { key, value } => insert(tree, layout, &reserved_prefix, key, value)?,
+        Command::QueryBytes { key } => query(tree, key)?,
+        Command::DeleteBytes { key } => delete(tree, layout, key)?,
+        Command::ProveBytes { key } => prove::<B>(tree, key)?,
+        Command::MultiQuery { keys } => multi_query(tree, keys),
+        Command::Range { start, end, limit } => range::<B>(tree, layout, start, end, *limit)?,
+        Command::ProveAbsence { key } => prove_absence::<B>(tree, layout, key)?,
+        Command::Replace { key, value } => {
+            replace(tree, layout, &reserved_prefix, key.as_bytes(), value)?
+        }
+        Command::InTree { .. } => {
+            return Err(DatabaseError::InvalidCommand(
+                "Tree scopes cannot be nested".to_string(),
+            ))
+        }
+        Command::Script { .. } => {
+            return Err(DatabaseError::InvalidCommand(
+                "Scripts cannot be scoped to a tree".to_string(),
+            ))
+        }
     };
+    if command.is_mutating() {
+        // A cleared tree is gone, and its versions with it.
+        if let Some(tree) = merkle_state.trees.get_mut(name) {
+            tree.record_root_with::<B>();
+            data["root"] = serde_json::json!(tree.cached_root.map(hex::encode));
+        }
+    }
+    Ok(data)
+}
 
-    let result = match command {
-        Command::Insert { key, value } => insert(&mut merkle_state, key.clone(), value.clone())?,
-        Command::Query { key } => query(&merkle_state, key)?,
-        Command::Prove { key } => prove(&merkle_state, key)?,
+/// `command` naming the keys its tree stores under `KeyDerivation::Sha256`
+/// instead of those it was given, with the key each stored key stands for.
+///
+/// Keys to insert are checked against `reserved_prefix` as given, since
+/// their hashes never fall under it.
+fn hash_keys(
+    command: &Command,
+    reserved_prefix: &str,
+) -> Result<(Command, BTreeMap<String, String>), DatabaseError> {
+    let mut keys = BTreeMap::new();
+    let mut hash = |key: &[u8]| {
+        let stored = hashed_key(key);
+        keys.insert(stored.clone(), display_key(key));
+        stored
+    };
+    let hashed = match command {
+        Command::Insert { key, value } => {
+            validate_key_bytes(key.as_bytes(), reserved_prefix)?;
+            Command::Insert {
+                key: hash(key.as_bytes()),
+                value: value.clone(),
+            }
+        }
+        Command::Replace { key, value } => {
+            validate_key_bytes(key.as_bytes(), reserved_prefix)?;
+            Command::Replace {
+                key: hash(key.as_bytes()),
+                value: value.clone(),
+            }
+        }
+        Command::InsertBytes { key, value } => {
+            validate_key_bytes(key, reserved_prefix)?;
+            Command::InsertBytes {
+                key: hash(key).into_bytes(),
+                value: value.clone(),
+            }
+        }
+        Command::BatchInsert { entries } => {
+            for (key, _) in entries {
+                validate_key_bytes(key.as_bytes(), reserved_prefix)?;
+            }
+            Command::BatchInsert {
+                entries: entries
+                    .iter()
+                    .map(|(key, value)| (hash(key.as_bytes()), value.clone()))
+                    .collect(),
+            }
+        }
+        Command::Query { key } => Command::Query {
+            key: hash(key.as_bytes()),
+        },
+        Command::Prove { key } => Command::Prove {
+            key: hash(key.as_bytes()),
+        },
+        Command::History { key } => Command::History {
+            key: hash(key.as_bytes()),
+        },
+        Command::Inspect { key } => Command::Inspect {
+            key: hash(key.as_bytes()),
+        },
+        Command::Delete { key } => Command::Delete {
+            key: hash(key.as_bytes()),
+        },
+        Command::ProofSize { key } => Command::ProofSize {
+            key: hash(key.as_bytes()),
+        },
+        Command::QueryBytes { key } => Command::QueryBytes {
+            key: hash(key).into_bytes(),
+        },
+        Command::DeleteBytes { key } => Command::DeleteBytes {
+            key: hash(key).into_bytes(),
+        },
+        Command::ProveBytes { key } => Command::ProveBytes {
+            key: hash(key).into_bytes(),
+        },
+        Command::MultiProve { keys } => Command::MultiProve {
+            keys: keys.iter().map(|key| hash(key.as_bytes())).collect(),
+        },
+        Command::MultiQuery { keys } => Command::MultiQuery {
+            keys: keys.iter().map(|key| hash(key.as_bytes())).collect(),
+        },
+        Command::BatchDelete { keys } => Command::BatchDelete {
+            keys: keys.iter().map(|key| hash(key.as_bytes())).collect(),
+        },
+        Command::Range { .. } | Command::ProveRange { .. } | Command::ProveAbsence { .. } => {
+            return Err(DatabaseError::InvalidCommand(format!(
+                "{} needs the key order, which hashed keys do not keep",
+                command.kind()
+            )))
+        }
+        Command::GetRoot
+        | Command::Clear
+        | Command::Compact
+        | Command::RootAt { .. }
+        | Command::InTree { .. }
+        | Command::Script { .. } => command.clone(),
     };
-    Ok(result)
+    Ok((hashed, keys))
+}
+
+/// Runs the commands of a script nested `depth` deep in order, reporting
+/// their outputs under `results`. The first to fail aborts the script with
+/// `ScriptFailed`, leaving `merkle_state` partly changed for the caller to
+/// drop.
+fn script<B: TreeBackend>(
+    merkle_state: &mut MerkleState,
+    commands: &[Command],
+    depth: usize,
+) -> Result<serde_json::Value, DatabaseError> {
+    DatabaseError::check_limit("script_depth", MAX_SCRIPT_DEPTH, depth)?;
+    let results = commands
+        .iter()
+        .enumerate()
+        .map(|(index, command)| {
+            apply::<B>(merkle_state, command, depth).map_err(|e| DatabaseError::ScriptFailed {
+                index: index as u64,
+                error: Box::new(e),
+            })
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(serde_json::json!({ "results": results }))
+}
+
+/// Checks that `signature` is the admin key's signature of `command` run
+/// against a tree whose root is `prev_root`, see `write_message`.
+fn authorize(
+    admin: &[u8; 32],
+    prev_root: &[u8; 32],
+    command: &Command,
+    signature: Option<&[u8]>,
+) -> Result<(), DatabaseError> {
+    let unauthorized = |reason: &str| DatabaseError::Unauthorized(reason.to_string());
+    let signature =
+        signature.ok_or_else(|| unauthorized("the state only accepts signed mutations"))?;
+    let key = VerifyingKey::from_bytes(admin)
+        .map_err(|_| unauthorized("the admin key is not a valid ed25519 key"))?;
+    let signature =
+        Signature::from_slice(signature).map_err(|_| unauthorized("the signature is malformed"))?;
+    key.verify_strict(&write_message(prev_root, command), &signature)
+        .map_err(|_| unauthorized("the signature is not by the admin key"))
+}
+
+/// Removes the tree named `name`, dropping every leaf, key and history entry.
+fn clear(state: &mut MerkleState, name: &str) -> Result<serde_json::Value, DatabaseError> {
+    let tree = state.trees.remove(name).unwrap_or_default();
+    Ok(serde_json::json!({
+        "cleared": true,
+        "keys_removed": tree.key_indices.len(),
+        "leaves_removed": tree.leaves.len(),
+    }))
+}
+
+/// Drops the leaves of the tree no key references.
+fn compact(tree: &mut TreeData) -> serde_json::Value {
+    let leaves_pruned = tree.compact();
+    serde_json::json!({
+        "leaves_pruned": leaves_pruned,
+        "leaf_count": tree.leaves.len(),
+    })
 }
 
 /// Inserts a new key-value pair into the Merkle tree.
 fn insert(
-    state: &mut MerkleState,
-    key: String,
-    value: String,
-) -> Result<QueryResult, DatabaseError> {
+    tree: &mut TreeData,
+    layout: TreeLayout,
+    reserved_prefix: &str,
+    key: &[u8],
+    value: &str,
+) -> Result<serde_json::Value, DatabaseError> {
+    let index = insert_leaf(tree, layout, reserved_prefix, key, value)?;
+
+    Ok(serde_json::json!({
+        "key": display_key(key),
+        "value": value,
+        "index": index,
+        "leaf": hex::encode(tree.leaves[index]),
+        "inserted": true,
+    }))
+}
+
+/// Inserts a key-value pair, reporting the leaf and value hash it replaced.
+fn replace(
+    tree: &mut TreeData,
+    layout: TreeLayout,
+    reserved_prefix: &str,
+    key: &[u8],
+    value: &str,
+) -> Result<serde_json::Value, DatabaseError> {
+    let old_leaf = tree
+        .key_indices
+        .get(key)
+        .map(|&index| hex::encode(tree.leaves[index]));
+    let old_value_hash = tree.value_hash(key).map(hex::encode);
+    let index = insert_leaf(tree, layout, reserved_prefix, key, value)?;
+
+    Ok(serde_json::json!({
+        "key": display_key(key),
+        "index": index,
+        "old_leaf": old_leaf,
+        "old_value_hash": old_value_hash,
+        "new_leaf": hex::encode(tree.leaves[index]),
+    }))
+}
+
+/// Inserts several key-value pairs in order, serializing the state once.
+///
+/// `first_index` is where the first new leaf goes under `TreeLayout::Append`;
+/// under `TreeLayout::Sorted` leaves land at the position of their key.
+fn batch_insert(
+    tree: &mut TreeData,
+    layout: TreeLayout,
+    reserved_prefix: &str,
+    entries: &[(String, String)],
+) -> Result<serde_json::Value, DatabaseError> {
+    let first_index = tree.leaves.len();
+    for (key, value) in entries {
+        insert_leaf(tree, layout, reserved_prefix, key.as_bytes(), value)?;
+    }
+
+    Ok(serde_json::json!({
+        "inserted": entries.len(),
+        "first_index": first_index,
+        "total_leaves": tree.leaves.len(),
+    }))
+}
+
+/// Writes the leaf of `key` holding the hex-encoded value hash `value` and
+/// returns its index.
+///
+/// Under `TreeLayout::Append` the value hash is appended as a new leaf.
+/// Under `TreeLayout::Sorted` the key's leaf is replaced in place, or a new
+/// one inserted at the key's rank, moving the leaves of later keys along.
+fn insert_leaf(
+    tree: &mut TreeData,
+    layout: TreeLayout,
+    reserved_prefix: &str,
+    key: &[u8],
+    value: &str,
+) -> Result<usize, DatabaseError> {
+    validate_key_bytes(key, reserved_prefix)?;
+    DatabaseError::check_limit("key_len", MAX_KEY_LEN, key.len())?;
+
     // Convert hex string back to bytes
-    let value_bytes = hex::decode(&value).map_err(|e| {
-        DatabaseError::QueryExecutionFailed(format!("Failed to decode hex value: {}", e))
-    })?;
+    let value_bytes = hex::decode(value)
+        .map_err(|e| DatabaseError::InvalidCommand(format!("Failed to decode hex value: {}", e)))?;
 
     // Convert to fixed size array for Merkle tree
-    let mut leaf = [0u8; 32];
-    leaf.copy_from_slice(&value_bytes);
+    let leaf = <[u8; 32]>::try_from(value_bytes).map_err(|bytes| {
+        DatabaseError::InvalidCommand(format!("Value hash must be 32 bytes, got {}", bytes.len()))
+    })?;
+
+    // Record the superseded leaf before overwriting the key.
+    let old_index = tree.key_indices.get(key).copied();
+    if let Some(old_index) = old_index {
+        record_history(tree, key, old_index);
+    }
+    tree.invalidate_root();
+
+    if layout == TreeLayout::Sorted {
+        tree.value_hashes.insert(key.to_vec(), leaf);
+        let keyed = keyed_leaf(key, &leaf);
+        if let Some(index) = old_index {
+            tree.leaves[index] = keyed;
+            return Ok(index);
+        }
+        let index = rank(tree, key);
+        tree.leaves.insert(index, keyed);
+        for later in tree.key_indices.values_mut() {
+            if *later >= index {
+                *later += 1;
+            }
+        }
+        tree.key_indices.insert(key.to_vec(), index);
+        return Ok(index);
+    }
 
     // Insert into the tree
-    state.leaves.push(leaf);
-    let index = state.leaves.len() - 1;
-    state.key_indices.insert(key.clone(), index);
-
-    Ok(QueryResult {
-        data: serde_json::json!({
-            "key": key.clone(),
-            "value": value.clone(),
-            "index": index,
-            "leaf": value.clone(),
-            "inserted": true,
-        }),
-        new_state: bincode::serialize(&state).unwrap(),
-    })
+    tree.leaves.push(leaf);
+    let index = tree.leaves.len() - 1;
+    tree.key_indices.insert(key.to_vec(), index);
+    Ok(index)
+}
+
+/// Records the value `key` holds at leaf `index` in the key's history.
+fn record_history(tree: &mut TreeData, key: &[u8], index: usize) {
+    let value_hash = tree.value_hash(key).unwrap_or(tree.leaves[index]);
+    let entry = HistoryEntry {
+        // The overwriting command records the next version once it is done.
+        timestamp: tree.latest_version() + 1,
+        value_hash: hex::encode(value_hash),
+        leaf_index: index,
+    };
+    tree.history.entry(key.to_vec()).or_default().push(entry);
+}
+
+/// Removes a key from the tree.
+///
+/// Under `TreeLayout::Append` the leaf is overwritten with zeros rather than
+/// removed so that the indices of other keys stay valid. Under
+/// `TreeLayout::Sorted` it is removed, moving the leaves of later keys back.
+/// The deleted value is kept in the key's history.
+fn delete(
+    tree: &mut TreeData,
+    layout: TreeLayout,
+    key: &[u8],
+) -> Result<serde_json::Value, DatabaseError> {
+    let index = delete_leaf(tree, layout, key)?;
+
+    Ok(serde_json::json!({
+        "key": display_key(key),
+        "index": index,
+        "deleted": true,
+    }))
+}
+
+/// Removes several keys in order, serializing the state once.
+///
+/// Fails without removing any key if one of them is not in the tree.
+fn batch_delete(
+    tree: &mut TreeData,
+    layout: TreeLayout,
+    keys: &[String],
+) -> Result<serde_json::Value, DatabaseError> {
+    for key in keys {
+        delete_leaf(tree, layout, key.as_bytes())?;
+    }
+
+    Ok(serde_json::json!({
+        "deleted": keys.len(),
+        "keys": keys,
+    }))
+}
+
+/// Zeroes or, under `TreeLayout::Sorted`, removes the leaf of `key`,
+/// recording it in the key's history, and returns its index.
+fn delete_leaf(
+    tree: &mut TreeData,
+    layout: TreeLayout,
+    key: &[u8],
+) -> Result<usize, DatabaseError> {
+    let index = *tree
+        .key_indices
+        .get(key)
+        .ok_or_else(|| DatabaseError::KeyNotFound(display_key(key)))?;
+    record_history(tree, key, index);
+    tree.key_indices.remove(key);
+    tree.value_hashes.remove(key);
+    tree.invalidate_root();
+    if layout == TreeLayout::Sorted {
+        tree.leaves.remove(index);
+        for later in tree.key_indices.values_mut() {
+            if *later > index {
+                *later -= 1;
+            }
+        }
+    } else {
+        tree.leaves[index] = [0u8; 32];
+    }
+    Ok(index)
 }
 
 /// Queries the value associated with a key.
-fn query(state: &MerkleState, key: &str) -> Result<QueryResult, DatabaseError> {
-    if let Some(&index) = state.key_indices.get(key) {
-        let value_hash = &state.leaves[index];
-        Ok(QueryResult {
-            data: serde_json::json!({
-                "key": key.to_string(),
-                "value": hex::encode(value_hash),
-                "index": index,
-                "leaf": hex::encode(value_hash),
+fn query(tree: &TreeData, key: &[u8]) -> Result<serde_json::Value, DatabaseError> {
+    if let Some(&index) = tree.key_indices.get(key) {
+        Ok(serde_json::json!({
+            "key": display_key(key),
+            "value": tree.value_hash(key).map(hex::encode),
+            "index": index,
+            "leaf": hex::encode(tree.leaves[index]),
+            "found": true,
+        }))
+    } else {
+        Err(DatabaseError::KeyNotFound(display_key(key)))
+    }
+}
+
+/// Looks up several keys, reporting for each whether the tree holds it and,
+/// if so, its leaf and index.
+fn multi_query(tree: &TreeData, keys: &[String]) -> serde_json::Value {
+    let results: Vec<_> = keys
+        .iter()
+        .map(|key| match tree.key_indices.get(key.as_bytes()) {
+            Some(&index) => serde_json::json!({
+                "key": key,
                 "found": true,
+                "value": tree.value_hash(key.as_bytes()).map(hex::encode),
+                "index": index,
+            }),
+            None => serde_json::json!({
+                "key": key,
+                "found": false,
+                "value": null,
+                "index": null,
             }),
-            new_state: bincode::serialize(&state).unwrap(),
         })
-    } else {
-        Err(DatabaseError::QueryExecutionFailed(
-            "Key not found".to_string(),
-        ))
-    }
+        .collect();
+    serde_json::json!(results)
 }
 
 /// Generates a Merkle Inclusion Proof for a given key.
-fn prove(state: &MerkleState, key: &str) -> Result<QueryResult, DatabaseError> {
-    if let Some(&index) = state.key_indices.get(key) {
-        let merkle_tree = MerkleTree::<Sha256>::from_leaves(&state.leaves);
-        let proof = merkle_tree.proof(&[index]);
+fn prove<B: TreeBackend>(tree: &TreeData, key: &[u8]) -> Result<serde_json::Value, DatabaseError> {
+    // No key can be proven against a tree without leaves.
+    if tree.leaves.is_empty() {
+        return Err(DatabaseError::EmptyTree);
+    }
+    if let Some(&index) = tree.key_indices.get(key) {
+        let merkle_tree = B::from_leaves(&tree.leaves);
         let root = merkle_tree
             .root()
             .ok_or_else(|| DatabaseError::QueryExecutionFailed("Tree is empty".to_string()))?;
+        let proof_encoded = base64::encode(merkle_tree.prove(&[index]));
+
+        Ok(serde_json::json!({
+            "key": display_key(key),
+            "root": hex::encode(root),
+            "proof": proof_encoded,
+            "index": index,
+            "leaf": hex::encode(tree.leaves[index]),
+            "value_hash": tree.value_hash(key).map(hex::encode),
+            "total_leaves": tree.leaves.len(),
+        }))
+    } else {
+        Err(DatabaseError::KeyNotFound(display_key(key)))
+    }
+}
+
+/// Reports the size of the proof `prove` would produce for a key.
+///
+/// Walks the layer sizes instead of building the tree: a node has a sibling
+/// in the proof unless it is the odd node out of its layer and is promoted.
+fn proof_size<B: TreeBackend>(
+    tree: &TreeData,
+    key: &[u8],
+) -> Result<serde_json::Value, DatabaseError> {
+    if tree.leaves.is_empty() {
+        return Err(DatabaseError::EmptyTree);
+    }
+    let index = *tree
+        .key_indices
+        .get(key)
+        .ok_or_else(|| DatabaseError::KeyNotFound(display_key(key)))?;
+
+    let mut sibling_count = 0;
+    let mut layer_len = tree.leaves.len();
+    let mut position = index;
+    while layer_len > 1 {
+        if position ^ 1 < layer_len {
+            sibling_count += 1;
+        }
+        layer_len = layer_len.div_ceil(2);
+        position /= 2;
+    }
+
+    Ok(serde_json::json!({
+        "key": display_key(key),
+        "kind": "single",
+        "serializer": "ReverseHashesOrder",
+        "sibling_count": sibling_count,
+        "bytes": sibling_count * B::HASH_SIZE,
+    }))
+}
+
+/// Returns the superseded values of a key, oldest first.
+fn history(tree: &TreeData, key: &[u8]) -> Result<serde_json::Value, DatabaseError> {
+    if !tree.key_indices.contains_key(key) {
+        return Err(DatabaseError::KeyNotFound(display_key(key)));
+    }
+    let entries = tree.history.get(key).cloned().unwrap_or_default();
+    Ok(serde_json::json!({
+        "key": display_key(key),
+        "history": entries,
+    }))
+}
+
+/// Describes the position of a key's leaf in the tree.
+///
+/// `sibling_hashes` holds the sibling at each level from the leaf up, or null
+/// where the node has no sibling and is promoted unchanged. `path_to_root`
+/// holds the node on the leaf's path at each level, ending with the root.
+fn inspect<B: TreeBackend>(
+    tree: &TreeData,
+    key: &[u8],
+) -> Result<serde_json::Value, DatabaseError> {
+    let index = *tree
+        .key_indices
+        .get(key)
+        .ok_or_else(|| DatabaseError::KeyNotFound(display_key(key)))?;
+
+    let mut sibling_hashes = Vec::new();
+    let mut path_to_root = vec![hex::encode(tree.leaves[index])];
+    for (sibling, parent) in B::from_leaves(&tree.leaves).path(index) {
+        sibling_hashes.push(sibling.map(hex::encode));
+        path_to_root.push(hex::encode(parent));
+    }
+
+    Ok(serde_json::json!({
+        "key": display_key(key),
+        "leaf_index": index,
+        "leaf_hex": hex::encode(tree.leaves[index]),
+        "sibling_hashes": sibling_hashes,
+        "path_to_root": path_to_root,
+    }))
+}
+
+/// Returns the root of the tree, or null when it is empty.
+fn get_root<B: TreeBackend>(tree: &TreeData) -> Result<serde_json::Value, DatabaseError> {
+    Ok(serde_json::json!({
+        // The cached root comes from the host, so it is not trusted here.
+        "root": tree.compute_root_with::<B>().map(hex::encode),
+        "leaf_count": tree.leaves.len(),
+    }))
+}
+
+/// Returns the root the tree had after its `version`th mutation.
+fn root_at(tree: &TreeData, version: u64) -> Result<serde_json::Value, DatabaseError> {
+    let root = tree
+        .root_at(version)
+        .ok_or(DatabaseError::UnknownVersion(version))?;
+    Ok(serde_json::json!({
+        "version": version,
+        "root": hex::encode(root),
+        "latest_version": tree.latest_version(),
+    }))
+}
+
+/// Generates inclusion proofs for several keys from a single tree build.
+///
+/// Returns a multiproof over all requested leaves along with a standalone
+/// proof for each key.
+fn multi_prove<B: TreeBackend>(
+    tree: &TreeData,
+    keys: &[String],
+) -> Result<serde_json::Value, DatabaseError> {
+    if tree.leaves.is_empty() {
+        return Err(DatabaseError::EmptyTree);
+    }
+    if keys.is_empty() {
+        return Err(DatabaseError::InvalidCommand(
+            "MultiProve requires at least one key".to_string(),
+        ));
+    }
+
+    let mut key_indices = Vec::with_capacity(keys.len());
+    for key in keys {
+        let index = *tree
+            .key_indices
+            .get(key.as_bytes())
+            .ok_or_else(|| DatabaseError::KeyNotFound(key.clone()))?;
+        key_indices.push((key, index));
+    }
 
-        let proof_serialized: Vec<u8> = proof.serialize::<proof_serializers::ReverseHashesOrder>();
-        let proof_encoded = base64::encode(proof_serialized);
+    let merkle_tree = B::from_leaves(&tree.leaves);
+    let root = merkle_tree
+        .root()
+        .ok_or_else(|| DatabaseError::QueryExecutionFailed("Tree is empty".to_string()))?;
 
-        Ok(QueryResult {
-            data: serde_json::json!({
-                "root": hex::encode(root),
-                "proof": proof_encoded,
+    let proofs: Vec<_> = key_indices
+        .iter()
+        .map(|(key, index)| {
+            serde_json::json!({
+                "key": key,
                 "index": index,
-                "leaf": hex::encode(state.leaves[index]),
-            }),
-            new_state: bincode::serialize(&state).unwrap(),
+                "leaf": hex::encode(tree.leaves[*index]),
+                "proof": base64::encode(merkle_tree.prove(&[*index])),
+            })
         })
-    } else {
-        Err(DatabaseError::QueryExecutionFailed(
-            "Key not found".to_string(),
+        .collect();
+
+    let mut data = multiproof_json(
+        tree,
+        &merkle_tree,
+        key_indices.iter().map(|(_, index)| *index).collect(),
+    );
+    data["root"] = serde_json::json!(hex::encode(root));
+    data["proofs"] = serde_json::json!(proofs);
+
+    Ok(data)
+}
+
+/// Proves every key in `[start, end)` with a single multiproof.
+///
+/// `keys` lists the covered keys in key order with their leaves, while
+/// `indices` and `leaves` follow the index order the multiproof expects.
+fn prove_range<B: TreeBackend>(
+    tree: &TreeData,
+    start: &str,
+    end: &str,
+) -> Result<serde_json::Value, DatabaseError> {
+    if tree.leaves.is_empty() {
+        return Err(DatabaseError::EmptyTree);
+    }
+    if start >= end {
+        return Err(DatabaseError::InvalidCommand(format!(
+            "Invalid range [{}, {})",
+            start, end
+        )));
+    }
+    let range: Vec<(&Vec<u8>, usize)> = tree
+        .key_indices
+        .range::<[u8], _>((
+            Bound::Included(start.as_bytes()),
+            Bound::Excluded(end.as_bytes()),
         ))
+        .map(|(key, index)| (key, *index))
+        .collect();
+    if range.is_empty() {
+        return Err(DatabaseError::QueryExecutionFailed(format!(
+            "No keys in range [{}, {})",
+            start, end
+        )));
+    }
+
+    let merkle_tree = B::from_leaves(&tree.leaves);
+    let root = merkle_tree
+        .root()
+        .ok_or_else(|| DatabaseError::QueryExecutionFailed("Tree is empty".to_string()))?;
+
+    let keys: Vec<_> = range
+        .iter()
+        .map(|(key, index)| {
+            serde_json::json!({
+                "key": display_key(key),
+                "index": index,
+                "leaf": hex::encode(tree.leaves[*index]),
+            })
+        })
+        .collect();
+    let mut data = multiproof_json(
+        tree,
+        &merkle_tree,
+        range.iter().map(|(_, index)| *index).collect(),
+    );
+    data["root"] = serde_json::json!(hex::encode(root));
+    data["keys"] = serde_json::json!(keys);
+
+    Ok(data)
+}
+
+/// Lists up to `limit` keys in `[start, end)` with a multiproof over their
+/// leaves.
+///
+/// `keys` lists them in key order, `binary` marking those reported
+/// hex-encoded, and `next` is the first key left out, to start the next
+/// page from. Under `TreeLayout::Append` leaves are laid out in insertion
+/// order, so the multiproof shows neither that they belong to those keys
+/// nor that no key in the range was left out; only a proof of the
+/// execution itself covers the whole page.
+///
+/// Under `TreeLayout::Sorted` the page is a run of adjacent leaves, each
+/// key listed with its `value_hash`, and the multiproof also covers the
+/// leaves of the keys just `before` and `after` it, so that it proves no
+/// key between them was left out. `complete` is then true.
+fn range<B: TreeBackend>(
+    tree: &TreeData,
+    layout: TreeLayout,
+    start: &str,
+    end: &str,
+    limit: usize,
+) -> Result<serde_json::Value, DatabaseError> {
+    if start >= end {
+        return Err(DatabaseError::InvalidCommand(format!(
+            "Invalid range [{}, {})",
+            start, end
+        )));
+    }
+    if limit == 0 {
+        return Err(DatabaseError::InvalidCommand(
+            "Range limit must be at least 1".to_string(),
+        ));
+    }
+    let mut matched = tree
+        .key_indices
+        .range::<[u8], _>((
+            Bound::Included(start.as_bytes()),
+            Bound::Excluded(end.as_bytes()),
+        ))
+        .map(|(key, index)| (key, *index));
+    let page: Vec<(&Vec<u8>, usize)> = matched.by_ref().take(limit).collect();
+    let next = matched.next().map(|(key, _)| display_key(key));
+    if layout == TreeLayout::Sorted {
+        return sorted_range::<B>(tree, start.as_bytes(), &page, next);
+    }
+    if page.is_empty() {
+        return Ok(serde_json::json!({
+            "keys": [],
+            "next": null,
+        }));
+    }
+
+    let merkle_tree = B::from_leaves(&tree.leaves);
+    let root = merkle_tree
+        .root()
+        .ok_or_else(|| DatabaseError::QueryExecutionFailed("Tree is empty".to_string()))?;
+
+    let keys: Vec<_> = page
+        .iter()
+        .map(|(key, index)| {
+            serde_json::json!({
+                "key": display_key(key),
+                "binary": core::str::from_utf8(key).is_err(),
+                "index": index,
+                "leaf": hex::encode(tree.leaves[*index]),
+            })
+        })
+        .collect();
+    let mut data = multiproof_json(
+        tree,
+        &merkle_tree,
+        page.iter().map(|(_, index)| *index).collect(),
+    );
+    data["root"] = serde_json::json!(hex::encode(root));
+    data["keys"] = serde_json::json!(keys);
+    data["next"] = serde_json::json!(next);
+
+    Ok(data)
+}
+
+/// `range` under `TreeLayout::Sorted`, for a page of adjacent leaves. An
+/// empty page is the gap where keys from `start` would go.
+fn sorted_range<B: TreeBackend>(
+    tree: &TreeData,
+    start: &[u8],
+    page: &[(&Vec<u8>, usize)],
+    next: Option<String>,
+) -> Result<serde_json::Value, DatabaseError> {
+    let (first, past) = match (page.first(), page.last()) {
+        (Some((_, first)), Some((_, last))) => (*first, last + 1),
+        _ => {
+            let rank = rank(tree, start);
+            (rank, rank)
+        }
+    };
+    let keys: Vec<_> = page
+        .iter()
+        .map(|(key, index)| sorted_entry(tree, key, *index))
+        .collect();
+    let mut data = neighbours_json::<B>(tree, first, past);
+    data["keys"] = serde_json::json!(keys);
+    data["next"] = serde_json::json!(next);
+    data["complete"] = serde_json::json!(true);
+    Ok(data)
+}
+
+/// Proves that `key` is not in the tree, which must be laid out as
+/// `TreeLayout::Sorted`.
+///
+/// Reports the keys just `before` and `after` the place `key` would take,
+/// with a multiproof over their leaves showing they are adjacent. Either is
+/// null at an end of the tree, and both when it is empty.
+fn prove_absence<B: TreeBackend>(
+    tree: &TreeData,
+    layout: TreeLayout,
+    key: &str,
+) -> Result<serde_json::Value, DatabaseError> {
+    if layout != TreeLayout::Sorted {
+        return Err(DatabaseError::InvalidCommand(
+            "Absence can only be proven under the sorted layout".to_string(),
+        ));
     }
+    if tree.key_indices.contains_key(key.as_bytes()) {
+        return Err(DatabaseError::QueryExecutionFailed(format!(
+            "Key {} is present",
+            key
+        )));
+    }
+    let rank = rank(tree, key.as_bytes());
+    let mut data = neighbours_json::<B>(tree, rank, rank);
+    data["key"] = serde_json::json!(key);
+    Ok(data)
+}
+
+/// Number of keys of the tree below `key`: the index its leaf has, or
+/// would take, in a sorted tree.
+fn rank(tree: &TreeData, key: &[u8]) -> usize {
+    tree.key_indices
+        .range::<[u8], _>((Bound::Unbounded, Bound::Excluded(key)))
+        .count()
+}
+
+/// Describes the key at leaf `index` of a sorted tree, with what a verifier
+/// needs to recompute its leaf.
+fn sorted_entry(tree: &TreeData, key: &[u8], index: usize) -> serde_json::Value {
+    serde_json::json!({
+        "key": display_key(key),
+        "binary": core::str::from_utf8(key).is_err(),
+        "index": index,
+        "leaf": hex::encode(tree.leaves[index]),
+        "value_hash": tree.value_hash(key).map(hex::encode),
+    })
+}
+
+/// Reports the keys at leaves `first - 1` and `past`, just outside the
+/// leaves `[first, past)` of a sorted tree, under `before` and `after`,
+/// with a multiproof over all of them and the root.
+fn neighbours_json<B: TreeBackend>(
+    tree: &TreeData,
+    first: usize,
+    past: usize,
+) -> serde_json::Value {
+    let Some(root) = tree.compute_root_with::<B>() else {
+        return serde_json::json!({
+            "before": null,
+            "after": null,
+            "root": null,
+            "total_leaves": 0,
+        });
+    };
+    let keys: Vec<&Vec<u8>> = tree.key_indices.keys().collect();
+    let before = first.checked_sub(1);
+    let after = Some(past).filter(|&index| index < tree.leaves.len());
+    let describe = |index: Option<usize>| index.map(|index| sorted_entry(tree, keys[index], index));
+
+    let merkle_tree = B::from_leaves(&tree.leaves);
+    let indices = before.into_iter().chain(first..past).chain(after).collect();
+    let mut data = multiproof_json(tree, &merkle_tree, indices);
+    data["root"] = serde_json::json!(hex::encode(root));
+    data["before"] = serde_json::json!(describe(before));
+    data["after"] = serde_json::json!(describe(after));
+    data
+}
+
+/// Serializes a multiproof over `indices` along with the leaves it covers.
+///
+/// The multiproof covers each distinct leaf once, in ascending index order.
+fn multiproof_json<B: TreeBackend>(
+    tree: &TreeData,
+    merkle_tree: &B,
+    mut indices: Vec<usize>,
+) -> serde_json::Value {
+    indices.sort_unstable();
+    indices.dedup();
+    let leaves: Vec<String> = indices
+        .iter()
+        .map(|index| hex::encode(tree.leaves[*index]))
+        .collect();
+
+    serde_json::json!({
+        "total_leaves": tree.leaves.len(),
+        "indices": indices,
+        "leaves": leaves,
+        "multiproof": base64::encode(merkle_tree.prove(&indices)),
+    })
 }
@@ -0,0 +1,289 @@
+//! A SP1 program for sparse Merkle tree-based database operations.
+//!
+//! Supports `insert`, `replace`, `batch_insert`, `delete`, `batch_delete`,
+//! `query`, `multi_query`, `prove`, `prove_absence`, `proof_size` and
+//! `get_root` commands, and the `_bytes` forms of those taking a single key, against
+//! one tree keyed by the SHA-256 of keys. Other commands fail.
+//! Leaves are not numbered, so query and proof output reports a key's
+//! `path` in place of an index. Proofs hold a bitmap of the siblings that
+//! are not empty followed by those siblings, base64-encoded, and prove a
+//! key present or absent alike.
+//! Every mutation reports the tree's new root under `root`, and the
+//! `OperationContext` the host sent under `context`.
+//! Keys longer than `MAX_KEY_LEN` and states larger than `MAX_STATE_BYTES`
+//! are rejected, as are inserts of empty keys and of keys under the state's
+//! reserved prefix.
+
+sp1_zkvm::entrypoint!(main);
+
+extern crate alloc;
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use sp1_zkvm::io;
+use zkdb_core::smt::{key_path, SmtState, EMPTY};
+use zkdb_core::{
+    decode_input, display_key, validate_key_bytes, Command, DatabaseEngine, DatabaseError,
+    OperationContext, OutputFormat, QueryResult, MAX_KEY_LEN, MAX_STATE_BYTES,
+};
+
+pub struct SmtEngine;
+
+impl DatabaseEngine for SmtEngine {
+    fn execute_query(
+        &mut self,
+        state: &[u8],
+        command: &Command,
+    ) -> Result<QueryResult, DatabaseError> {
+        main_internal(state, command)
+    }
+}
+
+pub fn main() {
+    let state: Vec<u8> = io::read_vec();
+    let input: Vec<u8> = io::read_vec();
+    let format: OutputFormat = io::read::<OutputFormat>();
+    let context: Option<OperationContext> = io::read::<Option<OperationContext>>();
+
+    let (state, command) = match decode_input(state, &input) {
+        Ok(decoded) => decoded,
+        Err(e) => {
+            let result = QueryResult {
+                data: serde_json::json!({ "error": e.to_output(0) }),
+                new_state: Vec::new(),
+            };
+            sp1_zkvm::io::commit_slice(&result.encode(format));
+            return;
+        }
+    };
+
+    let result = main_internal(&state, &command)
+        .map(|result| result.with_context(&command, context))
+        .unwrap_or_else(|e| QueryResult {
+            data: serde_json::json!({ "error": e.to_output(state.len()) }),
+            new_state: state,
+        });
+
+    let output = result.encode(format);
+    sp1_zkvm::io::commit_slice(&output);
+}
+
+/// Runs `command` against `state`.
+fn main_internal(state: &[u8], command: &Command) -> Result<QueryResult, DatabaseError> {
+    let mut smt = SmtState::from_bytes(state)?;
+    let mut data = match command {
+        Command::Insert { key, value } => insert(&mut smt, key.as_bytes(), value)?,
+        Command::InsertBytes { key, value } => insert(&mut smt, key, value)?,
+        Command::Replace { key, value } => replace(&mut smt, key.as_bytes(), value)?,
+        Command::BatchInsert { entries } => batch_insert(&mut smt, entries)?,
+        Command::Delete { key } => delete(&mut smt, key.as_bytes())?,
+        Command::DeleteBytes { key } => delete(&mut smt, key)?,
+        Command::BatchDelete { keys } => batch_delete(&mut smt, keys)?,
+        Command::Query { key } => query(&smt, key.as_bytes())?,
+        Command::QueryBytes { key } => query(&smt, key)?,
+        Command::MultiQuery { keys } => multi_query(&smt, keys),
+        Command::Prove { key } => prove(&smt, key.as_bytes())?,
+        Command::ProveBytes { key } => prove(&smt, key)?,
+        Command::ProveAbsence { key } => prove_absence(&smt, key.as_bytes())?,
+        Command::ProofSize { key } => proof_size(&smt, key.as_bytes()),
+        Command::GetRoot => get_root(&smt),
+        command => {
+            return Err(DatabaseError::InvalidCommand(format!(
+                "{} is not supported by the sparse Merkle engine",
+                command.kind()
+            )))
+        }
+    };
+    // Reads hand back the state they were given, without reserializing it.
+    let new_state = if command.is_mutating() {
+        data["root"] = root_json(&smt);
+        let new_state = smt.to_bytes();
+        DatabaseError::check_limit("state_bytes", MAX_STATE_BYTES, new_state.len())?;
+        new_state
+    } else {
+        state.to_vec()
+    };
+    Ok(QueryResult { data, new_state })
+}
+
+/// Hex-encoded root, null for an empty tree.
+fn root_json(smt: &SmtState) -> serde_json::Value {
+    serde_json::json!((!smt.is_empty()).then(|| hex::encode(smt.root())))
+}
+
+/// Sets the value hash of a key, given hex-encoded.
+fn insert(smt: &mut SmtState, key: &[u8], value: &str) -> Result<serde_json::Value, DatabaseError> {
+    let replaced = insert_leaf(smt, key, value)?;
+    let path = key_path(key);
+    Ok(serde_json::json!({
+        "key": display_key(key),
+        "value": value,
+        "path": hex::encode(path),
+        "leaf": hex::encode(smt.leaf(&path)),
+        "inserted": true,
+        "replaced": replaced,
+    }))
+}
+
+/// Sets the value hash of a key, reporting the leaf and value hash it
+/// replaced.
+fn replace(
+    smt: &mut SmtState,
+    key: &[u8],
+    value: &str,
+) -> Result<serde_json::Value, DatabaseError> {
+    let path = key_path(key);
+    let old_leaf = smt
+        .values
+        .contains_key(&path)
+        .then(|| hex::encode(smt.leaf(&path)));
+    let old_value_hash = smt.get(key).map(hex::encode);
+    insert_leaf(smt, key, value)?;
+    Ok(serde_json::json!({
+        "key": display_key(key),
+        "path": hex::encode(path),
+        "old_leaf": old_leaf,
+        "old_value_hash": old_value_hash,
+        "new_leaf": hex::encode(smt.leaf(&path)),
+    }))
+}
+
+/// Inserts several key-value pairs in order, serializing the state once.
+fn batch_insert(
+    smt: &mut SmtState,
+    entries: &[(String, String)],
+) -> Result<serde_json::Value, DatabaseError> {
+    for (key, value) in entries {
+        insert_leaf(smt, key.as_bytes(), value)?;
+    }
+    Ok(serde_json::json!({
+        "inserted": entries.len(),
+        "total_keys": smt.values.len(),
+    }))
+}
+
+/// Writes the leaf of `key` holding the hex-encoded value hash `value` and
+/// returns whether it replaced one.
+fn insert_leaf(smt: &mut SmtState, key: &[u8], value: &str) -> Result<bool, DatabaseError> {
+    validate_key_bytes(key, &smt.reserved_prefix)?;
+    DatabaseError::check_limit("key_len", MAX_KEY_LEN, key.len())?;
+    let value_hash: [u8; 32] = hex::decode(value)
+        .ok()
+        .and_then(|bytes| bytes.try_into().ok())
+        .ok_or_else(|| {
+            DatabaseError::InvalidCommand(format!(
+                "Value {} is not a hex-encoded 32-byte hash",
+                value
+            ))
+        })?;
+    Ok(smt.insert(key, value_hash).is_some())
+}
+
+/// Removes a key, emptying its leaf.
+fn delete(smt: &mut SmtState, key: &[u8]) -> Result<serde_json::Value, DatabaseError> {
+    smt.remove(key)
+        .ok_or_else(|| DatabaseError::KeyNotFound(display_key(key)))?;
+    Ok(serde_json::json!({
+        "key": display_key(key),
+        "path": hex::encode(key_path(key)),
+        "deleted": true,
+    }))
+}
+
+/// Removes several keys in order, failing on the first missing one.
+fn batch_delete(smt: &mut SmtState, keys: &[String]) -> Result<serde_json::Value, DatabaseError> {
+    for key in keys {
+        smt.remove(key.as_bytes())
+            .ok_or_else(|| DatabaseError::KeyNotFound(key.clone()))?;
+    }
+    Ok(serde_json::json!({
+        "deleted": keys.len(),
+        "total_keys": smt.values.len(),
+    }))
+}
+
+/// Queries the value hash of a key.
+fn query(smt: &SmtState, key: &[u8]) -> Result<serde_json::Value, DatabaseError> {
+    let value_hash = smt
+        .get(key)
+        .ok_or_else(|| DatabaseError::KeyNotFound(display_key(key)))?;
+    let path = key_path(key);
+    Ok(serde_json::json!({
+        "key": display_key(key),
+        "value": hex::encode(value_hash),
+        "path": hex::encode(path),
+        "leaf": hex::encode(smt.leaf(&path)),
+        "found": true,
+    }))
+}
+
+/// Looks up several keys, reporting for each whether the tree holds it and,
+/// if so, its value hash.
+fn multi_query(smt: &SmtState, keys: &[String]) -> serde_json::Value {
+    let results: Vec<_> = keys
+        .iter()
+        .map(|key| {
+            let value_hash = smt.get(key.as_bytes());
+            serde_json::json!({
+                "key": key,
+                "found": value_hash.is_some(),
+                "value": value_hash.map(hex::encode),
+                "index": null,
+            })
+        })
+        .collect();
+    serde_json::json!(results)
+}
+
+/// Proves the value hash of a key the tree holds.
+fn prove(smt: &SmtState, key: &[u8]) -> Result<serde_json::Value, DatabaseError> {
+    if smt.is_empty() {
+        return Err(DatabaseError::EmptyTree);
+    }
+    let value_hash = smt
+        .get(key)
+        .ok_or_else(|| DatabaseError::KeyNotFound(display_key(key)))?;
+    let path = key_path(key);
+    Ok(serde_json::json!({
+        "key": display_key(key),
+        "root": hex::encode(smt.root()),
+        "proof": base64::encode(smt.prove(key).to_bytes()),
+        "path": hex::encode(path),
+        "leaf": hex::encode(smt.leaf(&path)),
+        "value_hash": hex::encode(value_hash),
+    }))
+}
+
+/// Proves that the tree does not hold a key: its leaf is empty.
+fn prove_absence(smt: &SmtState, key: &[u8]) -> Result<serde_json::Value, DatabaseError> {
+    let path = key_path(key);
+    if smt.leaf(&path) != EMPTY {
+        return Err(DatabaseError::QueryExecutionFailed(format!(
+            "Key {} is present",
+            display_key(key)
+        )));
+    }
+    Ok(serde_json::json!({
+        "key": display_key(key),
+        "root": root_json(smt),
+        "proof": base64::encode(smt.prove(key).to_bytes()),
+        "path": hex::encode(path),
+    }))
+}
+
+/// Reports the size of the proof `prove` would produce for a key.
+fn proof_size(smt: &SmtState, key: &[u8]) -> serde_json::Value {
+    let proof = smt.prove(key);
+    serde_json::json!({
+        "key": display_key(key),
+        "sibling_count": proof.siblings.len(),
+        "bytes": proof.to_bytes().len(),
+    })
+}
+
+fn get_root(smt: &SmtState) -> serde_json::Value {
+    serde_json::json!({
+        "root": root_json(smt),
+        "leaf_count": smt.values.len(),
+    })
+}
@@ -0,0 +1,187 @@
+use crate::{CompactionStats, Store, StoreError, StoreResult};
+use async_trait::async_trait;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex};
+
+/// Size and recency of a value written through a `BoundedStore`.
+struct Entry {
+    size: u64,
+    last_access: u64,
+}
+
+/// Access bookkeeping, ordered so the least recently used key comes first.
+#[derive(Default)]
+struct Usage {
+    entries: HashMap<String, Entry>,
+    by_access: BTreeMap<u64, String>,
+    total_bytes: u64,
+    clock: u64,
+}
+
+impl Usage {
+    /// Marks `key` as the most recently used.
+    fn touch(&mut self, key: &str) {
+        self.clock += 1;
+        let now = self.clock;
+        if let Some(entry) = self.entries.get_mut(key) {
+            self.by_access.remove(&entry.last_access);
+            entry.last_access = now;
+            self.by_access.insert(now, key.to_string());
+        }
+    }
+
+    fn remove(&mut self, key: &str) -> Option<Entry> {
+        let entry = self.entries.remove(key)?;
+        self.by_access.remove(&entry.last_access);
+        self.total_bytes -= entry.size;
+        Some(entry)
+    }
+
+    fn insert(&mut self, key: &str, size: u64) {
+        self.remove(key);
+        self.clock += 1;
+        let now = self.clock;
+        self.entries.insert(
+            key.to_string(),
+            Entry {
+                size,
+                last_access: now,
+            },
+        );
+        self.by_access.insert(now, key.to_string());
+        self.total_bytes += size;
+    }
+}
+
+/// Wraps a store and keeps the values written through it under a byte budget
+/// by deleting the least recently used ones.
+///
+/// Only values are evicted; the Merkle tree of a `Database` on top of this
+/// store keeps committing to their hashes, so reading an evicted key returns
+/// `StoreError::NotFound` while it can still be proven. Values that were in
+/// the inner store before it was wrapped are not tracked or evicted.
+pub struct BoundedStore {
+    inner: Arc<dyn Store>,
+    max_bytes: u64,
+    usage: Mutex<Usage>,
+}
+
+impl BoundedStore {
+    pub fn new(inner: Arc<dyn Store>, max_bytes: u64) -> Self {
+        Self {
+            inner,
+            max_bytes,
+            usage: Mutex::new(Usage::default()),
+        }
+    }
+
+    /// Total size of the tracked values, unlike `Store::total_bytes`,
+    /// which reports the inner store.
+    pub fn tracked_bytes(&self) -> u64 {
+        self.usage.lock().unwrap().total_bytes
+    }
+
+    /// The byte budget values are kept under.
+    pub fn max_bytes(&self) -> u64 {
+        self.max_bytes
+    }
+
+    /// Picks the least recently used keys that must go for `incoming` bytes of
+    /// `key` to fit, and stops tracking them.
+    fn reserve(&self, key: &str, incoming: u64) -> Vec<String> {
+        let mut usage = self.usage.lock().unwrap();
+        let current = usage.entries.get(key).map_or(0, |entry| entry.size);
+        let mut victims = Vec::new();
+        while usage.total_bytes - current + incoming > self.max_bytes {
+            let Some(victim) = usage
+                .by_access
+                .values()
+                .find(|victim| victim.as_str() != key)
+                .cloned()
+            else {
+                break;
+            };
+            usage.remove(&victim);
+            victims.push(victim);
+        }
+        victims
+    }
+}
+
+#[async_trait]
+impl Store for BoundedStore {
+    async fn put(&self, key: &str, value: &[u8]) -> StoreResult<()> {
+        let size = value.len() as u64;
+        if size > self.max_bytes {
+            return Err(StoreError::Storage(format!(
+                "value of {} bytes exceeds the store budget of {} bytes",
+                size, self.max_bytes
+            )));
+        }
+
+        for victim in self.reserve(key, size) {
+            match self.inner.delete(&victim).await {
+                Ok(()) | Err(StoreError::NotFound(_)) => {}
+                Err(e) => return Err(e),
+            }
+        }
+
+        self.inner.put(key, value).await?;
+        self.usage.lock().unwrap().insert(key, size);
+        Ok(())
+    }
+
+    async fn atomic_swap(&self, key: &str, new_value: &[u8]) -> StoreResult<Option<Vec<u8>>> {
+        let size = new_value.len() as u64;
+        if size > self.max_bytes {
+            return Err(StoreError::Storage(format!(
+                "value of {} bytes exceeds the store budget of {} bytes",
+                size, self.max_bytes
+            )));
+        }
+
+        for victim in self.reserve(key, size) {
+            match self.inner.delete(&victim).await {
+                Ok(()) | Err(StoreError::NotFound(_)) => {}
+                Err(e) => return Err(e),
+            }
+        }
+
+        let old = self.inner.atomic_swap(key, new_value).await?;
+        self.usage.lock().unwrap().insert(key, size);
+        Ok(old)
+    }
+
+    async fn get(&self, key: &str) -> StoreResult<Vec<u8>> {
+        let value = self.inner.get(key).await?;
+        self.usage.lock().unwrap().touch(key);
+        Ok(value)
+    }
+
+    async fn delete(&self, key: &str) -> StoreResult<()> {
+        self.inner.delete(key).await?;
+        self.usage.lock().unwrap().remove(key);
+        Ok(())
+    }
+
+    async fn exists(&self, key: &str) -> StoreResult<bool> {
+        self.inner.exists(key).await
+    }
+
+    async fn keys_with_prefix(&self, prefix: &str) -> StoreResult<Vec<String>> {
+        self.inner.keys_with_prefix(prefix).await
+    }
+
+    async fn compact(&self) -> StoreResult<CompactionStats> {
+        self.inner.compact().await
+    }
+
+    async fn flush(&self) -> StoreResult<()> {
+        self.inner.flush().await
+    }
+
+    /// The bytes held by the inner store, untracked values included.
+    async fn total_bytes(&self) -> StoreResult<u64> {
+        self.inner.total_bytes().await
+    }
+}
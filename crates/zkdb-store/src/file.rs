@@ -1,21 +1,89 @@
-use crate::{Store, StoreError, StoreResult};
+use crate::{CompactionStats, Store, StoreError, StoreResult};
 use async_trait::async_trait;
+use std::ffi::OsString;
 use std::path::{Path, PathBuf};
+use std::time::Instant;
 use tokio::fs;
+use tokio::sync::Mutex;
+
+/// Suffix of the file a swap writes its value to before renaming it over
+/// the key's file. Keys ending in it are refused, as their files would be
+/// taken for the leftovers of a swap.
+pub const SWAP_SUFFIX: &str = ".__swap__";
+
+/// Most shard levels `FileStore::with_sharding` takes, one per byte of
+/// the key hash
+pub const MAX_SHARD_LEVELS: usize = 8;
 
 pub struct FileStore {
     base_path: PathBuf,
+    /// Number of directory levels keys are spread over, 0 for none
+    shard_levels: usize,
+    /// Serializes swaps so that each reads the value the last one wrote.
+    swap_lock: Mutex<()>,
 }
 
 impl FileStore {
     pub async fn new<P: AsRef<Path>>(base_path: P) -> StoreResult<Self> {
         let base_path = base_path.as_ref().to_owned();
         fs::create_dir_all(&base_path).await?;
-        Ok(Self { base_path })
+        Ok(Self {
+            base_path,
+            shard_levels: 0,
+            swap_lock: Mutex::new(()),
+        })
+    }
+
+    /// Spreads keys over `levels` levels of subdirectories named after the
+    /// leading bytes of a hash of the key, e.g. `ab/cd/<key>` for two, so
+    /// that no directory grows to hold every key
+    ///
+    /// Levels are capped at `MAX_SHARD_LEVELS`. The layout is not recorded
+    /// on disk: a directory must always be opened with the levels it was
+    /// written with, or its keys are not found.
+    pub fn with_sharding(mut self, levels: usize) -> Self {
+        self.shard_levels = levels.min(MAX_SHARD_LEVELS);
+        self
+    }
+
+    /// Number of directory levels keys are spread over, 0 for none
+    pub fn shard_levels(&self) -> usize {
+        self.shard_levels
     }
 
     fn key_to_path(&self, key: &str) -> PathBuf {
-        self.base_path.join(key)
+        let mut path = self.base_path.clone();
+        let hash = fnv1a(key.as_bytes()).to_be_bytes();
+        for byte in &hash[..self.shard_levels] {
+            path.push(format!("{:02x}", byte));
+        }
+        path.join(key)
+    }
+
+    /// Key stored at `path`, or `None` for a path outside the layout
+    fn path_to_key(&self, path: &Path) -> Option<String> {
+        let relative = path.strip_prefix(&self.base_path).ok()?;
+        let mut components = relative.components();
+        for _ in 0..self.shard_levels {
+            components.next()?;
+        }
+        let key = components
+            .map(|component| component.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/");
+        (!key.is_empty()).then_some(key)
+    }
+
+    /// Fails for a key whose file would be taken for a swap's, see
+    /// `SWAP_SUFFIX`.
+    fn check_key(key: &str) -> StoreResult<()> {
+        if key.ends_with(SWAP_SUFFIX) {
+            return Err(StoreError::Storage(format!(
+                "key '{}' ends with the reserved suffix {}",
+                key, SWAP_SUFFIX
+            )));
+        }
+        Ok(())
     }
 
     async fn ensure_parent_exists(&self, path: &Path) -> StoreResult<()> {
@@ -24,11 +92,36 @@ impl FileStore {
         }
         Ok(())
     }
+
+    /// Bytes taken up by the files and directories under the base path,
+    /// with every directory in the order it was found, parents first, and
+    /// the files left behind by swaps that were cut short
+    async fn walk(&self) -> StoreResult<(u64, Vec<PathBuf>, Vec<PathBuf>)> {
+        let mut bytes = 0;
+        let mut found = Vec::new();
+        let mut leftovers = Vec::new();
+        let mut pending = vec![self.base_path.clone()];
+        while let Some(dir) = pending.pop() {
+            let mut entries = fs::read_dir(&dir).await?;
+            while let Some(entry) = entries.next_entry().await? {
+                let metadata = entry.metadata().await?;
+                bytes += metadata.len();
+                if metadata.is_dir() {
+                    pending.push(entry.path());
+                    found.push(entry.path());
+                } else if entry.file_name().to_string_lossy().ends_with(SWAP_SUFFIX) {
+                    leftovers.push(entry.path());
+                }
+            }
+        }
+        Ok((bytes, found, leftovers))
+    }
 }
 
 #[async_trait]
 impl Store for FileStore {
     async fn put(&self, key: &str, value: &[u8]) -> StoreResult<()> {
+        Self::check_key(key)?;
         let path = self.key_to_path(key);
         self.ensure_parent_exists(&path).await?;
         fs::write(path, value).await?;
@@ -55,4 +148,141 @@ impl Store for FileStore {
         let path = self.key_to_path(key);
         Ok(path.exists())
     }
+
+    async fn keys_with_prefix(&self, prefix: &str) -> StoreResult<Vec<String>> {
+        // Keys map to paths, so only the directory the prefix ends in is
+        // walked. Sharded keys sharing a prefix land in any shard.
+        let start = match prefix.rfind('/') {
+            Some(end) if self.shard_levels == 0 => self.key_to_path(&prefix[..end]),
+            _ => self.base_path.clone(),
+        };
+        let mut keys = Vec::new();
+        let mut dirs = vec![start];
+        while let Some(dir) = dirs.pop() {
+            let mut entries = match fs::read_dir(&dir).await {
+                Ok(entries) => entries,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(e.into()),
+            };
+            while let Some(entry) = entries.next_entry().await? {
+                let path = entry.path();
+                if entry.file_type().await?.is_dir() {
+                    dirs.push(path);
+                    continue;
+                }
+                let Some(key) = self.path_to_key(&path) else {
+                    continue;
+                };
+                // Values being swapped in are not keys of their own.
+                if key.starts_with(prefix) && !key.ends_with(SWAP_SUFFIX) {
+                    keys.push(key);
+                }
+            }
+        }
+        keys.sort();
+        Ok(keys)
+    }
+
+    /// Writes the new value beside the key's file and renames it over it,
+    /// so readers see either value whole. Swaps through this store are
+    /// serialized; other processes writing the same directory are not.
+    async fn atomic_swap(&self, key: &str, new_value: &[u8]) -> StoreResult<Option<Vec<u8>>> {
+        Self::check_key(key)?;
+        let path = self.key_to_path(key);
+        let mut swap_name = OsString::from(path.file_name().unwrap_or_default());
+        swap_name.push(SWAP_SUFFIX);
+        let swap_path = path.with_file_name(swap_name);
+
+        let _guard = self.swap_lock.lock().await;
+        let old = match self.get(key).await {
+            Ok(value) => Some(value),
+            Err(StoreError::NotFound(_)) => None,
+            Err(e) => return Err(e),
+        };
+        self.ensure_parent_exists(&path).await?;
+        fs::write(&swap_path, new_value).await?;
+        fs::rename(&swap_path, &path).await?;
+        Ok(old)
+    }
+
+    /// Syncs every file and directory under the base path, the directories
+    /// holding the names of new and removed files.
+    async fn flush(&self) -> StoreResult<()> {
+        // Swaps rename files into place while they run.
+        let _guard = self.swap_lock.lock().await;
+        let mut pending = vec![self.base_path.clone()];
+        while let Some(dir) = pending.pop() {
+            let mut entries = fs::read_dir(&dir).await?;
+            while let Some(entry) = entries.next_entry().await? {
+                if entry.file_type().await?.is_dir() {
+                    pending.push(entry.path());
+                } else {
+                    fs::File::open(entry.path()).await?.sync_all().await?;
+                }
+            }
+            fs::File::open(&dir).await?.sync_all().await?;
+        }
+        Ok(())
+    }
+
+    /// Sums the sizes of the files under the base path, values being
+    /// swapped in included.
+    async fn total_bytes(&self) -> StoreResult<u64> {
+        let mut bytes = 0;
+        let mut pending = vec![self.base_path.clone()];
+        while let Some(dir) = pending.pop() {
+            let mut entries = fs::read_dir(&dir).await?;
+            while let Some(entry) = entries.next_entry().await? {
+                let metadata = entry.metadata().await?;
+                if metadata.is_dir() {
+                    pending.push(entry.path());
+                } else {
+                    bytes += metadata.len();
+                }
+            }
+        }
+        Ok(bytes)
+    }
+
+    /// Removes the directories deletes leave empty and the files of swaps
+    /// that were cut short.
+    async fn compact(&self) -> StoreResult<CompactionStats> {
+        let start = Instant::now();
+        // A swap in progress owns its file until it renames it.
+        let _guard = self.swap_lock.lock().await;
+        let (bytes_before, dirs, leftovers) = self.walk().await?;
+        for leftover in &leftovers {
+            match fs::remove_file(leftover).await {
+                Ok(()) => {}
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+        // Children come after their parents, so emptied parents go too.
+        for dir in dirs.iter().rev() {
+            match fs::remove_dir(dir).await {
+                Ok(()) => {}
+                Err(e)
+                    if matches!(
+                        e.kind(),
+                        std::io::ErrorKind::DirectoryNotEmpty | std::io::ErrorKind::NotFound
+                    ) => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+        let (bytes_after, _, _) = self.walk().await?;
+        Ok(CompactionStats {
+            bytes_before,
+            bytes_after,
+            duration_ms: start.elapsed().as_millis() as u64,
+        })
+    }
+}
+
+/// 64-bit FNV-1a hash, which picks the shard of a key. Fixed here so that
+/// the layout does not change with the standard library's hasher.
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ u64::from(*byte)).wrapping_mul(0x0000_0100_0000_01b3)
+    })
 }
@@ -1,4 +1,6 @@
 use async_trait::async_trait;
+use futures::future::{BoxFuture, FutureExt};
+use futures::stream::{self, StreamExt};
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
@@ -10,6 +12,8 @@ pub enum StoreError {
     NotFound(String),
     #[error("Storage error: {0}")]
     Storage(String),
+    #[error("Write conflict on key: {0}")]
+    Locked(String),
 }
 
 impl From<std::io::Error> for StoreError {
@@ -20,6 +24,31 @@ impl From<std::io::Error> for StoreError {
 
 pub type StoreResult<T> = Result<T, StoreError>;
 
+/// Prefix of the keys values stored with `Store::put_bytes` are kept under
+pub const BINARY_KEY_PREFIX: &str = "_bin/";
+
+/// Key a value stored with `Store::put_bytes` under `key` is kept at:
+/// `BINARY_KEY_PREFIX` followed by `key` in lowercase hex, which every
+/// store, file names included, can hold
+pub fn binary_key(key: &[u8]) -> String {
+    let mut encoded = String::with_capacity(BINARY_KEY_PREFIX.len() + key.len() * 2);
+    encoded.push_str(BINARY_KEY_PREFIX);
+    for byte in key {
+        encoded.push_str(&format!("{:02x}", byte));
+    }
+    encoded
+}
+
+/// Outcome of `Store::compact`
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CompactionStats {
+    /// Bytes the store took up on disk before compacting
+    pub bytes_before: u64,
+    /// Bytes the store takes up on disk after compacting
+    pub bytes_after: u64,
+    pub duration_ms: u64,
+}
+
 #[async_trait]
 pub trait Store: Send + Sync {
     /// Store a value and return its location reference
@@ -33,9 +62,123 @@ pub trait Store: Send + Sync {
 
     /// Check if a key exists
     async fn exists(&self, key: &str) -> StoreResult<bool>;
+
+    /// List the keys starting with `prefix`, in sorted order
+    async fn keys_with_prefix(&self, prefix: &str) -> StoreResult<Vec<String>>;
+
+    /// Retrieve the values of several keys, in the order given
+    async fn batch_get(&self, keys: &[&str]) -> StoreResult<Vec<Vec<u8>>> {
+        let mut values = Vec::with_capacity(keys.len());
+        for key in keys {
+            values.push(self.get(key).await?);
+        }
+        Ok(values)
+    }
+
+    /// Retrieve the values of several keys with at most `concurrency` gets in
+    /// flight, in the order given, with `None` for keys that are not found
+    async fn get_many(
+        &self,
+        keys: &[&str],
+        concurrency: usize,
+    ) -> StoreResult<Vec<Option<Vec<u8>>>> {
+        let mut values = vec![None; keys.len()];
+        // Boxed up front: a lazily mapped stream of borrowing futures is not
+        // provably `Send` inside the boxed future `async_trait` returns.
+        let gets: Vec<BoxFuture<'_, (usize, StoreResult<Vec<u8>>)>> = keys
+            .iter()
+            .enumerate()
+            .map(|(position, key)| async move { (position, self.get(key).await) }.boxed())
+            .collect();
+        let mut gets = stream::iter(gets).buffer_unordered(concurrency.max(1));
+        // Gets complete out of order; each lands at its key's position.
+        while let Some((position, result)) = gets.next().await {
+            values[position] = match result {
+                Ok(value) => Some(value),
+                Err(StoreError::NotFound(_)) => None,
+                Err(e) => return Err(e),
+            };
+        }
+        Ok(values)
+    }
+
+    /// Store several values, in the order given
+    async fn batch_put(&self, entries: &[(&str, &[u8])]) -> StoreResult<()> {
+        for (key, value) in entries {
+            self.put(key, value).await?;
+        }
+        Ok(())
+    }
+
+    /// Replace the value of `key` with `new_value` and return the value it
+    /// replaced, `None` if the key was absent
+    ///
+    /// Concurrent swaps of a key each get back a different value: the one
+    /// the swap before them stored. The default gets then puts, which does
+    /// not hold that; stores override it with an atomic swap.
+    async fn atomic_swap(&self, key: &str, new_value: &[u8]) -> StoreResult<Option<Vec<u8>>> {
+        let old = match self.get(key).await {
+            Ok(value) => Some(value),
+            Err(StoreError::NotFound(_)) => None,
+            Err(e) => return Err(e),
+        };
+        self.put(key, new_value).await?;
+        Ok(old)
+    }
+
+    /// Copy the value of `src_key` to `dst_key`, overwriting any existing value
+    async fn copy(&self, src_key: &str, dst_key: &str) -> StoreResult<()> {
+        let value = self.get(src_key).await?;
+        self.put(dst_key, &value).await
+    }
+
+    /// Store a value under a key of arbitrary bytes, at `binary_key(key)`
+    async fn put_bytes(&self, key: &[u8], value: &[u8]) -> StoreResult<()> {
+        self.put(&binary_key(key), value).await
+    }
+
+    /// Retrieve a value stored with `put_bytes`
+    async fn get_bytes(&self, key: &[u8]) -> StoreResult<Vec<u8>> {
+        self.get(&binary_key(key)).await
+    }
+
+    /// Delete a value stored with `put_bytes`
+    async fn delete_bytes(&self, key: &[u8]) -> StoreResult<()> {
+        self.delete(&binary_key(key)).await
+    }
+
+    /// Reclaim the space left behind by deleted and overwritten values
+    ///
+    /// Stores with nothing to reclaim report all zeros.
+    async fn compact(&self) -> StoreResult<CompactionStats> {
+        Ok(CompactionStats::default())
+    }
+
+    /// Make every completed write durable, so that it survives a crash
+    ///
+    /// Stores that write through report success right away.
+    async fn flush(&self) -> StoreResult<()> {
+        Ok(())
+    }
+
+    /// Total bytes of the values held, for capacity planning
+    ///
+    /// Stores that cannot tell without reading every value fail with
+    /// `StoreError::Storage`.
+    async fn total_bytes(&self) -> StoreResult<u64> {
+        Err(StoreError::Storage(
+            "this store cannot report its size".to_string(),
+        ))
+    }
 }
 
+/// LRU-bounded wrapper around another store
+pub mod bounded;
 /// Basic file-based implementation
 pub mod file;
+/// In-memory implementation
+pub mod mem;
+/// Wrapper retrying transient failures of another store
+pub mod retry;
 /// RocksDB-based implementation
 pub mod rocks;
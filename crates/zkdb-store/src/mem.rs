@@ -0,0 +1,80 @@
+use crate::{Store, StoreError, StoreResult};
+use async_trait::async_trait;
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+
+/// Keeps every value in memory, for tests and short-lived databases.
+#[derive(Default)]
+pub struct MemStore {
+    values: Mutex<BTreeMap<String, Vec<u8>>>,
+}
+
+impl MemStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[async_trait]
+impl Store for MemStore {
+    async fn put(&self, key: &str, value: &[u8]) -> StoreResult<()> {
+        self.values
+            .lock()
+            .unwrap()
+            .insert(key.to_string(), value.to_vec());
+        Ok(())
+    }
+
+    async fn get(&self, key: &str) -> StoreResult<Vec<u8>> {
+        self.values
+            .lock()
+            .unwrap()
+            .get(key)
+            .cloned()
+            .ok_or_else(|| StoreError::NotFound(key.to_string()))
+    }
+
+    async fn delete(&self, key: &str) -> StoreResult<()> {
+        self.values
+            .lock()
+            .unwrap()
+            .remove(key)
+            .map(|_| ())
+            .ok_or_else(|| StoreError::NotFound(key.to_string()))
+    }
+
+    async fn exists(&self, key: &str) -> StoreResult<bool> {
+        Ok(self.values.lock().unwrap().contains_key(key))
+    }
+
+    async fn keys_with_prefix(&self, prefix: &str) -> StoreResult<Vec<String>> {
+        Ok(self
+            .values
+            .lock()
+            .unwrap()
+            .range(prefix.to_string()..)
+            .map(|(key, _)| key)
+            .take_while(|key| key.starts_with(prefix))
+            .cloned()
+            .collect())
+    }
+
+    async fn atomic_swap(&self, key: &str, new_value: &[u8]) -> StoreResult<Option<Vec<u8>>> {
+        Ok(self
+            .values
+            .lock()
+            .unwrap()
+            .insert(key.to_string(), new_value.to_vec()))
+    }
+
+    /// Sums the lengths of the values, keys left out.
+    async fn total_bytes(&self) -> StoreResult<u64> {
+        Ok(self
+            .values
+            .lock()
+            .unwrap()
+            .values()
+            .map(|value| value.len() as u64)
+            .sum())
+    }
+}
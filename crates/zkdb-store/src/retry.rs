@@ -0,0 +1,138 @@
+use crate::{CompactionStats, Store, StoreError, StoreResult};
+use async_trait::async_trait;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// How a `RetryableStore` retries failed operations.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RetryConfig {
+    /// Attempts made in all, the first included. At least one is made.
+    pub max_attempts: u32,
+    /// Wait before the first retry.
+    pub initial_delay: Duration,
+    /// Longest wait between attempts, however many have failed.
+    pub max_delay: Duration,
+}
+
+impl Default for RetryConfig {
+    fn default() -> Self {
+        Self {
+            max_attempts: 5,
+            initial_delay: Duration::from_millis(50),
+            max_delay: Duration::from_secs(2),
+        }
+    }
+}
+
+impl RetryConfig {
+    /// Wait before the attempt after `failed` attempts, doubling from
+    /// `initial_delay` up to `max_delay`.
+    fn delay(&self, failed: u32) -> Duration {
+        let factor = 1u32
+            .checked_shl(failed.saturating_sub(1))
+            .unwrap_or(u32::MAX);
+        self.initial_delay
+            .checked_mul(factor)
+            .map_or(self.max_delay, |delay| delay.min(self.max_delay))
+    }
+}
+
+/// Wraps a store and retries operations that fail with `StoreError::Io` or
+/// `StoreError::Storage`, waiting exponentially longer between attempts,
+/// for stores reached over a network whose connections drop now and then.
+///
+/// `StoreError::NotFound` and `StoreError::Locked` report the state of the
+/// store rather than a failure to reach it, and are returned right away.
+/// Every operation is retried whole, so stores should only be wrapped if
+/// repeating a write that may have landed is harmless, as it is for `put`.
+pub struct RetryableStore {
+    inner: Arc<dyn Store>,
+    config: RetryConfig,
+}
+
+impl RetryableStore {
+    pub fn new(inner: Arc<dyn Store>, config: RetryConfig) -> Self {
+        Self { inner, config }
+    }
+
+    /// Runs `operation` until it succeeds, fails with an error that is not
+    /// transient, or has been attempted `max_attempts` times.
+    async fn retry<T, F, Fut>(&self, mut operation: F) -> StoreResult<T>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = StoreResult<T>>,
+    {
+        let mut failed = 0;
+        loop {
+            match operation().await {
+                Err(StoreError::Io(_) | StoreError::Storage(_))
+                    if failed + 1 < self.config.max_attempts =>
+                {
+                    failed += 1;
+                    tokio::time::sleep(self.config.delay(failed)).await;
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl Store for RetryableStore {
+    async fn put(&self, key: &str, value: &[u8]) -> StoreResult<()> {
+        self.retry(|| self.inner.put(key, value)).await
+    }
+
+    async fn get(&self, key: &str) -> StoreResult<Vec<u8>> {
+        self.retry(|| self.inner.get(key)).await
+    }
+
+    async fn delete(&self, key: &str) -> StoreResult<()> {
+        self.retry(|| self.inner.delete(key)).await
+    }
+
+    async fn exists(&self, key: &str) -> StoreResult<bool> {
+        self.retry(|| self.inner.exists(key)).await
+    }
+
+    async fn keys_with_prefix(&self, prefix: &str) -> StoreResult<Vec<String>> {
+        self.retry(|| self.inner.keys_with_prefix(prefix)).await
+    }
+
+    async fn batch_get(&self, keys: &[&str]) -> StoreResult<Vec<Vec<u8>>> {
+        self.retry(|| self.inner.batch_get(keys)).await
+    }
+
+    async fn get_many(
+        &self,
+        keys: &[&str],
+        concurrency: usize,
+    ) -> StoreResult<Vec<Option<Vec<u8>>>> {
+        self.retry(|| self.inner.get_many(keys, concurrency)).await
+    }
+
+    async fn batch_put(&self, entries: &[(&str, &[u8])]) -> StoreResult<()> {
+        self.retry(|| self.inner.batch_put(entries)).await
+    }
+
+    async fn atomic_swap(&self, key: &str, new_value: &[u8]) -> StoreResult<Option<Vec<u8>>> {
+        self.retry(|| self.inner.atomic_swap(key, new_value)).await
+    }
+
+    async fn copy(&self, src_key: &str, dst_key: &str) -> StoreResult<()> {
+        self.retry(|| self.inner.copy(src_key, dst_key)).await
+    }
+
+    async fn compact(&self) -> StoreResult<CompactionStats> {
+        self.retry(|| self.inner.compact()).await
+    }
+
+    async fn flush(&self) -> StoreResult<()> {
+        self.retry(|| self.inner.flush()).await
+    }
+
+    async fn total_bytes(&self) -> StoreResult<u64> {
+        self.retry(|| self.inner.total_bytes()).await
+    }
+}
@@ -1,10 +1,18 @@
-use crate::{Store, StoreError, StoreResult};
+use crate::{CompactionStats, Store, StoreError, StoreResult};
 use async_trait::async_trait;
-use rocksdb::{Options, DB};
+use rocksdb::{Direction, IteratorMode, Options, WriteBatch, DB};
 use std::path::Path;
+use std::sync::Mutex;
+use std::time::Instant;
+
+/// Suffix of the companion key holding a key's version for `optimistic_put`.
+const VERSION_SUFFIX: &str = "/__ver__";
 
 pub struct RocksStore {
     db: DB,
+    /// Serializes version checks and swaps so they and their writes are
+    /// atomic.
+    version_lock: Mutex<()>,
 }
 
 impl RocksStore {
@@ -15,8 +23,96 @@ impl RocksStore {
 
         let db = DB::open(&opts, path).map_err(|e| StoreError::Storage(e.to_string()))?;
 
-        Ok(Self { db })
+        Ok(Self {
+            db,
+            version_lock: Mutex::new(()),
+        })
+    }
+
+    /// Returns the version of a key, 0 if it was never written with
+    /// `optimistic_put`
+    pub async fn version_of(&self, key: &str) -> StoreResult<u64> {
+        self.read_version(key)
     }
+
+    /// Stores a value only if the key is still at `expected_version`, and
+    /// returns the new version
+    ///
+    /// Fails with `StoreError::Locked` when another writer got there first.
+    pub async fn optimistic_put(
+        &self,
+        key: &str,
+        expected_version: u64,
+        value: &[u8],
+    ) -> StoreResult<u64> {
+        let _guard = self.version_lock.lock().unwrap();
+        let current = self.read_version(key)?;
+        if current != expected_version {
+            return Err(StoreError::Locked(format!(
+                "{} is at version {}, expected {}",
+                key, current, expected_version
+            )));
+        }
+
+        let version = current + 1;
+        let mut batch = WriteBatch::default();
+        batch.put(key.as_bytes(), value);
+        batch.put(version_key(key), version.to_be_bytes());
+        self.db
+            .write(batch)
+            .map_err(|e| StoreError::Storage(e.to_string()))?;
+        Ok(version)
+    }
+
+    /// Bytes held in SST files and memtables
+    fn size_on_disk(&self) -> StoreResult<u64> {
+        let mut bytes = 0;
+        for property in [
+            "rocksdb.total-sst-files-size",
+            "rocksdb.cur-size-all-mem-tables",
+        ] {
+            bytes += self
+                .db
+                .property_int_value(property)
+                .map_err(|e| StoreError::Storage(e.to_string()))?
+                .unwrap_or(0);
+        }
+        Ok(bytes)
+    }
+
+    fn read_version(&self, key: &str) -> StoreResult<u64> {
+        let Some(bytes) = self
+            .db
+            .get(version_key(key))
+            .map_err(|e| StoreError::Storage(e.to_string()))?
+        else {
+            return Ok(0);
+        };
+        let bytes: [u8; 8] = bytes
+            .as_slice()
+            .try_into()
+            .map_err(|_| StoreError::Storage(format!("corrupt version for key: {}", key)))?;
+        Ok(u64::from_be_bytes(bytes))
+    }
+}
+
+/// Every key-value pair of the RocksDB database at `path`, in key order
+///
+/// The database is opened read-only, so it may be a backup or held open by
+/// another process, and is closed again before returning.
+pub fn read_all<P: AsRef<Path>>(path: P) -> StoreResult<Vec<(Vec<u8>, Vec<u8>)>> {
+    let db = DB::open_for_read_only(&Options::default(), path, false)
+        .map_err(|e| StoreError::Storage(e.to_string()))?;
+    db.iterator(IteratorMode::Start)
+        .map(|item| {
+            let (key, value) = item.map_err(|e| StoreError::Storage(e.to_string()))?;
+            Ok((key.into_vec(), value.into_vec()))
+        })
+        .collect()
+}
+
+fn version_key(key: &str) -> Vec<u8> {
+    format!("{}{}", key, VERSION_SUFFIX).into_bytes()
 }
 
 #[async_trait]
@@ -50,6 +146,103 @@ impl Store for RocksStore {
             .is_some();
         Ok(exists)
     }
+
+    async fn keys_with_prefix(&self, prefix: &str) -> StoreResult<Vec<String>> {
+        let mut keys = Vec::new();
+        let mode = IteratorMode::From(prefix.as_bytes(), Direction::Forward);
+        for item in self.db.iterator(mode) {
+            let (key, _) = item.map_err(|e| StoreError::Storage(e.to_string()))?;
+            if !key.starts_with(prefix.as_bytes()) {
+                break;
+            }
+            let key = String::from_utf8(key.into_vec())
+                .map_err(|e| StoreError::Storage(format!("key is not UTF-8: {}", e)))?;
+            // Versions kept for `optimistic_put` are not keys of their own.
+            if !key.ends_with(VERSION_SUFFIX) {
+                keys.push(key);
+            }
+        }
+        Ok(keys)
+    }
+
+    async fn batch_put(&self, entries: &[(&str, &[u8])]) -> StoreResult<()> {
+        let mut batch = WriteBatch::default();
+        for (key, value) in entries {
+            batch.put(key.as_bytes(), value);
+        }
+        self.db
+            .write(batch)
+            .map_err(|e| StoreError::Storage(e.to_string()))?;
+        Ok(())
+    }
+
+    async fn atomic_swap(&self, key: &str, new_value: &[u8]) -> StoreResult<Option<Vec<u8>>> {
+        let _guard = self.version_lock.lock().unwrap();
+        let old = self
+            .db
+            .get(key.as_bytes())
+            .map_err(|e| StoreError::Storage(e.to_string()))?;
+        let mut batch = WriteBatch::default();
+        batch.put(key.as_bytes(), new_value);
+        self.db
+            .write(batch)
+            .map_err(|e| StoreError::Storage(e.to_string()))?;
+        Ok(old)
+    }
+
+    async fn copy(&self, src_key: &str, dst_key: &str) -> StoreResult<()> {
+        // The pinned slice points into RocksDB's block cache, so the value is
+        // only copied once, into the write batch.
+        let mut batch = WriteBatch::default();
+        {
+            let value = self
+                .db
+                .get_pinned(src_key.as_bytes())
+                .map_err(|e| StoreError::Storage(e.to_string()))?
+                .ok_or_else(|| StoreError::NotFound(src_key.to_string()))?;
+            batch.put(dst_key.as_bytes(), &*value);
+        }
+        self.db
+            .write(batch)
+            .map_err(|e| StoreError::Storage(e.to_string()))?;
+        Ok(())
+    }
+
+    async fn compact(&self) -> StoreResult<CompactionStats> {
+        let start = Instant::now();
+        let bytes_before = self.size_on_disk()?;
+        // Flushed first so that tombstones still in memtables are compacted
+        // away too.
+        self.db
+            .flush()
+            .map_err(|e| StoreError::Storage(e.to_string()))?;
+        self.db.compact_range(None::<&[u8]>, None::<&[u8]>);
+        Ok(CompactionStats {
+            bytes_before,
+            bytes_after: self.size_on_disk()?,
+            duration_ms: start.elapsed().as_millis() as u64,
+        })
+    }
+
+    /// Syncs the write-ahead log, then flushes the memtables to SST files.
+    async fn flush(&self) -> StoreResult<()> {
+        self.db
+            .flush_wal(true)
+            .map_err(|e| StoreError::Storage(e.to_string()))?;
+        self.db
+            .flush()
+            .map_err(|e| StoreError::Storage(e.to_string()))
+    }
+
+    /// RocksDB's estimate of the live data, which lags behind recent
+    /// writes until they are flushed.
+    async fn total_bytes(&self) -> StoreResult<u64> {
+        Ok(self
+            .db
+            .property_int_value("rocksdb.estimate-live-data-size")
+            .map_err(|e| StoreError::Storage(e.to_string()))?
+            .unwrap_or(0))
+    }
 }
 
 impl Drop for RocksStore {
@@ -0,0 +1,403 @@
+use async_trait::async_trait;
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use zkdb_store::file::{FileStore, SWAP_SUFFIX};
+use zkdb_store::mem::MemStore;
+use zkdb_store::retry::{RetryConfig, RetryableStore};
+use zkdb_store::{binary_key, CompactionStats, Store, StoreError, StoreResult};
+
+/// In-memory store that records how many gets are in flight at once.
+#[derive(Default)]
+struct CountingStore {
+    values: HashMap<String, Vec<u8>>,
+    in_flight: AtomicUsize,
+    max_in_flight: AtomicUsize,
+}
+
+#[async_trait]
+impl Store for CountingStore {
+    async fn put(&self, _key: &str, _value: &[u8]) -> StoreResult<()> {
+        unimplemented!("the store is filled before it is shared")
+    }
+
+    async fn get(&self, key: &str) -> StoreResult<Vec<u8>> {
+        let in_flight = self.in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+        self.max_in_flight.fetch_max(in_flight, Ordering::SeqCst);
+        // Later keys finish first, so results arrive out of order.
+        let delay = 20u64.saturating_sub(key.len() as u64 * 2);
+        tokio::time::sleep(Duration::from_millis(delay)).await;
+        self.in_flight.fetch_sub(1, Ordering::SeqCst);
+        self.values
+            .get(key)
+            .cloned()
+            .ok_or_else(|| StoreError::NotFound(key.to_string()))
+    }
+
+    async fn delete(&self, _key: &str) -> StoreResult<()> {
+        unimplemented!()
+    }
+
+    async fn exists(&self, key: &str) -> StoreResult<bool> {
+        Ok(self.values.contains_key(key))
+    }
+
+    async fn keys_with_prefix(&self, prefix: &str) -> StoreResult<Vec<String>> {
+        let mut keys: Vec<String> = self
+            .values
+            .keys()
+            .filter(|key| key.starts_with(prefix))
+            .cloned()
+            .collect();
+        keys.sort();
+        Ok(keys)
+    }
+}
+
+#[tokio::test]
+async fn test_get_many_keeps_order_and_bounds_concurrency() {
+    let mut store = CountingStore::default();
+    for i in (0..10).filter(|i| i % 3 != 0) {
+        store
+            .values
+            .insert("k".repeat(i + 1), format!("value{}", i).into_bytes());
+    }
+
+    let keys: Vec<String> = (0..10).map(|i| "k".repeat(i + 1)).collect();
+    let keys: Vec<&str> = keys.iter().map(String::as_str).collect();
+    let values = store.get_many(&keys, 3).await.unwrap();
+
+    assert_eq!(values.len(), keys.len());
+    for (i, value) in values.iter().enumerate() {
+        if i % 3 == 0 {
+            assert_eq!(value, &None, "key {} is missing", i);
+        } else {
+            assert_eq!(value.as_deref(), Some(format!("value{}", i).as_bytes()));
+        }
+    }
+    assert_eq!(store.max_in_flight.load(Ordering::SeqCst), 3);
+    assert_eq!(store.in_flight.load(Ordering::SeqCst), 0);
+
+    // A concurrency of zero still makes progress, one get at a time.
+    store.max_in_flight.store(0, Ordering::SeqCst);
+    assert_eq!(store.get_many(&keys, 0).await.unwrap(), values);
+    assert_eq!(store.max_in_flight.load(Ordering::SeqCst), 1);
+}
+
+#[tokio::test]
+async fn test_binary_keys_stay_inside_a_file_store() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let store = FileStore::new(temp_dir.path().join("store")).await.unwrap();
+
+    // Separators, parent references and invalid UTF-8 would all escape or
+    // break a file name used as is.
+    let key = b"../\0\xff/escape".to_vec();
+    store.put_bytes(&key, b"value").await.unwrap();
+    assert_eq!(store.get_bytes(&key).await.unwrap(), b"value");
+    assert!(temp_dir
+        .path()
+        .join("store")
+        .join(binary_key(&key))
+        .exists());
+    assert_eq!(
+        store.keys_with_prefix("").await.unwrap(),
+        vec![binary_key(&key)]
+    );
+
+    store.delete_bytes(&key).await.unwrap();
+    assert!(matches!(
+        store.get_bytes(&key).await,
+        Err(StoreError::NotFound(_))
+    ));
+}
+
+#[tokio::test]
+async fn test_compaction() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let base = temp_dir.path().join("store");
+    let store = FileStore::new(&base).await.unwrap();
+    for i in 0..20 {
+        store
+            .put(&format!("shard_{}/nested/key_{}", i % 4, i), b"value")
+            .await
+            .unwrap();
+    }
+    for i in (0..20).filter(|i| i % 4 != 0) {
+        store
+            .delete(&format!("shard_{}/nested/key_{}", i % 4, i))
+            .await
+            .unwrap();
+    }
+
+    let stats = store.compact().await.unwrap();
+    assert!(stats.bytes_after < stats.bytes_before);
+    // Emptied directories are gone, with their emptied parents.
+    assert!(base.join("shard_0/nested").exists());
+    assert!(!base.join("shard_1").exists());
+    assert_eq!(store.keys_with_prefix("").await.unwrap().len(), 5);
+    assert_eq!(
+        store.get("shard_0/nested/key_4").await.unwrap(),
+        b"value".to_vec()
+    );
+    // Removed directories are created again on the next put.
+    store.put("shard_1/nested/key_1", b"again").await.unwrap();
+    assert_eq!(
+        store.get("shard_1/nested/key_1").await.unwrap(),
+        b"again".to_vec()
+    );
+
+    // Stores without anything to reclaim report zeros.
+    let stats = CountingStore::default().compact().await.unwrap();
+    assert_eq!(stats, CompactionStats::default());
+}
+
+#[tokio::test]
+async fn test_file_store_refuses_swap_suffixed_keys() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let store = FileStore::new(temp_dir.path()).await.unwrap();
+    let key = format!("user{}", SWAP_SUFFIX);
+
+    assert!(matches!(
+        store.put(&key, b"value").await,
+        Err(StoreError::Storage(_))
+    ));
+    assert!(matches!(
+        store.atomic_swap(&key, b"value").await,
+        Err(StoreError::Storage(_))
+    ));
+    assert!(!store.exists(&key).await.unwrap());
+
+    // Compaction leaves the other keys alone.
+    store.put("user", b"value").await.unwrap();
+    store.compact().await.unwrap();
+    assert_eq!(store.keys_with_prefix("").await.unwrap(), vec!["user"]);
+}
+
+#[tokio::test]
+async fn test_concurrent_swaps_return_distinct_values() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let store = Arc::new(FileStore::new(temp_dir.path()).await.unwrap());
+    assert_eq!(store.atomic_swap("key", b"initial").await.unwrap(), None);
+
+    let tasks: Vec<_> = (0..10)
+        .map(|i| {
+            let store = store.clone();
+            tokio::spawn(async move {
+                store
+                    .atomic_swap("key", format!("value{}", i).as_bytes())
+                    .await
+                    .unwrap()
+                    .expect("the key holds a value")
+            })
+        })
+        .collect();
+    let mut seen = HashSet::new();
+    for task in tasks {
+        let old = task.await.unwrap();
+        assert!(seen.insert(old.clone()), "{:?} was returned twice", old);
+    }
+
+    // Every value but the last one written came back exactly once.
+    seen.insert(store.get("key").await.unwrap());
+    let mut expected: HashSet<Vec<u8>> = (0..10)
+        .map(|i| format!("value{}", i).into_bytes())
+        .collect();
+    expected.insert(b"initial".to_vec());
+    assert_eq!(seen, expected);
+    assert_eq!(store.keys_with_prefix("").await.unwrap(), vec!["key"]);
+}
+
+#[tokio::test]
+async fn test_sharded_file_store() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let base = temp_dir.path().join("store");
+    let store = FileStore::new(&base).await.unwrap().with_sharding(2);
+    assert_eq!(store.shard_levels(), 2);
+    let keys: Vec<String> = (0..20)
+        .map(|i| format!("key_{:02}", i))
+        .chain(["_wal/head".to_string()])
+        .collect();
+    for key in &keys {
+        store.put(key, key.as_bytes()).await.unwrap();
+    }
+
+    // Every key sits two hex-named directories down, not at the top.
+    let mut shards = HashSet::new();
+    for key in &keys {
+        assert!(!base.join(key).exists());
+        let mut found = Vec::new();
+        for outer in std::fs::read_dir(&base).unwrap() {
+            let outer = outer.unwrap().path();
+            for inner in std::fs::read_dir(&outer).unwrap() {
+                let inner = inner.unwrap().path();
+                if inner.join(key).is_file() {
+                    found.push(inner.strip_prefix(&base).unwrap().to_path_buf());
+                }
+            }
+        }
+        assert_eq!(found.len(), 1, "{} is stored once", key);
+        let shard = found.pop().unwrap();
+        assert!(shard.components().all(|part| part.as_os_str().len() == 2));
+        shards.insert(shard);
+        assert_eq!(store.get(key).await.unwrap(), key.as_bytes());
+    }
+    assert!(shards.len() > 1);
+
+    let mut sorted = keys.clone();
+    sorted.sort();
+    assert_eq!(store.keys_with_prefix("").await.unwrap(), sorted);
+    assert_eq!(
+        store.keys_with_prefix("_wal/").await.unwrap(),
+        vec!["_wal/head"]
+    );
+    assert_eq!(store.keys_with_prefix("key_1").await.unwrap().len(), 10);
+
+    store.delete("key_00").await.unwrap();
+    assert!(!store.exists("key_00").await.unwrap());
+    assert_eq!(
+        store.atomic_swap("key_01", b"swapped").await.unwrap(),
+        Some(b"key_01".to_vec())
+    );
+    store.compact().await.unwrap();
+    assert_eq!(
+        store.keys_with_prefix("").await.unwrap().len(),
+        keys.len() - 1
+    );
+}
+
+#[tokio::test]
+async fn test_total_bytes() {
+    let store = MemStore::new();
+    assert_eq!(store.total_bytes().await.unwrap(), 0);
+    store.put("small", &[0; 10]).await.unwrap();
+    assert_eq!(store.total_bytes().await.unwrap(), 10);
+    store.put("large", &[0; 1000]).await.unwrap();
+    assert_eq!(store.total_bytes().await.unwrap(), 1010);
+    store.put("small", &[0; 4]).await.unwrap();
+    store.delete("large").await.unwrap();
+    assert_eq!(store.total_bytes().await.unwrap(), 4);
+
+    let temp_dir = tempfile::tempdir().unwrap();
+    let store = FileStore::new(temp_dir.path()).await.unwrap();
+    store.put("nested/value", &[0; 100]).await.unwrap();
+    assert_eq!(store.total_bytes().await.unwrap(), 100);
+
+    // Stores that cannot tell say so.
+    assert!(matches!(
+        CountingStore::default().total_bytes().await,
+        Err(StoreError::Storage(_))
+    ));
+}
+
+#[tokio::test]
+async fn test_flushed_writes_survive_reopening() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    {
+        let store = FileStore::new(temp_dir.path())
+            .await
+            .unwrap()
+            .with_sharding(1);
+        store.put("durable", b"value").await.unwrap();
+        store.atomic_swap("swapped", b"value").await.unwrap();
+        store.flush().await.unwrap();
+    }
+    let store = FileStore::new(temp_dir.path())
+        .await
+        .unwrap()
+        .with_sharding(1);
+    assert_eq!(store.get("durable").await.unwrap(), b"value");
+    assert_eq!(store.get("swapped").await.unwrap(), b"value");
+
+    // Stores that write through have nothing to flush.
+    MemStore::new().flush().await.unwrap();
+}
+
+/// In-memory store whose operations fail with an `error` until `failures`
+/// of them have.
+struct FlakyStore {
+    inner: MemStore,
+    error: fn(String) -> StoreError,
+    failures: usize,
+    attempts: AtomicUsize,
+}
+
+impl FlakyStore {
+    fn new(error: fn(String) -> StoreError, failures: usize) -> Self {
+        Self {
+            inner: MemStore::new(),
+            error,
+            failures,
+            attempts: AtomicUsize::new(0),
+        }
+    }
+
+    fn attempt(&self) -> StoreResult<()> {
+        if self.attempts.fetch_add(1, Ordering::SeqCst) < self.failures {
+            return Err((self.error)("injected failure".to_string()));
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Store for FlakyStore {
+    async fn put(&self, key: &str, value: &[u8]) -> StoreResult<()> {
+        self.attempt()?;
+        self.inner.put(key, value).await
+    }
+
+    async fn get(&self, key: &str) -> StoreResult<Vec<u8>> {
+        self.attempt()?;
+        self.inner.get(key).await
+    }
+
+    async fn delete(&self, key: &str) -> StoreResult<()> {
+        self.attempt()?;
+        self.inner.delete(key).await
+    }
+
+    async fn exists(&self, key: &str) -> StoreResult<bool> {
+        self.attempt()?;
+        self.inner.exists(key).await
+    }
+
+    async fn keys_with_prefix(&self, prefix: &str) -> StoreResult<Vec<String>> {
+        self.attempt()?;
+        self.inner.keys_with_prefix(prefix).await
+    }
+}
+
+#[tokio::test]
+async fn test_retryable_store_retries_transient_errors() {
+    let config = RetryConfig {
+        max_attempts: 5,
+        initial_delay: Duration::from_millis(1),
+        max_delay: Duration::from_millis(4),
+    };
+
+    let flaky = Arc::new(FlakyStore::new(StoreError::Io, 3));
+    let store = RetryableStore::new(flaky.clone(), config);
+    assert!(store.put("key", b"value").await.is_ok());
+    assert_eq!(flaky.attempts.load(Ordering::SeqCst), 4);
+    assert_eq!(store.get("key").await.unwrap(), b"value");
+    assert_eq!(flaky.attempts.load(Ordering::SeqCst), 5);
+
+    // Gives up after `max_attempts`.
+    let flaky = Arc::new(FlakyStore::new(StoreError::Storage, 10));
+    let store = RetryableStore::new(flaky.clone(), config);
+    assert!(matches!(
+        store.put("key", b"value").await,
+        Err(StoreError::Storage(_))
+    ));
+    assert_eq!(flaky.attempts.load(Ordering::SeqCst), 5);
+
+    // A missing key is not a transient failure.
+    let flaky = Arc::new(FlakyStore::new(StoreError::Io, 0));
+    let store = RetryableStore::new(flaky.clone(), config);
+    assert!(matches!(
+        store.get("missing").await,
+        Err(StoreError::NotFound(_))
+    ));
+    assert_eq!(flaky.attempts.load(Ordering::SeqCst), 1);
+}
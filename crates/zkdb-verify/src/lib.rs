@@ -0,0 +1,552 @@
+//! Pure verification rules for zkDB proofs.
+//!
+//! This crate has no SP1 or async runtime dependencies so the same rules run
+//! on the host (through zkdb-lib) and in the browser (through the `wasm`
+//! feature and wasm-bindgen).
+
+use ed25519_dalek::{Signature, Verifier, VerifyingKey};
+use rs_merkle::{algorithms::Sha256 as MerkleSha256, proof_serializers, MerkleProof};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+
+#[derive(Error, Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum VerifyError {
+    #[error("Invalid hex in {field}: {reason}")]
+    InvalidHex { field: String, reason: String },
+    #[error("Invalid base64 in {field}: {reason}")]
+    InvalidBase64 { field: String, reason: String },
+    #[error("Invalid Merkle proof: {0}")]
+    InvalidProof(String),
+    #[error("Invalid bundle JSON: {0}")]
+    InvalidJson(String),
+    #[error("Invalid public values: {0}")]
+    InvalidPublicValues(String),
+    #[error("Invalid signature: {0}")]
+    InvalidSignature(String),
+}
+
+/// Hashes a value into the leaf committed to the Merkle tree.
+pub fn hash_value(value: &[u8]) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update(value);
+    hasher.finalize().into()
+}
+
+/// Hex-encoded form of `hash_value`, as used in engine output.
+pub fn hash_value_hex(value: &[u8]) -> String {
+    hex::encode(hash_value(value))
+}
+
+/// Leaf committing to `key` holding a value hashing to `value_hash`, under
+/// `LeafLayout::Sorted`. Matches `zkdb_core::merkle::keyed_leaf`.
+pub fn keyed_leaf(key: &[u8], value_hash: &[u8; 32]) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update((key.len() as u64).to_le_bytes());
+    hasher.update(key);
+    hasher.update(value_hash);
+    hasher.finalize().into()
+}
+
+/// Decodes a hex-encoded 32-byte hash.
+pub fn decode_hash(field: &str, hex_str: &str) -> Result<[u8; 32], VerifyError> {
+    let bytes = hex::decode(hex_str).map_err(|e| VerifyError::InvalidHex {
+        field: field.to_string(),
+        reason: e.to_string(),
+    })?;
+    bytes
+        .try_into()
+        .map_err(|bytes: Vec<u8>| VerifyError::InvalidHex {
+            field: field.to_string(),
+            reason: format!("expected 32 bytes, got {}", bytes.len()),
+        })
+}
+
+/// Checks a serialized inclusion proof, as produced by the engine's `Prove`
+/// command, for a single leaf.
+pub fn verify_inclusion(
+    root: [u8; 32],
+    leaf: [u8; 32],
+    index: usize,
+    total_leaves: usize,
+    proof: &[u8],
+) -> Result<bool, VerifyError> {
+    if index >= total_leaves {
+        return Ok(false);
+    }
+    let proof =
+        MerkleProof::<MerkleSha256>::deserialize::<proof_serializers::ReverseHashesOrder>(proof)
+            .map_err(|e| VerifyError::InvalidProof(e.to_string()))?;
+    Ok(proof.verify(root, &[index], &[leaf], total_leaves))
+}
+
+/// A key of a tree laid out as `LeafLayout::Sorted`, with what is needed to
+/// recompute its leaf.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct KeyedLeaf {
+    pub key: Vec<u8>,
+    pub value_hash: [u8; 32],
+    pub index: usize,
+}
+
+impl KeyedLeaf {
+    pub fn leaf(&self) -> [u8; 32] {
+        keyed_leaf(&self.key, &self.value_hash)
+    }
+}
+
+/// Checks that `run`, bracketed by the keys `before` and `after`, is a run
+/// of adjacent leaves of a sorted tree: indices follow each other, keys
+/// ascend, a missing `before` or `after` stands for an end of the tree, and
+/// the multiproof proves every leaf against `root`.
+///
+/// No key of the tree then lies between `before` and `after` other than
+/// those of `run`.
+pub fn verify_sorted_run(
+    root: [u8; 32],
+    total_leaves: usize,
+    before: Option<&KeyedLeaf>,
+    run: &[KeyedLeaf],
+    after: Option<&KeyedLeaf>,
+    multiproof: &[u8],
+) -> Result<bool, VerifyError> {
+    let chain: Vec<&KeyedLeaf> = before.into_iter().chain(run).chain(after).collect();
+    let Some(first) = chain.first() else {
+        return Ok(total_leaves == 0);
+    };
+    let last = chain[chain.len() - 1];
+    let adjacent = chain
+        .windows(2)
+        .all(|pair| pair[1].index == pair[0].index + 1 && pair[0].key < pair[1].key);
+    if !adjacent
+        || (before.is_none() && first.index != 0)
+        || (after.is_none() && last.index + 1 != total_leaves)
+        || last.index >= total_leaves
+    {
+        return Ok(false);
+    }
+    let proof = MerkleProof::<MerkleSha256>::deserialize::<proof_serializers::ReverseHashesOrder>(
+        multiproof,
+    )
+    .map_err(|e| VerifyError::InvalidProof(e.to_string()))?;
+    let indices: Vec<usize> = chain.iter().map(|entry| entry.index).collect();
+    let leaves: Vec<[u8; 32]> = chain.iter().map(|entry| entry.leaf()).collect();
+    Ok(proof.verify(root, &indices, &leaves, total_leaves))
+}
+
+/// Checks that `key` is not in a sorted tree, given the adjacent keys
+/// around the place it would take.
+pub fn verify_absence(
+    root: [u8; 32],
+    total_leaves: usize,
+    key: &[u8],
+    before: Option<&KeyedLeaf>,
+    after: Option<&KeyedLeaf>,
+    multiproof: &[u8],
+) -> Result<bool, VerifyError> {
+    if before.is_some_and(|before| before.key.as_slice() >= key)
+        || after.is_some_and(|after| after.key.as_slice() <= key)
+    {
+        return Ok(false);
+    }
+    verify_sorted_run(root, total_leaves, before, &[], after, multiproof)
+}
+
+/// Levels of a sparse Merkle tree below its root.
+pub const SMT_DEPTH: usize = 256;
+
+/// Path of the leaf of `key` in a sparse Merkle tree. Matches
+/// `zkdb_core::smt::key_path`.
+pub fn smt_key_path(key: &[u8]) -> [u8; 32] {
+    hash_value(key)
+}
+
+/// Leaf at `path` of a sparse Merkle tree holding a value hashing to
+/// `value_hash`. Matches `zkdb_core::smt::smt_leaf`.
+pub fn smt_leaf(path: &[u8; 32], value_hash: &[u8; 32]) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update([0u8]);
+    hasher.update(path);
+    hasher.update(value_hash);
+    hasher.finalize().into()
+}
+
+/// Node of a sparse Merkle tree over `left` and `right`, all zeros over two
+/// empty children. Matches `zkdb_core::smt::smt_node`.
+pub fn smt_node(left: &[u8; 32], right: &[u8; 32]) -> [u8; 32] {
+    if *left == [0u8; 32] && *right == [0u8; 32] {
+        return [0u8; 32];
+    }
+    let mut hasher = Sha256::new();
+    hasher.update([1u8]);
+    hasher.update(left);
+    hasher.update(right);
+    hasher.finalize().into()
+}
+
+/// Checks a proof of the sparse Merkle engine that `key` holds a value
+/// hashing to `value_hash` under `root`, or nothing for `None`.
+///
+/// The proof is a 32-byte bitmap, bit `height % 8` of byte `height / 8`
+/// marking the levels above the leaf whose sibling is not empty, followed
+/// by those siblings from the leaf up. An empty tree has an all-zero root.
+pub fn verify_smt_proof(
+    root: [u8; 32],
+    key: &[u8],
+    value_hash: Option<&[u8; 32]>,
+    proof: &[u8],
+) -> Result<bool, VerifyError> {
+    let invalid = |reason: &str| VerifyError::InvalidProof(reason.to_string());
+    if proof.len() < 32 || !proof[32..].chunks_exact(32).remainder().is_empty() {
+        return Err(invalid("sparse Merkle proof is not a bitmap and siblings"));
+    }
+    let (bitmap, rest) = proof.split_at(32);
+    let mut siblings = rest.chunks_exact(32);
+    let path = smt_key_path(key);
+    let mut node = value_hash.map_or([0u8; 32], |value_hash| smt_leaf(&path, value_hash));
+    for height in 0..SMT_DEPTH {
+        let sibling: [u8; 32] = if bitmap[height / 8] >> (height % 8) & 1 == 1 {
+            let sibling = siblings
+                .next()
+                .ok_or_else(|| invalid("sparse Merkle proof is missing siblings"))?;
+            sibling.try_into().expect("chunks are 32 bytes")
+        } else {
+            [0u8; 32]
+        };
+        let index = SMT_DEPTH - 1 - height;
+        node = if path[index / 8] >> (7 - index % 8) & 1 == 1 {
+            smt_node(&sibling, &node)
+        } else {
+            smt_node(&node, &sibling)
+        };
+    }
+    if siblings.next().is_some() {
+        return Err(invalid("sparse Merkle proof has extra siblings"));
+    }
+    Ok(node == root)
+}
+
+/// Hash of a node of an IAVL tree. Matches `zkdb_core::iavl::iavl_node_hash`.
+pub fn iavl_node_hash(
+    key: &[u8],
+    value_hash: &[u8; 32],
+    height: u8,
+    size: u64,
+    left: &[u8; 32],
+    right: &[u8; 32],
+) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update([height]);
+    hasher.update(size.to_le_bytes());
+    hasher.update((key.len() as u64).to_le_bytes());
+    hasher.update(key);
+    hasher.update(value_hash);
+    hasher.update(left);
+    hasher.update(right);
+    hasher.finalize().into()
+}
+
+/// The node an IAVL proof starts from, for a key that is present.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IavlNodeProof {
+    pub height: u8,
+    pub size: u64,
+    pub left: [u8; 32],
+    pub right: [u8; 32],
+}
+
+/// An ancestor on the path of an IAVL proof.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IavlStep {
+    pub key: Vec<u8>,
+    pub value_hash: [u8; 32],
+    pub height: u8,
+    pub size: u64,
+    /// Hash of the child off the path.
+    pub sibling: [u8; 32],
+    /// Whether the path comes up from the left child.
+    pub from_left: bool,
+}
+
+/// Proof of the IAVL engine, as it reports it under `proof`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IavlProof {
+    pub node: Option<IavlNodeProof>,
+    pub path: Vec<IavlStep>,
+}
+
+/// Checks a proof of the IAVL engine that `key` holds a value hashing to
+/// `value_hash` under `root`, or nothing for `None`. An empty tree has an
+/// all-zero root.
+///
+/// Every ancestor must order `key` on the side the path comes up from, so
+/// a proof of absence ends in the one empty slot `key` could occupy.
+pub fn verify_iavl_proof(
+    root: [u8; 32],
+    key: &[u8],
+    value_hash: Option<&[u8; 32]>,
+    proof: &IavlProof,
+) -> Result<bool, VerifyError> {
+    let mut hash = match (&proof.node, value_hash) {
+        (Some(node), Some(value_hash)) => iavl_node_hash(
+            key,
+            value_hash,
+            node.height,
+            node.size,
+            &node.left,
+            &node.right,
+        ),
+        (None, None) => [0u8; 32],
+        (Some(_), None) => {
+            return Err(VerifyError::InvalidProof(
+                "proof is of a present key".into(),
+            ))
+        }
+        (None, Some(_)) => {
+            return Err(VerifyError::InvalidProof(
+                "proof is of an absent key".into(),
+            ))
+        }
+    };
+    for step in &proof.path {
+        if (key < step.key.as_slice()) != step.from_left || key == step.key.as_slice() {
+            return Ok(false);
+        }
+        let (left, right) = if step.from_left {
+            (&hash, &step.sibling)
+        } else {
+            (&step.sibling, &hash)
+        };
+        hash = iavl_node_hash(
+            &step.key,
+            &step.value_hash,
+            step.height,
+            step.size,
+            left,
+            right,
+        );
+    }
+    Ok(hash == root)
+}
+
+/// Decodes a serialized inclusion proof into its sibling hashes, ordered
+/// from the leaf up to the root.
+pub fn proof_hashes(proof: &[u8]) -> Result<Vec<[u8; 32]>, VerifyError> {
+    let proof =
+        MerkleProof::<MerkleSha256>::deserialize::<proof_serializers::ReverseHashesOrder>(proof)
+            .map_err(|e| VerifyError::InvalidProof(e.to_string()))?;
+    Ok(proof.proof_hashes().to_vec())
+}
+
+/// Prefix of the public values committed by an engine asked for binary
+/// output, `zkdb_core::BINARY_OUTPUT_MAGIC`.
+pub const BINARY_OUTPUT_MAGIC: [u8; 4] = *b"ZKB1";
+
+/// Decodes the `data` field of the public values committed by the engine.
+///
+/// The engine commits the `QueryResult` either as JSON, an object with
+/// `data` and `new_state` fields, or as `BINARY_OUTPUT_MAGIC` followed by
+/// the bincode encoding of `data` as JSON bytes and then `new_state`, each
+/// prefixed with its length as a little-endian `u64`.
+pub fn decode_public_values(public_values: &[u8]) -> Result<serde_json::Value, VerifyError> {
+    if let Some(encoded) = public_values.strip_prefix(&BINARY_OUTPUT_MAGIC) {
+        let data = encoded
+            .get(..8)
+            .and_then(|len| usize::try_from(u64::from_le_bytes(len.try_into().ok()?)).ok())
+            .and_then(|len| encoded[8..].get(..len))
+            .ok_or_else(|| {
+                VerifyError::InvalidPublicValues("truncated binary output".to_string())
+            })?;
+        return serde_json::from_slice(data)
+            .map_err(|e| VerifyError::InvalidPublicValues(e.to_string()));
+    }
+    let output: serde_json::Value = serde_json::from_slice(public_values)
+        .map_err(|e| VerifyError::InvalidPublicValues(e.to_string()))?;
+    output
+        .get("data")
+        .cloned()
+        .ok_or_else(|| VerifyError::InvalidPublicValues("missing data field".to_string()))
+}
+
+/// What the leaf of a value commits to.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CommitTo {
+    /// The hash of the value itself.
+    #[default]
+    Plaintext,
+    /// The hash of the value encrypted by the database, so that the leaf
+    /// reveals nothing about the value to whoever lacks the key.
+    Ciphertext,
+}
+
+/// How the leaves of the tree a bundle was proven against are laid out.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum LeafLayout {
+    /// Leaves are the hashes of the values.
+    #[default]
+    Append,
+    /// Leaves are `keyed_leaf`s of the keys and the hashes of their values.
+    Sorted,
+}
+
+impl LeafLayout {
+    fn is_append(&self) -> bool {
+        *self == LeafLayout::Append
+    }
+
+    /// The leaf `key` holding `value` has under this layout.
+    pub fn leaf(&self, key: &[u8], value: &[u8]) -> [u8; 32] {
+        match self {
+            LeafLayout::Append => hash_value(value),
+            LeafLayout::Sorted => keyed_leaf(key, &hash_value(value)),
+        }
+    }
+}
+
+/// A value together with everything needed to check it against a root.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProofBundle {
+    pub key: String,
+    /// Base64-encoded value bytes, sealed by the database when `commit_to`
+    /// is `CommitTo::Ciphertext`.
+    pub value: String,
+    /// Hex-encoded leaf hash.
+    pub leaf: String,
+    /// Hex-encoded tree root.
+    pub root: String,
+    /// Index of the leaf in the tree.
+    pub index: usize,
+    /// Number of leaves in the tree the proof was generated against.
+    pub total_leaves: usize,
+    /// Base64-encoded inclusion proof in `ReverseHashesOrder`.
+    pub proof: String,
+    /// Hex-encoded public values committed by the engine for the `Prove`
+    /// command, if the bundle was produced with an SP1 proof.
+    #[serde(default)]
+    pub public_values: Option<String>,
+    /// What `value` is: the value itself, or its ciphertext for databases
+    /// committing to ciphertext.
+    #[serde(default)]
+    pub commit_to: CommitTo,
+    /// Hex-encoded ed25519 signature over `signed_bytes`, if the database
+    /// producing the bundle had a signing key.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signature: Option<String>,
+    /// Layout of the leaves, left out of the encoding for
+    /// `LeafLayout::Append` so older bundles and signatures stay valid.
+    #[serde(default, skip_serializing_if = "LeafLayout::is_append")]
+    pub layout: LeafLayout,
+}
+
+impl ProofBundle {
+    /// Bytes the signature covers: the JSON encoding of the bundle without
+    /// its signature.
+    pub fn signed_bytes(&self) -> Vec<u8> {
+        let unsigned = ProofBundle {
+            signature: None,
+            ..self.clone()
+        };
+        serde_json::to_vec(&unsigned).expect("bundles always encode as JSON")
+    }
+
+    /// Checks that the bundle was signed with the key `public_key` belongs
+    /// to. Fails for unsigned bundles.
+    pub fn verify_signature(&self, public_key: &[u8; 32]) -> Result<(), VerifyError> {
+        let signature = self
+            .signature
+            .as_ref()
+            .ok_or_else(|| VerifyError::InvalidSignature("the bundle is unsigned".to_string()))?;
+        let signature = hex::decode(signature).map_err(|e| VerifyError::InvalidHex {
+            field: "signature".to_string(),
+            reason: e.to_string(),
+        })?;
+        verify_signature(public_key, &self.signed_bytes(), &signature)
+    }
+}
+
+/// Checks a detached ed25519 `signature` over `message`.
+pub fn verify_signature(
+    public_key: &[u8; 32],
+    message: &[u8],
+    signature: &[u8],
+) -> Result<(), VerifyError> {
+    let key = VerifyingKey::from_bytes(public_key)
+        .map_err(|e| VerifyError::InvalidSignature(format!("invalid public key: {}", e)))?;
+    let signature = Signature::from_slice(signature)
+        .map_err(|e| VerifyError::InvalidSignature(e.to_string()))?;
+    key.verify(message, &signature)
+        .map_err(|_| VerifyError::InvalidSignature("signature does not match".to_string()))
+}
+
+/// Outcome of checking a `ProofBundle`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct VerifyReport {
+    /// The leaf equals the hash of the value, or under `LeafLayout::Sorted`
+    /// the keyed leaf of the key and that hash.
+    pub leaf_matches_value: bool,
+    /// The inclusion proof reconstructs the root from the leaf.
+    pub proof_valid: bool,
+    /// The public values commit to the same root and leaf, if present.
+    pub public_values_match: Option<bool>,
+    /// All checks passed.
+    pub valid: bool,
+}
+
+/// Checks a bundle without trusting any of its fields.
+pub fn verify_bundle(bundle: &ProofBundle) -> Result<VerifyReport, VerifyError> {
+    let value = base64::decode(&bundle.value).map_err(|e| VerifyError::InvalidBase64 {
+        field: "value".to_string(),
+        reason: e.to_string(),
+    })?;
+    let proof = base64::decode(&bundle.proof).map_err(|e| VerifyError::InvalidBase64 {
+        field: "proof".to_string(),
+        reason: e.to_string(),
+    })?;
+    let leaf = decode_hash("leaf", &bundle.leaf)?;
+    let root = decode_hash("root", &bundle.root)?;
+
+    let leaf_matches_value = bundle.layout.leaf(bundle.key.as_bytes(), &value) == leaf;
+    let proof_valid = verify_inclusion(root, leaf, bundle.index, bundle.total_leaves, &proof)?;
+
+    let public_values_match = match &bundle.public_values {
+        Some(public_values) => {
+            let bytes = hex::decode(public_values).map_err(|e| VerifyError::InvalidHex {
+                field: "public_values".to_string(),
+                reason: e.to_string(),
+            })?;
+            let data = decode_public_values(&bytes)?;
+            Some(
+                data["root"].as_str() == Some(bundle.root.as_str())
+                    && data["leaf"].as_str() == Some(bundle.leaf.as_str())
+                    && data["index"].as_u64() == Some(bundle.index as u64),
+            )
+        }
+        None => None,
+    };
+
+    Ok(VerifyReport {
+        leaf_matches_value,
+        proof_valid,
+        public_values_match,
+        valid: leaf_matches_value && proof_valid && public_values_match.unwrap_or(true),
+    })
+}
+
+/// Parses a JSON-encoded `ProofBundle` and checks it.
+pub fn verify_bundle_json(json: &str) -> Result<VerifyReport, VerifyError> {
+    let bundle: ProofBundle =
+        serde_json::from_str(json).map_err(|e| VerifyError::InvalidJson(e.to_string()))?;
+    verify_bundle(&bundle)
+}
+
+#[cfg(feature = "wasm")]
+mod wasm {
+    use wasm_bindgen::prelude::*;
+
+    /// Checks a JSON-encoded bundle and returns the JSON-encoded `VerifyReport`.
+    #[wasm_bindgen(js_name = verifyBundleJson)]
+    pub fn verify_bundle_json(json: &str) -> Result<String, JsError> {
+        let report = super::verify_bundle_json(json).map_err(|e| JsError::new(&e.to_string()))?;
+        serde_json::to_string(&report).map_err(|e| JsError::new(&e.to_string()))
+    }
+}
@@ -0,0 +1,117 @@
+use ed25519_dalek::{Signer, SigningKey};
+use rs_merkle::{algorithms::Sha256, proof_serializers::ReverseHashesOrder, MerkleTree};
+use zkdb_verify::{
+    hash_value, verify_absence, verify_bundle, verify_bundle_json, verify_sorted_run, KeyedLeaf,
+    ProofBundle, VerifyError,
+};
+
+const BUNDLE: &str = include_str!("fixtures/bundle.json");
+
+#[test]
+fn test_fixture_bundle_verifies() {
+    let report = verify_bundle_json(BUNDLE).unwrap();
+    assert!(report.leaf_matches_value);
+    assert!(report.proof_valid);
+    assert_eq!(report.public_values_match, None);
+    assert!(report.valid);
+}
+
+#[test]
+fn test_tampered_bundle_fails() {
+    let bundle: ProofBundle = serde_json::from_str(BUNDLE).unwrap();
+
+    let mut wrong_value = bundle.clone();
+    wrong_value.value = base64::encode(b"omega");
+    let report = verify_bundle(&wrong_value).unwrap();
+    assert!(!report.leaf_matches_value);
+    assert!(!report.valid);
+
+    let mut wrong_index = bundle.clone();
+    wrong_index.index = 1;
+    let report = verify_bundle(&wrong_index).unwrap();
+    assert!(!report.proof_valid);
+    assert!(!report.valid);
+
+    let mut wrong_root = bundle;
+    wrong_root.root = hex::encode([0u8; 32]);
+    let report = verify_bundle(&wrong_root).unwrap();
+    assert!(!report.proof_valid);
+    assert!(!report.valid);
+}
+
+#[test]
+fn test_bundle_signatures() {
+    let key = SigningKey::from_bytes(&[1; 32]);
+    let public_key = key.verifying_key().to_bytes();
+    let mut bundle: ProofBundle = serde_json::from_str(BUNDLE).unwrap();
+    assert!(matches!(
+        bundle.verify_signature(&public_key),
+        Err(VerifyError::InvalidSignature(_))
+    ));
+
+    bundle.signature = Some(hex::encode(key.sign(&bundle.signed_bytes()).to_bytes()));
+    let json = serde_json::to_string(&bundle).unwrap();
+    let signed: ProofBundle = serde_json::from_str(&json).unwrap();
+    signed.verify_signature(&public_key).unwrap();
+
+    let other_key = SigningKey::from_bytes(&[2; 32]).verifying_key().to_bytes();
+    assert!(matches!(
+        signed.verify_signature(&other_key),
+        Err(VerifyError::InvalidSignature(_))
+    ));
+    let mut tampered = signed;
+    tampered.index += 1;
+    assert!(matches!(
+        tampered.verify_signature(&public_key),
+        Err(VerifyError::InvalidSignature(_))
+    ));
+}
+
+#[test]
+fn test_sorted_run_and_absence() {
+    let entries: Vec<KeyedLeaf> = ["b", "d", "f", "h"]
+        .iter()
+        .enumerate()
+        .map(|(index, key)| KeyedLeaf {
+            key: key.as_bytes().to_vec(),
+            value_hash: hash_value(key.as_bytes()),
+            index,
+        })
+        .collect();
+    let leaves: Vec<[u8; 32]> = entries.iter().map(KeyedLeaf::leaf).collect();
+    let tree = MerkleTree::<Sha256>::from_leaves(&leaves);
+    let root = tree.root().unwrap();
+    let prove = |indices: &[usize]| tree.proof(indices).serialize::<ReverseHashesOrder>();
+
+    // "d" and "f" with the keys around them.
+    let proof = prove(&[0, 1, 2, 3]);
+    assert!(verify_sorted_run(
+        root,
+        4,
+        Some(&entries[0]),
+        &entries[1..3],
+        Some(&entries[3]),
+        &proof
+    )
+    .unwrap());
+    // Leaving "f" out of the run breaks adjacency.
+    let proof = prove(&[0, 1, 3]);
+    assert!(!verify_sorted_run(
+        root,
+        4,
+        Some(&entries[0]),
+        &entries[1..2],
+        Some(&entries[3]),
+        &proof
+    )
+    .unwrap());
+
+    // "e" falls between "d" and "f", "a" before the first key.
+    let proof = prove(&[1, 2]);
+    assert!(verify_absence(root, 4, b"e", Some(&entries[1]), Some(&entries[2]), &proof).unwrap());
+    assert!(!verify_absence(root, 4, b"d", Some(&entries[1]), Some(&entries[2]), &proof).unwrap());
+    let proof = prove(&[0]);
+    assert!(verify_absence(root, 4, b"a", None, Some(&entries[0]), &proof).unwrap());
+    // Only the last key may stand for the end of the tree.
+    assert!(!verify_absence(root, 4, b"c", Some(&entries[0]), None, &proof).unwrap());
+}
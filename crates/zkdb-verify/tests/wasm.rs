@@ -0,0 +1,14 @@
+//! Run with `wasm-pack test --node crates/zkdb-verify --features wasm`.
+#![cfg(target_arch = "wasm32")]
+
+use wasm_bindgen_test::wasm_bindgen_test;
+
+// Written by `test_bundle_matches_native_database` in zkdb-lib when
+// `ZKDB_UPDATE_FIXTURES` is set.
+const BUNDLE: &str = include_str!("fixtures/bundle.json");
+
+#[wasm_bindgen_test]
+fn test_verify_bundle_in_wasm() {
+    let report = zkdb_verify::verify_bundle_json(BUNDLE).unwrap();
+    assert!(report.valid);
+}